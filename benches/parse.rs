@@ -0,0 +1,55 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use stun_message::*;
+
+/// A typical ICE connectivity check: USERNAME, PRIORITY, ICE-CONTROLLED, MESSAGE-INTEGRITY and a
+/// trailing FINGERPRINT-sized attribute
+fn connectivity_check() -> Vec<u8> {
+    let transaction_id = [0x5A; STUN_TRANSACTION_ID_NUM_BYTES];
+
+    let mut data = StunMessageBuilder::new(
+        StunMessageClass::Request,
+        StunMessageMethod::Binding,
+        &transaction_id,
+    )
+    .add_attribute(StunAttributeType::Username as u16, b"evtj:h6vY")
+    .add_u32_attribute(0x0024, 0x6E00_01FF)
+    .add_attribute(0x8029, &[0x93, 0x2F, 0xF9, 0xB1, 0x51, 0x26, 0x3B, 0x36])
+    .add_message_integrity(b"VOkJxbRl1RmTxUk/WvJxBt")
+    .build()
+    .unwrap();
+
+    data.extend_from_slice(&[0x80, 0x28, 0x00, 0x04, 0xE5, 0x7A, 0x3B, 0xCF]);
+    data[3] += 8;
+
+    data
+}
+
+fn bench_parse(c: &mut Criterion) {
+    let data = connectivity_check();
+
+    let mut group = c.benchmark_group("parse");
+
+    group.bench_function("parse_stun_message", |b| {
+        b.iter(|| {
+            let (_, message) = parse_stun_message(black_box(&data)).unwrap();
+            black_box(
+                message
+                    .get_attribute(StunAttributeType::Username as u16)
+                    .is_some(),
+            )
+        })
+    });
+
+    group.bench_function("parse_stun_message_view", |b| {
+        b.iter(|| {
+            let (_, view) = parse_stun_message_view(black_box(&data)).unwrap();
+            black_box(view.find(StunAttributeType::Username as u16).is_some())
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_parse);
+criterion_main!(benches);
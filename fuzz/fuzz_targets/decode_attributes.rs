@@ -0,0 +1,34 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use stun_message::*;
+
+fuzz_target!(|data: &[u8]| {
+    // the first 12 bytes are the transaction id used by the XOR'd addresses
+    if data.len() < STUN_TRANSACTION_ID_NUM_BYTES {
+        return;
+    }
+    let (transaction_id, value) = data.split_at(STUN_TRANSACTION_ID_NUM_BYTES);
+    let transaction_id: &[u8; STUN_TRANSACTION_ID_NUM_BYTES] = transaction_id.try_into().unwrap();
+
+    if let Ok((_, address)) = parse_address(value) {
+        assert_eq!(
+            parse_address(&serialize_address(&address)).unwrap().1,
+            address
+        );
+    }
+    if let Ok((_, address)) = parse_xor_address(value, transaction_id) {
+        let value = serialize_xor_address(&address, transaction_id);
+        assert_eq!(
+            parse_xor_address(&value, transaction_id).unwrap().1,
+            address
+        );
+    }
+
+    let _ = parse_error_code(value);
+    let _ = parse_username(value);
+    let _ = parse_realm(value);
+    let _ = parse_nonce(value);
+    let _ = parse_software(value);
+    let _ = parse_connection_id(value);
+});
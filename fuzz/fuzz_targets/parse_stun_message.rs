@@ -0,0 +1,39 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use stun_message::*;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok((_, message)) = parse_stun_message(data) {
+        let _ = validate(&message);
+        let _ = message.to_string();
+        let _ = verify_message_integrity(&message, b"key");
+        let _ = verify_fingerprint(&message);
+
+        // a parsed message serializes back to the bytes it was parsed from
+        assert_eq!(serialize(&message).unwrap(), message.raw);
+        assert_eq!(
+            OwnedStunMessage::from(&message).to_bytes().unwrap(),
+            message.raw
+        );
+    }
+
+    // both parsers bound the attributes by the message length, so they accept the same input
+    assert_eq!(
+        parse_stun_message(data).is_ok(),
+        parse_stun_message_view(data).is_ok()
+    );
+
+    if let Ok((_, view)) = parse_stun_message_view(data) {
+        for attribute in view.attributes() {
+            let _ = attribute.attribute_value.len();
+        }
+    }
+
+    let mut buffer = data.to_vec();
+    if let Ok(mut message) = StunMessageMut::new(&mut buffer) {
+        let _ = message.update_message_integrity(b"key");
+        let _ = message.update_fingerprint();
+        let _ = message.view();
+    }
+});
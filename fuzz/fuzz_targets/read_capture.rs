@@ -0,0 +1,12 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use stun_message::*;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(reader) = CaptureReader::new(data) {
+        for message in reader.flatten() {
+            let _ = message.payload();
+        }
+    }
+});
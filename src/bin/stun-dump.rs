@@ -0,0 +1,234 @@
+//! Decode STUN messages, e.g. ones copied from logs, and print them in a readable form.
+//!
+//! Each input holds one message, written as hex, base64 or raw binary.  By default the encoding
+//! is detected from the input.  Messages which fail to parse are reported with the parse error
+//! and the byte offset at which it was found.
+use stun_message::*;
+
+use base64::alphabet;
+use base64::engine::general_purpose::{GeneralPurpose, GeneralPurposeConfig};
+use base64::engine::DecodePaddingMode;
+use base64::Engine;
+use nom::Err::{Error, Failure, Incomplete};
+
+use std::env;
+use std::fs;
+use std::io::{self, Read};
+use std::process::ExitCode;
+
+const USAGE: &str = "\
+usage: stun-dump [OPTIONS] [FILE]...
+
+Decode the STUN message in each FILE, or in standard input if there is no FILE or FILE is -.
+
+Options:
+  --hex       the input is hex, optionally with whitespace, ':' separators or 0x prefixes
+  --base64    the input is base64
+  --raw       the input is the binary message
+  --json      print each message as JSON
+  -h, --help  print this help";
+
+/// base64 as it is usually logged, with or without padding
+const BASE64: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Auto,
+    Hex,
+    Base64,
+    Raw,
+}
+
+#[derive(Debug)]
+struct Options {
+    format: Format,
+    json: bool,
+    files: Vec<String>,
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(message) => {
+            eprintln!("stun-dump: {}\n\n{}", message, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    let Options {
+        format,
+        json,
+        mut files,
+    } = options;
+    if files.is_empty() {
+        files.push("-".to_string());
+    }
+
+    let mut ok = true;
+    for (index, file) in files.iter().enumerate() {
+        let name = match file.as_str() {
+            "-" => "<stdin>",
+            file => file,
+        };
+        if files.len() > 1 && !json {
+            if index > 0 {
+                println!();
+            }
+            println!("{}:", name);
+        }
+
+        let result = read(file)
+            .and_then(|input| decode(&input, format))
+            .and_then(|data| dump(&data, json));
+        if let Err(message) = result {
+            eprintln!("stun-dump: {}: {}", name, message);
+            ok = false;
+        }
+    }
+
+    if ok {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// Parse the command line, returning `None` if help was requested
+fn parse_args(args: impl Iterator<Item = String>) -> Result<Option<Options>, String> {
+    let mut options = Options {
+        format: Format::Auto,
+        json: false,
+        files: Vec::new(),
+    };
+
+    for arg in args {
+        let format = match arg.as_str() {
+            "--hex" => Format::Hex,
+            "--base64" => Format::Base64,
+            "--raw" => Format::Raw,
+            "--json" => {
+                options.json = true;
+                continue;
+            }
+            "-h" | "--help" => return Ok(None),
+            _ if arg.starts_with('-') && arg != "-" => {
+                return Err(format!("unknown option {}", arg))
+            }
+            _ => {
+                options.files.push(arg);
+                continue;
+            }
+        };
+
+        if options.format != Format::Auto && options.format != format {
+            return Err("only one of --hex, --base64 and --raw may be given".to_string());
+        }
+        options.format = format;
+    }
+
+    Ok(Some(options))
+}
+
+fn read(file: &str) -> Result<Vec<u8>, String> {
+    let mut input = Vec::new();
+    let result = match file {
+        "-" => io::stdin().read_to_end(&mut input).map(|_| input),
+        file => fs::read(file),
+    };
+
+    result.map_err(|e| e.to_string())
+}
+
+/// Decode the input to the bytes of the message
+fn decode(input: &[u8], format: Format) -> Result<Vec<u8>, String> {
+    match format {
+        Format::Hex => decode_hex(input).ok_or_else(|| "the input is not valid hex".to_string()),
+        Format::Base64 => {
+            decode_base64(input).ok_or_else(|| "the input is not valid base64".to_string())
+        }
+        Format::Raw => Ok(input.to_vec()),
+        Format::Auto => {
+            // a binary message starts with a control character, text is only printable characters
+            if input
+                .iter()
+                .any(|b| !b.is_ascii_graphic() && !b.is_ascii_whitespace())
+            {
+                return Ok(input.to_vec());
+            }
+
+            decode_hex(input)
+                .or_else(|| decode_base64(input))
+                .ok_or_else(|| "the input is neither hex nor base64".to_string())
+        }
+    }
+}
+
+fn decode_hex(input: &[u8]) -> Option<Vec<u8>> {
+    let text = std::str::from_utf8(input).ok()?;
+    let digits: String = text
+        .split(|c: char| c.is_ascii_whitespace() || c == ':' || c == ',')
+        .map(|token| {
+            token
+                .strip_prefix("0x")
+                .or_else(|| token.strip_prefix("0X"))
+                .unwrap_or(token)
+        })
+        .collect();
+
+    hex::decode(digits).ok()
+}
+
+fn decode_base64(input: &[u8]) -> Option<Vec<u8>> {
+    let text: Vec<u8> = input
+        .iter()
+        .copied()
+        .filter(|b| !b.is_ascii_whitespace())
+        .collect();
+
+    BASE64.decode(text).ok()
+}
+
+/// Parse the message and print it, or describe why it could not be parsed
+fn dump(data: &[u8], json: bool) -> Result<(), String> {
+    let (remaining, message) = match parse_stun_message(data) {
+        Ok(parsed) => parsed,
+        Err(Error(e)) | Err(Failure(e)) => {
+            return Err(match e.offset(data) {
+                Some(offset) => format!("parse error at byte {}: {}", offset, describe(&e)),
+                None => format!("parse error: {}", describe(&e)),
+            })
+        }
+        Err(Incomplete(_)) => return Err("the message is incomplete".to_string()),
+    };
+
+    if json {
+        let message = OwnedStunMessage::from(&message);
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&message).map_err(|e| e.to_string())?
+        );
+    } else {
+        println!("{}", message);
+        if !remaining.is_empty() {
+            println!("  ({} bytes after the message)", remaining.len());
+        }
+    }
+
+    Ok(())
+}
+
+/// The parse error, without the remaining input carried by some errors
+fn describe(error: &StunParseError<&[u8]>) -> String {
+    match error {
+        StunParseError::InvalidUtf8Error(_) => "InvalidUtf8Error".to_string(),
+        StunParseError::Nom(_, kind) => format!("Nom({:?})", kind),
+        error => format!("{:?}", error),
+    }
+}
@@ -0,0 +1,180 @@
+//! A STUN server answering Binding requests over UDP and TCP, see `stun_message::Server`.
+use stun_message::*;
+
+use std::env;
+use std::net::{SocketAddr, TcpListener, UdpSocket};
+use std::process::ExitCode;
+use std::thread;
+
+const USAGE: &str = "\
+usage: stun-server [OPTIONS]
+
+Answer STUN Binding requests over UDP and TCP.
+
+Options:
+  -l, --listen ADDRESS  listen on ADDRESS, which can be repeated (default 0.0.0.0:3478)
+  --alternate ADDRESS   support NAT behavior discovery (RFC 5780) with the single --listen
+                        address as the primary address and ADDRESS as the alternate one,
+                        which differs in both IP address and port
+  --software TEXT       send TEXT as the SOFTWARE attribute (default stun-message VERSION)
+  --no-software         do not send the SOFTWARE attribute
+  -h, --help            print this help";
+
+const DEFAULT_LISTEN_ADDRESS: &str = "0.0.0.0:3478";
+
+const DEFAULT_SOFTWARE: &str = concat!("stun-message ", env!("CARGO_PKG_VERSION"));
+
+#[derive(Debug)]
+struct Options {
+    listen: Vec<SocketAddr>,
+    alternate: Option<SocketAddr>,
+    software: Option<String>,
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(message) => {
+            eprintln!("stun-server: {}\n\n{}", message, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    let mut server = match &options.software {
+        Some(software) => match Server::with_software(software) {
+            Ok(server) => server,
+            Err(_) => {
+                eprintln!("stun-server: the SOFTWARE value is too long\n\n{}", USAGE);
+                return ExitCode::from(2);
+            }
+        },
+        None => Server::new(),
+    };
+
+    let mut threads = Vec::new();
+    for &address in &options.listen {
+        // with an alternate address the primary one is served over UDP together with the others
+        let mut udp_addresses = vec![address];
+        if let Some(alternate) = options.alternate {
+            let addresses = NatBehaviorAddresses {
+                primary: address,
+                alternate,
+            };
+            server.set_nat_behavior_addresses(addresses);
+            udp_addresses = addresses.all().to_vec();
+        }
+
+        let mut sockets = Vec::new();
+        for &udp_address in &udp_addresses {
+            match UdpSocket::bind(udp_address) {
+                Ok(socket) => sockets.push(socket),
+                Err(e) => {
+                    eprintln!("stun-server: cannot listen on udp {}: {}", udp_address, e);
+                    return ExitCode::FAILURE;
+                }
+            }
+        }
+        let listener = match TcpListener::bind(address) {
+            Ok(listener) => listener,
+            Err(e) => {
+                eprintln!("stun-server: cannot listen on tcp {}: {}", address, e);
+                return ExitCode::FAILURE;
+            }
+        };
+        eprintln!("stun-server: listening on {} over UDP and TCP", address);
+        for udp_address in &udp_addresses[1..] {
+            eprintln!("stun-server: listening on {} over UDP", udp_address);
+        }
+
+        let udp_server = server.clone();
+        threads.push(thread::spawn(move || {
+            (
+                format!("udp {}", address),
+                udp_server.serve_udp_sockets(&sockets),
+            )
+        }));
+
+        let tcp_server = server.clone();
+        threads.push(thread::spawn(move || {
+            (format!("tcp {}", address), tcp_server.serve_tcp(&listener))
+        }));
+    }
+
+    // the server only stops if a socket fails
+    for thread in threads {
+        if let Ok((name, Err(e))) = thread.join() {
+            eprintln!("stun-server: {}: {}", name, e);
+            return ExitCode::FAILURE;
+        }
+    }
+
+    ExitCode::SUCCESS
+}
+
+/// Parse the command line, returning `None` if help was requested
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<Options>, String> {
+    let mut options = Options {
+        listen: Vec::new(),
+        alternate: None,
+        software: Some(DEFAULT_SOFTWARE.to_string()),
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-l" | "--listen" => {
+                let address = args
+                    .next()
+                    .ok_or_else(|| format!("{} requires an address", arg))?;
+                let address = address
+                    .parse()
+                    .map_err(|_| format!("invalid address {}", address))?;
+                options.listen.push(address);
+            }
+            "--alternate" => {
+                let address = args.next().ok_or("--alternate requires an address")?;
+                let address = address
+                    .parse()
+                    .map_err(|_| format!("invalid address {}", address))?;
+                options.alternate = Some(address);
+            }
+            "--software" => {
+                let software = args.next().ok_or("--software requires a value")?;
+                options.software = Some(software);
+            }
+            "--no-software" => options.software = None,
+            "-h" | "--help" => return Ok(None),
+            _ => return Err(format!("unknown argument {}", arg)),
+        }
+    }
+
+    if let Some(alternate) = options.alternate {
+        let primary = match options.listen[..] {
+            [primary] => primary,
+            _ => return Err("--alternate requires a single --listen address".to_string()),
+        };
+        if primary.ip() == alternate.ip()
+            || primary.port() == alternate.port()
+            || primary.is_ipv4() != alternate.is_ipv4()
+        {
+            return Err(format!(
+                "the alternate address {} must be of the same family as {} and differ from it \
+                 in both IP address and port",
+                alternate, primary
+            ));
+        }
+        // the response to a CHANGE-REQUEST is sent from the socket bound to the changed address
+        if primary.ip().is_unspecified() || alternate.ip().is_unspecified() {
+            return Err("--listen and --alternate cannot be wildcard addresses".to_string());
+        }
+    }
+
+    if options.listen.is_empty() {
+        options.listen.push(DEFAULT_LISTEN_ADDRESS.parse().unwrap());
+    }
+
+    Ok(Some(options))
+}
@@ -0,0 +1,505 @@
+//! STUN clients built on `std::net`, and on `tokio::net` with the `tokio` feature.
+//!
+//! These run a single request to completion, on the calling thread or as a future, using a
+//! `ClientTransaction` for the retransmissions.  Applications which run many requests at once,
+//! or which have their own event loop, should drive `ClientTransaction` or `TransactionManager`
+//! directly.
+
+use crate::parser::{parse_attribute_value, parse_stun_message};
+use crate::stun_address::*;
+use crate::stun_attribute_types::*;
+use crate::stun_client_transaction::*;
+use crate::stun_constants::*;
+use crate::stun_error_code::*;
+use crate::stun_errors::StunClientError;
+use crate::stun_five_tuple::*;
+use crate::stun_integrity::*;
+use crate::stun_message_builder::*;
+use crate::stun_message_types::*;
+use crate::stun_text_attributes::*;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::time::Duration;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::time::Instant;
+
+/// Largest datagram the clients receive, which is enough for any STUN response over UDP
+const MAX_DATAGRAM_NUM_BYTES: usize = 2048;
+
+/// Options of a Binding request, https://tools.ietf.org/html/rfc5389#section-7.1
+#[derive(Debug, Default, Clone)]
+pub struct BindingOptions {
+    /// value of a SOFTWARE attribute to add to the request
+    pub software: Option<String>,
+
+    /// whether to add a FINGERPRINT attribute to the request
+    pub fingerprint: bool,
+
+    /// retransmission parameters of the request
+    pub config: TransactionConfig,
+}
+
+/// Send a Binding request to the given server from a new UDP socket and return the
+/// server-reflexive address of the socket, i.e. the address the server saw the request come from.
+///
+/// The request is retransmitted as described in https://tools.ietf.org/html/rfc5389#section-7.2.1
+/// until a response is received or the timeout expires.
+pub fn binding(server: SocketAddr, timeout: Duration) -> Result<SocketAddr, StunClientError> {
+    binding_with_options(server, timeout, &BindingOptions::default())
+}
+
+/// Send a Binding request with the given options from a new UDP socket, see `binding`
+pub fn binding_with_options(
+    server: SocketAddr,
+    timeout: Duration,
+    options: &BindingOptions,
+) -> Result<SocketAddr, StunClientError> {
+    let socket = UdpSocket::bind(unspecified_address(&server))?;
+
+    binding_with_socket(&socket, server, timeout, options)
+}
+
+/// Send a Binding request with the given options from an existing UDP socket, see `binding`.
+///
+/// Datagrams received on the socket which are not a response to the request are discarded.  The
+/// read timeout of the socket is restored before returning.
+pub fn binding_with_socket(
+    socket: &UdpSocket,
+    server: SocketAddr,
+    timeout: Duration,
+    options: &BindingOptions,
+) -> Result<SocketAddr, StunClientError> {
+    let (transaction_id, mut transaction) = start_binding(options)?;
+
+    let read_timeout = socket.read_timeout()?;
+    let result = run_transaction(socket, server, timeout, &mut transaction);
+    socket.set_read_timeout(read_timeout)?;
+
+    binding_result(result?, &transaction_id)
+}
+
+/// Send a Binding request to the given server from a new tokio UDP socket, see `binding`
+#[cfg(feature = "tokio")]
+pub async fn binding_async(
+    server: SocketAddr,
+    timeout: Duration,
+) -> Result<SocketAddr, StunClientError> {
+    binding_async_with_options(server, timeout, &BindingOptions::default()).await
+}
+
+/// Send a Binding request with the given options from a new tokio UDP socket, see `binding`
+#[cfg(feature = "tokio")]
+pub async fn binding_async_with_options(
+    server: SocketAddr,
+    timeout: Duration,
+    options: &BindingOptions,
+) -> Result<SocketAddr, StunClientError> {
+    let socket = tokio::net::UdpSocket::bind(unspecified_address(&server)).await?;
+
+    binding_async_with_socket(&socket, server, timeout, options).await
+}
+
+/// Send a Binding request with the given options from an existing tokio UDP socket, see
+/// `binding`.  Datagrams received on the socket which are not a response to the request are
+/// discarded.
+#[cfg(feature = "tokio")]
+pub async fn binding_async_with_socket(
+    socket: &tokio::net::UdpSocket,
+    server: SocketAddr,
+    timeout: Duration,
+    options: &BindingOptions,
+) -> Result<SocketAddr, StunClientError> {
+    let (transaction_id, mut transaction) = start_binding(options)?;
+    let deadline = Instant::now() + timeout;
+    let mut buffer = [0; MAX_DATAGRAM_NUM_BYTES];
+
+    let outcome = loop {
+        if let Some(request) = transaction.poll_transmit() {
+            socket.send_to(request, server).await?;
+        }
+        if let Some(outcome) = transaction.outcome() {
+            break outcome.clone();
+        }
+
+        let now = Instant::now();
+        if now >= deadline {
+            break TransactionOutcome::Timeout;
+        }
+
+        let wake = match transaction.poll_timeout() {
+            Some(wake) => wake.min(deadline),
+            None => deadline,
+        };
+        match tokio::time::timeout(
+            wake.saturating_duration_since(now),
+            socket.recv_from(&mut buffer),
+        )
+        .await
+        {
+            Ok(Ok((length, from))) if from == server => {
+                transaction.handle_input(&buffer[..length]);
+            }
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => return Err(e.into()),
+            Err(_) => transaction.handle_timeout(Instant::now()),
+        }
+    };
+
+    binding_result(outcome, &transaction_id)
+}
+
+/// The wildcard address of the same family as the given address, to bind a socket to
+fn unspecified_address(address: &SocketAddr) -> SocketAddr {
+    match address {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    }
+}
+
+/// Build a Binding request with a new transaction id and start its transaction
+fn start_binding(
+    options: &BindingOptions,
+) -> Result<([u8; STUN_TRANSACTION_ID_NUM_BYTES], ClientTransaction), StunClientError> {
+    let transaction_id = random_transaction_id()?;
+    let request = build_binding_request(&transaction_id, options)?;
+    let transaction = ClientTransaction::with_config(
+        request,
+        TransportProtocol::Udp,
+        options.config,
+        Instant::now(),
+    )
+    .map_err(|_| StunClientError::InvalidRequestError)?;
+
+    Ok((transaction_id, transaction))
+}
+
+/// The mapped address for the given outcome of a Binding transaction, or the error to return
+fn binding_result(
+    outcome: TransactionOutcome,
+    transaction_id: &[u8; STUN_TRANSACTION_ID_NUM_BYTES],
+) -> Result<SocketAddr, StunClientError> {
+    let response = match outcome {
+        TransactionOutcome::Success(response) => response,
+        TransactionOutcome::Error(response) => return Err(error_response_code(&response)),
+        TransactionOutcome::Timeout => return Err(StunClientError::TimeoutError),
+    };
+
+    mapped_address(&response, transaction_id).ok_or(StunClientError::InvalidResponseError)
+}
+
+fn build_binding_request(
+    transaction_id: &[u8; STUN_TRANSACTION_ID_NUM_BYTES],
+    options: &BindingOptions,
+) -> Result<Vec<u8>, StunClientError> {
+    let mut builder = StunMessageBuilder::new(
+        StunMessageClass::Request,
+        StunMessageMethod::Binding,
+        transaction_id,
+    );
+
+    if let Some(software) = &options.software {
+        let software =
+            StunSoftware::new(software).map_err(|_| StunClientError::InvalidRequestError)?;
+        builder.add_attribute(StunAttributeType::Software as u16, software.as_bytes());
+    }
+    if options.fingerprint {
+        builder.add_fingerprint();
+    }
+
+    builder
+        .build()
+        .map_err(|_| StunClientError::InvalidRequestError)
+}
+
+/// Send the request and its retransmissions until the transaction completes or the timeout
+/// expires
+fn run_transaction(
+    socket: &UdpSocket,
+    server: SocketAddr,
+    timeout: Duration,
+    transaction: &mut ClientTransaction,
+) -> Result<TransactionOutcome, StunClientError> {
+    let deadline = Instant::now() + timeout;
+    let mut buffer = [0; MAX_DATAGRAM_NUM_BYTES];
+
+    loop {
+        if let Some(request) = transaction.poll_transmit() {
+            socket.send_to(request, server)?;
+        }
+        if let Some(outcome) = transaction.outcome() {
+            return Ok(outcome.clone());
+        }
+
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(TransactionOutcome::Timeout);
+        }
+
+        let wake = match transaction.poll_timeout() {
+            Some(wake) => wake.min(deadline),
+            None => deadline,
+        };
+        if now >= wake {
+            transaction.handle_timeout(now);
+            continue;
+        }
+
+        socket.set_read_timeout(Some(wake - now))?;
+        match socket.recv_from(&mut buffer) {
+            Ok((length, from)) if from == server => {
+                transaction.handle_input(&buffer[..length]);
+            }
+            Ok(_) => {}
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                transaction.handle_timeout(Instant::now());
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// The error to return for the given error response
+fn error_response_code(response: &[u8]) -> StunClientError {
+    let code = parse_stun_message(response).ok().and_then(|(_, message)| {
+        let attribute = message.get_attribute(StunAttributeType::ErrorCode as u16)?;
+        parse_attribute_value(attribute, parse_error_code)
+            .ok()
+            .map(|error_code| error_code.code)
+    });
+
+    match code {
+        Some(code) => StunClientError::RequestFailedError(code),
+        None => StunClientError::InvalidResponseError,
+    }
+}
+
+/// The mapped address in the given success response, preferring XOR-MAPPED-ADDRESS and falling
+/// back to MAPPED-ADDRESS for servers which predate RFC 5389.  A response with a FINGERPRINT
+/// that does not match has no address.
+fn mapped_address(
+    response: &[u8],
+    transaction_id: &[u8; STUN_TRANSACTION_ID_NUM_BYTES],
+) -> Option<SocketAddr> {
+    let (_, message) = parse_stun_message(response).ok()?;
+
+    if message
+        .get_attribute(StunAttributeType::Fingerprint as u16)
+        .is_some()
+        && verify_fingerprint(&message).is_err()
+    {
+        return None;
+    }
+
+    if let Some(attribute) = message.get_attribute(StunAttributeType::XorMappedAddress as u16) {
+        return parse_attribute_value(attribute, |input| parse_xor_address(input, transaction_id))
+            .ok();
+    }
+
+    let attribute = message.get_attribute(StunAttributeType::MappedAddress as u16)?;
+    parse_attribute_value(attribute, parse_address).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stun_message::*;
+
+    use std::thread::{self, JoinHandle};
+
+    /// A loopback server which answers each request it receives with the response returned by
+    /// the given function, or drops the request if it returns `None`.  The server stops after
+    /// the given number of requests.
+    fn spawn_server<F>(requests: usize, respond: F) -> (SocketAddr, JoinHandle<Vec<Vec<u8>>>)
+    where
+        F: Fn(usize, &StunMessage, SocketAddr) -> Option<Vec<u8>> + Send + 'static,
+    {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap();
+
+        let handle = thread::spawn(move || {
+            let mut received = Vec::new();
+            let mut buffer = [0; MAX_DATAGRAM_NUM_BYTES];
+
+            for index in 0..requests {
+                let (length, from) = socket.recv_from(&mut buffer).unwrap();
+                let (_, message) = parse_stun_message(&buffer[..length]).unwrap();
+                if let Some(response) = respond(index, &message, from) {
+                    socket.send_to(&response, from).unwrap();
+                }
+                received.push(buffer[..length].to_vec());
+            }
+
+            received
+        });
+
+        (address, handle)
+    }
+
+    fn success_response(message: &StunMessage, from: SocketAddr) -> Vec<u8> {
+        StunMessageBuilder::new(
+            StunMessageClass::SuccessResponse,
+            StunMessageMethod::Binding,
+            message.transaction_id,
+        )
+        .add_xor_address_attribute(StunAttributeType::XorMappedAddress as u16, &from)
+        .add_fingerprint()
+        .build()
+        .unwrap()
+    }
+
+    fn fast_options() -> BindingOptions {
+        BindingOptions {
+            config: TransactionConfig {
+                rto: Duration::from_millis(50),
+                ..TransactionConfig::default()
+            },
+            ..BindingOptions::default()
+        }
+    }
+
+    #[test]
+    fn test_binding() {
+        let (server, handle) =
+            spawn_server(1, |_, message, from| Some(success_response(message, from)));
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = binding_with_socket(
+            &socket,
+            server,
+            Duration::from_secs(5),
+            &BindingOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(address, socket.local_addr().unwrap());
+
+        // the request has no attributes by default
+        let requests = handle.join().unwrap();
+        assert_eq!(requests[0].len(), STUN_HEADER_NUM_BYTES);
+        assert_eq!(socket.read_timeout().unwrap(), None);
+    }
+
+    #[test]
+    fn test_binding_retransmits_with_attributes() {
+        // the first two requests are lost
+        let (server, handle) = spawn_server(3, |index, message, from| match index {
+            2 => Some(success_response(message, from)),
+            _ => None,
+        });
+
+        let options = BindingOptions {
+            software: Some(String::from("stun-message test")),
+            fingerprint: true,
+            ..fast_options()
+        };
+        let address = binding_with_options(server, Duration::from_secs(5), &options).unwrap();
+        assert!(address.ip().is_loopback());
+
+        let requests = handle.join().unwrap();
+        assert!(requests.windows(2).all(|pair| pair[0] == pair[1]));
+
+        let (_, request) = parse_stun_message(&requests[0]).unwrap();
+        let software = request
+            .get_attribute(StunAttributeType::Software as u16)
+            .unwrap();
+        assert_eq!(software.attribute_value, b"stun-message test");
+        assert_eq!(verify_fingerprint(&request), Ok(()));
+    }
+
+    #[test]
+    fn test_binding_mapped_address_fallback() {
+        let mapped: SocketAddr = "192.0.2.1:32853".parse().unwrap();
+        let (server, handle) = spawn_server(1, move |_, message, _| {
+            StunMessageBuilder::new(
+                StunMessageClass::SuccessResponse,
+                StunMessageMethod::Binding,
+                message.transaction_id,
+            )
+            .add_address_attribute(StunAttributeType::MappedAddress as u16, &mapped)
+            .build()
+            .ok()
+        });
+
+        let address = binding(server, Duration::from_secs(5)).unwrap();
+        assert_eq!(address, mapped);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_binding_error_response() {
+        let (server, handle) = spawn_server(1, |_, message, _| {
+            StunMessageBuilder::new(
+                StunMessageClass::ErrorResponse,
+                StunMessageMethod::Binding,
+                message.transaction_id,
+            )
+            .add_error_code_attribute(400, "Bad Request")
+            .build()
+            .ok()
+        });
+
+        assert!(matches!(
+            binding(server, Duration::from_secs(5)),
+            Err(StunClientError::RequestFailedError(400))
+        ));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_binding_timeout() {
+        let (server, handle) = spawn_server(1, |_, _, _| None);
+
+        let start = Instant::now();
+        assert!(matches!(
+            binding_with_options(server, Duration::from_millis(300), &fast_options()),
+            Err(StunClientError::TimeoutError)
+        ));
+        assert!(start.elapsed() >= Duration::from_millis(300));
+        handle.join().unwrap();
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_binding_async() {
+        // the first request is lost
+        let (server, handle) = spawn_server(2, |index, message, from| match index {
+            1 => Some(success_response(message, from)),
+            _ => None,
+        });
+
+        let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address =
+            binding_async_with_socket(&socket, server, Duration::from_secs(5), &fast_options())
+                .await
+                .unwrap();
+        assert_eq!(address, socket.local_addr().unwrap());
+        handle.join().unwrap();
+
+        let (server, handle) = spawn_server(1, |_, _, _| None);
+        assert!(matches!(
+            binding_async_with_options(server, Duration::from_millis(300), &fast_options()).await,
+            Err(StunClientError::TimeoutError)
+        ));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_binding_invalid_software() {
+        let options = BindingOptions {
+            software: Some("x".repeat(STUN_TEXT_MAX_NUM_CHARS + 1)),
+            ..BindingOptions::default()
+        };
+        let server: SocketAddr = "127.0.0.1:3478".parse().unwrap();
+
+        assert!(matches!(
+            binding_with_options(server, Duration::from_secs(1), &options),
+            Err(StunClientError::InvalidRequestError)
+        ));
+    }
+}
@@ -5,10 +5,45 @@
 //!
 //! See also:
 //! - [RFC 5389](https://tools.ietf.org/html/rfc5389): Session Traversal Utilities for NAT (STUN)
+//! - [RFC 5766](https://tools.ietf.org/html/rfc5766): Traversal Using Relays around NAT (TURN)
+//! - [RFC 6062](https://tools.ietf.org/html/rfc6062): TURN Extensions for TCP Allocations
+//! - [RFC 5780](https://tools.ietf.org/html/rfc5780): NAT Behavior Discovery Using STUN
+//! - [RFC 7635](https://tools.ietf.org/html/rfc7635): STUN Extension for Third-Party Authorization
+//! - [RFC 8016](https://tools.ietf.org/html/rfc8016): Mobility with TURN
+//! - [RFC 8489](https://tools.ietf.org/html/rfc8489): Session Traversal Utilities for NAT (STUN),
+//!   for MESSAGE-INTEGRITY-SHA256 and the password algorithms
+//!
+//! ## Features
+//!
+//! - `std` (default): use the standard library.  Without it the crate is `no_std` and requires
+//!   only `alloc`; the SASLprep based credential functions, the `StunAuthenticator`, the client
+//!   transactions, NAT behavior discovery and the `TurnClient`, which are driven with
+//!   `std::time::Instant`, the blocking clients in `client`, the Binding `Server` and the
+//!   `TurnServer` are not available.
+//! - `serde`: implement `Serialize` and `Deserialize` for `OwnedStunMessage` and the message
+//!   class and method.
+//! - `tokio`: the async Binding client in `client`, and `StunCodec` for reading and writing
+//!   STUN and ChannelData messages on `Framed` streams.
+//! - `pcap`: read the STUN and ChannelData messages from pcap and pcapng captures.
+//! - `cli`: build the `stun-dump` tool, which decodes messages copied from logs, and the
+//!   `stun-server` Binding server.
+
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
 
 mod stun_message;
 pub use crate::stun_message::*;
 
+mod stun_message_view;
+pub use crate::stun_message_view::*;
+
+mod stun_message_mut;
+pub use crate::stun_message_mut::*;
+
+mod stun_message_owned;
+pub use crate::stun_message_owned::*;
+
 mod stun_message_types;
 pub use crate::stun_message_types::*;
 
@@ -29,3 +64,96 @@ pub use crate::parser::*;
 
 mod serializer;
 pub use crate::serializer::*;
+
+mod stun_address;
+pub use crate::stun_address::*;
+
+mod stun_credentials;
+pub use crate::stun_credentials::*;
+
+mod stun_error_code;
+pub use crate::stun_error_code::*;
+
+mod stun_nat_attributes;
+pub use crate::stun_nat_attributes::*;
+
+mod stun_password_algorithms;
+pub use crate::stun_password_algorithms::*;
+
+mod stun_five_tuple;
+pub use crate::stun_five_tuple::*;
+
+mod stun_integrity;
+pub use crate::stun_integrity::*;
+
+mod stun_message_builder;
+pub use crate::stun_message_builder::*;
+
+mod turn_tcp;
+pub use crate::turn_tcp::*;
+
+mod turn_attributes;
+pub use crate::turn_attributes::*;
+
+mod turn_channel_data;
+pub use crate::turn_channel_data::*;
+
+#[cfg(feature = "std")]
+mod stun_client_transaction;
+#[cfg(feature = "std")]
+pub use crate::stun_client_transaction::*;
+
+#[cfg(feature = "std")]
+mod stun_transaction_manager;
+#[cfg(feature = "std")]
+pub use crate::stun_transaction_manager::*;
+
+#[cfg(feature = "std")]
+mod stun_authenticator;
+#[cfg(feature = "std")]
+pub use crate::stun_authenticator::*;
+
+#[cfg(feature = "std")]
+mod stun_nat_behavior;
+#[cfg(feature = "std")]
+pub use crate::stun_nat_behavior::*;
+
+#[cfg(feature = "std")]
+mod turn_client;
+#[cfg(feature = "std")]
+pub use crate::turn_client::*;
+
+#[cfg(feature = "std")]
+pub mod client;
+
+#[cfg(feature = "std")]
+mod stun_server;
+#[cfg(feature = "std")]
+pub use crate::stun_server::*;
+
+#[cfg(feature = "std")]
+mod turn_server;
+#[cfg(feature = "std")]
+pub use crate::turn_server::*;
+
+#[cfg(feature = "tokio")]
+mod stun_codec;
+#[cfg(feature = "tokio")]
+pub use crate::stun_codec::*;
+
+#[cfg(feature = "pcap")]
+mod stun_capture;
+#[cfg(feature = "pcap")]
+pub use crate::stun_capture::*;
+
+mod stun_text_attributes;
+pub use crate::stun_text_attributes::*;
+
+mod stun_validator;
+pub use crate::stun_validator::*;
+
+mod stun_third_party_auth;
+pub use crate::stun_third_party_auth::*;
+
+mod turn_mobility;
+pub use crate::turn_mobility::*;
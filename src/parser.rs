@@ -3,16 +3,18 @@ use crate::stun_constants::*;
 use crate::stun_errors::StunParseError;
 use crate::stun_message::*;
 use crate::stun_message_types::*;
+use crate::stun_message_view::*;
 
-use std::convert::TryFrom;
-use std::convert::TryInto;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
 
 extern crate nom;
 use nom::bytes::complete::take;
-use nom::multi::many0;
+use nom::combinator::{all_consuming, map_res};
+use nom::error::ErrorKind;
 use nom::number::complete::{be_u16, be_u32};
 use nom::sequence::tuple;
-use nom::Err::Error;
+use nom::Err::{Error, Failure, Incomplete};
 use nom::IResult;
 
 /// Parse a STUN message from the given input buffer.
@@ -24,19 +26,23 @@ use nom::IResult;
 /// # Return
 ///
 /// A nom::IResult object.  On success a tuple containing the unparsed portion of the input
-/// buffer and a StunMessage object.  On error, an error object describing the error.
+/// buffer (anything after the message length) and a StunMessage object.  On error, an error
+/// object describing the error, including when the attributes do not exactly fill the message
+/// length.
 /// @see https://docs.rs/nom/0.3.5/nom/enum.IResult.html
-pub fn parse_stun_message(input: &[u8]) -> IResult<&[u8], StunMessage, StunParseError<&[u8]>> {
-    let (
-        input,
-        ((message_class, message_method), message_length, magic_cookie, transaction_id, attributes),
-    ) = tuple((
-        parse_message_type,
-        parse_message_length,
-        parse_magic_cookie,
-        parse_transaction_id,
-        parse_attributes,
-    ))(input)?;
+pub fn parse_stun_message(input: &[u8]) -> IResult<&[u8], StunMessage<'_>, StunParseError<&[u8]>> {
+    let message = input;
+    let (input, ((message_class, message_method), message_length, magic_cookie, transaction_id)) =
+        tuple((
+            parse_message_type,
+            parse_message_length,
+            parse_magic_cookie,
+            parse_transaction_id,
+        ))(input)?;
+
+    // the length has already been validated against the input, so this cannot fail
+    let (input, attributes) = take(message_length as usize)(input)?;
+    let (_, attributes) = parse_attributes(attributes)?;
 
     Ok((
         input,
@@ -47,10 +53,89 @@ pub fn parse_stun_message(input: &[u8]) -> IResult<&[u8], StunMessage, StunParse
             magic_cookie,
             transaction_id,
             attributes,
+            raw: &message[..message.len() - input.len()],
         },
     ))
 }
 
+/// Parse a STUN message from the given input buffer without allocating.
+///
+/// The header is parsed and the attributes are validated, but rather than being collected
+/// into a vector they are left in the input buffer and can be iterated over or looked up
+/// using the returned view.
+///
+/// # Arguments
+///
+/// @param input an array containing a serialized STUN message
+///
+/// # Return
+///
+/// A nom::IResult object.  On success a tuple containing the unparsed portion of the input
+/// buffer (anything after the message length) and a StunMessageView object.  On error, an
+/// error object describing the error, including when the attributes do not exactly fill the
+/// message length.
+pub fn parse_stun_message_view(
+    input: &[u8],
+) -> IResult<&[u8], StunMessageView<'_>, StunParseError<&[u8]>> {
+    let (input, ((message_class, message_method), message_length, magic_cookie, transaction_id)) =
+        tuple((
+            parse_message_type,
+            parse_message_length,
+            parse_magic_cookie,
+            parse_transaction_id,
+        ))(input)?;
+
+    // the length has already been validated against the input, so this cannot fail
+    let (input, attributes) = take(message_length as usize)(input)?;
+
+    // walk the attributes once to validate them, so that iterating over the view cannot fail
+    let mut remaining = attributes;
+    while !remaining.is_empty() {
+        let (rest, _) = parse_attribute(remaining)?;
+        remaining = rest;
+    }
+
+    Ok((
+        input,
+        StunMessageView::new(
+            message_class,
+            message_method,
+            message_length,
+            magic_cookie,
+            transaction_id,
+            attributes,
+        ),
+    ))
+}
+
+/// Parse the value of the given attribute with the given value parser.
+///
+/// # Arguments
+///
+/// * `attribute` - The attribute whose value should be parsed
+/// * `parser` - A nom parser for the attribute value, e.g. `parse_xor_address`
+///
+/// # Return
+///
+/// A Result object, when successful contains the parsed value.  The parser must consume the
+/// entire attribute value, otherwise an error is returned.
+pub fn parse_attribute_value<'a, O, F>(
+    attribute: &StunAttribute<'a>,
+    parser: F,
+) -> Result<O, StunParseError<&'a [u8]>>
+where
+    F: Fn(&'a [u8]) -> IResult<&'a [u8], O, StunParseError<&'a [u8]>>,
+{
+    match all_consuming(parser)(attribute.attribute_value) {
+        Ok((_, value)) => Ok(value),
+        Err(Error(e)) | Err(Failure(e)) => Err(e),
+        Err(Incomplete(_)) => Err(StunParseError::Nom(
+            attribute.attribute_value,
+            ErrorKind::Complete,
+        )),
+    }
+}
+
 fn parse_message_type(
     input: &[u8],
 ) -> IResult<&[u8], (StunMessageClass, StunMessageMethod), StunParseError<&[u8]>> {
@@ -143,16 +228,31 @@ fn parse_magic_cookie(input: &[u8]) -> IResult<&[u8], u32, StunParseError<&[u8]>
 }
 
 fn parse_transaction_id(input: &[u8]) -> IResult<&[u8], &[u8; 12], StunParseError<&[u8]>> {
-    let (input, transaction_id) = take(STUN_TRANSACTION_ID_NUM_BYTES)(input)?;
-
-    Ok((input, transaction_id.try_into().unwrap()))
+    map_res(take(STUN_TRANSACTION_ID_NUM_BYTES), <&[u8; 12]>::try_from)(input)
 }
 
-fn parse_attributes(input: &[u8]) -> IResult<&[u8], Vec<StunAttribute>, StunParseError<&[u8]>> {
-    many0(parse_attribute)(input)
+fn parse_attributes(input: &[u8]) -> IResult<&[u8], Vec<StunAttribute<'_>>, StunParseError<&[u8]>> {
+    let mut attributes: Vec<StunAttribute> = Vec::new();
+    let mut remaining = input;
+
+    // the input is bounded by the message length, so every byte of it must belong to an attribute
+    while !remaining.is_empty() {
+        let (rest, mut attribute) = parse_attribute(remaining)?;
+
+        // attributes immediately follow the header, so their offsets can be computed from the lengths
+        attribute.offset = attributes
+            .last()
+            .map_or(STUN_HEADER_NUM_BYTES, |a| a.offset + a.serialized_length());
+        attributes.push(attribute);
+        remaining = rest;
+    }
+
+    Ok((remaining, attributes))
 }
 
-fn parse_attribute(input: &[u8]) -> IResult<&[u8], StunAttribute, StunParseError<&[u8]>> {
+pub(crate) fn parse_attribute(
+    input: &[u8],
+) -> IResult<&[u8], StunAttribute<'_>, StunParseError<&[u8]>> {
     let (input, attribute_type) = be_u16(input)?;
     let (input, attribute_length) = be_u16(input)?;
     let (input, attribute_value) = take(attribute_length as usize)(input)?;
@@ -164,7 +264,7 @@ fn parse_attribute(input: &[u8]) -> IResult<&[u8], StunAttribute, StunParseError
         3 => 1,
         _ => 0,
     };
-    let (input, _) = take(padding_length)(input)?;
+    let (input, padding) = take(padding_length)(input)?;
 
     Ok((
         input,
@@ -172,6 +272,8 @@ fn parse_attribute(input: &[u8]) -> IResult<&[u8], StunAttribute, StunParseError
             attribute_type,
             attribute_length,
             attribute_value,
+            padding,
+            offset: 0,
         },
     ))
 }
@@ -200,22 +302,22 @@ mod tests {
     #[test]
     fn parse_message_type_valid() {
         parse_message_type_valid_helper(
-            0x0000,
+            0x0001,
             StunMessageClass::Request,
             StunMessageMethod::Binding,
         );
         parse_message_type_valid_helper(
-            0x0010,
+            0x0011,
             StunMessageClass::Indication,
             StunMessageMethod::Binding,
         );
         parse_message_type_valid_helper(
-            0x0100,
+            0x0101,
             StunMessageClass::SuccessResponse,
             StunMessageMethod::Binding,
         );
         parse_message_type_valid_helper(
-            0x0110,
+            0x0111,
             StunMessageClass::ErrorResponse,
             StunMessageMethod::Binding,
         );
@@ -343,6 +445,18 @@ mod tests {
         }
     }
 
+    #[test]
+    fn parse_transaction_id_too_short() {
+        let input = [0x01; 11];
+        let result = parse_transaction_id(&input);
+
+        assert!(result.is_err());
+        assert_eq!(
+            result.unwrap_err(),
+            Error(StunParseError::Nom(&input[..], ErrorKind::Eof))
+        );
+    }
+
     #[test]
     fn parse_transaction_id_valid() {
         let input: [u8; 12] = [0; 12];
@@ -413,7 +527,7 @@ mod tests {
     #[test]
     fn parse_stun_message_valid() {
         let input = vec![
-            0x00, 0x00, // message type
+            0x00, 0x01, // message type
             0x00, 0x0C, // message length
             0x21, 0x12, 0xA4, 0x42, // magic cookie
             0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B,
@@ -440,9 +554,171 @@ mod tests {
         assert_eq!(data.1.attributes.len(), 2);
         assert_eq!(data.1.attributes[0].attribute_type, 0xABCD);
         assert_eq!(data.1.attributes[0].attribute_length, 0);
-        assert_eq!(data.1.attributes[0].attribute_value, []);
+        assert_eq!(data.1.attributes[0].attribute_value, [0u8; 0]);
         assert_eq!(data.1.attributes[1].attribute_type, 0xEFFE);
         assert_eq!(data.1.attributes[1].attribute_length, 3);
         assert_eq!(data.1.attributes[1].attribute_value, [0xAA, 0xBB, 0xCC]);
     }
+
+    #[test]
+    fn parse_stun_message_raw_and_offsets() {
+        let input = vec![
+            0x00, 0x01, // message type
+            0x00, 0x0C, // message length
+            0x21, 0x12, 0xA4, 0x42, // magic cookie
+            0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B,
+            0x0C, // transaction id
+            0xAB, 0xCD, // first attribute type
+            0x00, 0x00, // first attribute length
+            0xEF, 0xFE, // second attribute type
+            0x00, 0x03, // second attribute length
+            0xAA, 0xBB, 0xCC, // second attribute value
+            0x5A, // second attribute padding, which is not required to be zero
+            0xFF, 0xFF, // trailing data
+        ];
+        let result = parse_stun_message(&input);
+
+        assert!(result.is_ok());
+
+        let data = result.unwrap();
+        assert_eq!(data.0, [0xFF, 0xFF]);
+        assert_eq!(data.1.raw, &input[..32]);
+        assert_eq!(data.1.attributes[0].offset, 20);
+        assert_eq!(data.1.attributes[0].padding, [0u8; 0]);
+        assert_eq!(data.1.attributes[1].offset, 24);
+        assert_eq!(data.1.attributes[1].padding, [0x5A]);
+    }
+
+    #[test]
+    fn parse_stun_message_invalid_attribute() {
+        let input = vec![
+            0x00, 0x01, // message type
+            0x00, 0x08, // message length
+            0x21, 0x12, 0xA4, 0x42, // magic cookie
+            0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B,
+            0x0C, // transaction id
+            0xEF, 0xFE, // attribute type
+            0x00, 0x08, // attribute length, longer than the message
+            0xAA, 0xBB, 0xCC, 0xDD, // attribute value
+            0xAA, 0xBB, 0xCC, 0xDD, // data after the message
+        ];
+        let result = parse_stun_message(&input);
+
+        assert_eq!(
+            result.unwrap_err(),
+            Error(StunParseError::Nom(&input[24..28], ErrorKind::Eof))
+        );
+    }
+
+    #[test]
+    fn parse_stun_message_view_valid() {
+        let input = vec![
+            0x00, 0x01, // message type
+            0x00, 0x0C, // message length
+            0x21, 0x12, 0xA4, 0x42, // magic cookie
+            0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B,
+            0x0C, // transaction id
+            0xAB, 0xCD, // first attribute type
+            0x00, 0x00, // first attribute length
+            0xEF, 0xFE, // second attribute type
+            0x00, 0x03, // second attribute length
+            0xAA, 0xBB, 0xCC, // second attribute value
+            0x00, // second attribute padding
+            0xFF, 0xFF, // trailing data
+        ];
+        let result = parse_stun_message_view(&input);
+
+        assert!(result.is_ok());
+
+        let data = result.unwrap();
+        assert_eq!(data.0, [0xFF, 0xFF]);
+
+        assert_eq!(data.1.message_class, StunMessageClass::Request);
+        assert_eq!(data.1.message_method, StunMessageMethod::Binding);
+        assert_eq!(data.1.message_length, 12);
+        assert_eq!(data.1.transaction_id, &input[8..20]);
+        assert_eq!(data.1.attributes().count(), 2);
+        assert_eq!(
+            data.1.find(0xEFFE).unwrap().attribute_value,
+            [0xAA, 0xBB, 0xCC]
+        );
+    }
+
+    #[test]
+    fn parse_stun_message_view_invalid_attribute() {
+        let input = vec![
+            0x00, 0x01, // message type
+            0x00, 0x08, // message length
+            0x21, 0x12, 0xA4, 0x42, // magic cookie
+            0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B,
+            0x0C, // transaction id
+            0xEF, 0xFE, // attribute type
+            0x00, 0x08, // attribute length, longer than the message
+            0xAA, 0xBB, 0xCC, 0xDD, // attribute value
+            0xAA, 0xBB, 0xCC, 0xDD, // data after the message
+        ];
+        let result = parse_stun_message_view(&input);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn parse_error_offset() {
+        let mut input = vec![
+            0x00, 0x01, // message type
+            0x00, 0x08, // message length
+            0x21, 0x12, 0xA4, 0x42, // magic cookie
+            0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B,
+            0x0C, // transaction id
+            0xEF, 0xFE, // attribute type
+            0x00, 0x08, // attribute length, longer than the message
+            0xAA, 0xBB, 0xCC, 0xDD, // attribute value
+            0xAA, 0xBB, 0xCC, 0xDD, // data after the message
+        ];
+
+        // the attribute value is cut short by the message length
+        let error = match parse_stun_message_view(&input) {
+            Err(Error(e)) => e,
+            result => panic!("Unexpected result:  {:?}", result),
+        };
+        assert_eq!(error.offset(&input), Some(24));
+        let error = match parse_stun_message(&input) {
+            Err(Error(e)) => e,
+            result => panic!("Unexpected result:  {:?}", result),
+        };
+        assert_eq!(error.offset(&input), Some(24));
+
+        // header errors are at the offending field
+        input[4] = 0x00;
+        let error = match parse_stun_message(&input) {
+            Err(Error(e)) => e,
+            result => panic!("Unexpected result:  {:?}", result),
+        };
+        assert_eq!(error, StunParseError::InvalidMagicCookieError(0x0012_A442));
+        assert_eq!(error.offset(&input), Some(4));
+
+        // an error pointing into some other buffer has no offset
+        assert_eq!(
+            StunParseError::Nom(&[0u8; 4][..], ErrorKind::Eof).offset(&input),
+            None
+        );
+    }
+
+    #[test]
+    fn parse_attribute_value_valid() {
+        let input: [u8; 8] = [0x00, 0x2A, 0x00, 0x04, 0x0A, 0x0B, 0x0C, 0x0D];
+        let (_, attribute) = parse_attribute(&input).unwrap();
+        let result = parse_attribute_value(&attribute, be_u32);
+
+        assert_eq!(result, Ok(0x0A0B_0C0D));
+    }
+
+    #[test]
+    fn parse_attribute_value_invalid_trailing_bytes() {
+        let input: [u8; 8] = [0x00, 0x2A, 0x00, 0x04, 0x0A, 0x0B, 0x0C, 0x0D];
+        let (_, attribute) = parse_attribute(&input).unwrap();
+        let result = parse_attribute_value(&attribute, be_u16);
+
+        assert!(result.is_err());
+    }
 }
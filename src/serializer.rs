@@ -9,7 +9,10 @@ use cookie_factory::multi::many_ref;
 use cookie_factory::sequence::tuple;
 use cookie_factory::{gen, GenError, SerializeFn};
 
-use std::io::Write;
+use cookie_factory::lib::std::io::Write;
+
+use alloc::vec;
+use alloc::vec::Vec;
 
 /// Serialize the given STUN message into the given output.
 ///
@@ -35,9 +38,18 @@ pub fn serialize_into(message: &StunMessage, output: &mut [u8]) -> Result<u64, G
 ///
 /// A Result object, when successful contains a Vec<u8> holding the serialized message
 pub fn serialize(message: &StunMessage) -> Result<Vec<u8>, GenError> {
-    let mut output = Vec::with_capacity(2048);
+    let mut output =
+        vec![0; STUN_HEADER_NUM_BYTES + serialized_attributes_length(&message.attributes)];
+    let length = serialize_into(message, &mut output)?;
+    output.truncate(length as usize);
+
+    Ok(output)
+}
 
-    gen(serialize_helper(message), &mut output).map(|res| res.0.to_vec())
+/// Compute the value of the message length field for a message with the given attributes,
+/// i.e. the size of each attribute header plus its value padded to a 4 byte boundary.
+pub fn serialized_attributes_length(attributes: &[StunAttribute]) -> usize {
+    attributes.iter().map(|a| a.serialized_length()).sum()
 }
 
 fn serialize_helper<'a, W: Write + 'a>(message: &'a StunMessage) -> impl SerializeFn<W> + 'a {
@@ -70,15 +82,24 @@ fn serialize_message_method(message_method: StunMessageMethod) -> u16 {
 
     // shift all the bits into their proper position
     ((message_method & 0x000F) << STUN_MESSAGE_METHOD_SHIFT_BIT_0_3)
-        | (message_method & 0x0070 << STUN_MESSAGE_METHOD_SHIFT_BIT_4_6)
-        | (message_method & 0x0F80 << STUN_MESSAGE_METHOD_SHIFT_BIT_7_11)
+        | ((message_method & 0x0070) << STUN_MESSAGE_METHOD_SHIFT_BIT_4_6)
+        | ((message_method & 0x0F80) << STUN_MESSAGE_METHOD_SHIFT_BIT_7_11)
 }
 
 fn serialize_attribute<'a, W: Write + 'a>(a: &'a StunAttribute) -> impl SerializeFn<W> + 'a {
+    // attributes must end on 4 byte boundaries, so pad the value if necessary, reusing the
+    // padding the attribute was received with so that parsed messages serialize unchanged
+    let padding_length = (4 - (a.attribute_value.len() % 4)) % 4;
+    let padding = match a.padding.len() {
+        n if n == padding_length => a.padding,
+        _ => &STUN_ATTRIBUTE_PADDING[..padding_length],
+    };
+
     tuple((
         be_u16(a.attribute_type),
         be_u16(a.attribute_length),
         slice(a.attribute_value),
+        slice(padding),
     ))
 }
 
@@ -105,7 +126,11 @@ mod tests {
 
     #[test]
     fn test_serialize_message_method() {
-        assert_eq!(serialize_message_method(StunMessageMethod::Binding), 0x0000);
+        assert_eq!(serialize_message_method(StunMessageMethod::Binding), 0x0001);
+        assert_eq!(
+            serialize_message_method(StunMessageMethod::ConnectionAttempt),
+            0x000C
+        );
     }
 
     #[test]
@@ -118,13 +143,14 @@ mod tests {
             magic_cookie: STUN_MAGIC_COOKIE,
             transaction_id: &transaction_id,
             attributes: vec![],
+            raw: &[],
         };
 
         let mut output = [0xFFu8; 2];
         let result = gen(serialize_message_type(&stun_message), &mut output[..]);
 
         assert!(result.is_ok());
-        assert_eq!(output, [0x01, 0x10]);
+        assert_eq!(output, [0x01, 0x11]);
     }
 
     #[test]
@@ -137,6 +163,7 @@ mod tests {
             magic_cookie: STUN_MAGIC_COOKIE,
             transaction_id: &transaction_id,
             attributes: vec![],
+            raw: &[],
         };
 
         let mut output = [0u8; 0];
@@ -155,6 +182,7 @@ mod tests {
             magic_cookie: STUN_MAGIC_COOKIE,
             transaction_id: &transaction_id,
             attributes: vec![],
+            raw: &[],
         };
 
         let mut output = [0u8; 2048];
@@ -163,7 +191,7 @@ mod tests {
         assert!(result.is_ok());
 
         assert_eq!(result.unwrap(), 20);
-        assert_eq!(output[0..2], [0x00, 0x00]);
+        assert_eq!(output[0..2], [0x00, 0x01]);
         assert_eq!(output[2..4], [0x00, 0x00]);
         assert_eq!(output[4..8], [0x21, 0x12, 0xA4, 0x42]);
         assert_eq!(output[8..20], transaction_id);
@@ -179,6 +207,7 @@ mod tests {
             magic_cookie: STUN_MAGIC_COOKIE,
             transaction_id: &transaction_id,
             attributes: vec![],
+            raw: &[],
         };
 
         let result = serialize(&stun_message);
@@ -186,7 +215,7 @@ mod tests {
 
         let data = result.unwrap();
         assert_eq!(data.len(), 20);
-        assert_eq!(data[0..2], [0x01, 0x10]);
+        assert_eq!(data[0..2], [0x01, 0x11]);
         assert_eq!(data[2..4], [0x00, 0x10]);
         assert_eq!(data[4..8], [0x21, 0x12, 0xA4, 0x42]);
         assert_eq!(data[8..20], transaction_id);
@@ -200,6 +229,8 @@ mod tests {
             attribute_type: 0x1122,
             attribute_length: 0x0004,
             attribute_value: &stun_attribute_value,
+            padding: &[],
+            offset: 20,
         };
         let stun_message = StunMessage {
             message_class: StunMessageClass::ErrorResponse,
@@ -208,6 +239,7 @@ mod tests {
             magic_cookie: STUN_MAGIC_COOKIE,
             transaction_id: &transaction_id,
             attributes: vec![stun_attribute],
+            raw: &[],
         };
 
         let result = serialize(&stun_message);
@@ -215,7 +247,7 @@ mod tests {
 
         let data = result.unwrap();
         assert_eq!(data.len(), 28);
-        assert_eq!(data[0..2], [0x01, 0x10]);
+        assert_eq!(data[0..2], [0x01, 0x11]);
         assert_eq!(data[2..4], [0x00, 0x10]);
         assert_eq!(data[4..8], [0x21, 0x12, 0xA4, 0x42]);
         assert_eq!(data[8..20], transaction_id);
@@ -223,4 +255,65 @@ mod tests {
         assert_eq!(data[22..24], [0x00, 0x04]);
         assert_eq!(data[24..28], stun_attribute_value);
     }
+
+    #[test]
+    fn test_serialize_with_padded_attribute_vector() {
+        let transaction_id = [0x34; STUN_TRANSACTION_ID_NUM_BYTES];
+        let stun_attribute_value = [0x56; 5];
+        let stun_attribute = StunAttribute {
+            attribute_type: 0x1122,
+            attribute_length: 0x0005,
+            attribute_value: &stun_attribute_value,
+            padding: &[],
+            offset: 20,
+        };
+        let stun_message = StunMessage {
+            message_class: StunMessageClass::Request,
+            message_method: StunMessageMethod::Binding,
+            message_length: 0x0C,
+            magic_cookie: STUN_MAGIC_COOKIE,
+            transaction_id: &transaction_id,
+            attributes: vec![stun_attribute],
+            raw: &[],
+        };
+
+        let result = serialize(&stun_message);
+        assert!(result.is_ok());
+
+        let data = result.unwrap();
+        assert_eq!(data.len(), 32);
+        assert_eq!(data[20..22], [0x11, 0x22]);
+        assert_eq!(data[22..24], [0x00, 0x05]);
+        assert_eq!(data[24..29], stun_attribute_value);
+        assert_eq!(data[29..32], [0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn test_serialize_preserves_received_padding() {
+        let transaction_id = [0x34; STUN_TRANSACTION_ID_NUM_BYTES];
+        let stun_attribute_value = [0x56; 2];
+        let stun_attribute = StunAttribute {
+            attribute_type: 0x1122,
+            attribute_length: 0x0002,
+            attribute_value: &stun_attribute_value,
+            padding: &[0xAB, 0xCD],
+            offset: 20,
+        };
+        let stun_message = StunMessage {
+            message_class: StunMessageClass::Request,
+            message_method: StunMessageMethod::Binding,
+            message_length: 0x08,
+            magic_cookie: STUN_MAGIC_COOKIE,
+            transaction_id: &transaction_id,
+            attributes: vec![stun_attribute],
+            raw: &[],
+        };
+
+        let result = serialize(&stun_message);
+        assert!(result.is_ok());
+
+        let data = result.unwrap();
+        assert_eq!(data.len(), 28);
+        assert_eq!(data[24..28], [0x56, 0x56, 0xAB, 0xCD]);
+    }
 }
@@ -0,0 +1,207 @@
+use crate::stun_constants::*;
+use crate::stun_errors::StunParseError;
+
+use alloc::vec::Vec;
+use core::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use nom::bytes::complete::take;
+use nom::number::complete::{be_u16, be_u8};
+use nom::sequence::tuple;
+use nom::Err::Error;
+use nom::IResult;
+
+/// Address family value for IPv4 addresses
+pub const STUN_ADDRESS_FAMILY_IPV4: u8 = 0x01;
+
+/// Address family value for IPv6 addresses
+pub const STUN_ADDRESS_FAMILY_IPV6: u8 = 0x02;
+
+/// Parse the value of an address attribute (e.g. MAPPED-ADDRESS), https://tools.ietf.org/html/rfc5389#section-15.1
+///
+/// 0                   1                   2                   3
+/// 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |0 0 0 0 0 0 0 0|    Family     |           Port                |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |                                                               |
+/// |                 Address (32 bits or 128 bits)                 |
+/// |                                                               |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+pub fn parse_address(input: &[u8]) -> IResult<&[u8], SocketAddr, StunParseError<&[u8]>> {
+    let (input, (_, family, port)) = tuple((be_u8, be_u8, be_u16))(input)?;
+
+    match family {
+        STUN_ADDRESS_FAMILY_IPV4 => {
+            let (input, address) = take(4usize)(input)?;
+            let mut octets = [0u8; 4];
+            octets.copy_from_slice(address);
+
+            Ok((
+                input,
+                SocketAddr::new(IpAddr::V4(Ipv4Addr::from(octets)), port),
+            ))
+        }
+        STUN_ADDRESS_FAMILY_IPV6 => {
+            let (input, address) = take(16usize)(input)?;
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(address);
+
+            Ok((
+                input,
+                SocketAddr::new(IpAddr::V6(Ipv6Addr::from(octets)), port),
+            ))
+        }
+        _ => Err(Error(StunParseError::InvalidAddressFamilyError(family))),
+    }
+}
+
+/// Parse the value of an XOR'd address attribute (e.g. XOR-MAPPED-ADDRESS), https://tools.ietf.org/html/rfc5389#section-15.2
+///
+/// The wire format is the same as for `parse_address`, except that the port is XOR'd with the
+/// most significant 16 bits of the magic cookie and the address is XOR'd with the magic cookie
+/// (IPv4) or the concatenation of the magic cookie and the transaction id (IPv6).
+pub fn parse_xor_address<'a>(
+    input: &'a [u8],
+    transaction_id: &[u8; STUN_TRANSACTION_ID_NUM_BYTES],
+) -> IResult<&'a [u8], SocketAddr, StunParseError<&'a [u8]>> {
+    let (input, address) = parse_address(input)?;
+
+    Ok((input, xor_address(&address, transaction_id)))
+}
+
+/// Serialize the given address into the value of an address attribute (e.g. MAPPED-ADDRESS)
+pub fn serialize_address(address: &SocketAddr) -> Vec<u8> {
+    let mut output = Vec::with_capacity(20);
+
+    output.push(0);
+    match address.ip() {
+        IpAddr::V4(ip) => {
+            output.push(STUN_ADDRESS_FAMILY_IPV4);
+            output.extend_from_slice(&address.port().to_be_bytes());
+            output.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            output.push(STUN_ADDRESS_FAMILY_IPV6);
+            output.extend_from_slice(&address.port().to_be_bytes());
+            output.extend_from_slice(&ip.octets());
+        }
+    }
+
+    output
+}
+
+/// Serialize the given address into the value of an XOR'd address attribute (e.g. XOR-MAPPED-ADDRESS)
+pub fn serialize_xor_address(
+    address: &SocketAddr,
+    transaction_id: &[u8; STUN_TRANSACTION_ID_NUM_BYTES],
+) -> Vec<u8> {
+    serialize_address(&xor_address(address, transaction_id))
+}
+
+fn xor_address(
+    address: &SocketAddr,
+    transaction_id: &[u8; STUN_TRANSACTION_ID_NUM_BYTES],
+) -> SocketAddr {
+    // the xor key is the magic cookie followed by the transaction id, IPv4 addresses only use the cookie
+    let mut key = [0u8; 16];
+    key[0..4].copy_from_slice(&STUN_MAGIC_COOKIE.to_be_bytes());
+    key[4..16].copy_from_slice(transaction_id);
+
+    let port = address.port() ^ ((STUN_MAGIC_COOKIE >> 16) as u16);
+
+    let ip = match address.ip() {
+        IpAddr::V4(ip) => {
+            let mut octets = ip.octets();
+            octets.iter_mut().zip(&key).for_each(|(o, k)| *o ^= k);
+            IpAddr::V4(Ipv4Addr::from(octets))
+        }
+        IpAddr::V6(ip) => {
+            let mut octets = ip.octets();
+            octets.iter_mut().zip(&key).for_each(|(o, k)| *o ^= k);
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+    };
+
+    SocketAddr::new(ip, port)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_address_valid_ipv4() {
+        let input: [u8; 8] = [0x00, 0x01, 0x12, 0x34, 0xC0, 0x00, 0x02, 0x01];
+        let result = parse_address(&input);
+
+        assert!(result.is_ok());
+
+        let data = result.unwrap();
+        assert_eq!(data.0.len(), 0);
+        assert_eq!(data.1, "192.0.2.1:4660".parse().unwrap());
+    }
+
+    #[test]
+    fn parse_address_valid_ipv6() {
+        let mut input = vec![0x00, 0x02, 0x12, 0x34];
+        input.extend_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        let result = parse_address(&input);
+
+        assert!(result.is_ok());
+
+        let data = result.unwrap();
+        assert_eq!(data.1, "[2001:db8::1]:4660".parse().unwrap());
+    }
+
+    #[test]
+    fn parse_address_invalid_family() {
+        let input: [u8; 8] = [0x00, 0x03, 0x12, 0x34, 0xC0, 0x00, 0x02, 0x01];
+        let result = parse_address(&input);
+
+        assert!(result.is_err());
+
+        let err = result.unwrap_err();
+        match err {
+            Error(e) => assert_eq!(e, StunParseError::InvalidAddressFamilyError(0x03)),
+            _ => panic!("Unexpected error:  {:?}", err),
+        }
+    }
+
+    #[test]
+    fn parse_address_invalid_length() {
+        let input: [u8; 7] = [0x00, 0x01, 0x12, 0x34, 0xC0, 0x00, 0x02];
+        let result = parse_address(&input);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn parse_xor_address_valid_ipv4() {
+        // 192.0.2.1:32853 from https://tools.ietf.org/html/rfc5769#section-2.2
+        let transaction_id = [
+            0xb7, 0xe7, 0xa7, 0x01, 0xbc, 0x34, 0xd6, 0x86, 0xfa, 0x87, 0xdf, 0xae,
+        ];
+        let input: [u8; 8] = [0x00, 0x01, 0xa1, 0x47, 0xe1, 0x12, 0xa6, 0x43];
+        let result = parse_xor_address(&input, &transaction_id);
+
+        assert!(result.is_ok());
+
+        let data = result.unwrap();
+        assert_eq!(data.1, "192.0.2.1:32853".parse().unwrap());
+    }
+
+    #[test]
+    fn serialize_xor_address_roundtrip() {
+        let transaction_id = [0x5A; STUN_TRANSACTION_ID_NUM_BYTES];
+
+        for address in &["203.0.113.7:3478", "[2001:db8:1234::5678]:49152"] {
+            let address: SocketAddr = address.parse().unwrap();
+            let value = serialize_xor_address(&address, &transaction_id);
+
+            assert_ne!(value, serialize_address(&address));
+
+            let result = parse_xor_address(&value, &transaction_id);
+            assert_eq!(result.unwrap().1, address);
+        }
+    }
+}
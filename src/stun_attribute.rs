@@ -1,3 +1,5 @@
+use crate::stun_constants::*;
+
 /// A Stun attribute, https://tools.ietf.org/html/rfc5389#section-15
 ///
 /// 0                   1                   2                   3
@@ -8,7 +10,7 @@
 /// |                         Value (variable)                ....
 /// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct StunAttribute<'a> {
     /// attribute type -- 2 bytes
     pub attribute_type: u16,
@@ -18,4 +20,18 @@ pub struct StunAttribute<'a> {
 
     /// attribute value -- length bytes
     pub attribute_value: &'a [u8],
+
+    /// padding after the value up to a 4 byte boundary, as received -- 0 to 3 bytes
+    pub padding: &'a [u8],
+
+    /// byte offset of the attribute (its type field) from the start of the message
+    pub offset: usize,
+}
+
+impl<'a> StunAttribute<'a> {
+    /// Number of bytes the attribute occupies in a message, i.e. the 4 byte header and the value
+    /// padded to a 4 byte boundary
+    pub fn serialized_length(&self) -> usize {
+        STUN_ATTRIBUTE_HEADER_NUM_BYTES + ((self.attribute_value.len() + 3) & !3)
+    }
 }
@@ -1,15 +1,77 @@
+use core::fmt;
+
+use num_enum::TryFromPrimitive;
+
 /// Sturn attribute types -- https://tools.ietf.org/html/rfc5389#section-18.2
+#[derive(Debug, Eq, PartialEq, TryFromPrimitive, Clone, Copy)]
 #[repr(u16)]
 pub enum StunAttributeType {
     MappedAddress = 0x0001,
+    ChangeRequest = 0x0003,
     Username = 0x0006,
     MessageIntegrity = 0x0008,
     ErrorCode = 0x0009,
     UnknownAttributes = 0x000A,
+    ChannelNumber = 0x000C,
+    Lifetime = 0x000D,
+    XorPeerAddress = 0x0012,
+    Data = 0x0013,
     Realm = 0x0014,
     Nonce = 0x0015,
+    XorRelayedAddress = 0x0016,
+    RequestedTransport = 0x0019,
+    AccessToken = 0x001B,
+    MessageIntegritySha256 = 0x001C,
+    PasswordAlgorithm = 0x001D,
     XorMappedAddress = 0x0020,
+    Padding = 0x0026,
+    ResponsePort = 0x0027,
+    ConnectionId = 0x002A,
+    PasswordAlgorithms = 0x8002,
     Software = 0x8022,
     AlternateServer = 0x8023,
     Fingerprint = 0x8028,
+    ResponseOrigin = 0x802B,
+    OtherAddress = 0x802C,
+    ThirdPartyAuthorization = 0x802E,
+    MobilityTicket = 0x8030,
+}
+
+impl fmt::Display for StunAttributeType {
+    /// The name of the attribute as registered with IANA, e.g. "XOR-MAPPED-ADDRESS"
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            StunAttributeType::MappedAddress => "MAPPED-ADDRESS",
+            StunAttributeType::ChangeRequest => "CHANGE-REQUEST",
+            StunAttributeType::Username => "USERNAME",
+            StunAttributeType::MessageIntegrity => "MESSAGE-INTEGRITY",
+            StunAttributeType::ErrorCode => "ERROR-CODE",
+            StunAttributeType::UnknownAttributes => "UNKNOWN-ATTRIBUTES",
+            StunAttributeType::ChannelNumber => "CHANNEL-NUMBER",
+            StunAttributeType::Lifetime => "LIFETIME",
+            StunAttributeType::XorPeerAddress => "XOR-PEER-ADDRESS",
+            StunAttributeType::Data => "DATA",
+            StunAttributeType::Realm => "REALM",
+            StunAttributeType::Nonce => "NONCE",
+            StunAttributeType::XorRelayedAddress => "XOR-RELAYED-ADDRESS",
+            StunAttributeType::RequestedTransport => "REQUESTED-TRANSPORT",
+            StunAttributeType::AccessToken => "ACCESS-TOKEN",
+            StunAttributeType::MessageIntegritySha256 => "MESSAGE-INTEGRITY-SHA256",
+            StunAttributeType::PasswordAlgorithm => "PASSWORD-ALGORITHM",
+            StunAttributeType::XorMappedAddress => "XOR-MAPPED-ADDRESS",
+            StunAttributeType::Padding => "PADDING",
+            StunAttributeType::ResponsePort => "RESPONSE-PORT",
+            StunAttributeType::ConnectionId => "CONNECTION-ID",
+            StunAttributeType::PasswordAlgorithms => "PASSWORD-ALGORITHMS",
+            StunAttributeType::Software => "SOFTWARE",
+            StunAttributeType::AlternateServer => "ALTERNATE-SERVER",
+            StunAttributeType::Fingerprint => "FINGERPRINT",
+            StunAttributeType::ResponseOrigin => "RESPONSE-ORIGIN",
+            StunAttributeType::OtherAddress => "OTHER-ADDRESS",
+            StunAttributeType::ThirdPartyAuthorization => "THIRD-PARTY-AUTHORIZATION",
+            StunAttributeType::MobilityTicket => "MOBILITY-TICKET",
+        };
+
+        f.write_str(name)
+    }
 }
@@ -0,0 +1,734 @@
+use crate::parser::parse_attribute_value;
+use crate::stun_attribute_types::*;
+use crate::stun_constants::*;
+use crate::stun_credentials::*;
+use crate::stun_error_code::*;
+use crate::stun_errors::StunAuthenticatorError;
+use crate::stun_integrity::*;
+use crate::stun_message::*;
+use crate::stun_message_builder::*;
+use crate::stun_message_types::*;
+use crate::stun_password_algorithms::*;
+use crate::stun_text_attributes::*;
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+/// What to do with a request once its response has been handled by `StunAuthenticator`
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum StunAuthenticationOutcome {
+    /// The server challenged the request, it must be built again with a new transaction id and
+    /// resent
+    Resend,
+
+    /// The response is the final answer to the request, a success response whose integrity has
+    /// been checked or an error response which is not a challenge
+    Complete,
+}
+
+/// An attribute of a `StunAuthenticatedRequest`
+#[derive(Debug, Clone)]
+enum RequestAttribute {
+    Raw(u16, Vec<u8>),
+
+    /// an address which is XORed with the transaction id of each request built
+    XorAddress(u16, SocketAddr),
+}
+
+/// A request to be authenticated by `StunAuthenticator`: the server it is sent to, its method
+/// and attributes, and whether it has already been resent after a challenge
+#[derive(Debug, Clone)]
+pub struct StunAuthenticatedRequest {
+    server: SocketAddr,
+    message_method: StunMessageMethod,
+    attributes: Vec<RequestAttribute>,
+    fingerprint: bool,
+    retried: bool,
+}
+
+impl StunAuthenticatedRequest {
+    /// Create a request with the given method to the given server and no attributes
+    pub fn new(server: SocketAddr, message_method: StunMessageMethod) -> Self {
+        StunAuthenticatedRequest {
+            server,
+            message_method,
+            attributes: Vec::new(),
+            fingerprint: false,
+            retried: false,
+        }
+    }
+
+    /// The server the request is sent to
+    pub fn server(&self) -> SocketAddr {
+        self.server
+    }
+
+    /// Append an attribute with the given type and raw value, the credentials are added after
+    /// all such attributes
+    pub fn add_attribute(&mut self, attribute_type: u16, attribute_value: &[u8]) -> &mut Self {
+        self.attributes.push(RequestAttribute::Raw(
+            attribute_type,
+            attribute_value.to_vec(),
+        ));
+        self
+    }
+
+    /// Append an attribute with the given type and an address, which is XORed with the
+    /// transaction id each time the request is built
+    pub fn add_xor_address_attribute(
+        &mut self,
+        attribute_type: u16,
+        address: &SocketAddr,
+    ) -> &mut Self {
+        self.attributes
+            .push(RequestAttribute::XorAddress(attribute_type, *address));
+        self
+    }
+
+    /// Append a FINGERPRINT attribute to the request
+    pub fn add_fingerprint(&mut self) -> &mut Self {
+        self.fingerprint = true;
+        self
+    }
+}
+
+/// The credentials of the last challenge from a server
+#[derive(Debug)]
+struct ServerCredentials {
+    realm: String,
+    nonce: String,
+    algorithm: StunPasswordAlgorithm,
+    /// the value of the PASSWORD-ALGORITHMS attribute of the challenge, which requests echo
+    password_algorithms: Option<Vec<u8>>,
+    key: Vec<u8>,
+}
+
+/// Authenticates requests with the long-term credential mechanism,
+/// https://tools.ietf.org/html/rfc8489#section-9.2
+///
+/// The REALM and NONCE of the last challenge from each server are cached, so that only the
+/// first request to a server goes without credentials.  When a response is 401 (Unauthorized)
+/// with REALM and NONCE, or 438 (Stale Nonce), the cache is updated and the request is resent
+/// once; a second challenge means the server rejects the credentials.
+///
+/// The key is derived with SHA-256 when the challenge offers it in PASSWORD-ALGORITHMS, the
+/// request is then signed with MESSAGE-INTEGRITY-SHA256.  Otherwise it is derived with MD5 and
+/// the request is signed with MESSAGE-INTEGRITY, see `long_term_credential_key_rfc8489` for a
+/// challenge offering MD5 and `long_term_credential_key` for one without PASSWORD-ALGORITHMS.
+///
+/// Like `ClientTransaction` the authenticator does no I/O:
+///
+/// - `build_request` serializes a request with the cached credentials of its server, if any
+/// - `handle_response` must be called with the response to the request, and tells whether it
+///   must be built and sent again
+#[derive(Debug)]
+pub struct StunAuthenticator {
+    username: String,
+    password: String,
+    servers: HashMap<SocketAddr, ServerCredentials>,
+}
+
+impl StunAuthenticator {
+    /// Create an authenticator with the given long-term credentials and no cached challenges
+    pub fn new(username: &str, password: &str) -> Result<Self, StunAuthenticatorError> {
+        StunUsername::new(username).map_err(|_| StunAuthenticatorError::InvalidCredentialsError)?;
+
+        Ok(StunAuthenticator {
+            username: username.to_string(),
+            password: password.to_string(),
+            servers: HashMap::new(),
+        })
+    }
+
+    /// The REALM of the last challenge from the given server, if it challenged a request
+    pub fn realm(&self, server: SocketAddr) -> Option<&str> {
+        self.servers
+            .get(&server)
+            .map(|credentials| credentials.realm.as_str())
+    }
+
+    /// Serialize the request with the given transaction id, with USERNAME, REALM, NONCE and
+    /// MESSAGE-INTEGRITY or MESSAGE-INTEGRITY-SHA256 if its server has challenged a request
+    /// before.  A resent request must use a new transaction id.
+    pub fn build_request(
+        &self,
+        request: &StunAuthenticatedRequest,
+        transaction_id: &[u8; STUN_TRANSACTION_ID_NUM_BYTES],
+    ) -> Result<Vec<u8>, StunAuthenticatorError> {
+        let mut builder = StunMessageBuilder::new(
+            StunMessageClass::Request,
+            request.message_method,
+            transaction_id,
+        );
+        for attribute in &request.attributes {
+            match attribute {
+                RequestAttribute::Raw(attribute_type, attribute_value) => {
+                    builder.add_attribute(*attribute_type, attribute_value)
+                }
+                RequestAttribute::XorAddress(attribute_type, address) => {
+                    builder.add_xor_address_attribute(*attribute_type, address)
+                }
+            };
+        }
+
+        if let Some(credentials) = self.servers.get(&request.server) {
+            builder
+                .add_attribute(StunAttributeType::Username as u16, self.username.as_bytes())
+                .add_attribute(
+                    StunAttributeType::Realm as u16,
+                    credentials.realm.as_bytes(),
+                )
+                .add_attribute(
+                    StunAttributeType::Nonce as u16,
+                    credentials.nonce.as_bytes(),
+                );
+
+            if let Some(password_algorithms) = &credentials.password_algorithms {
+                let password_algorithm = StunPasswordAlgorithmValue {
+                    algorithm: credentials.algorithm as u16,
+                    parameters: &[],
+                };
+                builder
+                    .add_attribute(
+                        StunAttributeType::PasswordAlgorithms as u16,
+                        password_algorithms,
+                    )
+                    .add_attribute(
+                        StunAttributeType::PasswordAlgorithm as u16,
+                        &serialize_password_algorithm(&password_algorithm),
+                    );
+            }
+
+            match credentials.algorithm {
+                StunPasswordAlgorithm::Md5 => builder.add_message_integrity(&credentials.key),
+                StunPasswordAlgorithm::Sha256 => {
+                    builder.add_message_integrity_sha256(&credentials.key)
+                }
+            };
+        }
+        if request.fingerprint {
+            builder.add_fingerprint();
+        }
+
+        builder
+            .build()
+            .map_err(|_| StunAuthenticatorError::InvalidRequestError)
+    }
+
+    /// Handle the response to a request built with `build_request`
+    ///
+    /// # Return
+    ///
+    /// A Result object, when successful contains whether the request must be resent or the
+    /// response is the final answer.  It is an error if the server challenges the request after
+    /// it has been resent, in which case the cached challenge is dropped, or if the response is
+    /// malformed or its integrity is missing or does not match.
+    pub fn handle_response(
+        &mut self,
+        request: &mut StunAuthenticatedRequest,
+        response: &StunMessage,
+    ) -> Result<StunAuthenticationOutcome, StunAuthenticatorError> {
+        match response.message_class {
+            StunMessageClass::SuccessResponse => {
+                self.verify_response(request.server, response)?;
+                Ok(StunAuthenticationOutcome::Complete)
+            }
+            StunMessageClass::ErrorResponse => self.handle_error_response(request, response),
+            _ => Err(StunAuthenticatorError::InvalidResponseError),
+        }
+    }
+
+    fn handle_error_response(
+        &mut self,
+        request: &mut StunAuthenticatedRequest,
+        response: &StunMessage,
+    ) -> Result<StunAuthenticationOutcome, StunAuthenticatorError> {
+        let error_code = response
+            .get_attribute(StunAttributeType::ErrorCode as u16)
+            .and_then(|attribute| parse_attribute_value(attribute, parse_error_code).ok())
+            .ok_or(StunAuthenticatorError::InvalidResponseError)?;
+        let realm = response
+            .get_attribute(StunAttributeType::Realm as u16)
+            .and_then(|attribute| parse_attribute_value(attribute, parse_realm).ok());
+        let nonce = response
+            .get_attribute(StunAttributeType::Nonce as u16)
+            .and_then(|attribute| parse_attribute_value(attribute, parse_nonce).ok());
+
+        let nonce = match (error_code.code, nonce) {
+            (STUN_ERROR_UNAUTHORIZED, Some(nonce)) if realm.is_some() => nonce,
+            (STUN_ERROR_STALE_NONCE, Some(nonce)) => nonce,
+            _ => return Ok(StunAuthenticationOutcome::Complete),
+        };
+        if request.retried {
+            self.servers.remove(&request.server);
+            return Err(StunAuthenticatorError::CredentialsRejectedError(
+                error_code.code,
+            ));
+        }
+
+        match realm {
+            Some(realm) => {
+                let credentials = self.derive_credentials(realm, nonce, response)?;
+                self.servers.insert(request.server, credentials);
+            }
+            // a stale NONCE keeps the REALM and the key of the earlier challenge
+            None => match self.servers.get_mut(&request.server) {
+                Some(credentials) => credentials.nonce = nonce.as_str().to_string(),
+                None => return Ok(StunAuthenticationOutcome::Complete),
+            },
+        }
+
+        request.retried = true;
+        Ok(StunAuthenticationOutcome::Resend)
+    }
+
+    /// Pick the password algorithm offered by the challenge and derive the key
+    fn derive_credentials(
+        &self,
+        realm: StunRealm,
+        nonce: StunNonce,
+        response: &StunMessage,
+    ) -> Result<ServerCredentials, StunAuthenticatorError> {
+        let password_algorithms =
+            response.get_attribute(StunAttributeType::PasswordAlgorithms as u16);
+        let algorithm = match password_algorithms {
+            Some(attribute) => {
+                let offered = parse_attribute_value(attribute, parse_password_algorithms)
+                    .map_err(|_| StunAuthenticatorError::InvalidResponseError)?;
+                let offers = |algorithm: StunPasswordAlgorithm| {
+                    offered
+                        .iter()
+                        .any(|value| value.known_algorithm() == Some(algorithm))
+                };
+
+                if offers(StunPasswordAlgorithm::Sha256) {
+                    StunPasswordAlgorithm::Sha256
+                } else if offers(StunPasswordAlgorithm::Md5) {
+                    StunPasswordAlgorithm::Md5
+                } else {
+                    return Err(StunAuthenticatorError::UnsupportedAlgorithmError);
+                }
+            }
+            None => StunPasswordAlgorithm::Md5,
+        };
+
+        let username = StunUsername::new(&self.username)
+            .map_err(|_| StunAuthenticatorError::InvalidCredentialsError)?;
+        // a server which lists the password algorithms follows RFC 8489, which prepares the
+        // realm and the password with OpaqueString rather than SASLprep
+        let key = match algorithm {
+            StunPasswordAlgorithm::Md5 if password_algorithms.is_some() => {
+                long_term_credential_key_rfc8489(&username, &realm, &self.password)
+                    .map(|key| key.to_vec())
+            }
+            StunPasswordAlgorithm::Md5 => {
+                long_term_credential_key(&username, &realm, &self.password).map(|key| key.to_vec())
+            }
+            StunPasswordAlgorithm::Sha256 => {
+                long_term_credential_key_sha256(&username, &realm, &self.password)
+                    .map(|key| key.to_vec())
+            }
+        }
+        .map_err(|_| StunAuthenticatorError::InvalidCredentialsError)?;
+
+        Ok(ServerCredentials {
+            realm: realm.as_str().to_string(),
+            nonce: nonce.as_str().to_string(),
+            algorithm,
+            password_algorithms: password_algorithms
+                .map(|attribute| attribute.attribute_value.to_vec()),
+            key,
+        })
+    }
+
+    /// Check the MESSAGE-INTEGRITY or MESSAGE-INTEGRITY-SHA256 of a success response to an
+    /// authenticated request, one of which is required, and its FINGERPRINT, if it is present
+    fn verify_response(
+        &self,
+        server: SocketAddr,
+        response: &StunMessage,
+    ) -> Result<(), StunAuthenticatorError> {
+        let invalid = |_| StunAuthenticatorError::InvalidResponseError;
+        let has_attribute = |attribute_type: StunAttributeType| {
+            response.get_attribute(attribute_type as u16).is_some()
+        };
+
+        if let Some(credentials) = self.servers.get(&server) {
+            if has_attribute(StunAttributeType::MessageIntegritySha256) {
+                verify_message_integrity_sha256(response, &credentials.key).map_err(invalid)?;
+            } else if has_attribute(StunAttributeType::MessageIntegrity) {
+                verify_message_integrity(response, &credentials.key).map_err(invalid)?;
+            } else {
+                // the request was signed, so the response must be too
+                return Err(StunAuthenticatorError::InvalidResponseError);
+            }
+        }
+        if has_attribute(StunAttributeType::Fingerprint) {
+            verify_fingerprint(response).map_err(invalid)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_stun_message;
+    use crate::stun_address::parse_xor_address;
+
+    const USERNAME: &str = "alice";
+    const PASSWORD: &str = "secret";
+    const REALM: &str = "example.org";
+
+    fn server() -> SocketAddr {
+        "192.0.2.1:3478".parse().unwrap()
+    }
+
+    fn binding_request() -> StunAuthenticatedRequest {
+        let mut request = StunAuthenticatedRequest::new(server(), StunMessageMethod::Binding);
+        request
+            .add_attribute(StunAttributeType::Software as u16, b"test")
+            .add_fingerprint();
+        request
+    }
+
+    fn md5_key() -> Vec<u8> {
+        let username = StunUsername::new(USERNAME).unwrap();
+        let realm = StunRealm::new(REALM).unwrap();
+        long_term_credential_key(&username, &realm, PASSWORD)
+            .unwrap()
+            .to_vec()
+    }
+
+    fn sha256_key() -> Vec<u8> {
+        let username = StunUsername::new(USERNAME).unwrap();
+        let realm = StunRealm::new(REALM).unwrap();
+        long_term_credential_key_sha256(&username, &realm, PASSWORD)
+            .unwrap()
+            .to_vec()
+    }
+
+    /// An error response with the given code, NONCE and, if given, REALM and PASSWORD-ALGORITHMS
+    fn challenge(
+        code: u16,
+        realm: Option<&str>,
+        nonce: &str,
+        password_algorithms: Option<&[StunPasswordAlgorithm]>,
+    ) -> Vec<u8> {
+        let mut builder = StunMessageBuilder::new(
+            StunMessageClass::ErrorResponse,
+            StunMessageMethod::Binding,
+            &[0x42; STUN_TRANSACTION_ID_NUM_BYTES],
+        );
+        builder.add_error_code_attribute(code, "Unauthorized");
+        if let Some(realm) = realm {
+            builder.add_attribute(StunAttributeType::Realm as u16, realm.as_bytes());
+        }
+        builder.add_attribute(StunAttributeType::Nonce as u16, nonce.as_bytes());
+        if let Some(algorithms) = password_algorithms {
+            let values: Vec<_> = algorithms
+                .iter()
+                .map(|&algorithm| StunPasswordAlgorithmValue {
+                    algorithm: algorithm as u16,
+                    parameters: &[],
+                })
+                .collect();
+            builder.add_attribute(
+                StunAttributeType::PasswordAlgorithms as u16,
+                &serialize_password_algorithms(&values),
+            );
+        }
+
+        builder.build().unwrap()
+    }
+
+    fn success(message_integrity: Option<(StunPasswordAlgorithm, &[u8])>) -> Vec<u8> {
+        let mut builder = StunMessageBuilder::new(
+            StunMessageClass::SuccessResponse,
+            StunMessageMethod::Binding,
+            &[0x42; STUN_TRANSACTION_ID_NUM_BYTES],
+        );
+        match message_integrity {
+            Some((StunPasswordAlgorithm::Md5, key)) => builder.add_message_integrity(key),
+            Some((StunPasswordAlgorithm::Sha256, key)) => builder.add_message_integrity_sha256(key),
+            None => &mut builder,
+        };
+
+        builder.add_fingerprint().build().unwrap()
+    }
+
+    fn handle(
+        authenticator: &mut StunAuthenticator,
+        request: &mut StunAuthenticatedRequest,
+        response: &[u8],
+    ) -> Result<StunAuthenticationOutcome, StunAuthenticatorError> {
+        let (_, message) = parse_stun_message(response).unwrap();
+        authenticator.handle_response(request, &message)
+    }
+
+    fn attribute_value(data: &[u8], attribute_type: StunAttributeType) -> Option<Vec<u8>> {
+        let (_, message) = parse_stun_message(data).unwrap();
+        message
+            .get_attribute(attribute_type as u16)
+            .map(|attribute| attribute.attribute_value.to_vec())
+    }
+
+    #[test]
+    fn test_challenge_md5() {
+        let mut authenticator = StunAuthenticator::new(USERNAME, PASSWORD).unwrap();
+        let mut request = binding_request();
+
+        // the first request goes without credentials
+        let data = authenticator.build_request(&request, &[1; 12]).unwrap();
+        assert_eq!(attribute_value(&data, StunAttributeType::Username), None);
+
+        let response = challenge(STUN_ERROR_UNAUTHORIZED, Some(REALM), "n1", None);
+        assert_eq!(
+            handle(&mut authenticator, &mut request, &response),
+            Ok(StunAuthenticationOutcome::Resend)
+        );
+        assert_eq!(authenticator.realm(server()), Some(REALM));
+
+        let data = authenticator.build_request(&request, &[2; 12]).unwrap();
+        let (_, message) = parse_stun_message(&data).unwrap();
+        assert_eq!(
+            attribute_value(&data, StunAttributeType::Username),
+            Some(USERNAME.as_bytes().to_vec())
+        );
+        assert_eq!(
+            attribute_value(&data, StunAttributeType::Nonce),
+            Some(b"n1".to_vec())
+        );
+        assert_eq!(
+            attribute_value(&data, StunAttributeType::PasswordAlgorithm),
+            None
+        );
+        assert_eq!(verify_message_integrity(&message, &md5_key()), Ok(()));
+        assert_eq!(verify_fingerprint(&message), Ok(()));
+
+        let response = success(Some((StunPasswordAlgorithm::Md5, &md5_key())));
+        assert_eq!(
+            handle(&mut authenticator, &mut request, &response),
+            Ok(StunAuthenticationOutcome::Complete)
+        );
+
+        // later requests to the server are authenticated right away
+        let data = authenticator
+            .build_request(&binding_request(), &[3; 12])
+            .unwrap();
+        let (_, message) = parse_stun_message(&data).unwrap();
+        assert_eq!(verify_message_integrity(&message, &md5_key()), Ok(()));
+    }
+
+    #[test]
+    fn test_challenge_sha256() {
+        let mut authenticator = StunAuthenticator::new(USERNAME, PASSWORD).unwrap();
+        let mut request = binding_request();
+
+        let algorithms = [StunPasswordAlgorithm::Md5, StunPasswordAlgorithm::Sha256];
+        let response = challenge(
+            STUN_ERROR_UNAUTHORIZED,
+            Some(REALM),
+            "n1",
+            Some(&algorithms),
+        );
+        assert_eq!(
+            handle(&mut authenticator, &mut request, &response),
+            Ok(StunAuthenticationOutcome::Resend)
+        );
+
+        // SHA-256 is preferred, and the offered algorithms are echoed
+        let data = authenticator.build_request(&request, &[2; 12]).unwrap();
+        let (_, message) = parse_stun_message(&data).unwrap();
+        assert_eq!(
+            attribute_value(&data, StunAttributeType::PasswordAlgorithms),
+            attribute_value(&response, StunAttributeType::PasswordAlgorithms)
+        );
+        assert_eq!(
+            attribute_value(&data, StunAttributeType::PasswordAlgorithm),
+            Some(vec![0x00, 0x02, 0x00, 0x00])
+        );
+        assert_eq!(
+            attribute_value(&data, StunAttributeType::MessageIntegrity),
+            None
+        );
+        assert_eq!(
+            verify_message_integrity_sha256(&message, &sha256_key()),
+            Ok(())
+        );
+
+        let response = success(Some((StunPasswordAlgorithm::Sha256, &sha256_key())));
+        assert_eq!(
+            handle(&mut authenticator, &mut request, &response),
+            Ok(StunAuthenticationOutcome::Complete)
+        );
+
+        // a response signed with another key is rejected
+        let response = success(Some((StunPasswordAlgorithm::Sha256, b"wrong")));
+        assert_eq!(
+            handle(&mut authenticator, &mut binding_request(), &response),
+            Err(StunAuthenticatorError::InvalidResponseError)
+        );
+
+        // as is one without any integrity
+        let response = success(None);
+        assert_eq!(
+            handle(&mut authenticator, &mut binding_request(), &response),
+            Err(StunAuthenticatorError::InvalidResponseError)
+        );
+    }
+
+    #[test]
+    fn test_challenge_md5_rfc8489() {
+        // SASLprep maps the password to "TheMatrIX", OpaqueString keeps it as it is
+        let password = "The\u{00AD}M\u{00AA}tr\u{2168}";
+        let mut authenticator = StunAuthenticator::new(USERNAME, password).unwrap();
+        let mut request = binding_request();
+
+        let algorithms = [StunPasswordAlgorithm::Md5];
+        let response = challenge(
+            STUN_ERROR_UNAUTHORIZED,
+            Some(REALM),
+            "n1",
+            Some(&algorithms),
+        );
+        assert_eq!(
+            handle(&mut authenticator, &mut request, &response),
+            Ok(StunAuthenticationOutcome::Resend)
+        );
+
+        let data = authenticator.build_request(&request, &[2; 12]).unwrap();
+        let (_, message) = parse_stun_message(&data).unwrap();
+        assert_eq!(
+            attribute_value(&data, StunAttributeType::PasswordAlgorithm),
+            Some(vec![0x00, 0x01, 0x00, 0x00])
+        );
+
+        let username = StunUsername::new(USERNAME).unwrap();
+        let realm = StunRealm::new(REALM).unwrap();
+        let key = long_term_credential_key_rfc8489(&username, &realm, password).unwrap();
+        let legacy_key = long_term_credential_key(&username, &realm, password).unwrap();
+        assert_ne!(key, legacy_key);
+        assert_eq!(verify_message_integrity(&message, &key), Ok(()));
+    }
+
+    #[test]
+    fn test_xor_address_attribute() {
+        let authenticator = StunAuthenticator::new(USERNAME, PASSWORD).unwrap();
+        let peer: SocketAddr = "[2001:db8::1]:3478".parse().unwrap();
+        let mut request = binding_request();
+        request.add_xor_address_attribute(StunAttributeType::XorPeerAddress as u16, &peer);
+
+        // the address is XORed with the transaction id of each request built
+        for transaction_id in &[[1; 12], [2; 12]] {
+            let data = authenticator
+                .build_request(&request, transaction_id)
+                .unwrap();
+            let (_, message) = parse_stun_message(&data).unwrap();
+            let attribute = message
+                .get_attribute(StunAttributeType::XorPeerAddress as u16)
+                .unwrap();
+            assert_eq!(
+                parse_attribute_value(attribute, |input| parse_xor_address(input, transaction_id)),
+                Ok(peer)
+            );
+        }
+    }
+
+    #[test]
+    fn test_unsupported_algorithm() {
+        let mut authenticator = StunAuthenticator::new(USERNAME, PASSWORD).unwrap();
+
+        let response = challenge(STUN_ERROR_UNAUTHORIZED, Some(REALM), "n1", Some(&[]));
+        assert_eq!(
+            handle(&mut authenticator, &mut binding_request(), &response),
+            Err(StunAuthenticatorError::UnsupportedAlgorithmError)
+        );
+        assert_eq!(authenticator.realm(server()), None);
+    }
+
+    #[test]
+    fn test_stale_nonce() {
+        let mut authenticator = StunAuthenticator::new(USERNAME, PASSWORD).unwrap();
+        let response = challenge(STUN_ERROR_UNAUTHORIZED, Some(REALM), "n1", None);
+        handle(&mut authenticator, &mut binding_request(), &response).unwrap();
+
+        // a stale NONCE is replaced and the request resent once
+        let mut request = binding_request();
+        let response = challenge(STUN_ERROR_STALE_NONCE, None, "n2", None);
+        assert_eq!(
+            handle(&mut authenticator, &mut request, &response),
+            Ok(StunAuthenticationOutcome::Resend)
+        );
+
+        let data = authenticator.build_request(&request, &[2; 12]).unwrap();
+        let (_, message) = parse_stun_message(&data).unwrap();
+        assert_eq!(
+            attribute_value(&data, StunAttributeType::Nonce),
+            Some(b"n2".to_vec())
+        );
+        assert_eq!(verify_message_integrity(&message, &md5_key()), Ok(()));
+
+        let response = challenge(STUN_ERROR_STALE_NONCE, None, "n3", None);
+        assert_eq!(
+            handle(&mut authenticator, &mut request, &response),
+            Err(StunAuthenticatorError::CredentialsRejectedError(
+                STUN_ERROR_STALE_NONCE
+            ))
+        );
+    }
+
+    #[test]
+    fn test_credentials_rejected() {
+        let mut authenticator = StunAuthenticator::new(USERNAME, PASSWORD).unwrap();
+        let mut request = binding_request();
+
+        let response = challenge(STUN_ERROR_UNAUTHORIZED, Some(REALM), "n1", None);
+        assert_eq!(
+            handle(&mut authenticator, &mut request, &response),
+            Ok(StunAuthenticationOutcome::Resend)
+        );
+        assert_eq!(
+            handle(&mut authenticator, &mut request, &response),
+            Err(StunAuthenticatorError::CredentialsRejectedError(
+                STUN_ERROR_UNAUTHORIZED
+            ))
+        );
+
+        // the challenge is dropped, the next request starts over without credentials
+        assert_eq!(authenticator.realm(server()), None);
+        let data = authenticator.build_request(&request, &[3; 12]).unwrap();
+        assert_eq!(attribute_value(&data, StunAttributeType::Username), None);
+    }
+
+    #[test]
+    fn test_other_error_response() {
+        let mut authenticator = StunAuthenticator::new(USERNAME, PASSWORD).unwrap();
+
+        // a 401 without REALM, or any other error, is the final answer
+        let response = challenge(STUN_ERROR_UNAUTHORIZED, None, "n1", None);
+        assert_eq!(
+            handle(&mut authenticator, &mut binding_request(), &response),
+            Ok(StunAuthenticationOutcome::Complete)
+        );
+        let response = challenge(STUN_ERROR_BAD_REQUEST, Some(REALM), "n1", None);
+        assert_eq!(
+            handle(&mut authenticator, &mut binding_request(), &response),
+            Ok(StunAuthenticationOutcome::Complete)
+        );
+        assert_eq!(authenticator.realm(server()), None);
+    }
+
+    #[test]
+    fn test_invalid_username() {
+        let username = "a".repeat(STUN_USERNAME_MAX_NUM_BYTES + 1);
+
+        assert_eq!(
+            StunAuthenticator::new(&username, PASSWORD).unwrap_err(),
+            StunAuthenticatorError::InvalidCredentialsError
+        );
+    }
+}
@@ -0,0 +1,1199 @@
+use crate::parser::{parse_stun_message, parse_stun_message_view};
+use crate::stun_constants::*;
+use crate::stun_errors::StunCaptureError;
+use crate::stun_five_tuple::*;
+use crate::stun_message::*;
+use crate::turn_channel_data::*;
+
+use core::convert::{TryFrom, TryInto};
+use std::collections::{HashMap, VecDeque};
+use std::io::{ErrorKind, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+/// Largest pcap record or pcapng block that is read, anything larger is treated as corruption
+const MAX_RECORD_NUM_BYTES: usize = 16 * 1024 * 1024;
+
+/// Number of out of order TCP segments held per stream before the missing data is given up on
+const MAX_OUT_OF_ORDER_SEGMENTS: usize = 64;
+
+const PCAPNG_SECTION_HEADER_BLOCK: [u8; 4] = [0x0A, 0x0D, 0x0D, 0x0A];
+const PCAPNG_INTERFACE_DESCRIPTION_BLOCK: u32 = 1;
+const PCAPNG_ENHANCED_PACKET_BLOCK: u32 = 6;
+const PCAPNG_OPTION_END: u16 = 0;
+const PCAPNG_OPTION_IF_TSRESOL: u16 = 9;
+
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LOOP: u32 = 108;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86DD;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const ETHERTYPE_QINQ: u16 = 0x88A8;
+
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_RST: u8 = 0x04;
+const TCP_ACK: u8 = 0x10;
+
+/// A STUN or ChannelData message found in a packet capture
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct CapturedMessage {
+    /// capture time of the packet that completed the message, since the UNIX epoch
+    pub timestamp: Duration,
+
+    /// the flow the message was sent on.  The client is the side that sent the TCP SYN, or the
+    /// side that sent the first message of the flow seen in the capture.
+    pub five_tuple: FiveTuple,
+
+    /// whether the message was sent by the client of the five tuple, otherwise by the server
+    pub from_client: bool,
+
+    data: Vec<u8>,
+}
+
+/// The contents of a `CapturedMessage`
+#[derive(Debug)]
+pub enum CapturedPayload<'a> {
+    Stun(StunMessage<'a>),
+    ChannelData(ChannelData<'a>),
+}
+
+impl CapturedMessage {
+    /// The serialized message, including the padding of ChannelData messages if it was sent
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The parsed message
+    pub fn payload(&self) -> CapturedPayload<'_> {
+        // only valid messages are captured
+        if is_channel_data(&self.data) {
+            match parse_channel_data(&self.data) {
+                Ok((_, message)) => CapturedPayload::ChannelData(message),
+                Err(_) => unreachable!("the captured message was validated"),
+            }
+        } else {
+            match parse_stun_message(&self.data) {
+                Ok((_, message)) => CapturedPayload::Stun(message),
+                Err(_) => unreachable!("the captured message was validated"),
+            }
+        }
+    }
+}
+
+/// Reader extracting the STUN and ChannelData messages from a pcap or pcapng capture.
+///
+/// Packets are decoded from Ethernet (optionally VLAN tagged), Linux cooked, loopback or raw IP
+/// captures, over IPv4 or IPv6.  A UDP datagram is captured if it holds exactly one message.
+/// TCP streams are reassembled and split into messages, either sent directly on the stream as
+/// for STUN and TURN, https://tools.ietf.org/html/rfc5766#section-11.5, or framed with a length
+/// prefix as for ICE-TCP, https://tools.ietf.org/html/rfc4571.  A stream is picked up at the
+/// first segment starting with a STUN message, so streams whose start was not captured are
+/// found too.  Fragmented IP packets are not reassembled.
+///
+/// Each item is a message, or an error reading the capture after which there are no more items.
+pub struct CaptureReader<R> {
+    reader: R,
+    format: CaptureFormat,
+    clients: HashMap<(SocketAddr, SocketAddr, TransportProtocol), SocketAddr>,
+    streams: HashMap<(SocketAddr, SocketAddr), TcpStream>,
+    messages: VecDeque<CapturedMessage>,
+    done: bool,
+}
+
+impl<R: Read> CaptureReader<R> {
+    /// Create a reader for the given pcap or pcapng capture, reading its file header.  The reader
+    /// is read in small pieces, so it should be buffered.
+    pub fn new(mut reader: R) -> Result<Self, StunCaptureError> {
+        let mut magic = [0u8; 4];
+        read_exact(&mut reader, &mut magic)?;
+
+        let format = match magic {
+            [0xD4, 0xC3, 0xB2, 0xA1] => pcap_format(&mut reader, ByteOrder::Little, 1_000_000)?,
+            [0xA1, 0xB2, 0xC3, 0xD4] => pcap_format(&mut reader, ByteOrder::Big, 1_000_000)?,
+            [0x4D, 0x3C, 0xB2, 0xA1] => pcap_format(&mut reader, ByteOrder::Little, 1_000_000_000)?,
+            [0xA1, 0xB2, 0x3C, 0x4D] => pcap_format(&mut reader, ByteOrder::Big, 1_000_000_000)?,
+            PCAPNG_SECTION_HEADER_BLOCK => {
+                let byte_order = read_section_header(&mut reader)?;
+                CaptureFormat::Pcapng {
+                    byte_order,
+                    interfaces: Vec::new(),
+                }
+            }
+            _ => return Err(StunCaptureError::InvalidFileError),
+        };
+
+        Ok(CaptureReader {
+            reader,
+            format,
+            clients: HashMap::new(),
+            streams: HashMap::new(),
+            messages: VecDeque::new(),
+            done: false,
+        })
+    }
+
+    /// Read the next packet, returning its timestamp, link type and data
+    fn read_packet(&mut self) -> Result<Option<(Duration, u32, Vec<u8>)>, StunCaptureError> {
+        match &mut self.format {
+            CaptureFormat::Pcap {
+                byte_order,
+                units_per_second,
+                link_type,
+            } => {
+                let mut header = [0u8; 16];
+                if !read_exact_or_end(&mut self.reader, &mut header)? {
+                    return Ok(None);
+                }
+
+                let seconds = byte_order.u32(&header[0..4]) as u64;
+                let units = byte_order.u32(&header[4..8]) as u64;
+                let length = byte_order.u32(&header[8..12]) as usize;
+                let timestamp = timestamp(seconds * *units_per_second + units, *units_per_second);
+
+                let data = read_record(&mut self.reader, length)?;
+                Ok(Some((timestamp, *link_type, data)))
+            }
+            CaptureFormat::Pcapng {
+                byte_order,
+                interfaces,
+            } => loop {
+                let mut block_type = [0u8; 4];
+                if !read_exact_or_end(&mut self.reader, &mut block_type)? {
+                    return Ok(None);
+                }
+
+                if block_type == PCAPNG_SECTION_HEADER_BLOCK {
+                    *byte_order = read_section_header(&mut self.reader)?;
+                    interfaces.clear();
+                    continue;
+                }
+
+                let mut length = [0u8; 4];
+                read_exact(&mut self.reader, &mut length)?;
+                let length = byte_order.u32(&length) as usize;
+                if length < 12 || length % 4 != 0 {
+                    return Err(StunCaptureError::InvalidFileError);
+                }
+
+                // the block body is followed by a copy of the block length
+                let mut body = read_record(&mut self.reader, length - 8)?;
+                body.truncate(length - 12);
+
+                match byte_order.u32(&block_type) {
+                    PCAPNG_INTERFACE_DESCRIPTION_BLOCK => {
+                        interfaces.push(read_interface(*byte_order, &body)?);
+                    }
+                    PCAPNG_ENHANCED_PACKET_BLOCK => {
+                        if body.len() < 20 {
+                            return Err(StunCaptureError::InvalidFileError);
+                        }
+
+                        let interface = byte_order.u32(&body[0..4]) as usize;
+                        let (link_type, units_per_second) = *interfaces
+                            .get(interface)
+                            .ok_or(StunCaptureError::InvalidFileError)?;
+                        let units = (byte_order.u32(&body[4..8]) as u64) << 32
+                            | byte_order.u32(&body[8..12]) as u64;
+                        let length = byte_order.u32(&body[12..16]) as usize;
+                        let data = body
+                            .get(20..20 + length)
+                            .ok_or(StunCaptureError::InvalidFileError)?;
+
+                        return Ok(Some((
+                            timestamp(units, units_per_second),
+                            link_type,
+                            data.to_vec(),
+                        )));
+                    }
+                    _ => {}
+                }
+            },
+        }
+    }
+
+    /// Extract the messages from a packet
+    fn handle_packet(&mut self, timestamp: Duration, link_type: u32, data: &[u8]) {
+        let segment = match link_payload(link_type, data).and_then(transport_segment) {
+            Some(segment) => segment,
+            None => return,
+        };
+
+        match segment.tcp {
+            None => {
+                if is_message(segment.payload) {
+                    self.capture(timestamp, &segment, segment.payload.to_vec());
+                }
+            }
+            Some(tcp) => self.handle_tcp_segment(timestamp, &segment, tcp),
+        }
+    }
+
+    fn handle_tcp_segment(&mut self, timestamp: Duration, segment: &Segment, tcp: TcpHeader) {
+        let key = (segment.source, segment.destination);
+
+        if tcp.flags & TCP_SYN != 0 {
+            let client = match tcp.flags & TCP_ACK {
+                0 => segment.source,
+                _ => segment.destination,
+            };
+            self.clients.insert(flow_key(segment), client);
+            self.streams
+                .insert(key, TcpStream::new(tcp.sequence.wrapping_add(1)));
+            return;
+        }
+
+        // a reset drops whatever is left of the connection
+        if tcp.flags & TCP_RST != 0 {
+            self.streams.remove(&key);
+            self.streams.remove(&(key.1, key.0));
+            return;
+        }
+
+        // a stream whose start was not captured is picked up at the first message
+        let stream = match self.streams.get_mut(&key) {
+            Some(stream) => stream,
+            None if detect_framing(segment.payload, None).is_some() => self
+                .streams
+                .entry(key)
+                .or_insert_with(|| TcpStream::new(tcp.sequence)),
+            None => return,
+        };
+
+        stream.receive(tcp.sequence, segment.payload);
+        for data in stream.messages() {
+            self.capture(timestamp, segment, data);
+        }
+
+        // a FIN often carries the last message, so the stream is closed only once it is read
+        if tcp.flags & TCP_FIN != 0 {
+            self.streams.remove(&key);
+        }
+    }
+
+    fn capture(&mut self, timestamp: Duration, segment: &Segment, data: Vec<u8>) {
+        let client = *self
+            .clients
+            .entry(flow_key(segment))
+            .or_insert(segment.source);
+        let from_client = client == segment.source;
+
+        self.messages.push_back(CapturedMessage {
+            timestamp,
+            five_tuple: FiveTuple {
+                client_address: client,
+                server_address: match from_client {
+                    true => segment.destination,
+                    false => segment.source,
+                },
+                protocol: segment.protocol,
+            },
+            from_client,
+            data,
+        });
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = Result<CapturedMessage, StunCaptureError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(message) = self.messages.pop_front() {
+                return Some(Ok(message));
+            }
+
+            if self.done {
+                return None;
+            }
+
+            match self.read_packet() {
+                Ok(Some((timestamp, link_type, data))) => {
+                    self.handle_packet(timestamp, link_type, &data)
+                }
+                Ok(None) => self.done = true,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ByteOrder {
+    Little,
+    Big,
+}
+
+impl ByteOrder {
+    fn u16(self, input: &[u8]) -> u16 {
+        let bytes = [input[0], input[1]];
+        match self {
+            ByteOrder::Little => u16::from_le_bytes(bytes),
+            ByteOrder::Big => u16::from_be_bytes(bytes),
+        }
+    }
+
+    fn u32(self, input: &[u8]) -> u32 {
+        let bytes = [input[0], input[1], input[2], input[3]];
+        match self {
+            ByteOrder::Little => u32::from_le_bytes(bytes),
+            ByteOrder::Big => u32::from_be_bytes(bytes),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum CaptureFormat {
+    /// https://datatracker.ietf.org/doc/html/draft-ietf-opsawg-pcap
+    Pcap {
+        byte_order: ByteOrder,
+        units_per_second: u64,
+        link_type: u32,
+    },
+
+    /// https://datatracker.ietf.org/doc/html/draft-ietf-opsawg-pcapng, with the link type and
+    /// timestamp resolution of each interface of the current section
+    Pcapng {
+        byte_order: ByteOrder,
+        interfaces: Vec<(u32, u64)>,
+    },
+}
+
+/// Read the rest of a pcap file header, after the magic number
+fn pcap_format<R: Read>(
+    reader: &mut R,
+    byte_order: ByteOrder,
+    units_per_second: u64,
+) -> Result<CaptureFormat, StunCaptureError> {
+    let mut header = [0u8; 20];
+    read_exact(reader, &mut header)?;
+
+    Ok(CaptureFormat::Pcap {
+        byte_order,
+        units_per_second,
+        // the upper bits hold the length of the frame check sequence, if any
+        link_type: byte_order.u32(&header[16..20]) & 0xFFFF,
+    })
+}
+
+/// Read the rest of a pcapng section header block, after the block type, returning the byte
+/// order of the section
+fn read_section_header<R: Read>(reader: &mut R) -> Result<ByteOrder, StunCaptureError> {
+    let mut header = [0u8; 8];
+    read_exact(reader, &mut header)?;
+
+    let byte_order = match header[4..8] {
+        [0x1A, 0x2B, 0x3C, 0x4D] => ByteOrder::Big,
+        [0x4D, 0x3C, 0x2B, 0x1A] => ByteOrder::Little,
+        _ => return Err(StunCaptureError::InvalidFileError),
+    };
+
+    let length = byte_order.u32(&header[0..4]) as usize;
+    if length < 28 || length % 4 != 0 {
+        return Err(StunCaptureError::InvalidFileError);
+    }
+    read_record(reader, length - 12)?;
+
+    Ok(byte_order)
+}
+
+/// Get the link type and timestamp resolution from the body of an interface description block
+fn read_interface(byte_order: ByteOrder, body: &[u8]) -> Result<(u32, u64), StunCaptureError> {
+    if body.len() < 8 {
+        return Err(StunCaptureError::InvalidFileError);
+    }
+    let link_type = byte_order.u16(&body[0..2]) as u32;
+
+    // microseconds unless the if_tsresol option says otherwise
+    let mut units_per_second = 1_000_000;
+    let mut options = &body[8..];
+    while options.len() >= 4 {
+        let code = byte_order.u16(&options[0..2]);
+        let length = byte_order.u16(&options[2..4]) as usize;
+        let value = options
+            .get(4..4 + length)
+            .ok_or(StunCaptureError::InvalidFileError)?;
+
+        match code {
+            PCAPNG_OPTION_END => break,
+            PCAPNG_OPTION_IF_TSRESOL if length == 1 => {
+                // a power of 2 if the top bit is set, otherwise a power of 10
+                let resolution = match value[0] & 0x80 {
+                    0 => 10u64.checked_pow(value[0] as u32),
+                    _ => 2u64.checked_pow((value[0] & 0x7F) as u32),
+                };
+                units_per_second = resolution.ok_or(StunCaptureError::InvalidFileError)?;
+            }
+            _ => {}
+        }
+
+        options = options.get(4 + ((length + 3) & !3)..).unwrap_or(&[]);
+    }
+
+    Ok((link_type, units_per_second))
+}
+
+fn timestamp(units: u64, units_per_second: u64) -> Duration {
+    let nanos = (units % units_per_second) as u128 * 1_000_000_000 / units_per_second as u128;
+    Duration::new(units / units_per_second, nanos as u32)
+}
+
+/// Read the given number of bytes
+fn read_record<R: Read>(reader: &mut R, length: usize) -> Result<Vec<u8>, StunCaptureError> {
+    if length > MAX_RECORD_NUM_BYTES {
+        return Err(StunCaptureError::InvalidFileError);
+    }
+
+    let mut record = vec![0u8; length];
+    read_exact(reader, &mut record)?;
+
+    Ok(record)
+}
+
+fn read_exact<R: Read>(reader: &mut R, buffer: &mut [u8]) -> Result<(), StunCaptureError> {
+    match read_exact_or_end(reader, buffer)? {
+        true => Ok(()),
+        false => Err(StunCaptureError::TruncatedFileError),
+    }
+}
+
+/// Fill the buffer, returning false if the reader is at its end before the first byte
+fn read_exact_or_end<R: Read>(reader: &mut R, buffer: &mut [u8]) -> Result<bool, StunCaptureError> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => return Err(StunCaptureError::TruncatedFileError),
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(StunCaptureError::IoError(e)),
+        }
+    }
+
+    Ok(true)
+}
+
+/// A UDP datagram or TCP segment
+struct Segment<'a> {
+    source: SocketAddr,
+    destination: SocketAddr,
+    protocol: TransportProtocol,
+    tcp: Option<TcpHeader>,
+    payload: &'a [u8],
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TcpHeader {
+    sequence: u32,
+    flags: u8,
+}
+
+fn flow_key(segment: &Segment) -> (SocketAddr, SocketAddr, TransportProtocol) {
+    match segment.source < segment.destination {
+        true => (segment.source, segment.destination, segment.protocol),
+        false => (segment.destination, segment.source, segment.protocol),
+    }
+}
+
+fn be_u16_at(input: &[u8], offset: usize) -> Option<u16> {
+    let bytes = input.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+fn be_u32_at(input: &[u8], offset: usize) -> Option<u32> {
+    let bytes = input.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Get the IP packet from a link layer frame
+fn link_payload(link_type: u32, frame: &[u8]) -> Option<&[u8]> {
+    let (ethertype, offset) = match link_type {
+        // the address family is in host byte order, so the IP version is used instead
+        LINKTYPE_NULL | LINKTYPE_LOOP => return frame.get(4..),
+        LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => return Some(frame),
+        LINKTYPE_ETHERNET => {
+            let mut offset = 12;
+            let mut ethertype = be_u16_at(frame, offset)?;
+            while ethertype == ETHERTYPE_VLAN || ethertype == ETHERTYPE_QINQ {
+                offset += 4;
+                ethertype = be_u16_at(frame, offset)?;
+            }
+            (ethertype, offset + 2)
+        }
+        LINKTYPE_LINUX_SLL => (be_u16_at(frame, 14)?, 16),
+        LINKTYPE_LINUX_SLL2 => (be_u16_at(frame, 0)?, 20),
+        _ => return None,
+    };
+
+    match ethertype {
+        ETHERTYPE_IPV4 | ETHERTYPE_IPV6 => frame.get(offset..),
+        _ => None,
+    }
+}
+
+/// Get the UDP datagram or TCP segment from an IP packet
+fn transport_segment(packet: &[u8]) -> Option<Segment<'_>> {
+    let (source, destination, protocol, payload) = match packet.first()? >> 4 {
+        4 => {
+            let header_length = (packet[0] & 0x0F) as usize * 4;
+            let total_length = be_u16_at(packet, 2)? as usize;
+            let fragment = be_u16_at(packet, 6)?;
+
+            // fragments are skipped, as is a packet cut short by the capture's snapshot length
+            if fragment & 0x3FFF != 0 || header_length < 20 || total_length < header_length {
+                return None;
+            }
+
+            let source = Ipv4Addr::from(be_u32_at(packet, 12)?);
+            let destination = Ipv4Addr::from(be_u32_at(packet, 16)?);
+            let payload = packet.get(header_length..total_length)?;
+            (
+                IpAddr::V4(source),
+                IpAddr::V4(destination),
+                packet[9],
+                payload,
+            )
+        }
+        6 => {
+            let payload_length = be_u16_at(packet, 4)? as usize;
+            let source: [u8; 16] = packet.get(8..24)?.try_into().ok()?;
+            let destination: [u8; 16] = packet.get(24..40)?.try_into().ok()?;
+            let mut payload = packet.get(40..40 + payload_length)?;
+
+            // skip the extension headers, a fragment header ends the walk and the packet is skipped
+            let mut next_header = packet[6];
+            loop {
+                let length = match next_header {
+                    0 | 43 | 60 => (*payload.get(1)? as usize + 1) * 8,
+                    51 => (*payload.get(1)? as usize + 2) * 4,
+                    _ => break,
+                };
+                next_header = payload[0];
+                payload = payload.get(length..)?;
+            }
+
+            (
+                IpAddr::V6(Ipv6Addr::from(source)),
+                IpAddr::V6(Ipv6Addr::from(destination)),
+                next_header,
+                payload,
+            )
+        }
+        _ => return None,
+    };
+
+    let source = SocketAddr::new(source, be_u16_at(payload, 0)?);
+    let destination = SocketAddr::new(destination, be_u16_at(payload, 2)?);
+
+    let (protocol, tcp, payload) = match TransportProtocol::try_from(protocol).ok()? {
+        TransportProtocol::Udp => {
+            let length = be_u16_at(payload, 4)? as usize;
+            (TransportProtocol::Udp, None, payload.get(8..length)?)
+        }
+        TransportProtocol::Tcp => {
+            let header_length = (*payload.get(12)? >> 4) as usize * 4;
+            let tcp = TcpHeader {
+                sequence: be_u32_at(payload, 4)?,
+                flags: *payload.get(13)?,
+            };
+            if header_length < 20 {
+                return None;
+            }
+            (
+                TransportProtocol::Tcp,
+                Some(tcp),
+                payload.get(header_length..)?,
+            )
+        }
+    };
+
+    Some(Segment {
+        source,
+        destination,
+        protocol,
+        tcp,
+        payload,
+    })
+}
+
+fn is_channel_data(data: &[u8]) -> bool {
+    // the first two bits are 0b01 rather than the 0b00 of a STUN message
+    data.first().is_some_and(|b| b & 0xC0 == 0x40)
+}
+
+/// Whether the data is exactly one STUN message, or one ChannelData message and its padding
+fn is_message(data: &[u8]) -> bool {
+    if is_channel_data(data) {
+        matches!(parse_channel_data(data), Ok((padding, _)) if padding.len() < 4)
+    } else {
+        matches!(parse_stun_message_view(data), Ok((remaining, _)) if remaining.is_empty())
+    }
+}
+
+/// How the messages on a TCP stream are delimited
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+enum Framing {
+    /// STUN and ChannelData messages sent directly on the stream
+    Stun,
+
+    /// each message is preceded by its 16 bit length, https://tools.ietf.org/html/rfc4571
+    Rfc4571,
+}
+
+/// Detect the framing of a stream from data which starts with a STUN message, if it does.
+/// Once the framing of a stream is known only that framing is looked for.
+fn detect_framing(data: &[u8], framing: Option<Framing>) -> Option<Framing> {
+    let starts_with_stun = |data: &[u8]| {
+        data.len() >= 8 && data[0] & 0xC0 == 0 && data[4..8] == STUN_MAGIC_COOKIE.to_be_bytes()
+    };
+
+    match framing {
+        Some(Framing::Stun) | None if starts_with_stun(data) => Some(Framing::Stun),
+        Some(Framing::Rfc4571) | None if starts_with_stun(data.get(2..)?) => Some(Framing::Rfc4571),
+        _ => None,
+    }
+}
+
+/// One direction of a TCP connection being reassembled
+#[derive(Debug)]
+struct TcpStream {
+    /// sequence number of the next byte expected on the stream
+    next_sequence: u32,
+
+    /// framing of the stream, once a message has been found on it
+    framing: Option<Framing>,
+
+    /// whether the data on the stream is known to start at a message boundary
+    synchronized: bool,
+
+    /// data received in order which does not yet form a complete message
+    buffer: Vec<u8>,
+
+    /// segments received ahead of the next expected byte
+    out_of_order: Vec<(u32, Vec<u8>)>,
+}
+
+impl TcpStream {
+    fn new(next_sequence: u32) -> Self {
+        TcpStream {
+            next_sequence,
+            framing: None,
+            synchronized: false,
+            buffer: Vec::new(),
+            out_of_order: Vec::new(),
+        }
+    }
+
+    /// Add a segment to the stream
+    fn receive(&mut self, sequence: u32, payload: &[u8]) {
+        if payload.is_empty() {
+            return;
+        }
+
+        let offset = sequence.wrapping_sub(self.next_sequence) as i32;
+        if offset > 0 {
+            self.out_of_order.push((sequence, payload.to_vec()));
+
+            // the segments filling the gap were not captured, so skip to the earliest held
+            if self.out_of_order.len() > MAX_OUT_OF_ORDER_SEGMENTS {
+                let earliest = self
+                    .out_of_order
+                    .iter()
+                    .map(|(sequence, _)| *sequence)
+                    .min_by_key(|sequence| sequence.wrapping_sub(self.next_sequence))
+                    .unwrap_or(sequence);
+                self.next_sequence = earliest;
+                self.buffer.clear();
+                self.synchronized = false;
+            }
+        } else {
+            self.append(sequence, payload);
+        }
+
+        // add the held segments which now follow on
+        while let Some(index) = self
+            .out_of_order
+            .iter()
+            .position(|(sequence, _)| sequence.wrapping_sub(self.next_sequence) as i32 <= 0)
+        {
+            let (sequence, payload) = self.out_of_order.swap_remove(index);
+            self.append(sequence, &payload);
+        }
+    }
+
+    /// Append the part of an in order segment which has not been received yet
+    fn append(&mut self, sequence: u32, payload: &[u8]) {
+        let overlap = self.next_sequence.wrapping_sub(sequence) as usize;
+        let data = match payload.get(overlap..) {
+            Some(data) if !data.is_empty() => data,
+            _ => return,
+        };
+        self.next_sequence = self.next_sequence.wrapping_add(data.len() as u32);
+
+        // after a gap the stream is picked up again at a segment starting with a message
+        if !self.synchronized {
+            match detect_framing(data, self.framing) {
+                Some(framing) => {
+                    self.framing = Some(framing);
+                    self.synchronized = true;
+                }
+                None => return,
+            }
+        }
+
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// Remove the complete messages from the buffer
+    fn messages(&mut self) -> Vec<Vec<u8>> {
+        let mut messages = Vec::new();
+
+        while self.synchronized {
+            let (header_length, length) = match self.framing {
+                Some(Framing::Rfc4571) => match be_u16_at(&self.buffer, 0) {
+                    Some(length) => (2, length as usize),
+                    None => break,
+                },
+                _ => match stream_message_length(&self.buffer) {
+                    Ok(Some(length)) => (0, length),
+                    Ok(None) => break,
+                    Err(_) => {
+                        self.desynchronize();
+                        break;
+                    }
+                },
+            };
+
+            let end = header_length + length;
+            if self.buffer.len() < end {
+                break;
+            }
+
+            let data = &self.buffer[header_length..end];
+            if is_message(data) {
+                messages.push(data.to_vec());
+            } else if self.framing == Some(Framing::Stun) {
+                // without a length prefix there is no way to skip something that is not a message
+                self.desynchronize();
+                break;
+            }
+            self.buffer.drain(..end);
+        }
+
+        messages
+    }
+
+    fn desynchronize(&mut self) {
+        self.buffer.clear();
+        self.synchronized = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stun_message_builder::*;
+    use crate::stun_message_types::*;
+
+    fn binding(message_class: StunMessageClass) -> Vec<u8> {
+        StunMessageBuilder::new(
+            message_class,
+            StunMessageMethod::Binding,
+            &[0x5A; STUN_TRANSACTION_ID_NUM_BYTES],
+        )
+        .add_attribute(0x8022, b"capture")
+        .build()
+        .unwrap()
+    }
+
+    fn ip(source: SocketAddr, destination: SocketAddr, protocol: u8, transport: &[u8]) -> Vec<u8> {
+        let mut packet = Vec::new();
+        match (source.ip(), destination.ip()) {
+            (IpAddr::V4(source), IpAddr::V4(destination)) => {
+                packet.extend_from_slice(&[0x45, 0x00]);
+                packet.extend_from_slice(&(20 + transport.len() as u16).to_be_bytes());
+                packet.extend_from_slice(&[0x00, 0x00, 0x40, 0x00, 64, protocol, 0x00, 0x00]);
+                packet.extend_from_slice(&source.octets());
+                packet.extend_from_slice(&destination.octets());
+            }
+            (IpAddr::V6(source), IpAddr::V6(destination)) => {
+                packet.extend_from_slice(&[0x60, 0x00, 0x00, 0x00]);
+                packet.extend_from_slice(&(transport.len() as u16).to_be_bytes());
+                packet.extend_from_slice(&[protocol, 64]);
+                packet.extend_from_slice(&source.octets());
+                packet.extend_from_slice(&destination.octets());
+            }
+            _ => panic!("mixed address families"),
+        }
+        packet.extend_from_slice(transport);
+
+        packet
+    }
+
+    fn udp(source: SocketAddr, destination: SocketAddr, payload: &[u8]) -> Vec<u8> {
+        let mut datagram = Vec::new();
+        datagram.extend_from_slice(&source.port().to_be_bytes());
+        datagram.extend_from_slice(&destination.port().to_be_bytes());
+        datagram.extend_from_slice(&(8 + payload.len() as u16).to_be_bytes());
+        datagram.extend_from_slice(&[0x00, 0x00]);
+        datagram.extend_from_slice(payload);
+
+        ip(source, destination, 17, &datagram)
+    }
+
+    fn tcp(
+        source: SocketAddr,
+        destination: SocketAddr,
+        sequence: u32,
+        flags: u8,
+        payload: &[u8],
+    ) -> Vec<u8> {
+        let mut segment = Vec::new();
+        segment.extend_from_slice(&source.port().to_be_bytes());
+        segment.extend_from_slice(&destination.port().to_be_bytes());
+        segment.extend_from_slice(&sequence.to_be_bytes());
+        segment.extend_from_slice(&[0x00; 4]);
+        segment.extend_from_slice(&[0x50, flags, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x00]);
+        segment.extend_from_slice(payload);
+
+        ip(source, destination, 6, &segment)
+    }
+
+    fn ethernet(packet: &[u8], vlan: bool) -> Vec<u8> {
+        let mut frame = vec![0x02; 12];
+        if vlan {
+            frame.extend_from_slice(&[0x81, 0x00, 0x00, 0x01]);
+        }
+        match packet[0] >> 4 {
+            4 => frame.extend_from_slice(&[0x08, 0x00]),
+            _ => frame.extend_from_slice(&[0x86, 0xDD]),
+        }
+        frame.extend_from_slice(packet);
+
+        frame
+    }
+
+    /// A little endian pcap file with microsecond timestamps
+    fn pcap(link_type: u32, packets: &[(Duration, Vec<u8>)]) -> Vec<u8> {
+        let mut capture = vec![0xD4, 0xC3, 0xB2, 0xA1, 0x02, 0x00, 0x04, 0x00];
+        capture.extend_from_slice(&[0x00; 8]);
+        capture.extend_from_slice(&65535u32.to_le_bytes());
+        capture.extend_from_slice(&link_type.to_le_bytes());
+
+        for (timestamp, data) in packets {
+            capture.extend_from_slice(&(timestamp.as_secs() as u32).to_le_bytes());
+            capture.extend_from_slice(&timestamp.subsec_micros().to_le_bytes());
+            capture.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            capture.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            capture.extend_from_slice(data);
+        }
+
+        capture
+    }
+
+    /// A big endian pcapng file with one interface with nanosecond timestamps
+    fn pcapng(link_type: u16, packets: &[(Duration, Vec<u8>)]) -> Vec<u8> {
+        fn block(capture: &mut Vec<u8>, block_type: u32, body: &[u8]) {
+            let padded = (body.len() + 3) & !3;
+            let length = (12 + padded) as u32;
+            capture.extend_from_slice(&block_type.to_be_bytes());
+            capture.extend_from_slice(&length.to_be_bytes());
+            capture.extend_from_slice(body);
+            capture.resize(capture.len() + padded - body.len(), 0);
+            capture.extend_from_slice(&length.to_be_bytes());
+        }
+
+        let mut capture = Vec::new();
+        let mut body = vec![0x1A, 0x2B, 0x3C, 0x4D, 0x00, 0x01, 0x00, 0x00];
+        body.extend_from_slice(&[0xFF; 8]);
+        block(&mut capture, 0x0A0D_0D0A, &body);
+
+        let mut body = link_type.to_be_bytes().to_vec();
+        body.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0xFF, 0xFF]);
+        body.extend_from_slice(&[0x00, 0x09, 0x00, 0x01, 0x09, 0x00, 0x00, 0x00]);
+        body.extend_from_slice(&[0x00; 4]);
+        block(&mut capture, 1, &body);
+
+        for (timestamp, data) in packets {
+            let units = timestamp.as_nanos() as u64;
+            let mut body = vec![0x00; 4];
+            body.extend_from_slice(&((units >> 32) as u32).to_be_bytes());
+            body.extend_from_slice(&(units as u32).to_be_bytes());
+            body.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            body.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            body.extend_from_slice(data);
+            block(&mut capture, 6, &body);
+        }
+
+        capture
+    }
+
+    fn read(capture: &[u8]) -> Vec<CapturedMessage> {
+        CaptureReader::new(capture)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    fn message_class(message: &CapturedMessage) -> StunMessageClass {
+        match message.payload() {
+            CapturedPayload::Stun(message) => message.message_class,
+            payload => panic!("Unexpected payload:  {:?}", payload),
+        }
+    }
+
+    #[test]
+    fn test_pcap_udp() {
+        let client: SocketAddr = "192.0.2.1:50000".parse().unwrap();
+        let server: SocketAddr = "198.51.100.1:3478".parse().unwrap();
+        let request = binding(StunMessageClass::Request);
+        let response = binding(StunMessageClass::SuccessResponse);
+
+        let mut trailing = request.clone();
+        trailing.extend_from_slice(&[0x00; 4]);
+
+        let capture = pcap(
+            LINKTYPE_ETHERNET,
+            &[
+                (
+                    Duration::new(10, 500_000_000),
+                    ethernet(&udp(client, server, &request), false),
+                ),
+                (
+                    Duration::new(10, 600_000_000),
+                    ethernet(&udp(client, server, b"not stun"), false),
+                ),
+                (
+                    Duration::new(10, 700_000_000),
+                    ethernet(&udp(client, server, &trailing), false),
+                ),
+                (
+                    Duration::new(10, 750_000_000),
+                    ethernet(&udp(server, client, &response), true),
+                ),
+            ],
+        );
+        let messages = read(&capture);
+
+        assert_eq!(messages.len(), 2);
+        let five_tuple = FiveTuple {
+            client_address: client,
+            server_address: server,
+            protocol: TransportProtocol::Udp,
+        };
+
+        assert_eq!(messages[0].timestamp, Duration::new(10, 500_000_000));
+        assert_eq!(messages[0].five_tuple, five_tuple);
+        assert!(messages[0].from_client);
+        assert_eq!(messages[0].as_bytes(), &request[..]);
+        assert_eq!(message_class(&messages[0]), StunMessageClass::Request);
+
+        assert_eq!(messages[1].timestamp, Duration::new(10, 750_000_000));
+        assert_eq!(messages[1].five_tuple, five_tuple);
+        assert!(!messages[1].from_client);
+        assert_eq!(
+            message_class(&messages[1]),
+            StunMessageClass::SuccessResponse
+        );
+    }
+
+    #[test]
+    fn test_pcapng_ipv6_channel_data() {
+        let client: SocketAddr = "[2001:db8::1]:50000".parse().unwrap();
+        let server: SocketAddr = "[2001:db8::2]:3478".parse().unwrap();
+        let padded = serialize_channel_data(0x4001, b"hello");
+
+        // a hop-by-hop options header before the UDP header
+        let mut packet = udp(client, server, &padded[..9]);
+        packet[6] = 0;
+        packet[5] += 8;
+        packet.splice(40..40, [17, 0, 0, 0, 0, 0, 0, 0]);
+
+        let capture = pcapng(
+            LINKTYPE_RAW as u16,
+            &[
+                (Duration::new(1, 123), udp(server, client, &padded)),
+                (Duration::new(2, 456), packet),
+            ],
+        );
+        let messages = read(&capture);
+
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].timestamp, Duration::new(1, 123));
+        assert_eq!(messages[0].five_tuple.client_address, server);
+        assert_eq!(messages[0].as_bytes(), &padded[..]);
+        assert_eq!(messages[1].timestamp, Duration::new(2, 456));
+        assert!(!messages[1].from_client);
+
+        for message in &messages {
+            match message.payload() {
+                CapturedPayload::ChannelData(channel_data) => {
+                    assert_eq!(channel_data.channel_number, 0x4001);
+                    assert_eq!(channel_data.data, b"hello");
+                }
+                payload => panic!("Unexpected payload:  {:?}", payload),
+            }
+        }
+    }
+
+    #[test]
+    fn test_tcp_reassembly() {
+        let client: SocketAddr = "192.0.2.1:50000".parse().unwrap();
+        let server: SocketAddr = "198.51.100.1:3478".parse().unwrap();
+        let request = binding(StunMessageClass::Request);
+        let response = binding(StunMessageClass::SuccessResponse);
+
+        let mut stream = request.clone();
+        stream.extend_from_slice(&serialize_channel_data(0x4000, b"data"));
+        let (first, rest) = stream.split_at(10);
+        let (second, third) = rest.split_at(20);
+
+        let packets = [
+            tcp(client, server, 1000, TCP_SYN, &[]),
+            tcp(server, client, 5000, TCP_SYN | TCP_ACK, &[]),
+            tcp(client, server, 1001, TCP_ACK, first),
+            tcp(client, server, 1031, TCP_ACK, third),
+            tcp(client, server, 1011, TCP_ACK, second),
+            tcp(client, server, 1011, TCP_ACK, second),
+            tcp(server, client, 5001, TCP_ACK, &response),
+        ];
+        let packets: Vec<(Duration, Vec<u8>)> = packets
+            .iter()
+            .enumerate()
+            .map(|(index, packet)| (Duration::from_secs(index as u64), ethernet(packet, false)))
+            .collect();
+        let messages = read(&pcap(LINKTYPE_ETHERNET, &packets));
+
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[0].as_bytes(), &request[..]);
+        assert_eq!(messages[0].timestamp, Duration::from_secs(4));
+        assert!(messages[0].from_client);
+        assert_eq!(
+            messages[0].five_tuple,
+            FiveTuple {
+                client_address: client,
+                server_address: server,
+                protocol: TransportProtocol::Tcp,
+            }
+        );
+
+        assert!(matches!(
+            messages[1].payload(),
+            CapturedPayload::ChannelData(_)
+        ));
+        assert_eq!(messages[1].timestamp, Duration::from_secs(4));
+
+        assert_eq!(
+            message_class(&messages[2]),
+            StunMessageClass::SuccessResponse
+        );
+        assert!(!messages[2].from_client);
+    }
+
+    #[test]
+    fn test_tcp_fin_with_data() {
+        let client: SocketAddr = "192.0.2.1:50000".parse().unwrap();
+        let server: SocketAddr = "198.51.100.1:3478".parse().unwrap();
+        let request = binding(StunMessageClass::Request);
+        let response = binding(StunMessageClass::SuccessResponse);
+        let (first, second) = response.split_at(8);
+
+        // the end of the response arrives before its start, which comes with the FIN
+        let packets = [
+            tcp(client, server, 1000, TCP_SYN, &[]),
+            tcp(server, client, 5000, TCP_SYN | TCP_ACK, &[]),
+            tcp(client, server, 1001, TCP_ACK | TCP_FIN, &request),
+            tcp(server, client, 5009, TCP_ACK, second),
+            tcp(server, client, 5001, TCP_ACK | TCP_FIN, first),
+        ];
+        let packets: Vec<(Duration, Vec<u8>)> = packets
+            .iter()
+            .enumerate()
+            .map(|(index, packet)| (Duration::from_secs(index as u64), ethernet(packet, false)))
+            .collect();
+        let messages = read(&pcap(LINKTYPE_ETHERNET, &packets));
+
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].as_bytes(), &request[..]);
+        assert!(messages[0].from_client);
+        assert_eq!(messages[1].as_bytes(), &response[..]);
+        assert_eq!(messages[1].timestamp, Duration::from_secs(4));
+    }
+
+    #[test]
+    fn test_tcp_rfc4571_mid_stream() {
+        let client: SocketAddr = "192.0.2.1:50000".parse().unwrap();
+        let server: SocketAddr = "198.51.100.1:9".parse().unwrap();
+        let request = binding(StunMessageClass::Request);
+        let indication = binding(StunMessageClass::Indication);
+
+        let frame = |data: &[u8]| {
+            let mut framed = (data.len() as u16).to_be_bytes().to_vec();
+            framed.extend_from_slice(data);
+            framed
+        };
+
+        // the capture starts part way through a frame, and media is framed along with STUN
+        let mut stream = frame(&request);
+        stream.extend_from_slice(&frame(&[0x80; 12]));
+        stream.extend_from_slice(&frame(&indication));
+        let (first, second) = stream.split_at(30);
+
+        let packets = [
+            tcp(server, client, 7000, TCP_ACK, &[0x01; 7]),
+            tcp(server, client, 7007, TCP_ACK, first),
+            tcp(server, client, 7037, TCP_ACK, second),
+        ];
+        let packets: Vec<(Duration, Vec<u8>)> = packets
+            .iter()
+            .map(|packet| (Duration::from_secs(1), packet.clone()))
+            .collect();
+        let messages = read(&pcap(LINKTYPE_RAW, &packets));
+
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].as_bytes(), &request[..]);
+        assert_eq!(messages[1].as_bytes(), &indication[..]);
+
+        // without the SYN, the client is the side that sent the first message
+        assert_eq!(messages[0].five_tuple.client_address, server);
+        assert!(messages[1].from_client);
+    }
+
+    #[test]
+    fn test_invalid_capture() {
+        assert!(matches!(
+            CaptureReader::new(&b"not a capture"[..]),
+            Err(StunCaptureError::InvalidFileError)
+        ));
+
+        let client: SocketAddr = "192.0.2.1:50000".parse().unwrap();
+        let server: SocketAddr = "198.51.100.1:3478".parse().unwrap();
+        let request = udp(client, server, &binding(StunMessageClass::Request));
+        let capture = pcap(
+            LINKTYPE_RAW,
+            &[
+                (Duration::from_secs(1), request.clone()),
+                (Duration::from_secs(2), request),
+            ],
+        );
+
+        // the capture was cut off part way through the second packet
+        let mut reader = CaptureReader::new(&capture[..capture.len() - 1]).unwrap();
+        assert!(matches!(reader.next(), Some(Ok(_))));
+        assert!(matches!(
+            reader.next(),
+            Some(Err(StunCaptureError::TruncatedFileError))
+        ));
+        assert!(reader.next().is_none());
+    }
+}
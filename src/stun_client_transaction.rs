@@ -0,0 +1,383 @@
+use crate::parser::{parse_stun_message, parse_stun_message_view};
+use crate::stun_constants::*;
+use crate::stun_errors::StunTransactionError;
+use crate::stun_five_tuple::*;
+use crate::stun_message::*;
+use crate::stun_message_types::*;
+
+use alloc::vec::Vec;
+use core::time::Duration;
+use std::io;
+use std::time::Instant;
+
+/// Generate a transaction id for a new request from the operating system's random number
+/// generator, as the transaction id must be uniformly and randomly chosen,
+/// https://tools.ietf.org/html/rfc5389#section-6
+pub fn random_transaction_id() -> io::Result<[u8; STUN_TRANSACTION_ID_NUM_BYTES]> {
+    let mut transaction_id = [0; STUN_TRANSACTION_ID_NUM_BYTES];
+    getrandom::fill(&mut transaction_id)?;
+
+    Ok(transaction_id)
+}
+
+/// Retransmission parameters of a client transaction, https://tools.ietf.org/html/rfc5389#section-7.2
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct TransactionConfig {
+    /// initial retransmission timeout (RTO) over UDP, doubled after each retransmission
+    pub rto: Duration,
+
+    /// number of times the request is sent over UDP (Rc)
+    pub max_transmits: u32,
+
+    /// multiple of the initial RTO to wait for a response after the last transmission over UDP (Rm)
+    pub last_timeout_multiplier: u32,
+
+    /// time to wait for a response over TCP (Ti)
+    pub reliable_timeout: Duration,
+}
+
+impl Default for TransactionConfig {
+    fn default() -> Self {
+        TransactionConfig {
+            rto: Duration::from_millis(500),
+            max_transmits: 7,
+            last_timeout_multiplier: 16,
+            reliable_timeout: Duration::from_millis(39_500),
+        }
+    }
+}
+
+/// The outcome of a client transaction
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum TransactionOutcome {
+    /// a success response was received, holding the serialized response
+    Success(Vec<u8>),
+
+    /// an error response was received, holding the serialized response
+    Error(Vec<u8>),
+
+    /// no response was received before the transaction timed out
+    Timeout,
+}
+
+/// A sans-IO STUN client transaction, https://tools.ietf.org/html/rfc5389#section-7.2
+///
+/// The transaction owns a serialized request and tells the caller when to send it, while the
+/// caller does the I/O and feeds the transaction with the current time and received messages:
+///
+/// - `poll_transmit` returns the request whenever it must be sent, which is right away and then
+///   on each retransmission over UDP
+/// - `poll_timeout` returns when `handle_timeout` must next be called
+/// - `handle_input` or `handle_response` complete the transaction with a matching response
+///
+/// Over UDP the request is retransmitted with an RTO that doubles each time, until it has been
+/// sent Rc times, and the transaction times out Rm times the initial RTO after the last
+/// transmission.  Over TCP it is sent once and the transaction times out after Ti.
+#[derive(Debug)]
+pub struct ClientTransaction {
+    request: Vec<u8>,
+    transaction_id: [u8; STUN_TRANSACTION_ID_NUM_BYTES],
+    message_method: StunMessageMethod,
+    protocol: TransportProtocol,
+    config: TransactionConfig,
+
+    /// number of times the request has been sent, or is due to be sent
+    transmits: u32,
+
+    /// retransmission timeout for the next transmission
+    rto: Duration,
+
+    /// whether the request is due to be sent
+    transmit: bool,
+
+    /// time at which the request is retransmitted or the transaction times out
+    deadline: Instant,
+
+    outcome: Option<TransactionOutcome>,
+}
+
+impl ClientTransaction {
+    /// Start a transaction for the given serialized request with the default parameters.  The
+    /// request must be sent right away, see `poll_transmit`.
+    pub fn new(
+        request: Vec<u8>,
+        protocol: TransportProtocol,
+        now: Instant,
+    ) -> Result<Self, StunTransactionError> {
+        Self::with_config(request, protocol, TransactionConfig::default(), now)
+    }
+
+    /// Start a transaction for the given serialized request with the given parameters
+    pub fn with_config(
+        request: Vec<u8>,
+        protocol: TransportProtocol,
+        config: TransactionConfig,
+        now: Instant,
+    ) -> Result<Self, StunTransactionError> {
+        let (transaction_id, message_method) = match parse_stun_message_view(&request) {
+            Ok((remaining, view))
+                if remaining.is_empty() && view.message_class == StunMessageClass::Request =>
+            {
+                (*view.transaction_id, view.message_method)
+            }
+            _ => return Err(StunTransactionError::InvalidRequestError),
+        };
+
+        let timeout = match protocol {
+            TransportProtocol::Udp if config.max_transmits > 1 => config.rto,
+            TransportProtocol::Udp => config.rto * config.last_timeout_multiplier,
+            TransportProtocol::Tcp => config.reliable_timeout,
+        };
+
+        Ok(ClientTransaction {
+            request,
+            transaction_id,
+            message_method,
+            protocol,
+            config,
+            transmits: 1,
+            rto: config.rto * 2,
+            transmit: true,
+            deadline: now + timeout,
+            outcome: None,
+        })
+    }
+
+    /// The transaction id of the request
+    pub fn transaction_id(&self) -> &[u8; STUN_TRANSACTION_ID_NUM_BYTES] {
+        &self.transaction_id
+    }
+
+    /// The serialized request
+    pub fn request(&self) -> &[u8] {
+        &self.request
+    }
+
+    /// The request, if it is due to be sent.  Each transmission is returned once.
+    pub fn poll_transmit(&mut self) -> Option<&[u8]> {
+        match core::mem::take(&mut self.transmit) {
+            true => Some(&self.request),
+            false => None,
+        }
+    }
+
+    /// The time at which `handle_timeout` must be called, or `None` once the transaction is
+    /// complete
+    pub fn poll_timeout(&self) -> Option<Instant> {
+        match self.outcome {
+            None => Some(self.deadline),
+            Some(_) => None,
+        }
+    }
+
+    /// Advance the transaction to the given time, scheduling a retransmission or timing out
+    pub fn handle_timeout(&mut self, now: Instant) {
+        if self.outcome.is_some() || now < self.deadline {
+            return;
+        }
+
+        if self.protocol == TransportProtocol::Tcp || self.transmits >= self.config.max_transmits {
+            self.outcome = Some(TransactionOutcome::Timeout);
+            self.transmit = false;
+            return;
+        }
+
+        self.transmits += 1;
+        self.transmit = true;
+        self.deadline = match self.transmits < self.config.max_transmits {
+            true => now + self.rto,
+            false => now + self.config.rto * self.config.last_timeout_multiplier,
+        };
+        self.rto *= 2;
+    }
+
+    /// Handle a received message.  Returns whether it is a response to this transaction, in
+    /// which case the transaction is complete.  Anything else, including a retransmitted
+    /// response once the transaction is complete, is ignored.
+    pub fn handle_input(&mut self, input: &[u8]) -> bool {
+        match parse_stun_message(input) {
+            Ok((_, message)) => self.handle_response(&message),
+            Err(_) => false,
+        }
+    }
+
+    /// Handle a received message which has already been parsed, see `handle_input`
+    pub fn handle_response(&mut self, message: &StunMessage) -> bool {
+        if self.outcome.is_some() || !self.is_response(message) {
+            return false;
+        }
+
+        let response = message.raw.to_vec();
+        self.outcome = Some(match message.message_class {
+            StunMessageClass::SuccessResponse => TransactionOutcome::Success(response),
+            _ => TransactionOutcome::Error(response),
+        });
+        self.transmit = false;
+
+        true
+    }
+
+    /// Whether the message is a response to this transaction, i.e. a success or error response
+    /// with the method and transaction id of the request
+    pub fn is_response(&self, message: &StunMessage) -> bool {
+        matches!(
+            message.message_class,
+            StunMessageClass::SuccessResponse | StunMessageClass::ErrorResponse
+        ) && message.message_method == self.message_method
+            && *message.transaction_id == self.transaction_id
+    }
+
+    /// The outcome of the transaction, once it is complete
+    pub fn outcome(&self) -> Option<&TransactionOutcome> {
+        self.outcome.as_ref()
+    }
+
+    /// Consume the transaction, returning its outcome if it is complete
+    pub fn into_outcome(self) -> Option<TransactionOutcome> {
+        self.outcome
+    }
+
+    /// The response, if one has been received
+    pub fn response(&self) -> Option<StunMessage<'_>> {
+        match &self.outcome {
+            Some(TransactionOutcome::Success(response))
+            | Some(TransactionOutcome::Error(response)) => parse_stun_message(response)
+                .ok()
+                .map(|(_, message)| message),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stun_message_builder::*;
+
+    const TRANSACTION_ID: [u8; STUN_TRANSACTION_ID_NUM_BYTES] = [0x42; 12];
+
+    fn build(message_class: StunMessageClass, transaction_id: &[u8; 12]) -> Vec<u8> {
+        StunMessageBuilder::new(message_class, StunMessageMethod::Binding, transaction_id)
+            .build()
+            .unwrap()
+    }
+
+    /// Run the transaction without responses, returning the times the request was sent at and
+    /// the time the transaction timed out at, relative to the start
+    fn run_to_timeout(protocol: TransportProtocol) -> (Vec<Duration>, Duration) {
+        let start = Instant::now();
+        let request = build(StunMessageClass::Request, &TRANSACTION_ID);
+        let mut transaction = ClientTransaction::new(request, protocol, start).unwrap();
+
+        let mut transmits = Vec::new();
+        let mut now = start;
+        loop {
+            if transaction.poll_transmit().is_some() {
+                transmits.push(now - start);
+            }
+
+            match transaction.poll_timeout() {
+                Some(deadline) => {
+                    now = deadline;
+                    transaction.handle_timeout(now);
+                }
+                None => break,
+            }
+        }
+
+        assert_eq!(transaction.outcome(), Some(&TransactionOutcome::Timeout));
+        (transmits, now - start)
+    }
+
+    #[test]
+    fn test_udp_retransmissions() {
+        // https://tools.ietf.org/html/rfc5389#section-7.2.1
+        let (transmits, timeout) = run_to_timeout(TransportProtocol::Udp);
+        let expected: Vec<Duration> = [0, 500, 1500, 3500, 7500, 15500, 31500]
+            .iter()
+            .map(|ms| Duration::from_millis(*ms))
+            .collect();
+
+        assert_eq!(transmits, expected);
+        assert_eq!(timeout, Duration::from_millis(39_500));
+    }
+
+    #[test]
+    fn test_tcp_single_transmission() {
+        let (transmits, timeout) = run_to_timeout(TransportProtocol::Tcp);
+
+        assert_eq!(transmits, [Duration::from_millis(0)]);
+        assert_eq!(timeout, Duration::from_millis(39_500));
+    }
+
+    #[test]
+    fn test_response() {
+        let start = Instant::now();
+        let request = build(StunMessageClass::Request, &TRANSACTION_ID);
+        let mut transaction =
+            ClientTransaction::new(request.clone(), TransportProtocol::Udp, start).unwrap();
+        assert_eq!(transaction.poll_transmit(), Some(&request[..]));
+        assert_eq!(transaction.poll_transmit(), None);
+
+        // an early timeout does nothing
+        transaction.handle_timeout(start + Duration::from_millis(499));
+        assert_eq!(transaction.poll_transmit(), None);
+        transaction.handle_timeout(start + Duration::from_millis(500));
+        assert_eq!(transaction.poll_transmit(), Some(&request[..]));
+
+        // messages which are not the response are ignored
+        assert!(!transaction.handle_input(b"not stun"));
+        assert!(!transaction.handle_input(&build(StunMessageClass::Indication, &TRANSACTION_ID)));
+        assert!(!transaction.handle_input(&build(StunMessageClass::SuccessResponse, &[0x43; 12])));
+        assert_eq!(transaction.outcome(), None);
+
+        let response = build(StunMessageClass::SuccessResponse, &TRANSACTION_ID);
+        assert!(transaction.handle_input(&response));
+        assert_eq!(
+            transaction.outcome(),
+            Some(&TransactionOutcome::Success(response.clone()))
+        );
+        assert_eq!(transaction.response().unwrap().raw, &response[..]);
+        assert_eq!(transaction.poll_timeout(), None);
+
+        // a retransmitted response is ignored
+        assert!(!transaction.handle_input(&response));
+        transaction.handle_timeout(start + Duration::from_secs(60));
+        assert_eq!(transaction.poll_transmit(), None);
+        assert_eq!(
+            transaction.outcome(),
+            Some(&TransactionOutcome::Success(response))
+        );
+    }
+
+    #[test]
+    fn test_error_response() {
+        let start = Instant::now();
+        let request = build(StunMessageClass::Request, &TRANSACTION_ID);
+        let mut transaction =
+            ClientTransaction::new(request, TransportProtocol::Tcp, start).unwrap();
+
+        let response = build(StunMessageClass::ErrorResponse, &TRANSACTION_ID);
+        assert!(transaction.handle_input(&response));
+        assert_eq!(
+            transaction.outcome(),
+            Some(&TransactionOutcome::Error(response))
+        );
+    }
+
+    #[test]
+    fn test_invalid_request() {
+        let start = Instant::now();
+        let indication = build(StunMessageClass::Indication, &TRANSACTION_ID);
+
+        assert_eq!(
+            ClientTransaction::new(indication, TransportProtocol::Udp, start).unwrap_err(),
+            StunTransactionError::InvalidRequestError
+        );
+        assert_eq!(
+            ClientTransaction::new(b"not stun".to_vec(), TransportProtocol::Udp, start)
+                .unwrap_err(),
+            StunTransactionError::InvalidRequestError
+        );
+    }
+}
@@ -0,0 +1,240 @@
+use crate::parser::parse_stun_message;
+use crate::stun_constants::*;
+use crate::stun_errors::StunCodecError;
+use crate::stun_message_owned::*;
+use crate::turn_channel_data::*;
+
+use alloc::vec::Vec;
+use bytes::BytesMut;
+use tokio_util::codec::{Decoder, Encoder};
+
+/// A message read from or written to a stream with `StunCodec`
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum StunFrame {
+    /// a STUN message
+    Message(OwnedStunMessage),
+
+    /// a ChannelData message, https://tools.ietf.org/html/rfc5766#section-11.4
+    ChannelData {
+        /// the channel the data is sent on, in the range 0x4000-0x7FFF
+        channel_number: u16,
+
+        /// the application data, without padding
+        data: Vec<u8>,
+    },
+}
+
+/// A `tokio_util` codec framing STUN messages on a TCP or TLS stream, where they follow each
+/// other without any framing, https://tools.ietf.org/html/rfc5389#section-7.2.2
+///
+/// The stream can also carry the ChannelData messages of TURN, which are padded to a multiple
+/// of 4 bytes, https://tools.ietf.org/html/rfc5766#section-11.5.  Once the decoder returns an
+/// error the stream can no longer be framed and should be closed.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StunCodec;
+
+impl StunCodec {
+    /// Create a codec
+    pub fn new() -> Self {
+        StunCodec
+    }
+}
+
+impl Decoder for StunCodec {
+    type Item = StunFrame;
+    type Error = StunCodecError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<StunFrame>, StunCodecError> {
+        let length = match stream_message_length(src) {
+            Ok(Some(length)) => length,
+            Ok(None) => return Ok(None),
+            Err(_) => return Err(StunCodecError::InvalidMessageError),
+        };
+        if src.len() < length {
+            src.reserve(length - src.len());
+            return Ok(None);
+        }
+
+        let frame = src.split_to(length);
+        let is_stun_message =
+            u16::from_be_bytes([frame[0], frame[1]]) & STUN_MESSAGE_TYPE_ZERO_MASK == 0;
+
+        if is_stun_message {
+            // the attributes must fill the frame, otherwise they disagree with the message length
+            match parse_stun_message(&frame) {
+                Ok(([], message)) => Ok(Some(StunFrame::Message(OwnedStunMessage::from(&message)))),
+                _ => Err(StunCodecError::InvalidMessageError),
+            }
+        } else {
+            match parse_channel_data(&frame) {
+                Ok((_, message)) => Ok(Some(StunFrame::ChannelData {
+                    channel_number: message.channel_number,
+                    data: message.data.to_vec(),
+                })),
+                Err(_) => Err(StunCodecError::InvalidMessageError),
+            }
+        }
+    }
+}
+
+impl Encoder<StunFrame> for StunCodec {
+    type Error = StunCodecError;
+
+    fn encode(&mut self, item: StunFrame, dst: &mut BytesMut) -> Result<(), StunCodecError> {
+        let output = match item {
+            StunFrame::Message(message) => message
+                .to_bytes()
+                .map_err(|_| StunCodecError::InvalidMessageError)?,
+            StunFrame::ChannelData {
+                channel_number,
+                data,
+            } => {
+                if !(TURN_CHANNEL_NUMBER_MIN..=TURN_CHANNEL_NUMBER_MAX).contains(&channel_number)
+                    || data.len() > u16::MAX as usize
+                {
+                    return Err(StunCodecError::InvalidMessageError);
+                }
+                serialize_channel_data(channel_number, &data)
+            }
+        };
+
+        dst.extend_from_slice(&output);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stun_message_builder::*;
+    use crate::stun_message_types::*;
+
+    use futures_util::{SinkExt, StreamExt};
+    use tokio_util::codec::{FramedRead, FramedWrite};
+
+    fn message(transaction_id: u8) -> OwnedStunMessage {
+        let data = StunMessageBuilder::new(
+            StunMessageClass::Request,
+            StunMessageMethod::Binding,
+            &[transaction_id; 12],
+        )
+        .add_attribute(0x8022, b"codec")
+        .add_fingerprint()
+        .build()
+        .unwrap();
+        let (_, message) = parse_stun_message(&data).unwrap();
+
+        OwnedStunMessage::from(&message)
+    }
+
+    #[test]
+    fn test_decode_partial_frames() {
+        let mut stream = message(1).to_bytes().unwrap();
+        stream.extend(serialize_channel_data(0x4000, b"hello"));
+        stream.extend(message(2).to_bytes().unwrap());
+
+        // the frames are returned once complete, however the stream is split
+        let mut codec = StunCodec::new();
+        let mut buffer = BytesMut::new();
+        let mut frames = Vec::new();
+        for chunk in stream.chunks(3) {
+            buffer.extend_from_slice(chunk);
+            while let Some(frame) = codec.decode(&mut buffer).unwrap() {
+                frames.push(frame);
+            }
+        }
+
+        assert!(buffer.is_empty());
+        assert_eq!(
+            frames,
+            [
+                StunFrame::Message(message(1)),
+                StunFrame::ChannelData {
+                    channel_number: 0x4000,
+                    data: b"hello".to_vec(),
+                },
+                StunFrame::Message(message(2)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_decode_invalid_stream() {
+        let mut codec = StunCodec::new();
+
+        // the first two bits are neither those of a STUN nor a ChannelData message
+        let mut buffer = BytesMut::from(&[0x80, 0x01, 0x00, 0x00][..]);
+        assert!(matches!(
+            codec.decode(&mut buffer),
+            Err(StunCodecError::InvalidMessageError)
+        ));
+
+        // a STUN header with a bad magic cookie
+        let mut data = message(1).to_bytes().unwrap();
+        data[4] = 0;
+        let mut buffer = BytesMut::from(&data[..]);
+        assert!(matches!(
+            codec.decode(&mut buffer),
+            Err(StunCodecError::InvalidMessageError)
+        ));
+    }
+
+    #[test]
+    fn test_decode_attribute_overrun() {
+        let mut codec = StunCodec::new();
+
+        // the SOFTWARE attribute claims more bytes than the message length covers
+        let mut data = message(1).to_bytes().unwrap();
+        data[22..24].copy_from_slice(&[0x00, 0x40]);
+        data.extend(message(2).to_bytes().unwrap());
+        let mut buffer = BytesMut::from(&data[..]);
+        assert!(matches!(
+            codec.decode(&mut buffer),
+            Err(StunCodecError::InvalidMessageError)
+        ));
+    }
+
+    #[test]
+    fn test_encode_invalid_channel_data() {
+        let mut codec = StunCodec::new();
+        let mut buffer = BytesMut::new();
+        let frame = StunFrame::ChannelData {
+            channel_number: 0x8000,
+            data: Vec::new(),
+        };
+
+        assert!(matches!(
+            codec.encode(frame, &mut buffer),
+            Err(StunCodecError::InvalidMessageError)
+        ));
+        assert!(buffer.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_framed_stream() {
+        let (client, server) = tokio::io::duplex(64);
+        let mut writer = FramedWrite::new(client, StunCodec::new());
+        let mut reader = FramedRead::new(server, StunCodec::new());
+
+        let frames = [
+            StunFrame::Message(message(1)),
+            StunFrame::ChannelData {
+                channel_number: 0x7FFF,
+                data: vec![0x42; 1000],
+            },
+        ];
+
+        let sent = frames.clone();
+        let writing = tokio::spawn(async move {
+            for frame in sent {
+                writer.send(frame).await.unwrap();
+            }
+        });
+
+        for frame in frames {
+            assert_eq!(reader.next().await.unwrap().unwrap(), frame);
+        }
+        writing.await.unwrap();
+        assert!(reader.next().await.is_none());
+    }
+}
@@ -33,3 +33,55 @@ pub const STUN_TRANSACTION_ID_NUM_BYTES: usize = 12;
 
 /// Number of bytes in the fixed STUN header after the length field
 pub const STUN_FIXED_HEADER_AFTER_LENGTH_NUM_BYTES: usize = STUN_TRANSACTION_ID_NUM_BYTES + 4;
+
+/// Number of bytes in the fixed STUN header
+pub const STUN_HEADER_NUM_BYTES: usize = STUN_FIXED_HEADER_AFTER_LENGTH_NUM_BYTES + 4;
+
+/// Zero bytes used to pad attribute values to a 4 byte boundary
+pub const STUN_ATTRIBUTE_PADDING: [u8; 3] = [0; 3];
+
+/// Number of bytes in the value of a MESSAGE-INTEGRITY attribute (HMAC-SHA1)
+pub const STUN_MESSAGE_INTEGRITY_NUM_BYTES: usize = 20;
+
+/// Number of bytes in the value of a MESSAGE-INTEGRITY-SHA256 attribute (HMAC-SHA256), which
+/// can be truncated to as few as 16 bytes, https://tools.ietf.org/html/rfc8489#section-14.6
+pub const STUN_MESSAGE_INTEGRITY_SHA256_NUM_BYTES: usize = 32;
+pub const STUN_MESSAGE_INTEGRITY_SHA256_MIN_NUM_BYTES: usize = 16;
+
+/// Number of bytes in the value of a FINGERPRINT attribute (CRC-32)
+pub const STUN_FINGERPRINT_NUM_BYTES: usize = 4;
+
+/// Value XOR'd with the CRC-32 of a message to compute its FINGERPRINT
+pub const STUN_FINGERPRINT_XOR: u32 = 0x5354_554E;
+
+/// Number of bytes in an attribute header (type and length)
+pub const STUN_ATTRIBUTE_HEADER_NUM_BYTES: usize = 4;
+
+/// Attribute types below this value are comprehension-required, the ones above it are
+/// comprehension-optional, https://tools.ietf.org/html/rfc5389#section-15
+pub const STUN_ATTRIBUTE_COMPREHENSION_OPTIONAL_MIN: u16 = 0x8000;
+
+/// Number of bytes in a ChannelData message header (channel number and length)
+pub const TURN_CHANNEL_DATA_HEADER_NUM_BYTES: usize = 4;
+
+/// Range of channel numbers a client can bind, https://tools.ietf.org/html/rfc5766#section-11
+pub const TURN_CHANNEL_NUMBER_MIN: u16 = 0x4000;
+pub const TURN_CHANNEL_NUMBER_MAX: u16 = 0x7FFF;
+
+/// Lifetime of an allocation when the client does not request one, in seconds,
+/// https://tools.ietf.org/html/rfc5766#section-2.2
+pub const TURN_DEFAULT_ALLOCATION_LIFETIME_SECS: u32 = 600;
+
+/// Longest lifetime a server grants to an allocation by default, in seconds, as recommended by
+/// https://tools.ietf.org/html/rfc5766#section-6.2
+pub const TURN_MAX_ALLOCATION_LIFETIME_SECS: u32 = 3600;
+
+/// Lifetime of a permission, in seconds, https://tools.ietf.org/html/rfc5766#section-8
+pub const TURN_PERMISSION_LIFETIME_SECS: u32 = 300;
+
+/// Lifetime of a channel binding, in seconds, https://tools.ietf.org/html/rfc5766#section-11
+pub const TURN_CHANNEL_LIFETIME_SECS: u32 = 600;
+
+/// Protocol number of UDP in a REQUESTED-TRANSPORT attribute,
+/// https://tools.ietf.org/html/rfc5766#section-14.7
+pub const TURN_TRANSPORT_UDP: u8 = 17;
@@ -0,0 +1,297 @@
+use crate::stun_errors::StunCredentialError;
+use crate::stun_text_attributes::*;
+
+use alloc::string::String;
+#[cfg(feature = "std")]
+use alloc::vec::Vec;
+
+use md5::Md5;
+use sha2::digest::Output;
+use sha2::{Digest, Sha256};
+use unicode_normalization::UnicodeNormalization;
+
+/// Number of bytes in a long-term credential key (MD5)
+pub const STUN_LONG_TERM_KEY_NUM_BYTES: usize = 16;
+/// Number of bytes in a long-term credential key derived with SHA-256
+pub const STUN_LONG_TERM_KEY_SHA256_NUM_BYTES: usize = 32;
+
+/// Prepare a string with the OpaqueString profile, https://tools.ietf.org/html/rfc8265#section-4.2
+///
+/// Non-ASCII space characters are mapped to ASCII space and the result is normalized to NFC.  The
+/// prepared string must not be empty and must not contain control characters or noncharacters.
+///
+/// # Arguments
+///
+/// * `input` - The string to prepare, e.g. a username or password
+///
+/// # Return
+///
+/// A Result object, when successful contains the prepared string.
+pub fn opaque_string(input: &str) -> Result<String, StunCredentialError> {
+    let prepared: String = input
+        .chars()
+        .map(|c| if is_non_ascii_space(c) { ' ' } else { c })
+        .nfc()
+        .collect();
+
+    if prepared.is_empty() {
+        return Err(StunCredentialError::EmptyStringError);
+    }
+
+    match prepared
+        .chars()
+        .find(|&c| c.is_control() || is_noncharacter(c))
+    {
+        Some(c) => Err(StunCredentialError::ProhibitedCharacterError(c)),
+        None => Ok(prepared),
+    }
+}
+
+/// Prepare a string with the SASLprep profile of stringprep, https://tools.ietf.org/html/rfc4013
+///
+/// SASLprep is the preparation required by RFC 5389, it has since been replaced by
+/// `opaque_string`.  Unlike OpaqueString it removes "commonly mapped to nothing" characters and
+/// normalizes to NFKC.
+#[cfg(feature = "std")]
+pub fn saslprep(input: &str) -> Result<String, StunCredentialError> {
+    let prepared = stringprep::saslprep(input).map_err(|_| StunCredentialError::SaslprepError)?;
+
+    if prepared.is_empty() {
+        return Err(StunCredentialError::EmptyStringError);
+    }
+
+    Ok(prepared.into_owned())
+}
+
+/// Derive the key for the long-term credential mechanism, https://tools.ietf.org/html/rfc5389#section-15.4
+///
+/// key = MD5(username ":" realm ":" SASLprep(password))
+///
+/// The username and realm are used exactly as they appear in the USERNAME and REALM attributes,
+/// the password is prepared with `saslprep` before hashing.  RFC 8489 prepares the realm and the
+/// password with OpaqueString instead, see `long_term_credential_key_rfc8489`.
+#[cfg(feature = "std")]
+pub fn long_term_credential_key(
+    username: &StunUsername,
+    realm: &StunRealm,
+    password: &str,
+) -> Result<[u8; STUN_LONG_TERM_KEY_NUM_BYTES], StunCredentialError> {
+    let password = saslprep(password)?;
+
+    Ok(hash_credentials::<Md5>(username, realm.as_str(), &password).into())
+}
+
+/// Derive the key for the long-term credential mechanism with the MD5 password algorithm,
+/// https://tools.ietf.org/html/rfc8489#section-9.2.2
+///
+/// key = MD5(username ":" OpaqueString(realm) ":" OpaqueString(password))
+///
+/// This is the key of a server which lists MD5 in PASSWORD-ALGORITHMS.  For an ASCII realm and
+/// password it is the same as `long_term_credential_key`.
+pub fn long_term_credential_key_rfc8489(
+    username: &StunUsername,
+    realm: &StunRealm,
+    password: &str,
+) -> Result<[u8; STUN_LONG_TERM_KEY_NUM_BYTES], StunCredentialError> {
+    let realm = opaque_string(realm.as_str())?;
+    let password = opaque_string(password)?;
+
+    Ok(hash_credentials::<Md5>(username, &realm, &password).into())
+}
+
+/// Derive the key for the long-term credential mechanism with the SHA-256 password algorithm,
+/// https://tools.ietf.org/html/rfc8489#section-9.2.2
+///
+/// key = SHA-256(username ":" OpaqueString(realm) ":" OpaqueString(password))
+pub fn long_term_credential_key_sha256(
+    username: &StunUsername,
+    realm: &StunRealm,
+    password: &str,
+) -> Result<[u8; STUN_LONG_TERM_KEY_SHA256_NUM_BYTES], StunCredentialError> {
+    let realm = opaque_string(realm.as_str())?;
+    let password = opaque_string(password)?;
+
+    Ok(hash_credentials::<Sha256>(username, &realm, &password).into())
+}
+
+/// Derive the key for the short-term credential mechanism, https://tools.ietf.org/html/rfc5389#section-15.4
+///
+/// key = SASLprep(password)
+#[cfg(feature = "std")]
+pub fn short_term_credential_key(password: &str) -> Result<Vec<u8>, StunCredentialError> {
+    saslprep(password).map(String::into_bytes)
+}
+
+/// Hash `username ":" realm ":" password` with the given digest, the realm and password having
+/// been prepared already
+fn hash_credentials<D: Digest>(username: &StunUsername, realm: &str, password: &str) -> Output<D> {
+    let mut hasher = D::new();
+    hasher.update(username.as_bytes());
+    hasher.update(b":");
+    hasher.update(realm.as_bytes());
+    hasher.update(b":");
+    hasher.update(password.as_bytes());
+
+    hasher.finalize()
+}
+
+fn is_non_ascii_space(c: char) -> bool {
+    // Unicode general category Zs, other than U+0020 itself
+    matches!(
+        c,
+        '\u{00A0}' | '\u{1680}' | '\u{2000}'..='\u{200A}' | '\u{202F}' | '\u{205F}' | '\u{3000}'
+    )
+}
+
+fn is_noncharacter(c: char) -> bool {
+    let c = c as u32;
+    (0xFDD0..=0xFDEF).contains(&c) || (c & 0xFFFE) == 0xFFFE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_opaque_string_maps_spaces() {
+        assert_eq!(
+            opaque_string("a\u{00A0}b\u{3000}c"),
+            Ok("a b c".to_string())
+        );
+    }
+
+    #[test]
+    fn test_opaque_string_normalizes_nfc() {
+        // 'e' followed by a combining acute accent composes into a single character
+        assert_eq!(
+            opaque_string("caf\u{0065}\u{0301}"),
+            Ok("caf\u{00E9}".to_string())
+        );
+
+        // compatibility characters are preserved, unlike SASLprep
+        assert_eq!(opaque_string("\u{2168}"), Ok("\u{2168}".to_string()));
+    }
+
+    #[test]
+    fn test_opaque_string_invalid() {
+        assert_eq!(
+            opaque_string(""),
+            Err(StunCredentialError::EmptyStringError)
+        );
+        assert_eq!(
+            opaque_string("pass\u{0007}word"),
+            Err(StunCredentialError::ProhibitedCharacterError('\u{0007}'))
+        );
+        assert_eq!(
+            opaque_string("\u{FFFF}"),
+            Err(StunCredentialError::ProhibitedCharacterError('\u{FFFF}'))
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_saslprep() {
+        // password from https://tools.ietf.org/html/rfc5769#section-2.4
+        assert_eq!(
+            saslprep("The\u{00AD}M\u{00AA}tr\u{2168}"),
+            Ok("TheMatrIX".to_string())
+        );
+        assert_eq!(
+            saslprep("\u{00AD}"),
+            Err(StunCredentialError::EmptyStringError)
+        );
+        assert_eq!(
+            saslprep("a\u{0007}"),
+            Err(StunCredentialError::SaslprepError)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_long_term_credential_key() {
+        let username = StunUsername::new("user").unwrap();
+        let realm = StunRealm::new("realm").unwrap();
+        let key = long_term_credential_key(&username, &realm, "pass").unwrap();
+
+        // MD5("user:realm:pass")
+        assert_eq!(
+            key,
+            [
+                0x84, 0x93, 0xFB, 0xC5, 0x3B, 0xA5, 0x82, 0xFB, 0x4C, 0x04, 0x4C, 0x45, 0x6B, 0xDC,
+                0x40, 0xEB
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_long_term_credential_key_prepares_password() {
+        let username = StunUsername::new("user").unwrap();
+        let realm = StunRealm::new("realm").unwrap();
+
+        assert_eq!(
+            long_term_credential_key(&username, &realm, "The\u{00AD}M\u{00AA}tr\u{2168}"),
+            long_term_credential_key(&username, &realm, "TheMatrIX")
+        );
+    }
+
+    #[test]
+    fn test_long_term_credential_key_rfc8489() {
+        let username = StunUsername::new("user").unwrap();
+        let realm = StunRealm::new("realm").unwrap();
+        let key = long_term_credential_key_rfc8489(&username, &realm, "pass").unwrap();
+
+        // MD5("user:realm:pass"), as without preparation
+        assert_eq!(
+            key,
+            [
+                0x84, 0x93, 0xFB, 0xC5, 0x3B, 0xA5, 0x82, 0xFB, 0x4C, 0x04, 0x4C, 0x45, 0x6B, 0xDC,
+                0x40, 0xEB
+            ]
+        );
+    }
+
+    #[test]
+    fn test_long_term_credential_key_rfc8489_prepares_realm_and_password() {
+        let username = StunUsername::new("user").unwrap();
+        let realm = StunRealm::new("caf\u{0065}\u{0301}").unwrap();
+        let prepared_realm = StunRealm::new("caf\u{00E9}").unwrap();
+
+        assert_eq!(
+            long_term_credential_key_rfc8489(&username, &realm, "a\u{00A0}b"),
+            long_term_credential_key_rfc8489(&username, &prepared_realm, "a b")
+        );
+        assert_eq!(
+            long_term_credential_key_rfc8489(&username, &realm, "a\u{0007}"),
+            Err(StunCredentialError::ProhibitedCharacterError('\u{0007}'))
+        );
+    }
+
+    #[test]
+    fn test_long_term_credential_key_sha256() {
+        let username = StunUsername::new("user").unwrap();
+        let realm = StunRealm::new("realm").unwrap();
+        let key = long_term_credential_key_sha256(&username, &realm, "pass").unwrap();
+
+        // SHA-256("user:realm:pass")
+        assert_eq!(
+            key,
+            [
+                0x07, 0xE9, 0x34, 0x11, 0x7A, 0xBD, 0x40, 0x83, 0x6E, 0x7C, 0x63, 0x29, 0xB5, 0x47,
+                0x31, 0xB2, 0xB2, 0xD2, 0xA5, 0xF9, 0xA7, 0x1F, 0x54, 0x49, 0x22, 0xD7, 0x5E, 0x07,
+                0x30, 0xD8, 0x25, 0x1B
+            ]
+        );
+
+        assert_eq!(
+            long_term_credential_key_sha256(&username, &realm, ""),
+            Err(StunCredentialError::EmptyStringError)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_short_term_credential_key() {
+        assert_eq!(short_term_credential_key("pass"), Ok(b"pass".to_vec()));
+    }
+}
@@ -0,0 +1,123 @@
+use crate::stun_errors::StunParseError;
+
+use alloc::vec::Vec;
+
+use nom::combinator::rest;
+use nom::number::complete::{be_u16, be_u8};
+use nom::sequence::tuple;
+use nom::Err::Error;
+use nom::IResult;
+
+/// Error code values, https://tools.ietf.org/html/rfc5389#section-15.6
+pub const STUN_ERROR_TRY_ALTERNATE: u16 = 300;
+pub const STUN_ERROR_BAD_REQUEST: u16 = 400;
+pub const STUN_ERROR_UNAUTHORIZED: u16 = 401;
+pub const STUN_ERROR_UNKNOWN_ATTRIBUTE: u16 = 420;
+pub const STUN_ERROR_STALE_NONCE: u16 = 438;
+pub const STUN_ERROR_SERVER_ERROR: u16 = 500;
+
+/// TURN error code values, https://tools.ietf.org/html/rfc5766#section-15
+pub const STUN_ERROR_FORBIDDEN: u16 = 403;
+pub const STUN_ERROR_ALLOCATION_MISMATCH: u16 = 437;
+pub const STUN_ERROR_WRONG_CREDENTIALS: u16 = 441;
+pub const STUN_ERROR_UNSUPPORTED_TRANSPORT_PROTOCOL: u16 = 442;
+pub const STUN_ERROR_ALLOCATION_QUOTA_REACHED: u16 = 486;
+pub const STUN_ERROR_INSUFFICIENT_CAPACITY: u16 = 508;
+
+/// TURN mobility error code value, https://tools.ietf.org/html/rfc8016#section-3.4
+pub const STUN_ERROR_MOBILITY_FORBIDDEN: u16 = 405;
+
+/// The value of an ERROR-CODE attribute, https://tools.ietf.org/html/rfc5389#section-15.6
+///
+/// 0                   1                   2                   3
+/// 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |           Reserved, should be 0         |Class|     Number    |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |      Reason Phrase (variable)                                ..
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct StunErrorCode<'a> {
+    /// error code, class * 100 + number -- between 300 and 699
+    pub code: u16,
+
+    /// UTF-8 encoded reason phrase
+    pub reason: &'a str,
+}
+
+/// Parse the value of an ERROR-CODE attribute
+pub fn parse_error_code(input: &[u8]) -> IResult<&[u8], StunErrorCode<'_>, StunParseError<&[u8]>> {
+    let (input, (_, class, number)) = tuple((be_u16, be_u8, be_u8))(input)?;
+
+    let code = (class & 0x07) as u16 * 100 + number as u16;
+    if !(300..700).contains(&code) || number > 99 {
+        return Err(Error(StunParseError::InvalidErrorCodeError(code)));
+    }
+
+    let (input, reason) = rest(input)?;
+    let reason = core::str::from_utf8(reason)
+        .map_err(|_| Error(StunParseError::InvalidUtf8Error(reason)))?;
+
+    Ok((input, StunErrorCode { code, reason }))
+}
+
+/// Serialize the given error code and reason phrase into the value of an ERROR-CODE attribute
+pub fn serialize_error_code(code: u16, reason: &str) -> Vec<u8> {
+    let mut output = Vec::with_capacity(4 + reason.len());
+
+    output.extend_from_slice(&[0, 0, (code / 100) as u8, (code % 100) as u8]);
+    output.extend_from_slice(reason.as_bytes());
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_error_code_valid() {
+        let input = [0x00, 0x00, 0x04, 0x14, b'N', b'o', b'p', b'e'];
+        let result = parse_error_code(&input);
+
+        assert!(result.is_ok());
+
+        let data = result.unwrap();
+        assert_eq!(data.0.len(), 0);
+        assert_eq!(data.1.code, 420);
+        assert_eq!(data.1.reason, "Nope");
+    }
+
+    #[test]
+    fn parse_error_code_invalid_class() {
+        let input = [0x00, 0x00, 0x07, 0x00];
+        let result = parse_error_code(&input);
+
+        assert!(result.is_err());
+
+        let err = result.unwrap_err();
+        match err {
+            Error(e) => assert_eq!(e, StunParseError::InvalidErrorCodeError(700)),
+            _ => panic!("Unexpected error:  {:?}", err),
+        }
+    }
+
+    #[test]
+    fn parse_error_code_invalid_reason() {
+        let input = [0x00, 0x00, 0x04, 0x00, 0xFF];
+        let result = parse_error_code(&input);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn serialize_error_code_roundtrip() {
+        let value = serialize_error_code(STUN_ERROR_STALE_NONCE, "Stale Nonce");
+
+        assert_eq!(value[0..4], [0x00, 0x00, 0x04, 0x26]);
+
+        let result = parse_error_code(&value).unwrap();
+        assert_eq!(result.1.code, STUN_ERROR_STALE_NONCE);
+        assert_eq!(result.1.reason, "Stale Nonce");
+    }
+}
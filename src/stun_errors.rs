@@ -1,3 +1,5 @@
+use crate::stun_message_types::*;
+
 use nom::error::ErrorKind;
 use nom::error::ParseError;
 
@@ -22,6 +24,27 @@ pub enum StunParseError<I> {
     /// The magic cookie field does not contain the correct value
     InvalidMagicCookieError(u32),
 
+    /// The address family in an address attribute is neither IPv4 nor IPv6
+    InvalidAddressFamilyError(u8),
+
+    /// The error code in an ERROR-CODE attribute is outside the range 300-699
+    InvalidErrorCodeError(u16),
+
+    /// A text attribute value is not valid UTF-8
+    InvalidUtf8Error(I),
+
+    /// The value of the attribute of the given type exceeds the maximum length for that attribute
+    AttributeValueTooLongError(u16),
+
+    /// The message does not contain an attribute of the given type, which is required
+    MissingAttributeError(u16),
+
+    /// The message class and method are not the ones expected by the parser
+    UnexpectedMessageTypeError(StunMessageClass, StunMessageMethod),
+
+    /// The channel number of a ChannelData message is outside the range 0x4000-0x7FFF
+    InvalidChannelNumberError(u16),
+
     Nom(I, ErrorKind),
 }
 
@@ -34,3 +57,338 @@ impl<I> ParseError<I> for StunParseError<I> {
         other
     }
 }
+
+impl StunParseError<&[u8]> {
+    /// The offset in the given input at which the error was found, where the input is the buffer
+    /// passed to `parse_stun_message`, `parse_stun_message_view` or `parse_channel_data`.  Errors
+    /// in the header are at the offset of the offending field, other errors are at the position
+    /// the parser had reached if they carry it, otherwise there is no offset.
+    pub fn offset(&self, input: &[u8]) -> Option<usize> {
+        match self {
+            StunParseError::InvalidMessageFirstTwoBitsError(_)
+            | StunParseError::InvalidMessageClassError(_)
+            | StunParseError::InvalidMessageMethodError(_)
+            | StunParseError::InvalidChannelNumberError(_) => Some(0),
+            StunParseError::InvalidMessageLengthTooLargeError(_)
+            | StunParseError::InvalidMessageLengthNotAlignedError(_) => Some(2),
+            StunParseError::InvalidMagicCookieError(_) => Some(4),
+            StunParseError::InvalidUtf8Error(remaining) | StunParseError::Nom(remaining, _) => {
+                let start = input.as_ptr() as usize;
+                let position = remaining.as_ptr() as usize;
+                match position.checked_sub(start) {
+                    Some(offset) if offset <= input.len() => Some(offset),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Stun related authentication errors
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum StunAuthError {
+    /// The message does not contain an attribute of the given type, which is required for authentication
+    MissingAttributeError(u16),
+
+    /// The value of the attribute of the given type is malformed
+    InvalidAttributeError(u16),
+
+    /// The MESSAGE-INTEGRITY attribute does not match the message contents
+    IntegrityCheckFailedError,
+
+    /// The FINGERPRINT attribute does not match the message contents
+    FingerprintCheckFailedError,
+
+    /// The key has an invalid length for the algorithm it is used with
+    InvalidKeyLengthError(usize),
+
+    /// The key id in the message does not match any known key
+    UnknownKeyIdError,
+
+    /// The ACCESS-TOKEN attribute could not be decrypted or failed its authentication check
+    AccessTokenDecryptionError,
+
+    /// The ACCESS-TOKEN could not be encrypted
+    AccessTokenEncryptionError,
+
+    /// The decrypted ACCESS-TOKEN is malformed
+    InvalidAccessTokenError,
+
+    /// The ACCESS-TOKEN is not valid at the current time
+    AccessTokenExpiredError,
+}
+
+/// TURN mobility ticket errors
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum StunMobilityError {
+    /// The message is not a Refresh request
+    UnexpectedMessageTypeError(StunMessageClass, StunMessageMethod),
+
+    /// The message does not contain a non-empty MOBILITY-TICKET attribute
+    MissingTicketError,
+
+    /// The ticket could not be decrypted or failed its authentication check
+    TicketDecryptionError,
+
+    /// The decrypted ticket is malformed
+    InvalidTicketError,
+
+    /// The ticket is past its expiry time
+    TicketExpiredError,
+
+    /// The request was authenticated with a different username than the allocation
+    UsernameMismatchError,
+
+    /// The request failed authentication
+    AuthError(StunAuthError),
+}
+
+impl From<StunAuthError> for StunMobilityError {
+    fn from(error: StunAuthError) -> Self {
+        StunMobilityError::AuthError(error)
+    }
+}
+
+/// Errors preparing credentials for use in key derivation
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum StunCredentialError {
+    /// The string is empty after preparation
+    EmptyStringError,
+
+    /// The string contains a character that is not allowed by the preparation profile
+    ProhibitedCharacterError(char),
+
+    /// The string could not be processed with SASLprep
+    SaslprepError,
+}
+
+/// Errors editing a serialized message in place
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum StunEditError {
+    /// The buffer does not start with a valid STUN message
+    InvalidMessageError,
+
+    /// The message does not contain an attribute of the given type
+    MissingAttributeError(u16),
+
+    /// The new value for the attribute of the given type differs in length from the current value
+    AttributeLengthMismatchError(u16),
+
+    /// The buffer has no room to append an attribute of the given type, or the message would
+    /// become too long for its length field
+    BufferTooSmallError(u16),
+}
+
+/// Errors reading a packet capture
+#[cfg(feature = "pcap")]
+#[derive(Debug)]
+pub enum StunCaptureError {
+    /// Reading the capture failed
+    IoError(std::io::Error),
+
+    /// The capture is neither a pcap nor a pcapng file, or it is malformed
+    InvalidFileError,
+
+    /// The capture ends part way through a packet
+    TruncatedFileError,
+}
+
+/// Errors running a request with the clients in `client`
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub enum StunClientError {
+    /// Sending or receiving the request failed
+    IoError(std::io::Error),
+
+    /// The request could not be built, e.g. the SOFTWARE value is too long
+    InvalidRequestError,
+
+    /// No response was received within the timeout
+    TimeoutError,
+
+    /// The server answered with an error response with the given error code
+    RequestFailedError(u16),
+
+    /// The response is malformed, e.g. a success response without a mapped address or with a
+    /// FINGERPRINT that does not match
+    InvalidResponseError,
+}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for StunClientError {
+    fn from(error: std::io::Error) -> Self {
+        StunClientError::IoError(error)
+    }
+}
+
+/// Errors discovering the behavior of a NAT with `NatBehaviorDiscovery`
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub enum StunNatBehaviorError {
+    /// Generating the transaction ids failed
+    IoError(std::io::Error),
+
+    /// The requests could not be built, e.g. the SOFTWARE value is too long
+    InvalidRequestError,
+
+    /// The server did not answer a request it must answer: the first Binding request, the
+    /// mapping tests or a request from a second socket
+    TimeoutError,
+
+    /// The server answered with an error response with the given error code
+    RequestFailedError(u16),
+
+    /// A success response is malformed, e.g. it has no mapped address or a FINGERPRINT that does
+    /// not match
+    InvalidResponseError,
+
+    /// The server does not support NAT behavior discovery, its response has no OTHER-ADDRESS
+    UnsupportedServerError,
+}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for StunNatBehaviorError {
+    fn from(error: std::io::Error) -> Self {
+        StunNatBehaviorError::IoError(error)
+    }
+}
+
+/// Errors running a TURN allocation with `TurnClient`
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub enum StunTurnClientError {
+    /// Generating a transaction id failed
+    IoError(std::io::Error),
+
+    /// The request could not be built, e.g. the username or the SOFTWARE value is too long, or
+    /// the password cannot be prepared for the password algorithm
+    InvalidRequestError,
+
+    /// There is no allocation, it is still being created or it has been deleted or has expired
+    NoAllocationError,
+
+    /// All the channel numbers are bound
+    NoChannelAvailableError,
+
+    /// The data is too long to be sent to a peer
+    DataTooLongError,
+
+    /// No response was received within the timeout
+    TimeoutError,
+
+    /// The server answered with an error response with the given error code, which is 401 or
+    /// 438 if it rejected the credentials
+    RequestFailedError(u16),
+
+    /// The PASSWORD-ALGORITHMS of the challenge offers neither MD5 nor SHA-256
+    UnsupportedAlgorithmError,
+
+    /// The response is malformed, e.g. an Allocate success response without XOR-RELAYED-ADDRESS
+    /// or a response with a MESSAGE-INTEGRITY that does not match
+    InvalidResponseError,
+}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for StunTurnClientError {
+    fn from(error: std::io::Error) -> Self {
+        StunTurnClientError::IoError(error)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<StunAuthenticatorError> for StunTurnClientError {
+    fn from(error: StunAuthenticatorError) -> Self {
+        match error {
+            StunAuthenticatorError::InvalidCredentialsError
+            | StunAuthenticatorError::InvalidRequestError => {
+                StunTurnClientError::InvalidRequestError
+            }
+            StunAuthenticatorError::CredentialsRejectedError(error_code) => {
+                StunTurnClientError::RequestFailedError(error_code)
+            }
+            StunAuthenticatorError::UnsupportedAlgorithmError => {
+                StunTurnClientError::UnsupportedAlgorithmError
+            }
+            StunAuthenticatorError::InvalidResponseError => {
+                StunTurnClientError::InvalidResponseError
+            }
+        }
+    }
+}
+
+/// Errors creating a `TurnServer`
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub enum StunTurnServerError {
+    /// Generating the NONCE failed
+    IoError(std::io::Error),
+
+    /// The realm, the SOFTWARE value or a username is too long, or a password cannot be
+    /// prepared with SASLprep
+    InvalidValueError,
+}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for StunTurnServerError {
+    fn from(error: std::io::Error) -> Self {
+        StunTurnServerError::IoError(error)
+    }
+}
+
+/// Errors authenticating requests with `StunAuthenticator`
+#[cfg(feature = "std")]
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum StunAuthenticatorError {
+    /// The username is too long, or the password cannot be prepared for the password algorithm
+    InvalidCredentialsError,
+
+    /// The request could not be built, e.g. an attribute value is too long
+    InvalidRequestError,
+
+    /// The server challenged the request again after it was resent with credentials, with the
+    /// given error code, 401 (Unauthorized) or 438 (Stale Nonce)
+    CredentialsRejectedError(u16),
+
+    /// The PASSWORD-ALGORITHMS of the challenge offers neither MD5 nor SHA-256
+    UnsupportedAlgorithmError,
+
+    /// The response is malformed, e.g. an error response without ERROR-CODE or a response with
+    /// a MESSAGE-INTEGRITY that does not match
+    InvalidResponseError,
+}
+
+/// Errors framing messages on a stream with `StunCodec`
+#[cfg(feature = "tokio")]
+#[derive(Debug)]
+pub enum StunCodecError {
+    /// Reading or writing the stream failed
+    IoError(std::io::Error),
+
+    /// The stream does not hold a valid STUN or ChannelData message at the current position, or
+    /// the message to write cannot be serialized
+    InvalidMessageError,
+}
+
+#[cfg(feature = "tokio")]
+impl From<std::io::Error> for StunCodecError {
+    fn from(error: std::io::Error) -> Self {
+        StunCodecError::IoError(error)
+    }
+}
+
+/// Errors starting client transactions or routing responses to them
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum StunTransactionError {
+    /// The message is not a valid STUN request
+    InvalidRequestError,
+
+    /// A transaction with the transaction id of the request is already outstanding
+    DuplicateTransactionIdError,
+
+    /// No outstanding transaction has the transaction id of the response
+    UnknownTransactionError([u8; 12]),
+
+    /// The message class and method are not those of a response to the transaction
+    UnexpectedMessageTypeError(StunMessageClass, StunMessageMethod),
+}
@@ -0,0 +1,25 @@
+use num_enum::TryFromPrimitive;
+
+use core::net::SocketAddr;
+
+/// Transport protocols STUN and TURN messages are exchanged over, using their IANA protocol numbers
+#[derive(Debug, Eq, PartialEq, Hash, TryFromPrimitive, Clone, Copy)]
+#[repr(u8)]
+pub enum TransportProtocol {
+    Tcp = 6,
+    Udp = 17,
+}
+
+/// The combination of client address, server address and transport protocol that identifies a
+/// client's association with a server, https://tools.ietf.org/html/rfc5766#section-2
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Copy)]
+pub struct FiveTuple {
+    /// client's address and port, as seen by the server
+    pub client_address: SocketAddr,
+
+    /// server's address and port
+    pub server_address: SocketAddr,
+
+    /// transport protocol
+    pub protocol: TransportProtocol,
+}
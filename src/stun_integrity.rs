@@ -0,0 +1,587 @@
+use crate::serializer::*;
+use crate::stun_attribute::*;
+use crate::stun_attribute_types::*;
+use crate::stun_constants::*;
+use crate::stun_errors::StunAuthError;
+use crate::stun_message::*;
+
+use alloc::borrow::Cow;
+use alloc::vec::Vec;
+
+use cookie_factory::GenError;
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use sha2::Sha256;
+
+type HmacSha1 = Hmac<Sha1>;
+type HmacSha256 = Hmac<Sha256>;
+
+/// Compute the MESSAGE-INTEGRITY value for the given message, https://tools.ietf.org/html/rfc5389#section-15.4
+///
+/// The HMAC covers the header and all attributes preceding the MESSAGE-INTEGRITY attribute, with
+/// the message length field adjusted to end at the MESSAGE-INTEGRITY attribute.  If the message
+/// does not contain a MESSAGE-INTEGRITY attribute, the value is computed as if one was appended.
+///
+/// For parsed messages the HMAC is computed over the bytes the message was received as, otherwise
+/// the message is serialized to compute it.
+///
+/// # Arguments
+///
+/// * `message` - The STUN message to compute the value for
+/// * `key` - The HMAC key, e.g. the short-term password or the long-term credential key
+///
+/// # Return
+///
+/// A Result object, when successful contains the 20 byte HMAC-SHA1 value.
+pub fn compute_message_integrity(
+    message: &StunMessage,
+    key: &[u8],
+) -> Result<[u8; STUN_MESSAGE_INTEGRITY_NUM_BYTES], GenError> {
+    let input = covered_input(message, StunAttributeType::MessageIntegrity as u16)?;
+
+    Ok(message_integrity_over(&input, key))
+}
+
+/// Verify the MESSAGE-INTEGRITY attribute of the given message using the given key.
+///
+/// # Return
+///
+/// A Result object, which is an error if the message does not contain a valid MESSAGE-INTEGRITY
+/// attribute or if the attribute does not match the message contents.
+pub fn verify_message_integrity(message: &StunMessage, key: &[u8]) -> Result<(), StunAuthError> {
+    let attribute_type = StunAttributeType::MessageIntegrity as u16;
+    let attribute = message
+        .get_attribute(attribute_type)
+        .ok_or(StunAuthError::MissingAttributeError(attribute_type))?;
+
+    if attribute.attribute_value.len() != STUN_MESSAGE_INTEGRITY_NUM_BYTES {
+        return Err(StunAuthError::InvalidAttributeError(attribute_type));
+    }
+
+    let expected = compute_message_integrity(message, key)
+        .map_err(|_| StunAuthError::IntegrityCheckFailedError)?;
+
+    // compare in constant time so that the expected value is not leaked through timing
+    let difference = expected
+        .iter()
+        .zip(attribute.attribute_value)
+        .fold(0u8, |acc, (a, b)| acc | (a ^ b));
+
+    match difference {
+        0 => Ok(()),
+        _ => Err(StunAuthError::IntegrityCheckFailedError),
+    }
+}
+
+/// Append a MESSAGE-INTEGRITY attribute to an already serialized STUN message, updating the
+/// message length field accordingly.
+///
+/// # Return
+///
+/// A Result object, which is an error if the output is shorter than a STUN header or if the
+/// message would be too long for its length field.
+pub fn append_message_integrity(output: &mut Vec<u8>, key: &[u8]) -> Result<(), GenError> {
+    check_appended_length(output, STUN_MESSAGE_INTEGRITY_NUM_BYTES)?;
+    let value = message_integrity_over(output, key);
+    let message_length = covered_message_length(output, STUN_MESSAGE_INTEGRITY_NUM_BYTES);
+    output[2..4].copy_from_slice(&message_length);
+
+    output.extend_from_slice(&(StunAttributeType::MessageIntegrity as u16).to_be_bytes());
+    output.extend_from_slice(&(STUN_MESSAGE_INTEGRITY_NUM_BYTES as u16).to_be_bytes());
+    output.extend_from_slice(&value);
+    Ok(())
+}
+
+/// Compute the MESSAGE-INTEGRITY-SHA256 value for the given message,
+/// https://tools.ietf.org/html/rfc8489#section-14.6
+///
+/// Like `compute_message_integrity`, with HMAC-SHA256 over the header and all attributes
+/// preceding the MESSAGE-INTEGRITY-SHA256 attribute, which include MESSAGE-INTEGRITY if the
+/// message has both.  The value is computed for an attribute that is not truncated.
+pub fn compute_message_integrity_sha256(
+    message: &StunMessage,
+    key: &[u8],
+) -> Result<[u8; STUN_MESSAGE_INTEGRITY_SHA256_NUM_BYTES], GenError> {
+    let input = covered_input(message, StunAttributeType::MessageIntegritySha256 as u16)?;
+
+    Ok(message_integrity_sha256_over(
+        &input,
+        key,
+        STUN_MESSAGE_INTEGRITY_SHA256_NUM_BYTES,
+    ))
+}
+
+/// Verify the MESSAGE-INTEGRITY-SHA256 attribute of the given message using the given key.  A
+/// value truncated to 16 to 32 bytes is checked against as many bytes of the HMAC.
+///
+/// # Return
+///
+/// A Result object, which is an error if the message does not contain a valid
+/// MESSAGE-INTEGRITY-SHA256 attribute or if the attribute does not match the message contents.
+pub fn verify_message_integrity_sha256(
+    message: &StunMessage,
+    key: &[u8],
+) -> Result<(), StunAuthError> {
+    let attribute_type = StunAttributeType::MessageIntegritySha256 as u16;
+    let attribute = message
+        .get_attribute(attribute_type)
+        .ok_or(StunAuthError::MissingAttributeError(attribute_type))?;
+
+    let length = attribute.attribute_value.len();
+    if !(STUN_MESSAGE_INTEGRITY_SHA256_MIN_NUM_BYTES..=STUN_MESSAGE_INTEGRITY_SHA256_NUM_BYTES)
+        .contains(&length)
+        || length % 4 != 0
+    {
+        return Err(StunAuthError::InvalidAttributeError(attribute_type));
+    }
+
+    // the message length covers the attribute as it was received, truncated or not
+    let input = covered_input(message, attribute_type)
+        .map_err(|_| StunAuthError::IntegrityCheckFailedError)?;
+    let expected = message_integrity_sha256_over(&input, key, length);
+
+    // compare in constant time so that the expected value is not leaked through timing
+    let difference = expected
+        .iter()
+        .zip(attribute.attribute_value)
+        .fold(0u8, |acc, (a, b)| acc | (a ^ b));
+
+    match difference {
+        0 => Ok(()),
+        _ => Err(StunAuthError::IntegrityCheckFailedError),
+    }
+}
+
+/// Append a MESSAGE-INTEGRITY-SHA256 attribute to an already serialized STUN message, updating
+/// the message length field accordingly.  It is an error if the output is shorter than a STUN
+/// header or if the message would be too long for its length field.
+pub fn append_message_integrity_sha256(output: &mut Vec<u8>, key: &[u8]) -> Result<(), GenError> {
+    check_appended_length(output, STUN_MESSAGE_INTEGRITY_SHA256_NUM_BYTES)?;
+    let value = message_integrity_sha256_over(output, key, STUN_MESSAGE_INTEGRITY_SHA256_NUM_BYTES);
+    let message_length = covered_message_length(output, STUN_MESSAGE_INTEGRITY_SHA256_NUM_BYTES);
+    output[2..4].copy_from_slice(&message_length);
+
+    output.extend_from_slice(&(StunAttributeType::MessageIntegritySha256 as u16).to_be_bytes());
+    output.extend_from_slice(&(STUN_MESSAGE_INTEGRITY_SHA256_NUM_BYTES as u16).to_be_bytes());
+    output.extend_from_slice(&value);
+    Ok(())
+}
+
+/// Compute the FINGERPRINT value for the given message, https://tools.ietf.org/html/rfc5389#section-15.5
+///
+/// The value is the CRC-32 of the header and all attributes preceding the FINGERPRINT attribute,
+/// with the message length field adjusted to end after the FINGERPRINT attribute, XOR'd with
+/// 0x5354554e.  If the message does not contain a FINGERPRINT attribute, the value is computed as
+/// if one was appended.
+pub fn compute_fingerprint(message: &StunMessage) -> Result<u32, GenError> {
+    let input = covered_input(message, StunAttributeType::Fingerprint as u16)?;
+
+    Ok(fingerprint_over(&input))
+}
+
+/// Verify the FINGERPRINT attribute of the given message.
+///
+/// # Return
+///
+/// A Result object, which is an error if the message does not contain a valid FINGERPRINT
+/// attribute or if the attribute does not match the message contents.
+pub fn verify_fingerprint(message: &StunMessage) -> Result<(), StunAuthError> {
+    let attribute_type = StunAttributeType::Fingerprint as u16;
+    let attribute = message
+        .get_attribute(attribute_type)
+        .ok_or(StunAuthError::MissingAttributeError(attribute_type))?;
+
+    if attribute.attribute_value.len() != STUN_FINGERPRINT_NUM_BYTES {
+        return Err(StunAuthError::InvalidAttributeError(attribute_type));
+    }
+
+    let expected =
+        compute_fingerprint(message).map_err(|_| StunAuthError::FingerprintCheckFailedError)?;
+
+    match attribute.attribute_value == expected.to_be_bytes() {
+        true => Ok(()),
+        false => Err(StunAuthError::FingerprintCheckFailedError),
+    }
+}
+
+/// Append a FINGERPRINT attribute to an already serialized STUN message, updating the message
+/// length field accordingly.  It is an error if the output is shorter than a STUN header or if
+/// the message would be too long for its length field.
+pub fn append_fingerprint(output: &mut Vec<u8>) -> Result<(), GenError> {
+    check_appended_length(output, STUN_FINGERPRINT_NUM_BYTES)?;
+    let value = fingerprint_over(output);
+    let message_length = covered_message_length(output, STUN_FINGERPRINT_NUM_BYTES);
+    output[2..4].copy_from_slice(&message_length);
+
+    output.extend_from_slice(&(StunAttributeType::Fingerprint as u16).to_be_bytes());
+    output.extend_from_slice(&(STUN_FINGERPRINT_NUM_BYTES as u16).to_be_bytes());
+    output.extend_from_slice(&value.to_be_bytes());
+    Ok(())
+}
+
+/// Get the input covered by an attribute of the given type, i.e. the message up to the first such
+/// attribute or the whole message if there is none.
+fn covered_input<'a>(
+    message: &StunMessage<'a>,
+    attribute_type: u16,
+) -> Result<Cow<'a, [u8]>, GenError> {
+    if !message.raw.is_empty() {
+        let end = message
+            .get_attribute(attribute_type)
+            .map_or(message.raw.len(), |a| a.offset);
+
+        return Ok(Cow::Borrowed(&message.raw[..end]));
+    }
+
+    let attributes: Vec<StunAttribute> = message
+        .attributes
+        .iter()
+        .take_while(|a| a.attribute_type != attribute_type)
+        .copied()
+        .collect();
+
+    serialize(&StunMessage {
+        message_class: message.message_class,
+        message_method: message.message_method,
+        message_length: serialized_attributes_length(&attributes) as u16,
+        magic_cookie: message.magic_cookie,
+        transaction_id: message.transaction_id,
+        attributes,
+        raw: &[],
+    })
+    .map(Cow::Owned)
+}
+
+/// Check that an attribute of the given value length can be appended to the serialized message
+fn check_appended_length(output: &[u8], value_length: usize) -> Result<(), GenError> {
+    if output.len() < STUN_HEADER_NUM_BYTES {
+        return Err(GenError::BufferTooSmall(
+            STUN_HEADER_NUM_BYTES - output.len(),
+        ));
+    }
+
+    let message_length =
+        output.len() - STUN_HEADER_NUM_BYTES + STUN_ATTRIBUTE_HEADER_NUM_BYTES + value_length;
+    match message_length > u16::MAX as usize {
+        true => Err(GenError::BufferTooBig(message_length)),
+        false => Ok(()),
+    }
+}
+
+/// The message length field of a message ending with an attribute of the given value length that
+/// is placed right after the covered input
+fn covered_message_length(input: &[u8], value_length: usize) -> [u8; 2] {
+    let message_length =
+        input.len() - STUN_HEADER_NUM_BYTES + STUN_ATTRIBUTE_HEADER_NUM_BYTES + value_length;
+
+    (message_length as u16).to_be_bytes()
+}
+
+/// Compute the MESSAGE-INTEGRITY value of a message, given the serialized header and attributes
+/// preceding the attribute.  The length field of the input does not need to be adjusted, the
+/// input is not copied to replace it.
+pub(crate) fn message_integrity_over(
+    input: &[u8],
+    key: &[u8],
+) -> [u8; STUN_MESSAGE_INTEGRITY_NUM_BYTES] {
+    let message_length = covered_message_length(input, STUN_MESSAGE_INTEGRITY_NUM_BYTES);
+
+    // HMAC accepts keys of any length, so this cannot fail
+    let mut mac = HmacSha1::new_from_slice(key).expect("HMAC can take a key of any size");
+    mac.update(&input[..2]);
+    mac.update(&message_length);
+    mac.update(&input[4..]);
+
+    mac.finalize().into_bytes().into()
+}
+
+/// Compute the MESSAGE-INTEGRITY-SHA256 value of a message, given the serialized header and
+/// attributes preceding the attribute and the length of the attribute value
+fn message_integrity_sha256_over(
+    input: &[u8],
+    key: &[u8],
+    value_length: usize,
+) -> [u8; STUN_MESSAGE_INTEGRITY_SHA256_NUM_BYTES] {
+    let message_length = covered_message_length(input, value_length);
+
+    // HMAC accepts keys of any length, so this cannot fail
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC can take a key of any size");
+    mac.update(&input[..2]);
+    mac.update(&message_length);
+    mac.update(&input[4..]);
+
+    mac.finalize().into_bytes().into()
+}
+
+/// Compute the FINGERPRINT value of a message, given the serialized header and attributes
+/// preceding the attribute
+pub(crate) fn fingerprint_over(input: &[u8]) -> u32 {
+    let message_length = covered_message_length(input, STUN_FINGERPRINT_NUM_BYTES);
+
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&input[..2]);
+    hasher.update(&message_length);
+    hasher.update(&input[4..]);
+
+    hasher.finalize() ^ STUN_FINGERPRINT_XOR
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_stun_message;
+    use crate::stun_message_builder::*;
+    use crate::stun_message_types::*;
+
+    fn build_signed_message(key: &[u8]) -> Vec<u8> {
+        let transaction_id = [0x42; STUN_TRANSACTION_ID_NUM_BYTES];
+
+        StunMessageBuilder::new(
+            StunMessageClass::Request,
+            StunMessageMethod::Binding,
+            &transaction_id,
+        )
+        .add_attribute(StunAttributeType::Username as u16, b"user")
+        .add_attribute(StunAttributeType::Software as u16, b"odd")
+        .add_message_integrity(key)
+        .build()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_append_message_integrity() {
+        let data = build_signed_message(b"secret");
+
+        // 8 bytes of USERNAME, 8 bytes of SOFTWARE and 24 bytes of MESSAGE-INTEGRITY
+        assert_eq!(data.len(), 60);
+        assert_eq!(data[2..4], [0x00, 0x28]);
+        assert_eq!(data[36..40], [0x00, 0x08, 0x00, 0x14]);
+
+        // the HMAC covers everything before the attribute, with the final length
+        let expected = HmacSha1::new_from_slice(b"secret")
+            .unwrap()
+            .chain_update(&data[..36])
+            .finalize()
+            .into_bytes();
+        assert_eq!(data[40..60], expected[..]);
+    }
+
+    #[test]
+    fn test_verify_message_integrity_valid() {
+        let data = build_signed_message(b"secret");
+        let (_, message) = parse_stun_message(&data).unwrap();
+
+        assert_eq!(verify_message_integrity(&message, b"secret"), Ok(()));
+    }
+
+    #[test]
+    fn test_verify_message_integrity_wrong_key() {
+        let data = build_signed_message(b"secret");
+        let (_, message) = parse_stun_message(&data).unwrap();
+
+        assert_eq!(
+            verify_message_integrity(&message, b"wrong"),
+            Err(StunAuthError::IntegrityCheckFailedError)
+        );
+    }
+
+    #[test]
+    fn test_verify_message_integrity_ignores_trailing_attributes() {
+        let mut data = build_signed_message(b"secret");
+
+        // attributes after MESSAGE-INTEGRITY (e.g. FINGERPRINT) are not covered by the HMAC
+        data.extend_from_slice(&[0x80, 0x28, 0x00, 0x04, 0x01, 0x02, 0x03, 0x04]);
+        data[3] += 8;
+
+        let (_, message) = parse_stun_message(&data).unwrap();
+        assert_eq!(verify_message_integrity(&message, b"secret"), Ok(()));
+    }
+
+    #[test]
+    fn test_verify_message_integrity_missing() {
+        let transaction_id = [0x42; STUN_TRANSACTION_ID_NUM_BYTES];
+        let data = StunMessageBuilder::new(
+            StunMessageClass::Request,
+            StunMessageMethod::Binding,
+            &transaction_id,
+        )
+        .build()
+        .unwrap();
+        let (_, message) = parse_stun_message(&data).unwrap();
+
+        assert_eq!(
+            verify_message_integrity(&message, b"secret"),
+            Err(StunAuthError::MissingAttributeError(0x0008))
+        );
+    }
+
+    #[test]
+    fn test_verify_message_integrity_received_padding() {
+        let mut data = build_signed_message(b"secret");
+
+        // the padding of SOFTWARE is not required to be zero, the HMAC covers it as received
+        data[35] = 0x5A;
+        append_message_integrity_over(&mut data, b"secret");
+
+        let (_, message) = parse_stun_message(&data).unwrap();
+        assert_eq!(message.attributes[1].padding, [0x5A]);
+        assert_eq!(verify_message_integrity(&message, b"secret"), Ok(()));
+    }
+
+    #[test]
+    fn test_compute_message_integrity_unparsed() {
+        let data = build_signed_message(b"secret");
+        let (_, parsed) = parse_stun_message(&data).unwrap();
+
+        // without the raw bytes the message is re-serialized, which gives the same value
+        let message = StunMessage {
+            message_class: parsed.message_class,
+            message_method: parsed.message_method,
+            message_length: parsed.message_length,
+            magic_cookie: parsed.magic_cookie,
+            transaction_id: parsed.transaction_id,
+            attributes: parsed.attributes.clone(),
+            raw: &[],
+        };
+        assert_eq!(
+            compute_message_integrity(&message, b"secret").unwrap(),
+            data[40..60]
+        );
+    }
+
+    #[test]
+    fn test_verify_fingerprint() {
+        let transaction_id = [0x42; STUN_TRANSACTION_ID_NUM_BYTES];
+        let mut data = StunMessageBuilder::new(
+            StunMessageClass::Request,
+            StunMessageMethod::Binding,
+            &transaction_id,
+        )
+        .add_attribute(StunAttributeType::Software as u16, b"odd")
+        .add_fingerprint()
+        .build()
+        .unwrap();
+
+        let (_, message) = parse_stun_message(&data).unwrap();
+        assert_eq!(verify_fingerprint(&message), Ok(()));
+
+        data[24] ^= 0x01;
+        let (_, message) = parse_stun_message(&data).unwrap();
+        assert_eq!(
+            verify_fingerprint(&message),
+            Err(StunAuthError::FingerprintCheckFailedError)
+        );
+    }
+
+    #[test]
+    fn test_verify_fingerprint_missing() {
+        let data = build_signed_message(b"secret");
+        let (_, message) = parse_stun_message(&data).unwrap();
+
+        assert_eq!(
+            verify_fingerprint(&message),
+            Err(StunAuthError::MissingAttributeError(0x8028))
+        );
+    }
+
+    #[test]
+    fn test_append_message_integrity_sha256() {
+        let transaction_id = [0x42; STUN_TRANSACTION_ID_NUM_BYTES];
+        let data = StunMessageBuilder::new(
+            StunMessageClass::Request,
+            StunMessageMethod::Binding,
+            &transaction_id,
+        )
+        .add_attribute(StunAttributeType::Username as u16, b"user")
+        .add_message_integrity(b"secret")
+        .add_message_integrity_sha256(b"secret")
+        .build()
+        .unwrap();
+
+        // MESSAGE-INTEGRITY-SHA256 follows MESSAGE-INTEGRITY and covers it
+        assert_eq!(data.len(), 88);
+        assert_eq!(data[2..4], [0x00, 0x44]);
+        assert_eq!(data[52..56], [0x00, 0x1C, 0x00, 0x20]);
+
+        let (_, message) = parse_stun_message(&data).unwrap();
+        assert_eq!(verify_message_integrity(&message, b"secret"), Ok(()));
+        assert_eq!(verify_message_integrity_sha256(&message, b"secret"), Ok(()));
+        assert_eq!(
+            compute_message_integrity_sha256(&message, b"secret").unwrap(),
+            data[56..88]
+        );
+        assert_eq!(
+            verify_message_integrity_sha256(&message, b"wrong"),
+            Err(StunAuthError::IntegrityCheckFailedError)
+        );
+    }
+
+    #[test]
+    fn test_verify_message_integrity_sha256_truncated() {
+        let mut data = build_signed_message(b"secret");
+        data.truncate(36);
+
+        // a value truncated to 16 bytes is computed with the length of the truncated attribute
+        let value = message_integrity_sha256_over(&data, b"secret", 16);
+        let message_length = covered_message_length(&data, 16);
+        data[2..4].copy_from_slice(&message_length);
+        data.extend_from_slice(&[0x00, 0x1C, 0x00, 0x10]);
+        data.extend_from_slice(&value[..16]);
+
+        let (_, message) = parse_stun_message(&data).unwrap();
+        assert_eq!(verify_message_integrity_sha256(&message, b"secret"), Ok(()));
+
+        // shorter than 16 bytes is not accepted
+        data.truncate(data.len() - 4);
+        data[3] -= 4;
+        data[39] = 0x0C;
+        let (_, message) = parse_stun_message(&data).unwrap();
+        assert_eq!(
+            verify_message_integrity_sha256(&message, b"secret"),
+            Err(StunAuthError::InvalidAttributeError(0x001C))
+        );
+    }
+
+    #[test]
+    fn test_verify_message_integrity_sha256_missing() {
+        let data = build_signed_message(b"secret");
+        let (_, message) = parse_stun_message(&data).unwrap();
+
+        assert_eq!(
+            verify_message_integrity_sha256(&message, b"secret"),
+            Err(StunAuthError::MissingAttributeError(0x001C))
+        );
+    }
+
+    #[test]
+    fn test_append_invalid_output() {
+        // shorter than a STUN header
+        let mut data = vec![0x00, 0x01, 0x00, 0x00];
+        assert!(matches!(
+            append_fingerprint(&mut data),
+            Err(GenError::BufferTooSmall(16))
+        ));
+        assert!(matches!(
+            append_message_integrity(&mut data, b"key"),
+            Err(GenError::BufferTooSmall(16))
+        ));
+        assert_eq!(data.len(), 4);
+
+        // a message length of 65532, which leaves no room for another attribute
+        let mut data = vec![0; STUN_HEADER_NUM_BYTES + 65532];
+        assert!(matches!(
+            append_fingerprint(&mut data),
+            Err(GenError::BufferTooBig(65540))
+        ));
+        assert!(matches!(
+            append_message_integrity_sha256(&mut data, b"key"),
+            Err(GenError::BufferTooBig(65568))
+        ));
+        assert_eq!(data.len(), STUN_HEADER_NUM_BYTES + 65532);
+    }
+
+    fn append_message_integrity_over(data: &mut Vec<u8>, key: &[u8]) {
+        data.truncate(36);
+        append_message_integrity(data, key).unwrap();
+    }
+}
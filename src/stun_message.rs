@@ -1,7 +1,14 @@
 use crate::stun_attribute::*;
+use crate::stun_attribute_types::*;
 use crate::stun_constants::*;
+use crate::stun_errors::StunParseError;
+use crate::stun_message_owned::{Hex, StunAttributeValue};
 use crate::stun_message_types::*;
 
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::fmt;
+
 /// A STUN packet, https://tools.ie
 /// tf.org/html/rfc5389#page-10
 /// 0                   1                   2                   3
@@ -35,4 +42,104 @@ pub struct StunMessage<'a> {
 
     /// 0 or more attributes -- N bytes
     pub attributes: Vec<StunAttribute<'a>>,
+
+    /// the serialized message the fields were parsed from, header and attributes, or empty if
+    /// the message was not parsed
+    pub raw: &'a [u8],
+}
+
+impl<'a> StunMessage<'a> {
+    /// Get the first attribute of the given type, if the message contains one
+    pub fn get_attribute(&self, attribute_type: u16) -> Option<&StunAttribute<'a>> {
+        self.attributes
+            .iter()
+            .find(|a| a.attribute_type == attribute_type)
+    }
+
+    /// Get the first attribute of the given type, returning an error if the message does not contain one
+    pub fn get_required_attribute(
+        &self,
+        attribute_type: u16,
+    ) -> Result<&StunAttribute<'a>, StunParseError<&'a [u8]>> {
+        self.get_attribute(attribute_type)
+            .ok_or(StunParseError::MissingAttributeError(attribute_type))
+    }
+}
+
+impl fmt::Display for StunMessage<'_> {
+    /// A dissection of the message, e.g.
+    ///
+    /// ```text
+    /// Binding Success Response, length 12, transaction id 0x0102030405060708090a0b0c
+    ///   XOR-MAPPED-ADDRESS: 192.0.2.1:32853
+    /// ```
+    ///
+    /// Known attributes are decoded, unknown attributes and values that cannot be decoded are
+    /// written as hex.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {}, length {}, transaction id 0x{}",
+            self.message_method,
+            self.message_class,
+            self.message_length,
+            Hex(self.transaction_id)
+        )?;
+
+        for attribute in &self.attributes {
+            match StunAttributeType::try_from(attribute.attribute_type) {
+                Ok(attribute_type) => write!(f, "\n  {}: ", attribute_type)?,
+                Err(_) => write!(f, "\n  0x{:04X}: ", attribute.attribute_type)?,
+            }
+
+            write!(
+                f,
+                "{}",
+                StunAttributeValue::decode(attribute, self.transaction_id)
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_stun_message;
+    use crate::stun_message_builder::*;
+
+    use alloc::string::ToString;
+
+    #[test]
+    fn test_display() {
+        let transaction_id = [
+            0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B, 0x0C,
+        ];
+        let data = StunMessageBuilder::new(
+            StunMessageClass::ErrorResponse,
+            StunMessageMethod::Allocate,
+            &transaction_id,
+        )
+        .add_xor_address_attribute(0x0020, &"192.0.2.1:32853".parse().unwrap())
+        .add_error_code_attribute(438, "Stale Nonce")
+        .add_attribute(0x8022, b"say \"hi\"")
+        .add_u32_attribute(0x000D, 600)
+        .add_attribute(0xC001, &[0xDE, 0xAD])
+        .add_attribute(0x0001, &[0x00, 0x07])
+        .build()
+        .unwrap();
+        let (_, message) = parse_stun_message(&data).unwrap();
+
+        assert_eq!(
+            message.to_string(),
+            "Allocate Error Response, length 68, transaction id 0x0102030405060708090a0b0c\n\
+             \x20 XOR-MAPPED-ADDRESS: 192.0.2.1:32853\n\
+             \x20 ERROR-CODE: 438 \"Stale Nonce\"\n\
+             \x20 SOFTWARE: \"say \\\"hi\\\"\"\n\
+             \x20 LIFETIME: 600\n\
+             \x20 0xC001: dead\n\
+             \x20 MAPPED-ADDRESS: 0007"
+        );
+    }
 }
@@ -0,0 +1,303 @@
+use crate::serializer::*;
+use crate::stun_address::*;
+use crate::stun_attribute::*;
+use crate::stun_attribute_types::*;
+use crate::stun_constants::*;
+use crate::stun_error_code::*;
+use crate::stun_integrity::*;
+use crate::stun_message::*;
+use crate::stun_message_types::*;
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::net::SocketAddr;
+
+use cookie_factory::GenError;
+
+/// A builder for serialized STUN messages.
+///
+/// Unlike `StunMessage`, which borrows its attribute values from a parsed buffer, the builder
+/// owns the values that are added to it and computes the message length (including attribute
+/// padding) when the message is built.
+#[derive(Debug, Clone)]
+pub struct StunMessageBuilder {
+    message_class: StunMessageClass,
+    message_method: StunMessageMethod,
+    transaction_id: [u8; STUN_TRANSACTION_ID_NUM_BYTES],
+    attributes: Vec<(u16, Vec<u8>)>,
+    message_integrity_key: Option<Vec<u8>>,
+    message_integrity_sha256_key: Option<Vec<u8>>,
+    fingerprint: bool,
+}
+
+impl StunMessageBuilder {
+    /// Create a builder for a message with the given class, method and transaction id and no attributes
+    pub fn new(
+        message_class: StunMessageClass,
+        message_method: StunMessageMethod,
+        transaction_id: &[u8; STUN_TRANSACTION_ID_NUM_BYTES],
+    ) -> Self {
+        StunMessageBuilder {
+            message_class,
+            message_method,
+            transaction_id: *transaction_id,
+            attributes: vec![],
+            message_integrity_key: None,
+            message_integrity_sha256_key: None,
+            fingerprint: false,
+        }
+    }
+
+    /// The transaction id of the message being built
+    pub fn transaction_id(&self) -> &[u8; STUN_TRANSACTION_ID_NUM_BYTES] {
+        &self.transaction_id
+    }
+
+    /// Append an attribute with the given type and raw value
+    pub fn add_attribute(&mut self, attribute_type: u16, attribute_value: &[u8]) -> &mut Self {
+        self.attributes
+            .push((attribute_type, attribute_value.to_vec()));
+        self
+    }
+
+    /// Append an attribute whose value is a 32 bit unsigned integer
+    pub fn add_u32_attribute(&mut self, attribute_type: u16, value: u32) -> &mut Self {
+        self.add_attribute(attribute_type, &value.to_be_bytes())
+    }
+
+    /// Append an address attribute (e.g. MAPPED-ADDRESS)
+    pub fn add_address_attribute(
+        &mut self,
+        attribute_type: u16,
+        address: &SocketAddr,
+    ) -> &mut Self {
+        let value = serialize_address(address);
+        self.add_attribute(attribute_type, &value)
+    }
+
+    /// Append an XOR'd address attribute (e.g. XOR-MAPPED-ADDRESS), using the transaction id of this message
+    pub fn add_xor_address_attribute(
+        &mut self,
+        attribute_type: u16,
+        address: &SocketAddr,
+    ) -> &mut Self {
+        let value = serialize_xor_address(address, &self.transaction_id);
+        self.add_attribute(attribute_type, &value)
+    }
+
+    /// Append an ERROR-CODE attribute with the given code and reason phrase
+    pub fn add_error_code_attribute(&mut self, code: u16, reason: &str) -> &mut Self {
+        let value = serialize_error_code(code, reason);
+        self.add_attribute(StunAttributeType::ErrorCode as u16, &value)
+    }
+
+    /// Sign the message with a MESSAGE-INTEGRITY attribute using the given key.  The attribute is
+    /// always placed after all other attributes added to the builder.
+    pub fn add_message_integrity(&mut self, key: &[u8]) -> &mut Self {
+        self.message_integrity_key = Some(key.to_vec());
+        self
+    }
+
+    /// Sign the message with a MESSAGE-INTEGRITY-SHA256 attribute using the given key,
+    /// https://tools.ietf.org/html/rfc8489#section-14.6.  The attribute is always placed after
+    /// all other attributes added to the builder, and after MESSAGE-INTEGRITY if the message has
+    /// both.
+    pub fn add_message_integrity_sha256(&mut self, key: &[u8]) -> &mut Self {
+        self.message_integrity_sha256_key = Some(key.to_vec());
+        self
+    }
+
+    /// Append a FINGERPRINT attribute to the message.  The attribute is always placed last, after
+    /// MESSAGE-INTEGRITY if the message is signed.
+    pub fn add_fingerprint(&mut self) -> &mut Self {
+        self.fingerprint = true;
+        self
+    }
+
+    /// Serialize the message into a dynamically allocated output
+    ///
+    /// # Return
+    ///
+    /// A Result object, when successful contains a Vec<u8> holding the serialized message.  It is
+    /// `GenError::BufferTooBig` with the offending length if an attribute value or the whole
+    /// message does not fit in its 16 bit length field.
+    pub fn build(&self) -> Result<Vec<u8>, GenError> {
+        let mut offset = STUN_HEADER_NUM_BYTES;
+        let mut attributes = Vec::with_capacity(self.attributes.len());
+        for (attribute_type, attribute_value) in &self.attributes {
+            let attribute = StunAttribute {
+                attribute_type: *attribute_type,
+                attribute_length: u16::try_from(attribute_value.len())
+                    .map_err(|_| GenError::BufferTooBig(attribute_value.len()))?,
+                attribute_value,
+                padding: &[],
+                offset,
+            };
+            offset += attribute.serialized_length();
+            attributes.push(attribute);
+        }
+
+        // the attributes appended after serializing are counted in the message length too
+        let mut message_length = serialized_attributes_length(&attributes);
+        if self.message_integrity_key.is_some() {
+            message_length += STUN_ATTRIBUTE_HEADER_NUM_BYTES + STUN_MESSAGE_INTEGRITY_NUM_BYTES;
+        }
+        if self.message_integrity_sha256_key.is_some() {
+            message_length +=
+                STUN_ATTRIBUTE_HEADER_NUM_BYTES + STUN_MESSAGE_INTEGRITY_SHA256_NUM_BYTES;
+        }
+        if self.fingerprint {
+            message_length += STUN_ATTRIBUTE_HEADER_NUM_BYTES + STUN_FINGERPRINT_NUM_BYTES;
+        }
+        u16::try_from(message_length).map_err(|_| GenError::BufferTooBig(message_length))?;
+
+        let message = StunMessage {
+            message_class: self.message_class,
+            message_method: self.message_method,
+            message_length: serialized_attributes_length(&attributes) as u16,
+            magic_cookie: STUN_MAGIC_COOKIE,
+            transaction_id: &self.transaction_id,
+            attributes,
+            raw: &[],
+        };
+
+        let mut output = serialize(&message)?;
+
+        if let Some(key) = &self.message_integrity_key {
+            append_message_integrity(&mut output, key)?;
+        }
+
+        if let Some(key) = &self.message_integrity_sha256_key {
+            append_message_integrity_sha256(&mut output, key)?;
+        }
+
+        if self.fingerprint {
+            append_fingerprint(&mut output)?;
+        }
+
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_stun_message;
+
+    #[test]
+    fn build_empty_message() {
+        let transaction_id = [0x12; STUN_TRANSACTION_ID_NUM_BYTES];
+        let result = StunMessageBuilder::new(
+            StunMessageClass::Request,
+            StunMessageMethod::Binding,
+            &transaction_id,
+        )
+        .build();
+
+        assert!(result.is_ok());
+
+        let data = result.unwrap();
+        assert_eq!(data.len(), 20);
+        assert_eq!(data[0..2], [0x00, 0x01]);
+        assert_eq!(data[2..4], [0x00, 0x00]);
+        assert_eq!(data[4..8], [0x21, 0x12, 0xA4, 0x42]);
+        assert_eq!(data[8..20], transaction_id);
+    }
+
+    #[test]
+    fn build_message_with_padded_attributes() {
+        let transaction_id = [0x34; STUN_TRANSACTION_ID_NUM_BYTES];
+        let result = StunMessageBuilder::new(
+            StunMessageClass::Indication,
+            StunMessageMethod::Binding,
+            &transaction_id,
+        )
+        .add_attribute(0x8022, &[0xAA, 0xBB, 0xCC])
+        .add_u32_attribute(0x002A, 0x0102_0304)
+        .build();
+
+        assert!(result.is_ok());
+
+        let data = result.unwrap();
+        assert_eq!(data.len(), 36);
+        assert_eq!(data[2..4], [0x00, 0x10]);
+        assert_eq!(
+            data[20..28],
+            [0x80, 0x22, 0x00, 0x03, 0xAA, 0xBB, 0xCC, 0x00]
+        );
+        assert_eq!(
+            data[28..36],
+            [0x00, 0x2A, 0x00, 0x04, 0x01, 0x02, 0x03, 0x04]
+        );
+
+        let (remaining, message) = parse_stun_message(&data).unwrap();
+        assert_eq!(remaining.len(), 0);
+        assert_eq!(message.message_class, StunMessageClass::Indication);
+        assert_eq!(message.attributes.len(), 2);
+        assert_eq!(message.attributes[0].attribute_value, [0xAA, 0xBB, 0xCC]);
+    }
+
+    #[test]
+    fn build_message_with_xor_address() {
+        let transaction_id = [0x56; STUN_TRANSACTION_ID_NUM_BYTES];
+        let address: SocketAddr = "192.0.2.1:32853".parse().unwrap();
+        let data = StunMessageBuilder::new(
+            StunMessageClass::SuccessResponse,
+            StunMessageMethod::Binding,
+            &transaction_id,
+        )
+        .add_xor_address_attribute(0x0020, &address)
+        .build()
+        .unwrap();
+
+        let (_, message) = parse_stun_message(&data).unwrap();
+        let result = parse_xor_address(message.attributes[0].attribute_value, &transaction_id);
+        assert_eq!(result.unwrap().1, address);
+    }
+
+    #[test]
+    fn build_message_with_fingerprint() {
+        let transaction_id = [0x78; STUN_TRANSACTION_ID_NUM_BYTES];
+        let data = StunMessageBuilder::new(
+            StunMessageClass::Request,
+            StunMessageMethod::Binding,
+            &transaction_id,
+        )
+        .add_fingerprint()
+        .add_attribute(0x8022, b"abc")
+        .add_message_integrity(b"key")
+        .build()
+        .unwrap();
+
+        // 8 bytes of SOFTWARE, 24 bytes of MESSAGE-INTEGRITY and 8 bytes of FINGERPRINT
+        assert_eq!(data.len(), 60);
+        assert_eq!(data[2..4], [0x00, 0x28]);
+        assert_eq!(data[28..30], [0x00, 0x08]);
+        assert_eq!(data[52..56], [0x80, 0x28, 0x00, 0x04]);
+
+        let (_, message) = parse_stun_message(&data).unwrap();
+        assert_eq!(verify_message_integrity(&message, b"key"), Ok(()));
+        assert_eq!(verify_fingerprint(&message), Ok(()));
+    }
+
+    #[test]
+    fn build_message_too_long() {
+        let transaction_id = [0x78; STUN_TRANSACTION_ID_NUM_BYTES];
+        let mut builder = StunMessageBuilder::new(
+            StunMessageClass::Request,
+            StunMessageMethod::Binding,
+            &transaction_id,
+        );
+
+        // an attribute value longer than its length field can hold
+        let result = builder.clone().add_attribute(0x8022, &[0; 65536]).build();
+        assert!(matches!(result, Err(GenError::BufferTooBig(65536))));
+
+        // attributes which fit, but not once FINGERPRINT is appended
+        builder.add_attribute(0x8022, &[0; 65528]);
+        assert!(builder.build().is_ok());
+        let result = builder.add_fingerprint().build();
+        assert!(matches!(result, Err(GenError::BufferTooBig(65540))));
+    }
+}
@@ -0,0 +1,339 @@
+use crate::parser::parse_stun_message_view;
+use crate::stun_address::*;
+use crate::stun_attribute_types::*;
+use crate::stun_constants::*;
+use crate::stun_errors::StunEditError;
+use crate::stun_integrity::{fingerprint_over, message_integrity_over};
+use crate::stun_message_view::*;
+
+use core::net::SocketAddr;
+
+/// A mutable view of a serialized STUN message, for rewriting a message in place.
+///
+/// Attribute values can be overwritten with values of the same length, attributes can be removed
+/// from the end of the message and MESSAGE-INTEGRITY and FINGERPRINT can be recomputed without
+/// parsing the message into a `StunMessage` and serializing it again.  The message is always kept
+/// valid, so it can be viewed or sent at any time.
+///
+/// Any bytes in the buffer after the message are spare room for appending attributes.
+#[derive(Debug)]
+pub struct StunMessageMut<'a> {
+    buffer: &'a mut [u8],
+    length: usize,
+}
+
+impl<'a> StunMessageMut<'a> {
+    /// Create a mutable view of the message at the start of the given buffer, validating the
+    /// header and the attributes of the message
+    pub fn new(buffer: &'a mut [u8]) -> Result<Self, StunEditError> {
+        let length = match parse_stun_message_view(buffer) {
+            Ok((_, view)) => STUN_HEADER_NUM_BYTES + view.message_length as usize,
+            Err(_) => return Err(StunEditError::InvalidMessageError),
+        };
+
+        Ok(StunMessageMut { buffer, length })
+    }
+
+    /// The serialized message
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buffer[..self.length]
+    }
+
+    /// A read-only view of the message, e.g. to inspect its attributes
+    pub fn view(&self) -> StunMessageView<'_> {
+        match parse_stun_message_view(self.as_bytes()) {
+            Ok((_, view)) => view,
+            Err(_) => unreachable!("the message is kept valid while editing"),
+        }
+    }
+
+    /// Overwrite the value of the first attribute of the given type.  The new value must have the
+    /// same length as the current value.
+    pub fn set_attribute_value(
+        &mut self,
+        attribute_type: u16,
+        attribute_value: &[u8],
+    ) -> Result<(), StunEditError> {
+        let (offset, length) = self.find(attribute_type)?;
+
+        if length != attribute_value.len() {
+            return Err(StunEditError::AttributeLengthMismatchError(attribute_type));
+        }
+
+        let start = offset + STUN_ATTRIBUTE_HEADER_NUM_BYTES;
+        self.buffer[start..start + length].copy_from_slice(attribute_value);
+
+        Ok(())
+    }
+
+    /// Overwrite the value of the first address attribute (e.g. MAPPED-ADDRESS) of the given
+    /// type.  The new address must be of the same family as the current one.
+    pub fn set_address_attribute(
+        &mut self,
+        attribute_type: u16,
+        address: &SocketAddr,
+    ) -> Result<(), StunEditError> {
+        self.set_attribute_value(attribute_type, &serialize_address(address))
+    }
+
+    /// Overwrite the value of the first XOR'd address attribute (e.g. XOR-MAPPED-ADDRESS) of the
+    /// given type, using the transaction id of this message.  The new address must be of the same
+    /// family as the current one.
+    pub fn set_xor_address_attribute(
+        &mut self,
+        attribute_type: u16,
+        address: &SocketAddr,
+    ) -> Result<(), StunEditError> {
+        let transaction_id = *self.view().transaction_id;
+        let value = serialize_xor_address(address, &transaction_id);
+
+        self.set_attribute_value(attribute_type, &value)
+    }
+
+    /// Remove the first attribute of the given type and all attributes following it, e.g. to drop
+    /// MESSAGE-INTEGRITY and FINGERPRINT before signing the message with a different key
+    pub fn remove_trailing_attributes(&mut self, attribute_type: u16) -> Result<(), StunEditError> {
+        let (offset, _) = self.find(attribute_type)?;
+        self.set_length(offset);
+
+        Ok(())
+    }
+
+    /// Recompute the value of the MESSAGE-INTEGRITY attribute of the message using the given key,
+    /// e.g. after overwriting an attribute covered by it
+    pub fn update_message_integrity(&mut self, key: &[u8]) -> Result<(), StunEditError> {
+        let attribute_type = StunAttributeType::MessageIntegrity as u16;
+        let (offset, length) = self.find(attribute_type)?;
+        if length != STUN_MESSAGE_INTEGRITY_NUM_BYTES {
+            return Err(StunEditError::AttributeLengthMismatchError(attribute_type));
+        }
+
+        let value = message_integrity_over(&self.buffer[..offset], key);
+
+        let start = offset + STUN_ATTRIBUTE_HEADER_NUM_BYTES;
+        self.buffer[start..start + STUN_MESSAGE_INTEGRITY_NUM_BYTES].copy_from_slice(&value);
+
+        Ok(())
+    }
+
+    /// Recompute the value of the FINGERPRINT attribute of the message.  This must be done after
+    /// any other change to the message.
+    pub fn update_fingerprint(&mut self) -> Result<(), StunEditError> {
+        let attribute_type = StunAttributeType::Fingerprint as u16;
+        let (offset, length) = self.find(attribute_type)?;
+        if length != STUN_FINGERPRINT_NUM_BYTES {
+            return Err(StunEditError::AttributeLengthMismatchError(attribute_type));
+        }
+
+        let value = fingerprint_over(&self.buffer[..offset]);
+
+        let start = offset + STUN_ATTRIBUTE_HEADER_NUM_BYTES;
+        self.buffer[start..start + STUN_FINGERPRINT_NUM_BYTES]
+            .copy_from_slice(&value.to_be_bytes());
+
+        Ok(())
+    }
+
+    /// Append a MESSAGE-INTEGRITY attribute computed with the given key, using the spare room in
+    /// the buffer after the message
+    pub fn append_message_integrity(&mut self, key: &[u8]) -> Result<(), StunEditError> {
+        let value = message_integrity_over(self.as_bytes(), key);
+
+        self.append_attribute(StunAttributeType::MessageIntegrity as u16, &value)
+    }
+
+    /// Append a FINGERPRINT attribute, using the spare room in the buffer after the message
+    pub fn append_fingerprint(&mut self) -> Result<(), StunEditError> {
+        let value = fingerprint_over(self.as_bytes());
+
+        self.append_attribute(StunAttributeType::Fingerprint as u16, &value.to_be_bytes())
+    }
+
+    /// Get the offset and value length of the first attribute of the given type
+    fn find(&self, attribute_type: u16) -> Result<(usize, usize), StunEditError> {
+        self.view()
+            .find(attribute_type)
+            .map(|a| (a.offset, a.attribute_value.len()))
+            .ok_or(StunEditError::MissingAttributeError(attribute_type))
+    }
+
+    /// Append an attribute whose value is a multiple of 4 bytes long, so needs no padding
+    fn append_attribute(
+        &mut self,
+        attribute_type: u16,
+        attribute_value: &[u8],
+    ) -> Result<(), StunEditError> {
+        let start = self.length;
+        let end = start + STUN_ATTRIBUTE_HEADER_NUM_BYTES + attribute_value.len();
+
+        // the message length field must also be able to hold the new length
+        if end > self.buffer.len() || end - STUN_HEADER_NUM_BYTES > u16::MAX as usize {
+            return Err(StunEditError::BufferTooSmallError(attribute_type));
+        }
+
+        self.buffer[start..start + 2].copy_from_slice(&attribute_type.to_be_bytes());
+        self.buffer[start + 2..start + 4]
+            .copy_from_slice(&(attribute_value.len() as u16).to_be_bytes());
+        self.buffer[start + 4..end].copy_from_slice(attribute_value);
+        self.set_length(end);
+
+        Ok(())
+    }
+
+    /// Set the length of the message, including the header, and update the length field
+    fn set_length(&mut self, length: usize) {
+        let message_length = (length - STUN_HEADER_NUM_BYTES) as u16;
+        self.buffer[2..4].copy_from_slice(&message_length.to_be_bytes());
+        self.length = length;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_stun_message;
+    use crate::stun_integrity::*;
+    use crate::stun_message_builder::*;
+    use crate::stun_message_types::*;
+
+    fn build_response(address: &SocketAddr) -> Vec<u8> {
+        let transaction_id = [0x61; STUN_TRANSACTION_ID_NUM_BYTES];
+
+        StunMessageBuilder::new(
+            StunMessageClass::SuccessResponse,
+            StunMessageMethod::Binding,
+            &transaction_id,
+        )
+        .add_xor_address_attribute(StunAttributeType::XorMappedAddress as u16, address)
+        .add_attribute(StunAttributeType::Software as u16, b"proxy")
+        .add_message_integrity(b"key")
+        .add_fingerprint()
+        .build()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_rewrite_xor_mapped_address() {
+        let mut data = build_response(&"192.0.2.1:32853".parse().unwrap());
+        let address: SocketAddr = "198.51.100.7:4000".parse().unwrap();
+
+        let mut message = StunMessageMut::new(&mut data).unwrap();
+        message
+            .set_xor_address_attribute(StunAttributeType::XorMappedAddress as u16, &address)
+            .unwrap();
+        message.update_message_integrity(b"key").unwrap();
+        message.update_fingerprint().unwrap();
+
+        // the message is identical to one built with the new address
+        assert_eq!(message.as_bytes(), &build_response(&address)[..]);
+
+        let (_, parsed) = parse_stun_message(&data).unwrap();
+        assert_eq!(verify_message_integrity(&parsed, b"key"), Ok(()));
+        assert_eq!(verify_fingerprint(&parsed), Ok(()));
+    }
+
+    #[test]
+    fn test_set_attribute_value_length_mismatch() {
+        let mut data = build_response(&"192.0.2.1:32853".parse().unwrap());
+        let address: SocketAddr = "[2001:db8::1]:4000".parse().unwrap();
+
+        let mut message = StunMessageMut::new(&mut data).unwrap();
+        assert_eq!(
+            message.set_xor_address_attribute(0x0020, &address),
+            Err(StunEditError::AttributeLengthMismatchError(0x0020))
+        );
+        assert_eq!(
+            message.set_attribute_value(0x0006, b"user"),
+            Err(StunEditError::MissingAttributeError(0x0006))
+        );
+    }
+
+    #[test]
+    fn test_remove_trailing_attributes_and_resign() {
+        // the removed attributes leave room for new ones in the same buffer
+        let mut data = build_response(&"192.0.2.1:32853".parse().unwrap());
+
+        let mut message = StunMessageMut::new(&mut data).unwrap();
+        message
+            .remove_trailing_attributes(StunAttributeType::MessageIntegrity as u16)
+            .unwrap();
+
+        // 12 bytes of XOR-MAPPED-ADDRESS and 12 bytes of SOFTWARE
+        assert_eq!(message.as_bytes().len(), 44);
+        assert_eq!(message.as_bytes()[2..4], [0x00, 0x18]);
+        assert_eq!(message.view().attributes().count(), 2);
+
+        message.append_message_integrity(b"other").unwrap();
+        message.append_fingerprint().unwrap();
+        assert_eq!(
+            message.append_fingerprint(),
+            Err(StunEditError::BufferTooSmallError(0x8028))
+        );
+
+        let (_, parsed) = parse_stun_message(&data).unwrap();
+        assert_eq!(verify_message_integrity(&parsed, b"other"), Ok(()));
+        assert_eq!(verify_fingerprint(&parsed), Ok(()));
+    }
+
+    #[test]
+    fn test_append_message_too_long() {
+        // the message length is 65532, so there is no room for a FINGERPRINT even though the
+        // buffer has plenty
+        let transaction_id = [0x61; STUN_TRANSACTION_ID_NUM_BYTES];
+        let mut data = StunMessageBuilder::new(
+            StunMessageClass::Request,
+            StunMessageMethod::Binding,
+            &transaction_id,
+        )
+        .add_attribute(StunAttributeType::Software as u16, &[0x61; 65528])
+        .build()
+        .unwrap();
+        data.resize(data.len() + 1024, 0);
+        assert!(data.len() > 65536);
+
+        let mut message = StunMessageMut::new(&mut data).unwrap();
+        assert_eq!(
+            message.append_fingerprint(),
+            Err(StunEditError::BufferTooSmallError(0x8028))
+        );
+        assert_eq!(
+            message.append_message_integrity(b"key"),
+            Err(StunEditError::BufferTooSmallError(0x0008))
+        );
+        assert_eq!(message.view().message_length, 65532);
+    }
+
+    #[test]
+    fn test_update_malformed_attribute() {
+        // a FINGERPRINT attribute that is too short to hold a CRC-32
+        let transaction_id = [0x61; STUN_TRANSACTION_ID_NUM_BYTES];
+        let mut data = StunMessageBuilder::new(
+            StunMessageClass::Request,
+            StunMessageMethod::Binding,
+            &transaction_id,
+        )
+        .add_attribute(StunAttributeType::Fingerprint as u16, &[0x00, 0x00])
+        .add_attribute(StunAttributeType::Software as u16, b"test")
+        .build()
+        .unwrap();
+        let original = data.clone();
+
+        let mut message = StunMessageMut::new(&mut data).unwrap();
+        assert_eq!(
+            message.update_fingerprint(),
+            Err(StunEditError::AttributeLengthMismatchError(0x8028))
+        );
+        assert_eq!(message.as_bytes(), &original[..]);
+    }
+
+    #[test]
+    fn test_new_invalid_message() {
+        let mut data = build_response(&"192.0.2.1:32853".parse().unwrap());
+        let length = data.len();
+
+        assert_eq!(
+            StunMessageMut::new(&mut data[..length - 1]).unwrap_err(),
+            StunEditError::InvalidMessageError
+        );
+    }
+}
@@ -0,0 +1,388 @@
+use crate::serializer::*;
+use crate::stun_address::*;
+use crate::stun_attribute::*;
+use crate::stun_attribute_types::*;
+use crate::stun_constants::*;
+use crate::stun_error_code::*;
+use crate::stun_message::*;
+use crate::stun_message_types::*;
+use crate::stun_text_attributes::*;
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::fmt;
+use core::net::SocketAddr;
+
+use cookie_factory::GenError;
+use nom::combinator::all_consuming;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// An owned copy of a STUN message with decoded attribute values, e.g. for logging messages or
+/// replaying them later.
+///
+/// With the `serde` feature the message can be serialized, the transaction id and raw attribute
+/// values are written as hex strings and addresses as strings.  Everything needed to reproduce
+/// the original message is kept, so `to_bytes` gives back the exact bytes the message was parsed
+/// from, including any non-zero padding and an inconsistent message length.
+#[derive(Debug, Eq, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct OwnedStunMessage {
+    /// message class, encoded into message type
+    pub message_class: StunMessageClass,
+
+    /// message method, encoded into message type
+    pub message_method: StunMessageMethod,
+
+    /// message length, only if it differs from the length of the attributes
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub message_length: Option<u16>,
+
+    /// transaction id -- 96 bits
+    #[cfg_attr(feature = "serde", serde(with = "hex::serde"))]
+    pub transaction_id: [u8; STUN_TRANSACTION_ID_NUM_BYTES],
+
+    /// attributes, in the order they appear in the message
+    pub attributes: Vec<OwnedStunAttribute>,
+}
+
+/// An owned copy of a STUN attribute, see `OwnedStunMessage`
+#[derive(Debug, Eq, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct OwnedStunAttribute {
+    /// attribute type
+    #[cfg_attr(feature = "serde", serde(rename = "type"))]
+    pub attribute_type: u16,
+
+    /// attribute value
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub value: StunAttributeValue,
+
+    /// padding after the value, only if it is not all zeros
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty", with = "hex::serde")
+    )]
+    pub padding: Vec<u8>,
+}
+
+/// A decoded attribute value.  Values of unknown attribute types, and values that cannot be
+/// decoded as their type requires, are kept as raw bytes.
+#[derive(Debug, Eq, PartialEq, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum StunAttributeValue {
+    /// an address, e.g. MAPPED-ADDRESS
+    Address(SocketAddr),
+
+    /// an XOR'd address, e.g. XOR-MAPPED-ADDRESS
+    XorAddress(SocketAddr),
+
+    /// a UTF-8 string, e.g. USERNAME or SOFTWARE
+    Text(String),
+
+    /// an ERROR-CODE value
+    ErrorCode { code: u16, reason: String },
+
+    /// a 32 bit unsigned integer, e.g. LIFETIME
+    U32(u32),
+
+    /// an opaque value
+    Raw(#[cfg_attr(feature = "serde", serde(with = "hex::serde"))] Vec<u8>),
+}
+
+impl OwnedStunMessage {
+    /// Serialize the message, reproducing the message it was created from
+    pub fn to_bytes(&self) -> Result<Vec<u8>, GenError> {
+        let values: Vec<Vec<u8>> = self
+            .attributes
+            .iter()
+            .map(|a| a.value.to_bytes(&self.transaction_id))
+            .collect();
+
+        let mut offset = STUN_HEADER_NUM_BYTES;
+        let attributes: Vec<StunAttribute> = self
+            .attributes
+            .iter()
+            .zip(&values)
+            .map(|(a, value)| {
+                let attribute = StunAttribute {
+                    attribute_type: a.attribute_type,
+                    attribute_length: value.len() as u16,
+                    attribute_value: value,
+                    padding: &a.padding,
+                    offset,
+                };
+                offset += attribute.serialized_length();
+                attribute
+            })
+            .collect();
+
+        let message_length = self
+            .message_length
+            .unwrap_or(serialized_attributes_length(&attributes) as u16);
+
+        serialize(&StunMessage {
+            message_class: self.message_class,
+            message_method: self.message_method,
+            message_length,
+            magic_cookie: STUN_MAGIC_COOKIE,
+            transaction_id: &self.transaction_id,
+            attributes,
+            raw: &[],
+        })
+    }
+}
+
+impl<'a> From<&StunMessage<'a>> for OwnedStunMessage {
+    fn from(message: &StunMessage<'a>) -> Self {
+        let attributes: Vec<OwnedStunAttribute> = message
+            .attributes
+            .iter()
+            .map(|a| OwnedStunAttribute {
+                attribute_type: a.attribute_type,
+                value: StunAttributeValue::decode(a, message.transaction_id),
+                padding: match a.padding.iter().all(|&b| b == 0) {
+                    true => Vec::new(),
+                    false => a.padding.to_vec(),
+                },
+            })
+            .collect();
+
+        let message_length = match serialized_attributes_length(&message.attributes) {
+            length if length == message.message_length as usize => None,
+            _ => Some(message.message_length),
+        };
+
+        OwnedStunMessage {
+            message_class: message.message_class,
+            message_method: message.message_method,
+            message_length,
+            transaction_id: *message.transaction_id,
+            attributes,
+        }
+    }
+}
+
+impl StunAttributeValue {
+    /// Decode the value of the given attribute according to its type
+    pub(crate) fn decode(
+        attribute: &StunAttribute,
+        transaction_id: &[u8; STUN_TRANSACTION_ID_NUM_BYTES],
+    ) -> Self {
+        let value = attribute.attribute_value;
+        let decoded = match StunAttributeType::try_from(attribute.attribute_type) {
+            Ok(StunAttributeType::MappedAddress)
+            | Ok(StunAttributeType::AlternateServer)
+            | Ok(StunAttributeType::ResponseOrigin)
+            | Ok(StunAttributeType::OtherAddress) => all_consuming(parse_address)(value)
+                .ok()
+                .map(|(_, address)| StunAttributeValue::Address(address)),
+            Ok(StunAttributeType::XorMappedAddress)
+            | Ok(StunAttributeType::XorPeerAddress)
+            | Ok(StunAttributeType::XorRelayedAddress) => {
+                all_consuming(|input| parse_xor_address(input, transaction_id))(value)
+                    .ok()
+                    .map(|(_, address)| StunAttributeValue::XorAddress(address))
+            }
+            Ok(StunAttributeType::Username) => parse_username(value)
+                .ok()
+                .map(|(_, text)| StunAttributeValue::Text(text.as_str().to_string())),
+            Ok(StunAttributeType::Realm) => parse_realm(value)
+                .ok()
+                .map(|(_, text)| StunAttributeValue::Text(text.as_str().to_string())),
+            Ok(StunAttributeType::Nonce) => parse_nonce(value)
+                .ok()
+                .map(|(_, text)| StunAttributeValue::Text(text.as_str().to_string())),
+            Ok(StunAttributeType::Software) => parse_software(value)
+                .ok()
+                .map(|(_, text)| StunAttributeValue::Text(text.as_str().to_string())),
+            Ok(StunAttributeType::ErrorCode) => {
+                parse_error_code(value)
+                    .ok()
+                    .map(|(_, error_code)| StunAttributeValue::ErrorCode {
+                        code: error_code.code,
+                        reason: error_code.reason.to_string(),
+                    })
+            }
+            Ok(StunAttributeType::Lifetime)
+            | Ok(StunAttributeType::ConnectionId)
+            | Ok(StunAttributeType::ChangeRequest)
+            | Ok(StunAttributeType::ChannelNumber)
+            | Ok(StunAttributeType::RequestedTransport) => <[u8; 4]>::try_from(value)
+                .ok()
+                .map(|bytes| StunAttributeValue::U32(u32::from_be_bytes(bytes))),
+            _ => None,
+        };
+
+        // only keep the decoded value if it encodes back to the same bytes, e.g. an address with
+        // non-zero reserved bits is kept raw
+        match decoded {
+            Some(decoded) if decoded.to_bytes(transaction_id) == value => decoded,
+            _ => StunAttributeValue::Raw(value.to_vec()),
+        }
+    }
+
+    /// Encode the value, using the given transaction id for XOR'd addresses
+    fn to_bytes(&self, transaction_id: &[u8; STUN_TRANSACTION_ID_NUM_BYTES]) -> Vec<u8> {
+        match self {
+            StunAttributeValue::Address(address) => serialize_address(address),
+            StunAttributeValue::XorAddress(address) => {
+                serialize_xor_address(address, transaction_id)
+            }
+            StunAttributeValue::Text(text) => text.as_bytes().to_vec(),
+            StunAttributeValue::ErrorCode { code, reason } => serialize_error_code(*code, reason),
+            StunAttributeValue::U32(value) => value.to_be_bytes().to_vec(),
+            StunAttributeValue::Raw(value) => value.clone(),
+        }
+    }
+}
+
+impl fmt::Display for StunAttributeValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StunAttributeValue::Address(address) | StunAttributeValue::XorAddress(address) => {
+                write!(f, "{}", address)
+            }
+            // quoted and escaped, the text comes from the network
+            StunAttributeValue::Text(text) => write!(f, "{:?}", text),
+            StunAttributeValue::ErrorCode { code, reason } => write!(f, "{} {:?}", code, reason),
+            StunAttributeValue::U32(value) => write!(f, "{}", value),
+            StunAttributeValue::Raw(value) => write!(f, "{}", Hex(value)),
+        }
+    }
+}
+
+/// Formats bytes as lowercase hex without separators
+pub(crate) struct Hex<'a>(pub &'a [u8]);
+
+impl fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.iter().try_for_each(|b| write!(f, "{:02x}", b))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_stun_message;
+    use crate::stun_message_builder::*;
+
+    fn build_message() -> Vec<u8> {
+        let transaction_id = [0x0B; STUN_TRANSACTION_ID_NUM_BYTES];
+
+        StunMessageBuilder::new(
+            StunMessageClass::ErrorResponse,
+            StunMessageMethod::Allocate,
+            &transaction_id,
+        )
+        .add_xor_address_attribute(0x0020, &"192.0.2.1:32853".parse().unwrap())
+        .add_address_attribute(0x0001, &"[2001:db8::1]:3478".parse().unwrap())
+        .add_error_code_attribute(401, "Unauthorized")
+        .add_attribute(0x8022, b"odd")
+        .add_u32_attribute(0x000D, 600)
+        .add_attribute(0xC001, &[0xDE, 0xAD])
+        .add_fingerprint()
+        .build()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_decode_attributes() {
+        let data = build_message();
+        let (_, message) = parse_stun_message(&data).unwrap();
+        let owned = OwnedStunMessage::from(&message);
+
+        assert_eq!(owned.message_length, None);
+        let values: Vec<&StunAttributeValue> = owned.attributes.iter().map(|a| &a.value).collect();
+        assert_eq!(
+            values[..6],
+            [
+                &StunAttributeValue::XorAddress("192.0.2.1:32853".parse().unwrap()),
+                &StunAttributeValue::Address("[2001:db8::1]:3478".parse().unwrap()),
+                &StunAttributeValue::ErrorCode {
+                    code: 401,
+                    reason: "Unauthorized".to_string()
+                },
+                &StunAttributeValue::Text("odd".to_string()),
+                &StunAttributeValue::U32(600),
+                &StunAttributeValue::Raw(vec![0xDE, 0xAD]),
+            ]
+        );
+        assert_eq!(owned.to_bytes().unwrap(), data);
+    }
+
+    #[test]
+    fn test_undecodable_values_are_raw() {
+        let mut data = build_message();
+
+        // set the reserved byte of the XOR-MAPPED-ADDRESS and a bit of the SOFTWARE padding
+        data[24] = 0x80;
+        data[83] = 0x01;
+
+        let (_, message) = parse_stun_message(&data).unwrap();
+        let owned = OwnedStunMessage::from(&message);
+
+        assert_eq!(
+            owned.attributes[0].value,
+            StunAttributeValue::Raw(data[24..32].to_vec())
+        );
+        assert_eq!(owned.attributes[3].padding, [0x01]);
+        assert_eq!(owned.to_bytes().unwrap(), data);
+    }
+
+    #[test]
+    fn test_message_length_mismatch() {
+        let mut data = build_message();
+
+        // the parser rejects such a message, but one can be put together by hand
+        let (_, mut message) = parse_stun_message(&data).unwrap();
+        message.message_length -= 4;
+        let owned = OwnedStunMessage::from(&message);
+
+        assert_eq!(owned.message_length, Some(message.message_length));
+        data[3] -= 4;
+        assert_eq!(owned.to_bytes().unwrap(), data);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_json_roundtrip() {
+        let mut data = build_message();
+        data[83] = 0x01;
+
+        let (_, message) = parse_stun_message(&data).unwrap();
+        let json = serde_json::to_value(OwnedStunMessage::from(&message)).unwrap();
+
+        assert_eq!(json["message_class"], "ErrorResponse");
+        assert_eq!(json["message_method"], "Allocate");
+        assert_eq!(json["transaction_id"], "0b0b0b0b0b0b0b0b0b0b0b0b");
+        assert_eq!(
+            json["attributes"][0],
+            serde_json::json!({"type": 0x0020, "xor_address": "192.0.2.1:32853"})
+        );
+        assert_eq!(
+            json["attributes"][2],
+            serde_json::json!({"type": 0x0009, "error_code": {"code": 401, "reason": "Unauthorized"}})
+        );
+        assert_eq!(
+            json["attributes"][3],
+            serde_json::json!({"type": 0x8022, "text": "odd", "padding": "01"})
+        );
+        assert_eq!(
+            json["attributes"][5],
+            serde_json::json!({"type": 0xC001, "raw": "dead"})
+        );
+        assert!(json.get("message_length").is_none());
+
+        let owned: OwnedStunMessage = serde_json::from_value(json).unwrap();
+        assert_eq!(owned.to_bytes().unwrap(), data);
+    }
+}
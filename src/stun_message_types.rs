@@ -1,7 +1,12 @@
+use core::fmt;
+
 use num_enum::TryFromPrimitive;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 /// Stun message classes
 #[derive(Debug, Eq, PartialEq, TryFromPrimitive, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[repr(u16)]
 pub enum StunMessageClass {
     Request = 0b00,
@@ -12,7 +17,53 @@ pub enum StunMessageClass {
 
 /// Stun message methods
 #[derive(Debug, Eq, PartialEq, TryFromPrimitive, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[repr(u16)]
 pub enum StunMessageMethod {
-    Binding = 0b00,
+    Binding = 0x0001,
+
+    /// TURN methods -- https://tools.ietf.org/html/rfc5766#section-13
+    Allocate = 0x0003,
+    Refresh = 0x0004,
+    Send = 0x0006,
+    Data = 0x0007,
+    CreatePermission = 0x0008,
+    ChannelBind = 0x0009,
+
+    /// TURN-TCP methods -- https://tools.ietf.org/html/rfc6062#section-6.1
+    Connect = 0x000A,
+    ConnectionBind = 0x000B,
+    ConnectionAttempt = 0x000C,
+}
+
+impl fmt::Display for StunMessageClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            StunMessageClass::Request => "Request",
+            StunMessageClass::Indication => "Indication",
+            StunMessageClass::SuccessResponse => "Success Response",
+            StunMessageClass::ErrorResponse => "Error Response",
+        };
+
+        f.write_str(name)
+    }
+}
+
+impl fmt::Display for StunMessageMethod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            StunMessageMethod::Binding => "Binding",
+            StunMessageMethod::Allocate => "Allocate",
+            StunMessageMethod::Refresh => "Refresh",
+            StunMessageMethod::Send => "Send",
+            StunMessageMethod::Data => "Data",
+            StunMessageMethod::CreatePermission => "CreatePermission",
+            StunMessageMethod::ChannelBind => "ChannelBind",
+            StunMessageMethod::Connect => "Connect",
+            StunMessageMethod::ConnectionBind => "ConnectionBind",
+            StunMessageMethod::ConnectionAttempt => "ConnectionAttempt",
+        };
+
+        f.write_str(name)
+    }
 }
@@ -0,0 +1,165 @@
+use crate::parser::parse_attribute;
+use crate::stun_attribute::*;
+use crate::stun_constants::*;
+use crate::stun_message_types::*;
+
+/// A zero-allocation view of a STUN message, produced by `parse_stun_message_view`.
+///
+/// The header fields are decoded up front, while the attributes are left in the input buffer
+/// and decoded on demand.  The attributes are validated when the view is created, so iterating
+/// over them cannot fail.
+#[derive(Debug, Clone, Copy)]
+pub struct StunMessageView<'a> {
+    /// message class, encoded into message type
+    pub message_class: StunMessageClass,
+
+    /// message method, encoded into message type
+    pub message_method: StunMessageMethod,
+
+    /// message length -- 16 bits
+    pub message_length: u16,
+
+    /// magic cookie -- 32 bits
+    pub magic_cookie: u32,
+
+    /// transaction id -- 96 bits
+    pub transaction_id: &'a [u8; STUN_TRANSACTION_ID_NUM_BYTES],
+
+    /// serialized attributes -- message length bytes
+    attributes: &'a [u8],
+}
+
+impl<'a> StunMessageView<'a> {
+    pub(crate) fn new(
+        message_class: StunMessageClass,
+        message_method: StunMessageMethod,
+        message_length: u16,
+        magic_cookie: u32,
+        transaction_id: &'a [u8; STUN_TRANSACTION_ID_NUM_BYTES],
+        attributes: &'a [u8],
+    ) -> Self {
+        StunMessageView {
+            message_class,
+            message_method,
+            message_length,
+            magic_cookie,
+            transaction_id,
+            attributes,
+        }
+    }
+
+    /// Iterate over the attributes of the message, in the order they appear in the message
+    pub fn attributes(&self) -> StunAttributeIter<'a> {
+        StunAttributeIter {
+            input: self.attributes,
+            offset: STUN_HEADER_NUM_BYTES,
+        }
+    }
+
+    /// Get the first attribute of the given type, if the message contains one
+    pub fn find(&self, attribute_type: u16) -> Option<StunAttribute<'a>> {
+        self.attributes()
+            .find(|a| a.attribute_type == attribute_type)
+    }
+}
+
+/// An iterator over the attributes of a `StunMessageView`
+#[derive(Debug, Clone)]
+pub struct StunAttributeIter<'a> {
+    input: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Iterator for StunAttributeIter<'a> {
+    type Item = StunAttribute<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // the attributes were validated when the view was created, so an error here means
+        // there are no attributes left
+        match parse_attribute(self.input) {
+            Ok((input, mut attribute)) => {
+                attribute.offset = self.offset;
+                self.input = input;
+                self.offset += attribute.serialized_length();
+                Some(attribute)
+            }
+            Err(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::*;
+    use crate::stun_message_builder::*;
+
+    #[test]
+    fn test_view_matches_parsed_message() {
+        let transaction_id = [0x21; STUN_TRANSACTION_ID_NUM_BYTES];
+        let data = StunMessageBuilder::new(
+            StunMessageClass::SuccessResponse,
+            StunMessageMethod::Binding,
+            &transaction_id,
+        )
+        .add_attribute(0x0020, &[0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07])
+        .add_attribute(0x8022, b"software")
+        .add_attribute(0x8022, b"second")
+        .add_message_integrity(b"key")
+        .build()
+        .unwrap();
+
+        let (_, message) = parse_stun_message(&data).unwrap();
+        let (remaining, view) = parse_stun_message_view(&data).unwrap();
+
+        assert_eq!(remaining.len(), 0);
+        assert_eq!(view.message_class, message.message_class);
+        assert_eq!(view.message_method, message.message_method);
+        assert_eq!(view.message_length, message.message_length);
+        assert_eq!(view.transaction_id, message.transaction_id);
+
+        let attributes: Vec<StunAttribute> = view.attributes().collect();
+        assert_eq!(attributes.len(), message.attributes.len());
+        for (a, b) in attributes.iter().zip(&message.attributes) {
+            assert_eq!(a.attribute_type, b.attribute_type);
+            assert_eq!(a.attribute_length, b.attribute_length);
+            assert_eq!(a.attribute_value, b.attribute_value);
+            assert_eq!(a.offset, b.offset);
+        }
+    }
+
+    #[test]
+    fn test_view_find() {
+        let transaction_id = [0x22; STUN_TRANSACTION_ID_NUM_BYTES];
+        let data = StunMessageBuilder::new(
+            StunMessageClass::Request,
+            StunMessageMethod::Binding,
+            &transaction_id,
+        )
+        .add_attribute(0x8022, b"first")
+        .add_attribute(0x8022, b"second")
+        .build()
+        .unwrap();
+
+        let (_, view) = parse_stun_message_view(&data).unwrap();
+
+        assert_eq!(view.find(0x8022).unwrap().attribute_value, b"first");
+        assert!(view.find(0x0006).is_none());
+    }
+
+    #[test]
+    fn test_view_empty() {
+        let transaction_id = [0x23; STUN_TRANSACTION_ID_NUM_BYTES];
+        let data = StunMessageBuilder::new(
+            StunMessageClass::Request,
+            StunMessageMethod::Binding,
+            &transaction_id,
+        )
+        .build()
+        .unwrap();
+
+        let (_, view) = parse_stun_message_view(&data).unwrap();
+
+        assert_eq!(view.attributes().count(), 0);
+    }
+}
@@ -0,0 +1,111 @@
+use crate::stun_errors::StunParseError;
+
+use alloc::vec::Vec;
+
+use nom::number::complete::{be_u16, be_u32};
+use nom::IResult;
+
+/// Flag of a CHANGE-REQUEST attribute asking for the response to be sent from the alternate IP
+/// address, https://tools.ietf.org/html/rfc5780#section-7.2
+pub const STUN_CHANGE_REQUEST_CHANGE_IP: u32 = 0x04;
+
+/// Flag of a CHANGE-REQUEST attribute asking for the response to be sent from the alternate port
+pub const STUN_CHANGE_REQUEST_CHANGE_PORT: u32 = 0x02;
+
+/// The value of a CHANGE-REQUEST attribute, https://tools.ietf.org/html/rfc5780#section-7.2
+///
+/// 0                   1                   2                   3
+/// 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 A B 0|
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+#[derive(Debug, Default, Eq, PartialEq, Clone, Copy)]
+pub struct StunChangeRequest {
+    /// send the response from the alternate IP address (A)
+    pub change_ip: bool,
+
+    /// send the response from the alternate port (B)
+    pub change_port: bool,
+}
+
+/// Parse the value of a CHANGE-REQUEST attribute, ignoring the unused bits
+pub fn parse_change_request(
+    input: &[u8],
+) -> IResult<&[u8], StunChangeRequest, StunParseError<&[u8]>> {
+    let (input, flags) = be_u32(input)?;
+
+    Ok((
+        input,
+        StunChangeRequest {
+            change_ip: flags & STUN_CHANGE_REQUEST_CHANGE_IP != 0,
+            change_port: flags & STUN_CHANGE_REQUEST_CHANGE_PORT != 0,
+        },
+    ))
+}
+
+/// Serialize the value of a CHANGE-REQUEST attribute
+pub fn serialize_change_request(change_request: &StunChangeRequest) -> Vec<u8> {
+    let mut flags = 0;
+    if change_request.change_ip {
+        flags |= STUN_CHANGE_REQUEST_CHANGE_IP;
+    }
+    if change_request.change_port {
+        flags |= STUN_CHANGE_REQUEST_CHANGE_PORT;
+    }
+
+    flags.to_be_bytes().to_vec()
+}
+
+/// Parse the value of a RESPONSE-PORT attribute, the port a response over UDP must be sent
+/// to, https://tools.ietf.org/html/rfc5780#section-7.5
+///
+/// 0                   1                   2                   3
+/// 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |             Port              |            Padding            |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+pub fn parse_response_port(input: &[u8]) -> IResult<&[u8], u16, StunParseError<&[u8]>> {
+    let (input, port) = be_u16(input)?;
+    let (input, _) = be_u16(input)?;
+
+    Ok((input, port))
+}
+
+/// Serialize the value of a RESPONSE-PORT attribute
+pub fn serialize_response_port(port: u16) -> Vec<u8> {
+    let mut output = Vec::with_capacity(4);
+
+    output.extend_from_slice(&port.to_be_bytes());
+    output.extend_from_slice(&[0, 0]);
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn change_request_roundtrip() {
+        let change_request = StunChangeRequest {
+            change_ip: true,
+            change_port: false,
+        };
+        let value = serialize_change_request(&change_request);
+        assert_eq!(value, [0x00, 0x00, 0x00, 0x04]);
+        assert_eq!(parse_change_request(&value).unwrap().1, change_request);
+
+        // unused bits are ignored
+        let (_, change_request) = parse_change_request(&[0xFF, 0xFF, 0xFF, 0xF9]).unwrap();
+        assert_eq!(change_request, StunChangeRequest::default());
+    }
+
+    #[test]
+    fn response_port_roundtrip() {
+        let value = serialize_response_port(3479);
+        assert_eq!(value, [0x0D, 0x97, 0x00, 0x00]);
+        assert_eq!(parse_response_port(&value).unwrap(), (&[][..], 3479));
+
+        assert!(parse_response_port(&[0x0D]).is_err());
+    }
+}
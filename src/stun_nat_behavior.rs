@@ -0,0 +1,1021 @@
+use crate::parser::{parse_attribute_value, parse_stun_message};
+use crate::stun_address::*;
+use crate::stun_attribute_types::*;
+use crate::stun_client_transaction::*;
+use crate::stun_constants::*;
+use crate::stun_error_code::*;
+use crate::stun_errors::StunNatBehaviorError;
+use crate::stun_five_tuple::*;
+use crate::stun_integrity::*;
+use crate::stun_message_builder::*;
+use crate::stun_message_types::*;
+use crate::stun_nat_attributes::*;
+use crate::stun_text_attributes::*;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::time::Duration;
+use std::net::SocketAddr;
+use std::time::Instant;
+
+/// The mapping behavior of a NAT, https://tools.ietf.org/html/rfc4787#section-4.1
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum NatMapping {
+    /// the mapped address is the local address, there is no NAT
+    NoNat,
+
+    /// the same mapping is used for all destinations
+    EndpointIndependent,
+
+    /// the same mapping is used for destinations with the same IP address
+    AddressDependent,
+
+    /// the same mapping is only used for the same destination IP address and port
+    AddressAndPortDependent,
+}
+
+/// The filtering behavior of a NAT or firewall, https://tools.ietf.org/html/rfc4787#section-5
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum NatFiltering {
+    /// packets from any address are let through to a mapping
+    EndpointIndependent,
+
+    /// only packets from the IP addresses the mapping has sent to are let through
+    AddressDependent,
+
+    /// only packets from the addresses the mapping has sent to are let through
+    AddressAndPortDependent,
+}
+
+/// Bounds on the lifetime of a NAT binding without outbound traffic,
+/// https://tools.ietf.org/html/rfc5780#section-4.6
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct BindingLifetime {
+    /// the binding was alive after this long, which is zero if it had expired at the first probe
+    pub at_least: Duration,
+
+    /// the binding had expired after this long, or `None` if it was alive at the last probe
+    pub less_than: Option<Duration>,
+}
+
+/// The behavior of the NAT between a client and a server, as found by `NatBehaviorDiscovery`
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct NatBehavior {
+    /// the address the server saw the first request come from
+    pub mapped_address: SocketAddr,
+
+    /// how the NAT maps local addresses to public ones
+    pub mapping: NatMapping,
+
+    /// which packets the NAT lets through to a mapping
+    pub filtering: NatFiltering,
+
+    /// whether a packet sent to the mapped address from behind the NAT is looped back, or `None`
+    /// if it was not tested
+    pub hairpinning: Option<bool>,
+
+    /// how long the first mapping lives without outbound traffic, or `None` if it was not
+    /// tested
+    pub binding_lifetime: Option<BindingLifetime>,
+}
+
+/// Options of `NatBehaviorDiscovery`
+#[derive(Debug, Clone)]
+pub struct NatBehaviorOptions {
+    /// the SOFTWARE attribute to add to the requests, if any
+    pub software: Option<String>,
+
+    /// add a FINGERPRINT attribute to the requests
+    pub fingerprint: bool,
+
+    /// the retransmission parameters of each request, which also set how long a filtering test
+    /// waits for a response that the NAT may drop
+    pub config: TransactionConfig,
+
+    /// test whether the NAT supports hairpinning, which sends a request from the secondary socket
+    pub hairpinning: bool,
+
+    /// the times after the last request from the primary socket at which to probe whether its
+    /// mapping is still alive, in increasing order.  Probing stops at the first one at which it
+    /// has expired.  Without probes the binding lifetime is not tested.
+    pub lifetime_probes: Vec<Duration>,
+}
+
+impl Default for NatBehaviorOptions {
+    fn default() -> Self {
+        NatBehaviorOptions {
+            software: None,
+            fingerprint: false,
+            config: TransactionConfig::default(),
+            hairpinning: true,
+            lifetime_probes: Vec::new(),
+        }
+    }
+}
+
+/// The local socket a request of `NatBehaviorDiscovery` must be sent from
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum NatBehaviorSocket {
+    /// the socket whose NAT behavior is discovered, bound to the local address given to
+    /// `NatBehaviorDiscovery::new`
+    Primary,
+
+    /// a second socket on the same host, used to test hairpinning and the binding lifetime
+    Secondary,
+}
+
+/// A request of `NatBehaviorDiscovery` which is due to be sent
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct NatBehaviorTransmit<'a> {
+    /// the socket to send the request from
+    pub socket: NatBehaviorSocket,
+
+    /// the address to send the request to
+    pub destination: SocketAddr,
+
+    /// the serialized request
+    pub data: &'a [u8],
+}
+
+/// The tests run by `NatBehaviorDiscovery`, in order
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+enum Test {
+    /// a Binding request to the primary address of the server, test I
+    Binding,
+
+    /// a request to send the response from the alternate address, filtering test II
+    FilteringChangeAddress,
+
+    /// a request to send the response from the alternate port, filtering test III
+    FilteringChangePort,
+
+    /// a Binding request to the alternate IP address and primary port, mapping test II
+    MappingAlternateAddress,
+
+    /// a Binding request to the alternate address, mapping test III
+    MappingAlternatePort,
+
+    /// a request from the secondary socket to the mapped address
+    Hairpinning,
+
+    /// a request from the secondary socket with a RESPONSE-PORT of the mapped port, after the
+    /// lifetime probe with the given index
+    BindingLifetime(usize),
+}
+
+/// A sans-IO procedure discovering the behavior of the NAT between a client and a server which
+/// supports NAT behavior discovery, https://tools.ietf.org/html/rfc5780#section-4
+///
+/// The procedure runs these tests one after the other, each one a Binding request in a
+/// `ClientTransaction`:
+///
+/// - test I finds the mapped address, and the alternate address of the server in its
+///   OTHER-ADDRESS.  There is no NAT if the mapped address is the local address.
+/// - the filtering tests ask the server to answer from its alternate address and then from its
+///   alternate port, a response which times out means the NAT dropped it.  They run before the
+///   mapping tests, which would let responses from the alternate address through.
+/// - the mapping tests send requests to the alternate IP address and then to the alternate
+///   address, and compare the mapped addresses, unless there is no NAT
+/// - the hairpinning test sends a request from the secondary socket to the mapped address, which
+///   the primary socket receives if the NAT loops it back
+/// - each binding lifetime probe waits for the given time after the last request from the
+///   primary socket, and sends a request from the secondary socket with a RESPONSE-PORT of the
+///   mapped port, whose response only reaches the primary socket if its mapping is alive
+///
+/// Like `ClientTransaction` the procedure does no I/O:
+///
+/// - `poll_transmit` returns each request when it is due, with the socket to send it from
+/// - `poll_timeout` returns when `handle_timeout` must next be called
+/// - `handle_input` must be called with each datagram received on either socket
+/// - `outcome` returns the `NatBehavior` report, or the error, once the procedure is complete
+#[derive(Debug)]
+pub struct NatBehaviorDiscovery {
+    server: SocketAddr,
+    local: SocketAddr,
+    options: NatBehaviorOptions,
+
+    /// transaction ids for the requests still to be sent
+    transaction_ids: Vec<[u8; STUN_TRANSACTION_ID_NUM_BYTES]>,
+
+    test: Test,
+    destination: SocketAddr,
+
+    /// the request of the current test, which is `None` while waiting for a lifetime probe
+    transaction: Option<ClientTransaction>,
+
+    /// time at which to send the next lifetime probe
+    probe_at: Option<Instant>,
+
+    /// time at which the primary socket last sent a request
+    refreshed_at: Instant,
+
+    /// the mapped address and the alternate address of the server, from test I
+    mapped_address: Option<SocketAddr>,
+    other_address: Option<SocketAddr>,
+
+    /// the mapped address of mapping test II
+    alternate_mapped_address: Option<SocketAddr>,
+
+    mapping: Option<NatMapping>,
+    filtering: Option<NatFiltering>,
+    hairpinning: Option<bool>,
+    binding_lifetime: Option<BindingLifetime>,
+
+    outcome: Option<Result<NatBehavior, StunNatBehaviorError>>,
+}
+
+impl NatBehaviorDiscovery {
+    /// Start discovering the behavior of the NAT between the given local address, which must
+    /// be the address the primary socket is bound to rather than a wildcard address, and the
+    /// primary address of the server, with the default options.  The first request must be sent
+    /// right away, see `poll_transmit`.
+    pub fn new(
+        server: SocketAddr,
+        local: SocketAddr,
+        now: Instant,
+    ) -> Result<Self, StunNatBehaviorError> {
+        Self::with_options(server, local, NatBehaviorOptions::default(), now)
+    }
+
+    /// Start discovering the behavior of the NAT with the given options
+    pub fn with_options(
+        server: SocketAddr,
+        local: SocketAddr,
+        options: NatBehaviorOptions,
+        now: Instant,
+    ) -> Result<Self, StunNatBehaviorError> {
+        if let Some(software) = &options.software {
+            StunSoftware::new(software).map_err(|_| StunNatBehaviorError::InvalidRequestError)?;
+        }
+
+        // one request for each of the six tests at most, and for each lifetime probe
+        let mut transaction_ids = Vec::new();
+        for _ in 0..6 + options.lifetime_probes.len() {
+            transaction_ids.push(random_transaction_id()?);
+        }
+
+        let mut discovery = NatBehaviorDiscovery {
+            server,
+            local,
+            options,
+            transaction_ids,
+            test: Test::Binding,
+            destination: server,
+            transaction: None,
+            probe_at: None,
+            refreshed_at: now,
+            mapped_address: None,
+            other_address: None,
+            alternate_mapped_address: None,
+            mapping: None,
+            filtering: None,
+            hairpinning: None,
+            binding_lifetime: None,
+            outcome: None,
+        };
+        discovery.start(Test::Binding, now)?;
+
+        Ok(discovery)
+    }
+
+    /// The request, if one is due to be sent.  Each transmission is returned once.
+    pub fn poll_transmit(&mut self) -> Option<NatBehaviorTransmit<'_>> {
+        let socket = Self::socket(self.test);
+        let destination = self.destination;
+
+        self.transaction
+            .as_mut()?
+            .poll_transmit()
+            .map(|data| NatBehaviorTransmit {
+                socket,
+                destination,
+                data,
+            })
+    }
+
+    /// The time at which `handle_timeout` must be called, or `None` once the procedure is
+    /// complete
+    pub fn poll_timeout(&self) -> Option<Instant> {
+        if self.outcome.is_some() {
+            return None;
+        }
+
+        match (self.probe_at, &self.transaction) {
+            (Some(probe_at), _) => Some(probe_at),
+            (None, Some(transaction)) => transaction.poll_timeout(),
+            (None, None) => None,
+        }
+    }
+
+    /// Advance the procedure to the given time, retransmitting the current request, moving on
+    /// from a test which timed out or sending a lifetime probe
+    pub fn handle_timeout(&mut self, now: Instant) {
+        if self.outcome.is_some() {
+            return;
+        }
+
+        if let Some(probe_at) = self.probe_at {
+            if now >= probe_at {
+                self.probe_at = None;
+                let result = self.start(self.test, now);
+                self.finish_on_error(result);
+            }
+            return;
+        }
+
+        let transaction = match &mut self.transaction {
+            Some(transaction) => transaction,
+            None => return,
+        };
+        let deadline = transaction.poll_timeout();
+        transaction.handle_timeout(now);
+
+        match transaction.outcome() {
+            Some(TransactionOutcome::Timeout) => {
+                let result = self.complete(None, now);
+                self.finish_on_error(result);
+            }
+            // a retransmission
+            _ if transaction.poll_timeout() != deadline
+                && Self::socket(self.test) == NatBehaviorSocket::Primary =>
+            {
+                self.refreshed_at = now
+            }
+            _ => {}
+        }
+    }
+
+    /// Handle a datagram received on either socket.  Returns whether it is a response to the
+    /// current request, or the request itself looped back by the NAT.  Anything else is ignored.
+    pub fn handle_input(&mut self, input: &[u8], now: Instant) -> bool {
+        let transaction = match (&self.outcome, &mut self.transaction) {
+            (None, Some(transaction)) => transaction,
+            _ => return false,
+        };
+        let message = match parse_stun_message(input) {
+            Ok((_, message)) => message,
+            Err(_) => return false,
+        };
+
+        if self.test == Test::Hairpinning {
+            if message.message_class != StunMessageClass::Request
+                || message.transaction_id != transaction.transaction_id()
+            {
+                return false;
+            }
+            self.hairpinning = Some(true);
+            let result = self.start_lifetime_probe(0, now);
+            self.finish_on_error(result);
+            return true;
+        }
+
+        if !transaction.handle_response(&message) {
+            return false;
+        }
+        let response = match transaction.outcome() {
+            Some(TransactionOutcome::Success(response)) => Ok(response.clone()),
+            Some(TransactionOutcome::Error(response)) => Err(error_code(response)),
+            _ => return true,
+        };
+
+        let result = response.and_then(|response| self.complete(Some(&response), now));
+        self.finish_on_error(result);
+        true
+    }
+
+    /// The outcome of the procedure, once it is complete
+    pub fn outcome(&self) -> Option<&Result<NatBehavior, StunNatBehaviorError>> {
+        self.outcome.as_ref()
+    }
+
+    /// Consume the procedure, returning its outcome if it is complete
+    pub fn into_outcome(self) -> Option<Result<NatBehavior, StunNatBehaviorError>> {
+        self.outcome
+    }
+
+    fn socket(test: Test) -> NatBehaviorSocket {
+        match test {
+            Test::Hairpinning | Test::BindingLifetime(_) => NatBehaviorSocket::Secondary,
+            _ => NatBehaviorSocket::Primary,
+        }
+    }
+
+    /// Move on from the current test, with the success response to its request or `None` if it
+    /// timed out
+    fn complete(
+        &mut self,
+        response: Option<&[u8]>,
+        now: Instant,
+    ) -> Result<(), StunNatBehaviorError> {
+        let transaction_id = match &self.transaction {
+            Some(transaction) => *transaction.transaction_id(),
+            None => return Ok(()),
+        };
+        let mapped_address = match response {
+            Some(response) => Some(parse_response(response, &transaction_id)?),
+            None => None,
+        };
+
+        match (self.test, mapped_address) {
+            (Test::Binding, Some((mapped_address, other_address))) => {
+                let other_address =
+                    other_address.ok_or(StunNatBehaviorError::UnsupportedServerError)?;
+                self.mapped_address = Some(mapped_address);
+                self.other_address = Some(other_address);
+                if mapped_address == self.local {
+                    self.mapping = Some(NatMapping::NoNat);
+                }
+                self.start(Test::FilteringChangeAddress, now)
+            }
+            (Test::FilteringChangeAddress, Some(_)) => {
+                self.filtering = Some(NatFiltering::EndpointIndependent);
+                self.start_mapping_tests(now)
+            }
+            (Test::FilteringChangeAddress, None) => self.start(Test::FilteringChangePort, now),
+            (Test::FilteringChangePort, response) => {
+                self.filtering = Some(match response {
+                    Some(_) => NatFiltering::AddressDependent,
+                    None => NatFiltering::AddressAndPortDependent,
+                });
+                self.start_mapping_tests(now)
+            }
+            (Test::MappingAlternateAddress, Some((mapped_address, _))) => {
+                if Some(mapped_address) == self.mapped_address {
+                    self.mapping = Some(NatMapping::EndpointIndependent);
+                    return self.start_hairpinning(now);
+                }
+                self.alternate_mapped_address = Some(mapped_address);
+                self.start(Test::MappingAlternatePort, now)
+            }
+            (Test::MappingAlternatePort, Some((mapped_address, _))) => {
+                self.mapping = Some(
+                    match Some(mapped_address) == self.alternate_mapped_address {
+                        true => NatMapping::AddressDependent,
+                        false => NatMapping::AddressAndPortDependent,
+                    },
+                );
+                self.start_hairpinning(now)
+            }
+            (Test::Hairpinning, _) => {
+                self.hairpinning = Some(false);
+                self.start_lifetime_probe(0, now)
+            }
+            (Test::BindingLifetime(index), response) => {
+                let probe = self.options.lifetime_probes[index];
+                let lifetime = self.binding_lifetime.get_or_insert(BindingLifetime {
+                    at_least: Duration::from_secs(0),
+                    less_than: None,
+                });
+                match response {
+                    Some(_) => {
+                        lifetime.at_least = probe;
+                        self.start_lifetime_probe(index + 1, now)
+                    }
+                    None => {
+                        lifetime.less_than = Some(probe);
+                        self.start_lifetime_probe(self.options.lifetime_probes.len(), now)
+                    }
+                }
+            }
+            // test I and the mapping tests must be answered
+            (_, None) => Err(StunNatBehaviorError::TimeoutError),
+        }
+    }
+
+    fn start_mapping_tests(&mut self, now: Instant) -> Result<(), StunNatBehaviorError> {
+        match self.mapping {
+            Some(NatMapping::NoNat) => self.start_hairpinning(now),
+            _ => self.start(Test::MappingAlternateAddress, now),
+        }
+    }
+
+    fn start_hairpinning(&mut self, now: Instant) -> Result<(), StunNatBehaviorError> {
+        match self.options.hairpinning {
+            true => self.start(Test::Hairpinning, now),
+            false => self.start_lifetime_probe(0, now),
+        }
+    }
+
+    /// Wait for the lifetime probe with the given index, or complete the procedure if there is
+    /// none
+    fn start_lifetime_probe(
+        &mut self,
+        index: usize,
+        now: Instant,
+    ) -> Result<(), StunNatBehaviorError> {
+        self.transaction = None;
+
+        let probe = match self.options.lifetime_probes.get(index) {
+            Some(probe) => *probe,
+            None => {
+                self.outcome = Some(self.report());
+                return Ok(());
+            }
+        };
+
+        self.test = Test::BindingLifetime(index);
+        let probe_at = self.refreshed_at + probe;
+        match now >= probe_at {
+            true => self.start(self.test, now),
+            false => {
+                self.probe_at = Some(probe_at);
+                Ok(())
+            }
+        }
+    }
+
+    /// Send the request of the given test
+    fn start(&mut self, test: Test, now: Instant) -> Result<(), StunNatBehaviorError> {
+        let transaction_id = self
+            .transaction_ids
+            .pop()
+            .ok_or(StunNatBehaviorError::InvalidRequestError)?;
+        let mut builder = StunMessageBuilder::new(
+            StunMessageClass::Request,
+            StunMessageMethod::Binding,
+            &transaction_id,
+        );
+
+        let (mapped_address, other_address) = (self.mapped_address, self.other_address);
+        let change_request = |change_ip, change_port| {
+            serialize_change_request(&StunChangeRequest {
+                change_ip,
+                change_port,
+            })
+        };
+        self.destination = match (test, mapped_address, other_address) {
+            (Test::Binding, _, _) => self.server,
+            (Test::FilteringChangeAddress, _, _) => {
+                builder.add_attribute(
+                    StunAttributeType::ChangeRequest as u16,
+                    &change_request(true, true),
+                );
+                self.server
+            }
+            (Test::FilteringChangePort, _, _) => {
+                builder.add_attribute(
+                    StunAttributeType::ChangeRequest as u16,
+                    &change_request(false, true),
+                );
+                self.server
+            }
+            (Test::MappingAlternateAddress, _, Some(other_address)) => {
+                SocketAddr::new(other_address.ip(), self.server.port())
+            }
+            (Test::MappingAlternatePort, _, Some(other_address)) => other_address,
+            (Test::Hairpinning, Some(mapped_address), _) => mapped_address,
+            (Test::BindingLifetime(_), Some(mapped_address), _) => {
+                builder.add_attribute(
+                    StunAttributeType::ResponsePort as u16,
+                    &serialize_response_port(mapped_address.port()),
+                );
+                self.server
+            }
+            _ => return Err(StunNatBehaviorError::InvalidRequestError),
+        };
+
+        if let Some(software) = &self.options.software {
+            builder.add_attribute(StunAttributeType::Software as u16, software.as_bytes());
+        }
+        if self.options.fingerprint {
+            builder.add_fingerprint();
+        }
+        let request = builder
+            .build()
+            .map_err(|_| StunNatBehaviorError::InvalidRequestError)?;
+
+        let transaction = ClientTransaction::with_config(
+            request,
+            TransportProtocol::Udp,
+            self.options.config,
+            now,
+        )
+        .map_err(|_| StunNatBehaviorError::InvalidRequestError)?;
+
+        self.test = test;
+        self.transaction = Some(transaction);
+        if Self::socket(test) == NatBehaviorSocket::Primary {
+            self.refreshed_at = now;
+        }
+
+        Ok(())
+    }
+
+    fn finish_on_error(&mut self, result: Result<(), StunNatBehaviorError>) {
+        if let Err(error) = result {
+            self.transaction = None;
+            self.probe_at = None;
+            self.outcome = Some(Err(error));
+        }
+    }
+
+    fn report(&self) -> Result<NatBehavior, StunNatBehaviorError> {
+        match (self.mapped_address, self.mapping, self.filtering) {
+            (Some(mapped_address), Some(mapping), Some(filtering)) => Ok(NatBehavior {
+                mapped_address,
+                mapping,
+                filtering,
+                hairpinning: self.hairpinning,
+                binding_lifetime: self.binding_lifetime,
+            }),
+            _ => Err(StunNatBehaviorError::InvalidResponseError),
+        }
+    }
+}
+
+/// The mapped address and the OTHER-ADDRESS of a success response
+fn parse_response(
+    response: &[u8],
+    transaction_id: &[u8; STUN_TRANSACTION_ID_NUM_BYTES],
+) -> Result<(SocketAddr, Option<SocketAddr>), StunNatBehaviorError> {
+    fn invalid<E>(_: E) -> StunNatBehaviorError {
+        StunNatBehaviorError::InvalidResponseError
+    }
+    let (_, message) = parse_stun_message(response).map_err(invalid)?;
+
+    if message
+        .get_attribute(StunAttributeType::Fingerprint as u16)
+        .is_some()
+    {
+        verify_fingerprint(&message).map_err(invalid)?;
+    }
+
+    let mapped_address = match (
+        message.get_attribute(StunAttributeType::XorMappedAddress as u16),
+        message.get_attribute(StunAttributeType::MappedAddress as u16),
+    ) {
+        (Some(attribute), _) => {
+            parse_attribute_value(attribute, |input| parse_xor_address(input, transaction_id))
+                .map_err(invalid)?
+        }
+        (None, Some(attribute)) => {
+            parse_attribute_value(attribute, parse_address).map_err(invalid)?
+        }
+        (None, None) => return Err(StunNatBehaviorError::InvalidResponseError),
+    };
+
+    let other_address = match message.get_attribute(StunAttributeType::OtherAddress as u16) {
+        Some(attribute) => Some(parse_attribute_value(attribute, parse_address).map_err(invalid)?),
+        None => None,
+    };
+
+    Ok((mapped_address, other_address))
+}
+
+/// The error code of an error response
+fn error_code(response: &[u8]) -> StunNatBehaviorError {
+    let code = parse_stun_message(response)
+        .ok()
+        .and_then(|(_, message)| {
+            let attribute = message.get_attribute(StunAttributeType::ErrorCode as u16)?;
+            parse_attribute_value(attribute, parse_error_code).ok()
+        })
+        .map(|error_code| error_code.code);
+
+    match code {
+        Some(code) => StunNatBehaviorError::RequestFailedError(code),
+        None => StunNatBehaviorError::InvalidResponseError,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stun_server::*;
+
+    use std::net::IpAddr;
+
+    const PRIMARY_SOCKET: &str = "10.0.0.2:5000";
+    const SECONDARY_SOCKET: &str = "10.0.0.2:5001";
+    const PUBLIC_IP: &str = "203.0.113.1";
+
+    fn server_addresses() -> NatBehaviorAddresses {
+        NatBehaviorAddresses {
+            primary: "198.51.100.1:3478".parse().unwrap(),
+            alternate: "198.51.100.2:3479".parse().unwrap(),
+        }
+    }
+
+    /// A mapping of a simulated NAT
+    struct Binding {
+        internal: SocketAddr,
+        /// the destinations the mapping is used for, `None` for all of them
+        scope: Option<SocketAddr>,
+        external: SocketAddr,
+        /// the destinations the mapping has sent to
+        contacted: Vec<SocketAddr>,
+        refreshed_at: Instant,
+    }
+
+    /// A simulated NAT between the two sockets of the client and the internet
+    struct Nat {
+        mapping: NatMapping,
+        filtering: NatFiltering,
+        hairpinning: bool,
+        lifetime: Duration,
+        bindings: Vec<Binding>,
+    }
+
+    impl Nat {
+        fn new(mapping: NatMapping, filtering: NatFiltering) -> Self {
+            Nat {
+                mapping,
+                filtering,
+                hairpinning: false,
+                lifetime: Duration::from_secs(3600),
+                bindings: Vec::new(),
+            }
+        }
+
+        fn is_alive(&self, binding: &Binding, now: Instant) -> bool {
+            now < binding.refreshed_at + self.lifetime
+        }
+
+        /// Translate the source of an outbound packet
+        fn outbound(
+            &mut self,
+            internal: SocketAddr,
+            destination: SocketAddr,
+            now: Instant,
+        ) -> SocketAddr {
+            if self.mapping == NatMapping::NoNat {
+                return internal;
+            }
+
+            let scope = match self.mapping {
+                NatMapping::AddressDependent => Some(SocketAddr::new(destination.ip(), 0)),
+                NatMapping::AddressAndPortDependent => Some(destination),
+                _ => None,
+            };
+            let index = self.bindings.iter().position(|binding| {
+                binding.internal == internal
+                    && binding.scope == scope
+                    && self.is_alive(binding, now)
+            });
+            let index = index.unwrap_or_else(|| {
+                let external = SocketAddr::new(
+                    PUBLIC_IP.parse().unwrap(),
+                    40000 + self.bindings.len() as u16,
+                );
+                self.bindings.push(Binding {
+                    internal,
+                    scope,
+                    external,
+                    contacted: Vec::new(),
+                    refreshed_at: now,
+                });
+                self.bindings.len() - 1
+            });
+
+            let binding = &mut self.bindings[index];
+            binding.refreshed_at = now;
+            binding.contacted.push(destination);
+            binding.external
+        }
+
+        /// Translate the destination of an inbound packet, or drop it
+        fn inbound(
+            &self,
+            source: SocketAddr,
+            external: SocketAddr,
+            now: Instant,
+        ) -> Option<SocketAddr> {
+            if self.mapping == NatMapping::NoNat {
+                return Some(external);
+            }
+
+            let binding = self
+                .bindings
+                .iter()
+                .find(|binding| binding.external == external && self.is_alive(binding, now))?;
+            let allowed = match self.filtering {
+                NatFiltering::EndpointIndependent => true,
+                NatFiltering::AddressDependent => {
+                    binding.contacted.iter().any(|c| c.ip() == source.ip())
+                }
+                NatFiltering::AddressAndPortDependent => binding.contacted.contains(&source),
+            };
+
+            match allowed {
+                true => Some(binding.internal),
+                false => None,
+            }
+        }
+
+        /// Send a packet from a client socket, returning the packet and the socket which receives
+        /// it, if any
+        fn send(
+            &mut self,
+            server: &Server,
+            local: SocketAddr,
+            destination: SocketAddr,
+            data: &[u8],
+            now: Instant,
+        ) -> Option<(SocketAddr, Vec<u8>)> {
+            let source = self.outbound(local, destination, now);
+
+            if destination.ip() == PUBLIC_IP.parse::<IpAddr>().unwrap()
+                || destination.ip() == local.ip()
+            {
+                // hairpinned packets are not filtered
+                let binding = self.bindings.iter().find(|b| b.external == destination);
+                return match (
+                    self.hairpinning || self.mapping == NatMapping::NoNat,
+                    binding,
+                ) {
+                    (true, Some(binding)) => Some((binding.internal, data.to_vec())),
+                    (true, None) => Some((destination, data.to_vec())),
+                    (false, _) => None,
+                };
+            }
+
+            let transmit = server.handle_datagram(data, source, destination)?;
+            let internal = self.inbound(transmit.source, transmit.destination, now)?;
+            Some((internal, transmit.data))
+        }
+    }
+
+    fn fast_options() -> NatBehaviorOptions {
+        NatBehaviorOptions {
+            config: TransactionConfig {
+                rto: Duration::from_millis(100),
+                max_transmits: 3,
+                last_timeout_multiplier: 4,
+                ..TransactionConfig::default()
+            },
+            ..NatBehaviorOptions::default()
+        }
+    }
+
+    /// Run the procedure through the NAT against a NAT behavior discovery server, on a virtual
+    /// clock
+    fn discover(
+        nat: &mut Nat,
+        options: NatBehaviorOptions,
+    ) -> Result<NatBehavior, StunNatBehaviorError> {
+        let mut server = Server::new();
+        server.set_nat_behavior_addresses(server_addresses());
+        discover_with_server(nat, &server, options)
+    }
+
+    fn discover_with_server(
+        nat: &mut Nat,
+        server: &Server,
+        options: NatBehaviorOptions,
+    ) -> Result<NatBehavior, StunNatBehaviorError> {
+        let primary = PRIMARY_SOCKET.parse().unwrap();
+        let secondary = SECONDARY_SOCKET.parse().unwrap();
+        let mut now = Instant::now();
+        let mut discovery =
+            NatBehaviorDiscovery::with_options(server_addresses().primary, primary, options, now)
+                .unwrap();
+
+        loop {
+            let mut received = Vec::new();
+            while let Some(transmit) = discovery.poll_transmit() {
+                let local = match transmit.socket {
+                    NatBehaviorSocket::Primary => primary,
+                    NatBehaviorSocket::Secondary => secondary,
+                };
+                let data = transmit.data.to_vec();
+                received.extend(nat.send(server, local, transmit.destination, &data, now));
+            }
+            for (_, data) in &received {
+                discovery.handle_input(data, now);
+            }
+            if !received.is_empty() {
+                continue;
+            }
+
+            match discovery.poll_timeout() {
+                Some(deadline) => {
+                    now = deadline;
+                    discovery.handle_timeout(now);
+                }
+                None => return discovery.into_outcome().unwrap(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_mapping_and_filtering() {
+        let mappings = [
+            NatMapping::EndpointIndependent,
+            NatMapping::AddressDependent,
+            NatMapping::AddressAndPortDependent,
+        ];
+        let filterings = [
+            NatFiltering::EndpointIndependent,
+            NatFiltering::AddressDependent,
+            NatFiltering::AddressAndPortDependent,
+        ];
+
+        for &mapping in &mappings {
+            for &filtering in &filterings {
+                let mut nat = Nat::new(mapping, filtering);
+                let behavior = discover(&mut nat, fast_options()).unwrap();
+
+                assert_eq!(behavior.mapping, mapping);
+                assert_eq!(behavior.filtering, filtering);
+                assert_eq!(behavior.mapped_address, nat.bindings[0].external);
+                assert_eq!(behavior.hairpinning, Some(false));
+                assert_eq!(behavior.binding_lifetime, None);
+            }
+        }
+    }
+
+    #[test]
+    fn test_no_nat() {
+        let mut nat = Nat::new(NatMapping::NoNat, NatFiltering::EndpointIndependent);
+        let behavior = discover(&mut nat, fast_options()).unwrap();
+
+        assert_eq!(
+            behavior,
+            NatBehavior {
+                mapped_address: PRIMARY_SOCKET.parse().unwrap(),
+                mapping: NatMapping::NoNat,
+                filtering: NatFiltering::EndpointIndependent,
+                hairpinning: Some(true),
+                binding_lifetime: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_hairpinning_and_binding_lifetime() {
+        let mut nat = Nat::new(
+            NatMapping::EndpointIndependent,
+            NatFiltering::AddressAndPortDependent,
+        );
+        nat.hairpinning = true;
+        nat.lifetime = Duration::from_secs(30);
+
+        let options = NatBehaviorOptions {
+            lifetime_probes: [10, 20, 40, 80]
+                .iter()
+                .map(|s| Duration::from_secs(*s))
+                .collect(),
+            ..fast_options()
+        };
+        let behavior = discover(&mut nat, options).unwrap();
+
+        assert_eq!(behavior.hairpinning, Some(true));
+        assert_eq!(
+            behavior.binding_lifetime,
+            Some(BindingLifetime {
+                at_least: Duration::from_secs(20),
+                less_than: Some(Duration::from_secs(40)),
+            })
+        );
+
+        // the mapping is alive at the last probe
+        let mut nat = Nat::new(
+            NatMapping::EndpointIndependent,
+            NatFiltering::EndpointIndependent,
+        );
+        let options = NatBehaviorOptions {
+            hairpinning: false,
+            lifetime_probes: vec![Duration::from_secs(10)],
+            ..fast_options()
+        };
+        let behavior = discover(&mut nat, options).unwrap();
+
+        assert_eq!(behavior.hairpinning, None);
+        assert_eq!(
+            behavior.binding_lifetime,
+            Some(BindingLifetime {
+                at_least: Duration::from_secs(10),
+                less_than: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        // a server without alternate addresses does not send OTHER-ADDRESS
+        let mut nat = Nat::new(
+            NatMapping::EndpointIndependent,
+            NatFiltering::EndpointIndependent,
+        );
+        let result = discover_with_server(&mut nat, &Server::new(), fast_options());
+        assert!(matches!(
+            result,
+            Err(StunNatBehaviorError::UnsupportedServerError)
+        ));
+
+        let options = NatBehaviorOptions {
+            software: Some("x".repeat(1000)),
+            ..fast_options()
+        };
+        let result = NatBehaviorDiscovery::with_options(
+            server_addresses().primary,
+            PRIMARY_SOCKET.parse().unwrap(),
+            options,
+            Instant::now(),
+        );
+        assert!(matches!(
+            result,
+            Err(StunNatBehaviorError::InvalidRequestError)
+        ));
+    }
+}
@@ -0,0 +1,156 @@
+use crate::stun_errors::StunParseError;
+
+use alloc::vec::Vec;
+
+use nom::bytes::complete::take;
+use nom::number::complete::be_u16;
+use nom::IResult;
+use num_enum::TryFromPrimitive;
+
+/// Password algorithms of the long-term credential mechanism,
+/// https://tools.ietf.org/html/rfc8489#section-18.5
+#[derive(Debug, Eq, PartialEq, TryFromPrimitive, Clone, Copy)]
+#[repr(u16)]
+pub enum StunPasswordAlgorithm {
+    Md5 = 0x0001,
+    Sha256 = 0x0002,
+}
+
+/// The value of a PASSWORD-ALGORITHM attribute, or one of the algorithms listed in a
+/// PASSWORD-ALGORITHMS attribute, https://tools.ietf.org/html/rfc8489#section-14.11
+///
+/// The algorithm is kept as a number so that a list offering unknown algorithms can still be
+/// parsed and echoed back.
+///
+/// 0                   1                   2                   3
+/// 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |          Algorithm            |  Algorithm Parameters Length  |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |                    Algorithm Parameters (variable)
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct StunPasswordAlgorithmValue<'a> {
+    pub algorithm: u16,
+    pub parameters: &'a [u8],
+}
+
+impl<'a> StunPasswordAlgorithmValue<'a> {
+    /// The known algorithm, if it is one
+    pub fn known_algorithm(&self) -> Option<StunPasswordAlgorithm> {
+        StunPasswordAlgorithm::try_from_primitive(self.algorithm).ok()
+    }
+}
+
+/// Parse the value of a PASSWORD-ALGORITHM attribute, the parameters are padded to a multiple of
+/// 4 bytes
+pub fn parse_password_algorithm(
+    input: &[u8],
+) -> IResult<&[u8], StunPasswordAlgorithmValue<'_>, StunParseError<&[u8]>> {
+    let (input, algorithm) = be_u16(input)?;
+    let (input, parameters_length) = be_u16(input)?;
+    let (input, parameters) = take(parameters_length as usize)(input)?;
+    let (input, _) = take((4 - parameters.len() % 4) % 4)(input)?;
+
+    Ok((
+        input,
+        StunPasswordAlgorithmValue {
+            algorithm,
+            parameters,
+        },
+    ))
+}
+
+/// Serialize the value of a PASSWORD-ALGORITHM attribute
+pub fn serialize_password_algorithm(value: &StunPasswordAlgorithmValue) -> Vec<u8> {
+    let mut output = Vec::with_capacity(4 + value.parameters.len() + 3);
+    append_password_algorithm(&mut output, value);
+
+    output
+}
+
+/// Parse the value of a PASSWORD-ALGORITHMS attribute, the list of algorithms a server
+/// supports in order of preference, https://tools.ietf.org/html/rfc8489#section-14.11
+pub fn parse_password_algorithms(
+    mut input: &[u8],
+) -> IResult<&[u8], Vec<StunPasswordAlgorithmValue<'_>>, StunParseError<&[u8]>> {
+    let mut algorithms = Vec::new();
+
+    while !input.is_empty() {
+        let (rest, algorithm) = parse_password_algorithm(input)?;
+        algorithms.push(algorithm);
+        input = rest;
+    }
+
+    Ok((input, algorithms))
+}
+
+/// Serialize the value of a PASSWORD-ALGORITHMS attribute
+pub fn serialize_password_algorithms(algorithms: &[StunPasswordAlgorithmValue]) -> Vec<u8> {
+    let mut output = Vec::new();
+    for algorithm in algorithms {
+        append_password_algorithm(&mut output, algorithm);
+    }
+
+    output
+}
+
+fn append_password_algorithm(output: &mut Vec<u8>, value: &StunPasswordAlgorithmValue) {
+    output.extend_from_slice(&value.algorithm.to_be_bytes());
+    output.extend_from_slice(&(value.parameters.len() as u16).to_be_bytes());
+    output.extend_from_slice(value.parameters);
+    output.resize(output.len() + (4 - value.parameters.len() % 4) % 4, 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn password_algorithm_roundtrip() {
+        let value = StunPasswordAlgorithmValue {
+            algorithm: StunPasswordAlgorithm::Sha256 as u16,
+            parameters: &[],
+        };
+        let data = serialize_password_algorithm(&value);
+        assert_eq!(data, [0x00, 0x02, 0x00, 0x00]);
+        assert_eq!(parse_password_algorithm(&data).unwrap(), (&[][..], value));
+        assert_eq!(value.known_algorithm(), Some(StunPasswordAlgorithm::Sha256));
+
+        // parameters are padded
+        let value = StunPasswordAlgorithmValue {
+            algorithm: 0x1234,
+            parameters: &[0xAB],
+        };
+        let data = serialize_password_algorithm(&value);
+        assert_eq!(data, [0x12, 0x34, 0x00, 0x01, 0xAB, 0x00, 0x00, 0x00]);
+        assert_eq!(parse_password_algorithm(&data).unwrap(), (&[][..], value));
+        assert_eq!(value.known_algorithm(), None);
+
+        assert!(parse_password_algorithm(&[0x00, 0x01, 0x00, 0x04, 0x00]).is_err());
+    }
+
+    #[test]
+    fn password_algorithms_roundtrip() {
+        let algorithms = [
+            StunPasswordAlgorithmValue {
+                algorithm: 0x1234,
+                parameters: &[0x01, 0x02],
+            },
+            StunPasswordAlgorithmValue {
+                algorithm: StunPasswordAlgorithm::Sha256 as u16,
+                parameters: &[],
+            },
+            StunPasswordAlgorithmValue {
+                algorithm: StunPasswordAlgorithm::Md5 as u16,
+                parameters: &[],
+            },
+        ];
+        let data = serialize_password_algorithms(&algorithms);
+        assert_eq!(data.len(), 16);
+        assert_eq!(parse_password_algorithms(&data).unwrap().1, algorithms);
+
+        assert_eq!(parse_password_algorithms(&[]).unwrap().1, []);
+        assert!(parse_password_algorithms(&data[..14]).is_err());
+    }
+}
@@ -0,0 +1,808 @@
+use crate::parser::{parse_attribute_value, parse_stun_message, parse_stun_message_view};
+use crate::stun_attribute_types::*;
+use crate::stun_constants::*;
+use crate::stun_error_code::*;
+use crate::stun_errors::StunParseError;
+use crate::stun_integrity::*;
+use crate::stun_message_builder::*;
+use crate::stun_message_types::*;
+use crate::stun_nat_attributes::*;
+use crate::stun_text_attributes::*;
+use crate::stun_validator::*;
+use crate::turn_channel_data::*;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::panic;
+use std::thread;
+
+/// Largest datagram the server receives, anything longer is truncated and answered with 400
+const MAX_DATAGRAM_NUM_BYTES: usize = 2048;
+
+/// A STUN Binding server, https://tools.ietf.org/html/rfc5389#section-7.3
+///
+/// Binding requests are answered with the address they were received from, in an
+/// XOR-MAPPED-ADDRESS attribute, or in a MAPPED-ADDRESS attribute for RFC 3489 clients, which
+/// do not send the magic cookie.  Responses carry SOFTWARE if the server has a value for it, and
+/// FINGERPRINT unless they are sent to an RFC 3489 client.
+///
+/// - malformed requests and requests for other methods are answered with 400 (Bad Request)
+/// - requests with unknown comprehension-required attributes are answered with 420 (Unknown
+///   Attribute), listing them in UNKNOWN-ATTRIBUTES
+/// - indications, responses, messages with a FINGERPRINT that does not match and anything that
+///   is not a STUN message are dropped
+///
+/// With `set_nat_behavior_addresses` the server also supports NAT behavior discovery over UDP,
+/// https://tools.ietf.org/html/rfc5780#section-6.1: it honors CHANGE-REQUEST and RESPONSE-PORT,
+/// and adds RESPONSE-ORIGIN and OTHER-ADDRESS to its success responses.  Without alternate
+/// addresses a request to change the IP address or port, or a datagram with RESPONSE-PORT, is
+/// answered with 420.
+///
+/// `handle_message` and `handle_datagram` do no I/O, while `serve_udp`, `serve_udp_sockets` and
+/// `serve_tcp` run the server on sockets.
+#[derive(Debug, Default, Clone)]
+pub struct Server {
+    software: Option<String>,
+    nat_behavior_addresses: Option<NatBehaviorAddresses>,
+}
+
+/// The addresses of a server which supports NAT behavior discovery,
+/// https://tools.ietf.org/html/rfc5780#section-4.1
+///
+/// The server has two IP addresses and two ports, and listens on each of the four combinations
+/// of them, see `all`.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct NatBehaviorAddresses {
+    /// the primary IP address and port
+    pub primary: SocketAddr,
+
+    /// the alternate IP address and port, which both differ from the primary ones
+    pub alternate: SocketAddr,
+}
+
+impl NatBehaviorAddresses {
+    /// The four addresses the server listens on: the primary address, the primary IP address with
+    /// the alternate port, the alternate IP address with the primary port and the alternate
+    /// address
+    pub fn all(&self) -> [SocketAddr; 4] {
+        [
+            self.primary,
+            SocketAddr::new(self.primary.ip(), self.alternate.port()),
+            SocketAddr::new(self.alternate.ip(), self.primary.port()),
+            self.alternate,
+        ]
+    }
+
+    /// The address to send from instead of the given one, with the IP address and/or the port
+    /// changed to the other one, or `None` if the given address is not one of the four
+    pub fn changed(
+        &self,
+        local: SocketAddr,
+        change_ip: bool,
+        change_port: bool,
+    ) -> Option<SocketAddr> {
+        let other_ip = match local.ip() {
+            ip if ip == self.primary.ip() => self.alternate.ip(),
+            ip if ip == self.alternate.ip() => self.primary.ip(),
+            _ => return None,
+        };
+        let other_port = match local.port() {
+            port if port == self.primary.port() => self.alternate.port(),
+            port if port == self.alternate.port() => self.primary.port(),
+            _ => return None,
+        };
+
+        Some(SocketAddr::new(
+            if change_ip { other_ip } else { local.ip() },
+            if change_port {
+                other_port
+            } else {
+                local.port()
+            },
+        ))
+    }
+}
+
+/// A response to a datagram, with the addresses to send it from and to
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct ServerTransmit {
+    /// the local address to send the response from, which is the one the request was received on
+    /// unless the request asked for it to be changed
+    pub source: SocketAddr,
+
+    /// the address to send the response to, which is the source of the request unless the
+    /// request has a RESPONSE-PORT
+    pub destination: SocketAddr,
+
+    /// the serialized response
+    pub data: Vec<u8>,
+}
+
+impl Server {
+    /// Create a server which does not send SOFTWARE
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a server which sends the given SOFTWARE value in its responses.  An error if the
+    /// value exceeds the length limits of the attribute.
+    pub fn with_software(software: &str) -> Result<Self, StunParseError<&[u8]>> {
+        StunSoftware::new(software)?;
+
+        Ok(Server {
+            software: Some(String::from(software)),
+            nat_behavior_addresses: None,
+        })
+    }
+
+    /// Support NAT behavior discovery with the given addresses, all of which the server must
+    /// listen on over UDP, see `serve_udp_sockets`
+    pub fn set_nat_behavior_addresses(&mut self, addresses: NatBehaviorAddresses) -> &mut Self {
+        self.nat_behavior_addresses = Some(addresses);
+        self
+    }
+
+    /// Handle a message received from the given address on a stream, returning the response to
+    /// send back to it, if any.  CHANGE-REQUEST and RESPONSE-PORT only apply to datagrams, so a
+    /// request to change the IP address or port is answered with 420 and RESPONSE-PORT is
+    /// ignored.
+    pub fn handle_message(&self, input: &[u8], source: SocketAddr) -> Option<Vec<u8>> {
+        self.respond(input, source, None)
+            .map(|transmit| transmit.data)
+    }
+
+    /// Handle a datagram received from the given address on the given local address, returning
+    /// the response and where to send it from and to, if there is a response
+    pub fn handle_datagram(
+        &self,
+        input: &[u8],
+        source: SocketAddr,
+        local: SocketAddr,
+    ) -> Option<ServerTransmit> {
+        self.respond(input, source, Some(local))
+    }
+
+    /// Answer a message, with the local address it was received on if it is a datagram.  The
+    /// source of the returned transmit is only meaningful for a datagram.
+    fn respond(
+        &self,
+        input: &[u8],
+        source: SocketAddr,
+        local: Option<SocketAddr>,
+    ) -> Option<ServerTransmit> {
+        let reply = |data| ServerTransmit {
+            source: local.unwrap_or(source),
+            destination: source,
+            data,
+        };
+
+        if input.len() < STUN_HEADER_NUM_BYTES {
+            return None;
+        }
+
+        // an RFC 3489 transaction id takes the place of the magic cookie, the request is parsed
+        // with the cookie in its place and the response is sent back with the original bytes
+        let patched;
+        let (input, classic_cookie) = match input[4..8] == STUN_MAGIC_COOKIE.to_be_bytes() {
+            true => (input, None),
+            false => {
+                let mut data = input.to_vec();
+                data[4..8].copy_from_slice(&STUN_MAGIC_COOKIE.to_be_bytes());
+                patched = data;
+                (&patched[..], Some([input[4], input[5], input[6], input[7]]))
+            }
+        };
+
+        let request = match parse_stun_message(input) {
+            Ok((_, message)) => message,
+            Err(_) => {
+                // the attributes are malformed, which is answered if the header is intact
+                let (message_class, message_method, transaction_id) = parse_header(input)?;
+                if message_class != StunMessageClass::Request {
+                    return None;
+                }
+
+                let response = Response::new(message_method, &transaction_id, classic_cookie);
+                return self
+                    .error_response(response, STUN_ERROR_BAD_REQUEST, "Bad Request", &[])
+                    .map(reply);
+            }
+        };
+
+        if request.message_class != StunMessageClass::Request {
+            return None;
+        }
+        if request
+            .get_attribute(StunAttributeType::Fingerprint as u16)
+            .is_some()
+            && verify_fingerprint(&request).is_err()
+        {
+            return None;
+        }
+
+        let response = Response::new(
+            request.message_method,
+            request.transaction_id,
+            classic_cookie,
+        );
+
+        let mut unknown_attributes: Vec<u16> = Vec::new();
+        for attribute in &request.attributes {
+            let attribute_type = attribute.attribute_type;
+            if attribute_type < STUN_ATTRIBUTE_COMPREHENSION_OPTIONAL_MIN
+                && StunAttributeType::try_from(attribute_type).is_err()
+                && !unknown_attributes.contains(&attribute_type)
+            {
+                unknown_attributes.push(attribute_type);
+            }
+        }
+        if !unknown_attributes.is_empty() {
+            return self
+                .error_response(
+                    response,
+                    STUN_ERROR_UNKNOWN_ATTRIBUTE,
+                    "Unknown Attribute",
+                    &unknown_attributes,
+                )
+                .map(reply);
+        }
+
+        if request.message_method != StunMessageMethod::Binding || !validate(&request).is_empty() {
+            return self
+                .error_response(response, STUN_ERROR_BAD_REQUEST, "Bad Request", &[])
+                .map(reply);
+        }
+
+        // NAT behavior discovery, https://tools.ietf.org/html/rfc5780#section-6.1
+        let nat_behavior = match (local, &self.nat_behavior_addresses) {
+            (Some(local), Some(addresses)) => addresses
+                .changed(local, true, true)
+                .map(|other_address| (local, addresses, other_address)),
+            _ => None,
+        };
+        let mut transmit = reply(Vec::new());
+
+        if let Some(attribute) = request.get_attribute(StunAttributeType::ChangeRequest as u16) {
+            let change_request = parse_attribute_value(attribute, parse_change_request).ok()?;
+            if change_request.change_ip || change_request.change_port {
+                match nat_behavior {
+                    Some((local, addresses, _)) => {
+                        transmit.source = addresses.changed(
+                            local,
+                            change_request.change_ip,
+                            change_request.change_port,
+                        )?
+                    }
+                    None => {
+                        return self
+                            .error_response(
+                                response,
+                                STUN_ERROR_UNKNOWN_ATTRIBUTE,
+                                "Unknown Attribute",
+                                &[StunAttributeType::ChangeRequest as u16],
+                            )
+                            .map(reply)
+                    }
+                }
+            }
+        }
+
+        if let Some(attribute) = request.get_attribute(StunAttributeType::ResponsePort as u16) {
+            let port = parse_attribute_value(attribute, parse_response_port).ok()?;
+            match (local, nat_behavior) {
+                (Some(_), Some(_)) => transmit.destination.set_port(port),
+                // a plain Binding server does not send responses to ports the request names
+                (Some(_), None) => {
+                    return self
+                        .error_response(
+                            response,
+                            STUN_ERROR_UNKNOWN_ATTRIBUTE,
+                            "Unknown Attribute",
+                            &[StunAttributeType::ResponsePort as u16],
+                        )
+                        .map(reply)
+                }
+                (None, _) => {}
+            }
+        }
+
+        let mut builder = response.builder(StunMessageClass::SuccessResponse);
+        match classic_cookie {
+            None => builder
+                .add_xor_address_attribute(StunAttributeType::XorMappedAddress as u16, &source),
+            Some(_) => {
+                builder.add_address_attribute(StunAttributeType::MappedAddress as u16, &source)
+            }
+        };
+        if let (Some((_, _, other_address)), None) = (nat_behavior, classic_cookie) {
+            builder
+                .add_address_attribute(StunAttributeType::ResponseOrigin as u16, &transmit.source)
+                .add_address_attribute(StunAttributeType::OtherAddress as u16, &other_address);
+        }
+
+        transmit.data = self.finish(response, &mut builder)?;
+        Some(transmit)
+    }
+
+    /// Answer requests received on the given UDP socket until receiving fails
+    pub fn serve_udp(&self, socket: &UdpSocket) -> io::Result<()> {
+        self.serve_udp_sockets(core::slice::from_ref(socket))
+    }
+
+    /// Answer requests received on the given UDP sockets, each on its own thread, until
+    /// receiving fails on all of them, returning the first error.  A response is sent from the
+    /// socket bound to its source address, so for NAT behavior discovery the sockets must be
+    /// bound to each of the addresses of the server, rather than to a wildcard address.
+    pub fn serve_udp_sockets(&self, sockets: &[UdpSocket]) -> io::Result<()> {
+        let locals = sockets
+            .iter()
+            .map(UdpSocket::local_addr)
+            .collect::<io::Result<Vec<SocketAddr>>>()?;
+        let locals = &locals[..];
+
+        thread::scope(|scope| {
+            let threads: Vec<_> = sockets
+                .iter()
+                .zip(locals)
+                .map(|(socket, &local)| {
+                    scope.spawn(move || self.serve_udp_socket(socket, local, sockets, locals))
+                })
+                .collect();
+
+            let mut result = Ok(());
+            for thread in threads {
+                let thread_result = thread
+                    .join()
+                    .unwrap_or_else(|payload| panic::resume_unwind(payload));
+                result = result.and(thread_result);
+            }
+            result
+        })
+    }
+
+    fn serve_udp_socket(
+        &self,
+        socket: &UdpSocket,
+        local: SocketAddr,
+        sockets: &[UdpSocket],
+        locals: &[SocketAddr],
+    ) -> io::Result<()> {
+        let mut buffer = [0; MAX_DATAGRAM_NUM_BYTES];
+
+        loop {
+            let (length, source) = match socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                // an ICMP error for an earlier response, on some platforms
+                Err(e) if e.kind() == io::ErrorKind::ConnectionReset => continue,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+
+            let transmit = match self.handle_datagram(&buffer[..length], source, local) {
+                Some(transmit) => transmit,
+                None => continue,
+            };
+            if let Some(index) = locals.iter().position(|&l| l == transmit.source) {
+                // a response which cannot be sent is lost, like any other datagram
+                let _ = sockets[index].send_to(&transmit.data, transmit.destination);
+            }
+        }
+    }
+
+    /// Accept connections on the given TCP listener until accepting fails, answering the
+    /// requests on each connection on its own thread
+    pub fn serve_tcp(&self, listener: &TcpListener) -> io::Result<()> {
+        loop {
+            let stream = match listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::ConnectionAborted
+                            | io::ErrorKind::ConnectionReset
+                            | io::ErrorKind::Interrupted
+                    ) =>
+                {
+                    continue
+                }
+                Err(e) => return Err(e),
+            };
+
+            let server = self.clone();
+            thread::spawn(move || server.serve_tcp_stream(stream));
+        }
+    }
+
+    /// Answer the requests received on the given TCP connection until it is closed.  The
+    /// connection is closed if it carries something other than STUN messages.
+    pub fn serve_tcp_stream(&self, mut stream: TcpStream) -> io::Result<()> {
+        let source = stream.peer_addr()?;
+        let mut buffer = Vec::new();
+        let mut chunk = [0; MAX_DATAGRAM_NUM_BYTES];
+
+        loop {
+            loop {
+                let length = match stream_message_length(&buffer) {
+                    Ok(Some(length)) if length <= buffer.len() => length,
+                    Ok(_) => break,
+                    Err(_) => return Ok(()),
+                };
+
+                let message: Vec<u8> = buffer.drain(..length).collect();
+                if let Some(response) = self.handle_message(&message, source) {
+                    stream.write_all(&response)?;
+                }
+            }
+
+            let length = stream.read(&mut chunk)?;
+            if length == 0 {
+                return Ok(());
+            }
+            buffer.extend_from_slice(&chunk[..length]);
+        }
+    }
+
+    fn error_response(
+        &self,
+        response: Response,
+        code: u16,
+        reason: &str,
+        unknown_attributes: &[u16],
+    ) -> Option<Vec<u8>> {
+        let mut builder = response.builder(StunMessageClass::ErrorResponse);
+        builder.add_error_code_attribute(code, reason);
+
+        if !unknown_attributes.is_empty() {
+            let value: Vec<u8> = unknown_attributes
+                .iter()
+                .flat_map(|attribute_type| attribute_type.to_be_bytes())
+                .collect();
+            builder.add_attribute(StunAttributeType::UnknownAttributes as u16, &value);
+        }
+
+        self.finish(response, &mut builder)
+    }
+
+    /// Add SOFTWARE and FINGERPRINT to the response and serialize it
+    fn finish(&self, response: Response, builder: &mut StunMessageBuilder) -> Option<Vec<u8>> {
+        if let Some(software) = &self.software {
+            builder.add_attribute(StunAttributeType::Software as u16, software.as_bytes());
+        }
+        if response.classic_cookie.is_none() {
+            builder.add_fingerprint();
+        }
+
+        let mut output = builder.build().ok()?;
+        if let Some(cookie) = response.classic_cookie {
+            output[4..8].copy_from_slice(&cookie);
+        }
+
+        Some(output)
+    }
+}
+
+/// The header fields of a response
+#[derive(Clone, Copy)]
+struct Response {
+    message_method: StunMessageMethod,
+    transaction_id: [u8; STUN_TRANSACTION_ID_NUM_BYTES],
+
+    /// the first 4 bytes of the transaction id of an RFC 3489 request
+    classic_cookie: Option<[u8; 4]>,
+}
+
+impl Response {
+    fn new(
+        message_method: StunMessageMethod,
+        transaction_id: &[u8; STUN_TRANSACTION_ID_NUM_BYTES],
+        classic_cookie: Option<[u8; 4]>,
+    ) -> Self {
+        Response {
+            message_method,
+            transaction_id: *transaction_id,
+            classic_cookie,
+        }
+    }
+
+    fn builder(&self, message_class: StunMessageClass) -> StunMessageBuilder {
+        StunMessageBuilder::new(message_class, self.message_method, &self.transaction_id)
+    }
+}
+
+/// The class, method and transaction id of a message whose attributes cannot be parsed
+fn parse_header(
+    input: &[u8],
+) -> Option<(
+    StunMessageClass,
+    StunMessageMethod,
+    [u8; STUN_TRANSACTION_ID_NUM_BYTES],
+)> {
+    // parse the header as that of a message without attributes
+    let mut header = [0; STUN_HEADER_NUM_BYTES];
+    header.copy_from_slice(input.get(..STUN_HEADER_NUM_BYTES)?);
+    header[2..4].copy_from_slice(&[0, 0]);
+
+    let (_, view) = parse_stun_message_view(&header).ok()?;
+    Some((
+        view.message_class,
+        view.message_method,
+        *view.transaction_id,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::*;
+    use crate::stun_address::*;
+    use crate::stun_message::*;
+
+    use core::time::Duration;
+
+    const TRANSACTION_ID: [u8; STUN_TRANSACTION_ID_NUM_BYTES] = [0x42; 12];
+
+    fn source() -> SocketAddr {
+        "192.0.2.1:32853".parse().unwrap()
+    }
+
+    fn request(message_class: StunMessageClass, attributes: &[(u16, &[u8])]) -> Vec<u8> {
+        let mut builder =
+            StunMessageBuilder::new(message_class, StunMessageMethod::Binding, &TRANSACTION_ID);
+        for (attribute_type, value) in attributes {
+            builder.add_attribute(*attribute_type, value);
+        }
+        builder.build().unwrap()
+    }
+
+    fn error_code(response: &StunMessage) -> u16 {
+        let attribute = response
+            .get_attribute(StunAttributeType::ErrorCode as u16)
+            .unwrap();
+        parse_attribute_value(attribute, parse_error_code)
+            .unwrap()
+            .code
+    }
+
+    #[test]
+    fn test_binding_response() {
+        let server = Server::with_software("test server").unwrap();
+        let response = server
+            .handle_message(
+                &request(StunMessageClass::Request, &[(0x8022, b"client")]),
+                source(),
+            )
+            .unwrap();
+
+        let (_, response) = parse_stun_message(&response).unwrap();
+        assert_eq!(response.message_class, StunMessageClass::SuccessResponse);
+        assert_eq!(*response.transaction_id, TRANSACTION_ID);
+        assert!(validate(&response).is_empty());
+        assert_eq!(verify_fingerprint(&response), Ok(()));
+
+        let attribute = response
+            .get_attribute(StunAttributeType::XorMappedAddress as u16)
+            .unwrap();
+        let address =
+            parse_attribute_value(attribute, |i| parse_xor_address(i, &TRANSACTION_ID)).unwrap();
+        assert_eq!(address, source());
+
+        let software = response
+            .get_attribute(StunAttributeType::Software as u16)
+            .unwrap();
+        assert_eq!(software.attribute_value, b"test server");
+    }
+
+    #[test]
+    fn test_classic_binding_response() {
+        let mut data = request(StunMessageClass::Request, &[]);
+        data[4..8].copy_from_slice(&[1, 2, 3, 4]);
+
+        let response = Server::new().handle_message(&data, source()).unwrap();
+
+        // the whole 16 byte transaction id is echoed, and the address is not XOR'd
+        assert_eq!(response[4..20], data[4..20]);
+        assert_eq!(
+            response[20..],
+            [0x00, 0x01, 0x00, 0x08, 0x00, 0x01, 0x80, 0x55, 192, 0, 2, 1]
+        );
+    }
+
+    #[test]
+    fn test_error_responses() {
+        let server = Server::new();
+
+        // an attribute which overruns the message
+        let mut data = request(StunMessageClass::Request, &[(0x8022, b"client")]);
+        data[22..24].copy_from_slice(&[0x00, 0x40]);
+        let response = server.handle_message(&data, source()).unwrap();
+        let (_, response) = parse_stun_message(&response).unwrap();
+        assert_eq!(response.message_class, StunMessageClass::ErrorResponse);
+        assert_eq!(*response.transaction_id, TRANSACTION_ID);
+        assert_eq!(error_code(&response), STUN_ERROR_BAD_REQUEST);
+
+        // an attribute with a fixed length of 4 bytes
+        let data = request(StunMessageClass::Request, &[(0x000D, &[0; 8])]);
+        let response = server.handle_message(&data, source()).unwrap();
+        let (_, response) = parse_stun_message(&response).unwrap();
+        assert_eq!(error_code(&response), STUN_ERROR_BAD_REQUEST);
+
+        // unknown comprehension-required attributes are listed, optional ones are ignored
+        let data = request(
+            StunMessageClass::Request,
+            &[(0x0030, b""), (0x8050, b""), (0x0031, b""), (0x0030, b"")],
+        );
+        let response = server.handle_message(&data, source()).unwrap();
+        let (_, response) = parse_stun_message(&response).unwrap();
+        assert_eq!(error_code(&response), STUN_ERROR_UNKNOWN_ATTRIBUTE);
+        let unknown = response
+            .get_attribute(StunAttributeType::UnknownAttributes as u16)
+            .unwrap();
+        assert_eq!(unknown.attribute_value, [0x00, 0x30, 0x00, 0x31]);
+        assert!(validate(&response).is_empty());
+    }
+
+    #[test]
+    fn test_dropped_messages() {
+        let server = Server::new();
+
+        let indication = request(StunMessageClass::Indication, &[]);
+        assert_eq!(server.handle_message(&indication, source()), None);
+
+        let response = request(StunMessageClass::SuccessResponse, &[]);
+        assert_eq!(server.handle_message(&response, source()), None);
+
+        let mut data = StunMessageBuilder::new(
+            StunMessageClass::Request,
+            StunMessageMethod::Binding,
+            &TRANSACTION_ID,
+        )
+        .add_fingerprint()
+        .build()
+        .unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        assert_eq!(server.handle_message(&data, source()), None);
+
+        assert_eq!(server.handle_message(&data[..12], source()), None);
+        assert_eq!(
+            server.handle_message(&serialize_channel_data(0x4000, &[0; 16]), source()),
+            None
+        );
+    }
+
+    #[test]
+    fn test_nat_behavior_datagrams() {
+        let addresses = NatBehaviorAddresses {
+            primary: "198.51.100.1:3478".parse().unwrap(),
+            alternate: "198.51.100.2:3479".parse().unwrap(),
+        };
+        let mut server = Server::new();
+        server.set_nat_behavior_addresses(addresses);
+
+        let change_request = serialize_change_request(&StunChangeRequest {
+            change_ip: true,
+            change_port: false,
+        });
+        let response_port = serialize_response_port(40000);
+        let data = request(
+            StunMessageClass::Request,
+            &[
+                (StunAttributeType::ChangeRequest as u16, &change_request),
+                (StunAttributeType::ResponsePort as u16, &response_port),
+            ],
+        );
+
+        // received on the primary port of the alternate IP address, sent from the primary one
+        let local = "198.51.100.2:3478".parse().unwrap();
+        let transmit = server.handle_datagram(&data, source(), local).unwrap();
+        assert_eq!(transmit.source, addresses.primary);
+        assert_eq!(transmit.destination, "192.0.2.1:40000".parse().unwrap());
+
+        let (_, response) = parse_stun_message(&transmit.data).unwrap();
+        assert!(validate(&response).is_empty());
+        let address = |attribute_type: StunAttributeType| {
+            let attribute = response.get_attribute(attribute_type as u16).unwrap();
+            parse_attribute_value(attribute, parse_address).unwrap()
+        };
+        assert_eq!(
+            address(StunAttributeType::ResponseOrigin),
+            addresses.primary
+        );
+        assert_eq!(
+            address(StunAttributeType::OtherAddress),
+            "198.51.100.1:3479".parse().unwrap()
+        );
+
+        // the address cannot be changed on a stream, nor without alternate addresses
+        let responses = [
+            server.handle_message(&data, source()).unwrap(),
+            Server::new()
+                .handle_datagram(&data, source(), local)
+                .unwrap()
+                .data,
+        ];
+        for response in &responses {
+            let (_, response) = parse_stun_message(response).unwrap();
+            assert_eq!(error_code(&response), STUN_ERROR_UNKNOWN_ATTRIBUTE);
+        }
+    }
+
+    #[test]
+    fn test_response_port_without_nat_behavior() {
+        let server = Server::new();
+        let response_port = serialize_response_port(40000);
+        let data = request(
+            StunMessageClass::Request,
+            &[(StunAttributeType::ResponsePort as u16, &response_port)],
+        );
+
+        // the response is not redirected, the attribute is reported as unknown instead
+        let local = "198.51.100.1:3478".parse().unwrap();
+        let transmit = server.handle_datagram(&data, source(), local).unwrap();
+        assert_eq!(transmit.destination, source());
+        let (_, response) = parse_stun_message(&transmit.data).unwrap();
+        assert_eq!(error_code(&response), STUN_ERROR_UNKNOWN_ATTRIBUTE);
+        let unknown = response
+            .get_attribute(StunAttributeType::UnknownAttributes as u16)
+            .unwrap();
+        assert_eq!(unknown.attribute_value, [0x00, 0x27]);
+
+        // on a stream it is ignored
+        let response = server.handle_message(&data, source()).unwrap();
+        let (_, response) = parse_stun_message(&response).unwrap();
+        assert_eq!(response.message_class, StunMessageClass::SuccessResponse);
+    }
+
+    #[test]
+    fn test_serve_udp_and_tcp() {
+        let server = Server::with_software("test server").unwrap();
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let udp_address = socket.local_addr().unwrap();
+        let udp_server = server.clone();
+        thread::spawn(move || udp_server.serve_udp(&socket));
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let tcp_address = listener.local_addr().unwrap();
+        thread::spawn(move || server.serve_tcp(&listener));
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let options = BindingOptions {
+            fingerprint: true,
+            ..BindingOptions::default()
+        };
+        let address =
+            binding_with_socket(&client, udp_address, Duration::from_secs(5), &options).unwrap();
+        assert_eq!(address, client.local_addr().unwrap());
+
+        // two requests written at once, and read back one after the other
+        let mut stream = TcpStream::connect(tcp_address).unwrap();
+        let mut data = request(StunMessageClass::Request, &[]);
+        data.extend(request(StunMessageClass::Request, &[(0x8022, b"client")]));
+        stream.write_all(&data).unwrap();
+
+        let local = stream.local_addr().unwrap();
+        for _ in 0..2 {
+            let mut header = [0; STUN_HEADER_NUM_BYTES];
+            stream.read_exact(&mut header).unwrap();
+            let length = stream_message_length(&header).unwrap().unwrap();
+            let mut response = header.to_vec();
+            response.resize(length, 0);
+            stream
+                .read_exact(&mut response[STUN_HEADER_NUM_BYTES..])
+                .unwrap();
+
+            let (_, response) = parse_stun_message(&response).unwrap();
+            let attribute = response
+                .get_attribute(StunAttributeType::XorMappedAddress as u16)
+                .unwrap();
+            let address =
+                parse_attribute_value(attribute, |i| parse_xor_address(i, &TRANSACTION_ID))
+                    .unwrap();
+            assert_eq!(address, local);
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use crate::stun_attribute_types::*;
+use crate::stun_errors::StunParseError;
+
+use nom::combinator::rest;
+use nom::Err::Error;
+use nom::IResult;
+
+/// Maximum number of bytes in the value of a USERNAME attribute (less than 513), https://tools.ietf.org/html/rfc5389#section-15.3
+pub const STUN_USERNAME_MAX_NUM_BYTES: usize = 512;
+
+/// Maximum number of characters in the value of a REALM, NONCE or SOFTWARE attribute
+pub const STUN_TEXT_MAX_NUM_CHARS: usize = 127;
+
+/// Maximum number of bytes in the value of a REALM, NONCE or SOFTWARE attribute
+pub const STUN_TEXT_MAX_NUM_BYTES: usize = 763;
+
+/// The value of a USERNAME attribute, https://tools.ietf.org/html/rfc5389#section-15.3
+///
+/// A UTF-8 encoded sequence of less than 513 bytes.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct StunUsername<'a>(&'a str);
+
+/// The value of a REALM attribute, https://tools.ietf.org/html/rfc5389#section-15.7
+///
+/// A UTF-8 encoded sequence of less than 128 characters, which can be as long as 763 bytes.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct StunRealm<'a>(&'a str);
+
+/// The value of a NONCE attribute, https://tools.ietf.org/html/rfc5389#section-15.8
+///
+/// A sequence of less than 128 characters, which can be as long as 763 bytes.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct StunNonce<'a>(&'a str);
+
+/// The value of a SOFTWARE attribute, https://tools.ietf.org/html/rfc5389#section-15.10
+///
+/// A UTF-8 encoded sequence of less than 128 characters, which can be as long as 763 bytes.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct StunSoftware<'a>(&'a str);
+
+macro_rules! stun_text_attribute {
+    ($name:ident, $parser:ident, $attribute_type:expr, $max_num_bytes:expr, $max_num_chars:expr) => {
+        impl<'a> $name<'a> {
+            /// Create a value from the given string, checking the length limits of the attribute
+            pub fn new(value: &'a str) -> Result<Self, StunParseError<&'a [u8]>> {
+                check_text_length(value, $attribute_type, $max_num_bytes, $max_num_chars)?;
+
+                Ok($name(value))
+            }
+
+            /// The value as a string
+            pub fn as_str(&self) -> &'a str {
+                self.0
+            }
+
+            /// The value as it appears on the wire
+            pub fn as_bytes(&self) -> &'a [u8] {
+                self.0.as_bytes()
+            }
+        }
+
+        /// Parse the value of the attribute, validating that it is UTF-8 and within the length limits
+        pub fn $parser(input: &[u8]) -> IResult<&[u8], $name<'_>, StunParseError<&[u8]>> {
+            let (input, value) = rest(input)?;
+            let value = core::str::from_utf8(value)
+                .map_err(|_| Error(StunParseError::InvalidUtf8Error(value)))?;
+
+            match $name::new(value) {
+                Ok(value) => Ok((input, value)),
+                Err(e) => Err(Error(e)),
+            }
+        }
+    };
+}
+
+stun_text_attribute!(
+    StunUsername,
+    parse_username,
+    StunAttributeType::Username,
+    STUN_USERNAME_MAX_NUM_BYTES,
+    usize::MAX
+);
+stun_text_attribute!(
+    StunRealm,
+    parse_realm,
+    StunAttributeType::Realm,
+    STUN_TEXT_MAX_NUM_BYTES,
+    STUN_TEXT_MAX_NUM_CHARS
+);
+stun_text_attribute!(
+    StunNonce,
+    parse_nonce,
+    StunAttributeType::Nonce,
+    STUN_TEXT_MAX_NUM_BYTES,
+    STUN_TEXT_MAX_NUM_CHARS
+);
+stun_text_attribute!(
+    StunSoftware,
+    parse_software,
+    StunAttributeType::Software,
+    STUN_TEXT_MAX_NUM_BYTES,
+    STUN_TEXT_MAX_NUM_CHARS
+);
+
+fn check_text_length(
+    value: &str,
+    attribute_type: StunAttributeType,
+    max_num_bytes: usize,
+    max_num_chars: usize,
+) -> Result<(), StunParseError<&[u8]>> {
+    if value.len() > max_num_bytes || value.chars().count() > max_num_chars {
+        return Err(StunParseError::AttributeValueTooLongError(
+            attribute_type as u16,
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_username_valid() {
+        let input = "\u{30DE}\u{30C8}\u{30EA}\u{30C3}\u{30AF}\u{30B9}".as_bytes();
+        let result = parse_username(input);
+
+        assert!(result.is_ok());
+
+        let data = result.unwrap();
+        assert_eq!(data.0.len(), 0);
+        assert_eq!(data.1.as_str(), "マトリックス");
+        assert_eq!(data.1.as_bytes(), input);
+    }
+
+    #[test]
+    fn parse_username_invalid_utf8() {
+        let input = [b'a', 0xC3, 0x28];
+        let result = parse_username(&input);
+
+        assert!(result.is_err());
+
+        let err = result.unwrap_err();
+        match err {
+            Error(e) => assert_eq!(e, StunParseError::InvalidUtf8Error(&input[..])),
+            _ => panic!("Unexpected error:  {:?}", err),
+        }
+    }
+
+    #[test]
+    fn parse_username_too_long() {
+        let input = [b'a'; 513];
+        let result = parse_username(&input);
+
+        assert!(result.is_err());
+
+        let err = result.unwrap_err();
+        match err {
+            Error(e) => assert_eq!(e, StunParseError::AttributeValueTooLongError(0x0006)),
+            _ => panic!("Unexpected error:  {:?}", err),
+        }
+
+        assert!(parse_username(&input[..512]).is_ok());
+    }
+
+    #[test]
+    fn parse_realm_too_many_chars() {
+        let input = "r".repeat(128);
+
+        assert!(parse_realm(&input.as_bytes()[..127]).is_ok());
+        assert_eq!(
+            parse_realm(input.as_bytes()).unwrap_err(),
+            Error(StunParseError::AttributeValueTooLongError(0x0014))
+        );
+    }
+
+    #[test]
+    fn parse_nonce_multibyte_chars() {
+        // 127 characters of 3 bytes each is 381 bytes, which is allowed
+        let input = "\u{30DE}".repeat(127);
+
+        assert!(parse_nonce(input.as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn software_new() {
+        assert_eq!(
+            StunSoftware::new("stun-message").unwrap().as_str(),
+            "stun-message"
+        );
+        assert_eq!(
+            StunSoftware::new(&"s".repeat(200)),
+            Err(StunParseError::AttributeValueTooLongError(0x8022))
+        );
+    }
+}
@@ -0,0 +1,503 @@
+use crate::stun_attribute_types::*;
+use crate::stun_constants::*;
+use crate::stun_error_code::*;
+use crate::stun_errors::StunAuthError;
+use crate::stun_integrity::*;
+use crate::stun_message::*;
+use crate::stun_message_builder::*;
+use crate::stun_message_types::*;
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes128Gcm, Aes256Gcm, Nonce};
+use cookie_factory::GenError;
+
+/// Number of bytes in the nonce of an AES-GCM encrypted access token
+pub const STUN_ACCESS_TOKEN_NONCE_NUM_BYTES: usize = 12;
+
+/// Allowed difference between the clocks of the authorization server and the STUN server when
+/// checking the validity period of an access token, https://tools.ietf.org/html/rfc7635#section-6.2
+pub const STUN_ACCESS_TOKEN_CLOCK_SKEW: Duration = Duration::from_secs(5);
+
+/// AEAD algorithms used to protect access tokens, https://tools.ietf.org/html/rfc7635#section-6.2
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum AccessTokenAlgorithm {
+    /// AEAD_AES_128_GCM, 16 byte key
+    Aes128Gcm,
+
+    /// AEAD_AES_256_GCM, 32 byte key
+    Aes256Gcm,
+}
+
+/// A key shared between the authorization server and the STUN server, used to protect access tokens
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct AccessTokenKey {
+    /// algorithm the key is used with
+    pub algorithm: AccessTokenAlgorithm,
+
+    /// key material -- 16 or 32 bytes depending on the algorithm
+    pub key: Vec<u8>,
+}
+
+/// The keys known to a STUN server, indexed by key id.  The client sends the key id of the key
+/// that protects its access token in the USERNAME attribute.
+#[derive(Debug, Default, Clone)]
+pub struct AccessTokenKeyTable {
+    keys: BTreeMap<Vec<u8>, AccessTokenKey>,
+}
+
+impl AccessTokenKeyTable {
+    /// Create an empty key table
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a key with the given key id, replacing any existing key with the same id
+    pub fn insert(&mut self, key_id: &[u8], key: AccessTokenKey) {
+        self.keys.insert(key_id.to_vec(), key);
+    }
+
+    /// Remove the key with the given key id
+    pub fn remove(&mut self, key_id: &[u8]) -> Option<AccessTokenKey> {
+        self.keys.remove(key_id)
+    }
+
+    /// Get the key with the given key id
+    pub fn get(&self, key_id: &[u8]) -> Option<&AccessTokenKey> {
+        self.keys.get(key_id)
+    }
+}
+
+/// The decrypted contents of an ACCESS-TOKEN attribute, https://tools.ietf.org/html/rfc7635#section-6.2
+///
+/// struct {
+///     uint16_t nonce_length;
+///     opaque nonce[nonce_length];
+///     opaque {
+///         uint16_t key_length;
+///         opaque mac_key[key_length];
+///         uint64_t timestamp;
+///         uint32_t lifetime;
+///     } encrypted_block;
+/// } token;
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct AccessToken {
+    /// session key shared with the client, used as the MESSAGE-INTEGRITY key
+    pub mac_key: Vec<u8>,
+
+    /// time the token was issued -- 48 bits of seconds since the UNIX epoch and 16 bits of fraction
+    pub timestamp: u64,
+
+    /// number of seconds the token is valid for, starting at timestamp
+    pub lifetime: u32,
+}
+
+impl AccessToken {
+    /// Create a token issued at the given time (since the UNIX epoch) valid for the given number of seconds
+    pub fn new(mac_key: &[u8], issued_at: Duration, lifetime: u32) -> Self {
+        let fraction = (u64::from(issued_at.subsec_nanos()) << 16) / 1_000_000_000;
+
+        AccessToken {
+            mac_key: mac_key.to_vec(),
+            timestamp: (issued_at.as_secs() << 16) | fraction,
+            lifetime,
+        }
+    }
+
+    /// The time the token was issued, since the UNIX epoch
+    pub fn issued_at(&self) -> Duration {
+        let nanos = ((self.timestamp & 0xFFFF) * 1_000_000_000) >> 16;
+
+        Duration::new(self.timestamp >> 16, nanos as u32)
+    }
+
+    /// Whether the token is valid at the given time (since the UNIX epoch), allowing for clock skew
+    pub fn is_valid_at(&self, now: Duration) -> bool {
+        let issued_at = self.issued_at();
+        let expires_at = issued_at + Duration::from_secs(u64::from(self.lifetime));
+
+        now + STUN_ACCESS_TOKEN_CLOCK_SKEW >= issued_at
+            && now < expires_at + STUN_ACCESS_TOKEN_CLOCK_SKEW
+    }
+
+    /// The key used to compute and verify MESSAGE-INTEGRITY for requests carrying this token
+    pub fn message_integrity_key(&self) -> &[u8] {
+        &self.mac_key
+    }
+}
+
+/// Encrypt the given token into the value of an ACCESS-TOKEN attribute.  This is normally done by
+/// the authorization server, the STUN server only decrypts tokens.
+///
+/// # Arguments
+///
+/// * `token` - The token to encrypt
+/// * `key` - The key shared between the authorization server and the STUN server
+/// * `nonce` - A nonce that must never be reused with the same key
+/// * `server_name` - The name of the STUN server, which is authenticated as associated data
+pub fn encrypt_access_token(
+    token: &AccessToken,
+    key: &AccessTokenKey,
+    nonce: &[u8; STUN_ACCESS_TOKEN_NONCE_NUM_BYTES],
+    server_name: &[u8],
+) -> Result<Vec<u8>, StunAuthError> {
+    let mut block = Vec::with_capacity(14 + token.mac_key.len());
+    block.extend_from_slice(&(token.mac_key.len() as u16).to_be_bytes());
+    block.extend_from_slice(&token.mac_key);
+    block.extend_from_slice(&token.timestamp.to_be_bytes());
+    block.extend_from_slice(&token.lifetime.to_be_bytes());
+
+    let payload = Payload {
+        msg: &block,
+        aad: server_name,
+    };
+    let nonce_slice = Nonce::from_slice(nonce);
+
+    let encrypted_block = match key.algorithm {
+        AccessTokenAlgorithm::Aes128Gcm => Aes128Gcm::new_from_slice(&key.key)
+            .map_err(|_| StunAuthError::InvalidKeyLengthError(key.key.len()))?
+            .encrypt(nonce_slice, payload),
+        AccessTokenAlgorithm::Aes256Gcm => Aes256Gcm::new_from_slice(&key.key)
+            .map_err(|_| StunAuthError::InvalidKeyLengthError(key.key.len()))?
+            .encrypt(nonce_slice, payload),
+    }
+    .map_err(|_| StunAuthError::AccessTokenEncryptionError)?;
+
+    let mut output = Vec::with_capacity(2 + nonce.len() + encrypted_block.len());
+    output.extend_from_slice(&(nonce.len() as u16).to_be_bytes());
+    output.extend_from_slice(nonce);
+    output.extend_from_slice(&encrypted_block);
+
+    Ok(output)
+}
+
+/// Decrypt the value of an ACCESS-TOKEN attribute.
+///
+/// # Arguments
+///
+/// * `value` - The value of the ACCESS-TOKEN attribute
+/// * `key` - The key shared between the authorization server and the STUN server
+/// * `server_name` - The name of the STUN server, which is authenticated as associated data
+///
+/// # Return
+///
+/// A Result object, when successful contains the decrypted token.  Note that the validity period
+/// of the token is not checked.
+pub fn decrypt_access_token(
+    value: &[u8],
+    key: &AccessTokenKey,
+    server_name: &[u8],
+) -> Result<AccessToken, StunAuthError> {
+    if value.len() < 2 {
+        return Err(StunAuthError::InvalidAttributeError(
+            StunAttributeType::AccessToken as u16,
+        ));
+    }
+
+    let nonce_length = u16::from_be_bytes([value[0], value[1]]) as usize;
+    if nonce_length != STUN_ACCESS_TOKEN_NONCE_NUM_BYTES || value.len() < 2 + nonce_length {
+        return Err(StunAuthError::InvalidAttributeError(
+            StunAttributeType::AccessToken as u16,
+        ));
+    }
+
+    let nonce = Nonce::from_slice(&value[2..2 + nonce_length]);
+    let payload = Payload {
+        msg: &value[2 + nonce_length..],
+        aad: server_name,
+    };
+
+    let block = match key.algorithm {
+        AccessTokenAlgorithm::Aes128Gcm => Aes128Gcm::new_from_slice(&key.key)
+            .map_err(|_| StunAuthError::InvalidKeyLengthError(key.key.len()))?
+            .decrypt(nonce, payload),
+        AccessTokenAlgorithm::Aes256Gcm => Aes256Gcm::new_from_slice(&key.key)
+            .map_err(|_| StunAuthError::InvalidKeyLengthError(key.key.len()))?
+            .decrypt(nonce, payload),
+    }
+    .map_err(|_| StunAuthError::AccessTokenDecryptionError)?;
+
+    parse_access_token_block(&block)
+}
+
+fn parse_access_token_block(block: &[u8]) -> Result<AccessToken, StunAuthError> {
+    if block.len() < 2 {
+        return Err(StunAuthError::InvalidAccessTokenError);
+    }
+
+    // the key length is followed by the key, an 8 byte timestamp and a 4 byte lifetime
+    let key_length = u16::from_be_bytes([block[0], block[1]]) as usize;
+    if block.len() != 2 + key_length + 12 {
+        return Err(StunAuthError::InvalidAccessTokenError);
+    }
+
+    let (mac_key, rest) = block[2..].split_at(key_length);
+    let (timestamp, lifetime) = rest.split_at(8);
+
+    let mut timestamp_bytes = [0u8; 8];
+    timestamp_bytes.copy_from_slice(timestamp);
+    let mut lifetime_bytes = [0u8; 4];
+    lifetime_bytes.copy_from_slice(lifetime);
+
+    Ok(AccessToken {
+        mac_key: mac_key.to_vec(),
+        timestamp: u64::from_be_bytes(timestamp_bytes),
+        lifetime: u32::from_be_bytes(lifetime_bytes),
+    })
+}
+
+/// Authenticate a request that uses third-party authorization, https://tools.ietf.org/html/rfc7635#section-4
+///
+/// The key id is taken from the USERNAME attribute and used to find the key that decrypts the
+/// ACCESS-TOKEN attribute.  The token must be valid at the given time, and its mac_key must
+/// verify the MESSAGE-INTEGRITY attribute of the message.
+///
+/// # Arguments
+///
+/// * `message` - The parsed request
+/// * `keys` - The keys shared with the authorization server
+/// * `server_name` - The name of this STUN server
+/// * `now` - The current time, since the UNIX epoch
+///
+/// # Return
+///
+/// A Result object, when successful contains the decrypted token.
+pub fn verify_access_token(
+    message: &StunMessage,
+    keys: &AccessTokenKeyTable,
+    server_name: &[u8],
+    now: Duration,
+) -> Result<AccessToken, StunAuthError> {
+    let key_id = get_auth_attribute(message, StunAttributeType::Username)?;
+    let value = get_auth_attribute(message, StunAttributeType::AccessToken)?;
+
+    let key = keys.get(key_id).ok_or(StunAuthError::UnknownKeyIdError)?;
+    let token = decrypt_access_token(value, key, server_name)?;
+
+    if !token.is_valid_at(now) {
+        return Err(StunAuthError::AccessTokenExpiredError);
+    }
+
+    verify_message_integrity(message, token.message_integrity_key())?;
+
+    Ok(token)
+}
+
+/// Build a 401 error response advertising third-party authorization with the given server name,
+/// https://tools.ietf.org/html/rfc7635#section-4
+pub fn build_third_party_authorization_challenge(
+    message_method: StunMessageMethod,
+    transaction_id: &[u8; STUN_TRANSACTION_ID_NUM_BYTES],
+    realm: &[u8],
+    server_name: &[u8],
+) -> Result<Vec<u8>, GenError> {
+    StunMessageBuilder::new(
+        StunMessageClass::ErrorResponse,
+        message_method,
+        transaction_id,
+    )
+    .add_error_code_attribute(STUN_ERROR_UNAUTHORIZED, "Unauthorized")
+    .add_attribute(StunAttributeType::Realm as u16, realm)
+    .add_attribute(
+        StunAttributeType::ThirdPartyAuthorization as u16,
+        server_name,
+    )
+    .build()
+}
+
+fn get_auth_attribute<'a>(
+    message: &StunMessage<'a>,
+    attribute_type: StunAttributeType,
+) -> Result<&'a [u8], StunAuthError> {
+    message
+        .get_attribute(attribute_type as u16)
+        .map(|a| a.attribute_value)
+        .ok_or(StunAuthError::MissingAttributeError(attribute_type as u16))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_stun_message;
+
+    const SERVER_NAME: &[u8] = b"turn.example.com";
+    const ISSUED_AT: Duration = Duration::from_secs(1_600_000_000);
+
+    fn test_key() -> AccessTokenKey {
+        AccessTokenKey {
+            algorithm: AccessTokenAlgorithm::Aes128Gcm,
+            key: vec![0x11; 16],
+        }
+    }
+
+    fn test_key_table() -> AccessTokenKeyTable {
+        let mut keys = AccessTokenKeyTable::new();
+        keys.insert(b"kid-1", test_key());
+        keys
+    }
+
+    fn build_request(token: &AccessToken, key_id: &[u8], mac_key: &[u8]) -> Vec<u8> {
+        let value = encrypt_access_token(token, &test_key(), &[0x22; 12], SERVER_NAME).unwrap();
+
+        StunMessageBuilder::new(
+            StunMessageClass::Request,
+            StunMessageMethod::Binding,
+            &[0x33; STUN_TRANSACTION_ID_NUM_BYTES],
+        )
+        .add_attribute(StunAttributeType::Username as u16, key_id)
+        .add_attribute(StunAttributeType::AccessToken as u16, &value)
+        .add_message_integrity(mac_key)
+        .build()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_access_token_timestamp() {
+        let token = AccessToken::new(b"k", Duration::new(1234, 500_000_000), 60);
+
+        assert_eq!(token.timestamp, (1234 << 16) | 0x8000);
+        assert_eq!(token.issued_at(), Duration::new(1234, 500_000_000));
+    }
+
+    #[test]
+    fn test_access_token_validity() {
+        let token = AccessToken::new(b"k", ISSUED_AT, 60);
+
+        assert!(!token.is_valid_at(ISSUED_AT - Duration::from_secs(10)));
+        assert!(token.is_valid_at(ISSUED_AT - Duration::from_secs(1)));
+        assert!(token.is_valid_at(ISSUED_AT + Duration::from_secs(60)));
+        assert!(!token.is_valid_at(ISSUED_AT + Duration::from_secs(70)));
+    }
+
+    fn encrypt_decrypt_roundtrip(key: &AccessTokenKey) {
+        let token = AccessToken::new(&[0x55; 20], ISSUED_AT, 3600);
+        let value = encrypt_access_token(&token, key, &[0x66; 12], SERVER_NAME).unwrap();
+
+        assert_eq!(value[0..2], [0x00, 0x0C]);
+        assert_eq!(decrypt_access_token(&value, key, SERVER_NAME), Ok(token));
+    }
+
+    #[test]
+    fn test_encrypt_decrypt_roundtrip_aes_128_gcm() {
+        encrypt_decrypt_roundtrip(&test_key());
+    }
+
+    #[test]
+    fn test_encrypt_decrypt_roundtrip_aes_256_gcm() {
+        encrypt_decrypt_roundtrip(&AccessTokenKey {
+            algorithm: AccessTokenAlgorithm::Aes256Gcm,
+            key: vec![0x44; 32],
+        });
+    }
+
+    #[test]
+    fn test_decrypt_wrong_server_name() {
+        let token = AccessToken::new(&[0x55; 20], ISSUED_AT, 3600);
+        let value = encrypt_access_token(&token, &test_key(), &[0x66; 12], SERVER_NAME).unwrap();
+
+        assert_eq!(
+            decrypt_access_token(&value, &test_key(), b"other.example.com"),
+            Err(StunAuthError::AccessTokenDecryptionError)
+        );
+    }
+
+    #[test]
+    fn test_decrypt_truncated_value() {
+        // a value shorter than its 12 byte nonce, and one whose nonce length is not 12
+        assert_eq!(
+            decrypt_access_token(&[0x00, 0x0C, 0x00], &test_key(), SERVER_NAME),
+            Err(StunAuthError::InvalidAttributeError(0x001B))
+        );
+        assert_eq!(
+            decrypt_access_token(&[0x00; 30], &test_key(), SERVER_NAME),
+            Err(StunAuthError::InvalidAttributeError(0x001B))
+        );
+    }
+
+    #[test]
+    fn test_decrypt_invalid_key_length() {
+        let key = AccessTokenKey {
+            algorithm: AccessTokenAlgorithm::Aes256Gcm,
+            key: vec![0x44; 16],
+        };
+
+        let mut value = vec![0x00, 0x0C];
+        value.extend_from_slice(&[0x00; 28]);
+        assert_eq!(
+            decrypt_access_token(&value, &key, SERVER_NAME),
+            Err(StunAuthError::InvalidKeyLengthError(16))
+        );
+    }
+
+    #[test]
+    fn test_verify_access_token_valid() {
+        let token = AccessToken::new(&[0x77; 32], ISSUED_AT, 3600);
+        let data = build_request(&token, b"kid-1", &token.mac_key);
+        let (_, message) = parse_stun_message(&data).unwrap();
+
+        let result = verify_access_token(
+            &message,
+            &test_key_table(),
+            SERVER_NAME,
+            ISSUED_AT + Duration::from_secs(30),
+        );
+        assert_eq!(result, Ok(token));
+    }
+
+    #[test]
+    fn test_verify_access_token_unknown_key_id() {
+        let token = AccessToken::new(&[0x77; 32], ISSUED_AT, 3600);
+        let data = build_request(&token, b"kid-2", &token.mac_key);
+        let (_, message) = parse_stun_message(&data).unwrap();
+
+        let result = verify_access_token(&message, &test_key_table(), SERVER_NAME, ISSUED_AT);
+        assert_eq!(result, Err(StunAuthError::UnknownKeyIdError));
+    }
+
+    #[test]
+    fn test_verify_access_token_expired() {
+        let token = AccessToken::new(&[0x77; 32], ISSUED_AT, 60);
+        let data = build_request(&token, b"kid-1", &token.mac_key);
+        let (_, message) = parse_stun_message(&data).unwrap();
+
+        let result = verify_access_token(
+            &message,
+            &test_key_table(),
+            SERVER_NAME,
+            ISSUED_AT + Duration::from_secs(3600),
+        );
+        assert_eq!(result, Err(StunAuthError::AccessTokenExpiredError));
+    }
+
+    #[test]
+    fn test_verify_access_token_wrong_mac_key() {
+        let token = AccessToken::new(&[0x77; 32], ISSUED_AT, 3600);
+        let data = build_request(&token, b"kid-1", &[0x88; 32]);
+        let (_, message) = parse_stun_message(&data).unwrap();
+
+        let result = verify_access_token(&message, &test_key_table(), SERVER_NAME, ISSUED_AT);
+        assert_eq!(result, Err(StunAuthError::IntegrityCheckFailedError));
+    }
+
+    #[test]
+    fn test_build_third_party_authorization_challenge() {
+        let transaction_id = [0x99; STUN_TRANSACTION_ID_NUM_BYTES];
+        let data = build_third_party_authorization_challenge(
+            StunMessageMethod::Binding,
+            &transaction_id,
+            b"example.com",
+            SERVER_NAME,
+        )
+        .unwrap();
+        let (_, message) = parse_stun_message(&data).unwrap();
+
+        assert_eq!(message.message_class, StunMessageClass::ErrorResponse);
+        assert_eq!(
+            message.get_attribute(0x802E).unwrap().attribute_value,
+            SERVER_NAME
+        );
+    }
+}
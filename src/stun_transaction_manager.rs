@@ -0,0 +1,393 @@
+use crate::stun_client_transaction::*;
+use crate::stun_constants::*;
+use crate::stun_errors::StunTransactionError;
+use crate::stun_five_tuple::*;
+use crate::stun_message::*;
+use crate::stun_message_types::*;
+
+use alloc::vec::Vec;
+use std::collections::{HashMap, VecDeque};
+use std::time::Instant;
+
+/// A table of outstanding client transactions keyed by transaction id, for running many
+/// transactions at once, e.g. the connectivity checks of an ICE agent.
+///
+/// Each transaction carries a context chosen by the caller, e.g. the address the request is sent
+/// to, which is returned along with its transmissions and its outcome.  Like a single
+/// `ClientTransaction` the manager does no I/O:
+///
+/// - `poll_transmit` returns the requests that must be sent
+/// - `poll_timeout` returns the earliest deadline of all transactions, when `handle_timeout` must
+///   next be called
+/// - `handle_response` routes a received response to its transaction
+/// - `poll_outcome` returns the outcome of each transaction as it completes
+#[derive(Debug)]
+pub struct TransactionManager<T> {
+    transactions: HashMap<[u8; STUN_TRANSACTION_ID_NUM_BYTES], (ClientTransaction, T)>,
+
+    /// transactions with a transmission due, in the order they became due
+    transmits: VecDeque<[u8; STUN_TRANSACTION_ID_NUM_BYTES]>,
+
+    /// completed transactions whose outcome has not been polled
+    outcomes: VecDeque<(T, TransactionOutcome)>,
+}
+
+impl<T> Default for TransactionManager<T> {
+    fn default() -> Self {
+        TransactionManager {
+            transactions: HashMap::new(),
+            transmits: VecDeque::new(),
+            outcomes: VecDeque::new(),
+        }
+    }
+}
+
+impl<T> TransactionManager<T> {
+    /// Create an empty transaction table
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start a transaction for the given serialized request with the default parameters,
+    /// returning its transaction id.  The request is returned by `poll_transmit` right away.
+    pub fn start(
+        &mut self,
+        request: Vec<u8>,
+        protocol: TransportProtocol,
+        context: T,
+        now: Instant,
+    ) -> Result<[u8; STUN_TRANSACTION_ID_NUM_BYTES], StunTransactionError> {
+        self.start_with_config(
+            request,
+            protocol,
+            TransactionConfig::default(),
+            context,
+            now,
+        )
+    }
+
+    /// Start a transaction for the given serialized request with the given parameters
+    pub fn start_with_config(
+        &mut self,
+        request: Vec<u8>,
+        protocol: TransportProtocol,
+        config: TransactionConfig,
+        context: T,
+        now: Instant,
+    ) -> Result<[u8; STUN_TRANSACTION_ID_NUM_BYTES], StunTransactionError> {
+        let transaction = ClientTransaction::with_config(request, protocol, config, now)?;
+        let transaction_id = *transaction.transaction_id();
+
+        if self.transactions.contains_key(&transaction_id) {
+            return Err(StunTransactionError::DuplicateTransactionIdError);
+        }
+
+        self.transactions
+            .insert(transaction_id, (transaction, context));
+        self.transmits.push_back(transaction_id);
+
+        Ok(transaction_id)
+    }
+
+    /// Stop the transaction with the given id without an outcome, returning its context
+    pub fn cancel(&mut self, transaction_id: &[u8; STUN_TRANSACTION_ID_NUM_BYTES]) -> Option<T> {
+        self.transactions
+            .remove(transaction_id)
+            .map(|(_, context)| context)
+    }
+
+    /// The number of outstanding transactions
+    pub fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    /// Whether there are no outstanding transactions
+    pub fn is_empty(&self) -> bool {
+        self.transactions.is_empty()
+    }
+
+    /// The next request that is due to be sent and the context of its transaction
+    pub fn poll_transmit(&mut self) -> Option<(&T, &[u8])> {
+        while let Some(transaction_id) = self.transmits.pop_front() {
+            // the transaction may have completed or been cancelled since
+            if self.transactions.contains_key(&transaction_id) {
+                let (transaction, context) = &self.transactions[&transaction_id];
+                return Some((context, transaction.request()));
+            }
+        }
+
+        None
+    }
+
+    /// The earliest time at which `handle_timeout` must be called, or `None` if there are no
+    /// outstanding transactions
+    pub fn poll_timeout(&self) -> Option<Instant> {
+        self.transactions
+            .values()
+            .filter_map(|(transaction, _)| transaction.poll_timeout())
+            .min()
+    }
+
+    /// Advance all transactions to the given time, scheduling retransmissions and timing out
+    /// transactions
+    pub fn handle_timeout(&mut self, now: Instant) {
+        let mut timed_out = Vec::new();
+
+        for (transaction_id, (transaction, _)) in self.transactions.iter_mut() {
+            if transaction
+                .poll_timeout()
+                .is_some_and(|deadline| deadline > now)
+            {
+                continue;
+            }
+
+            transaction.handle_timeout(now);
+            if transaction.poll_transmit().is_some() {
+                self.transmits.push_back(*transaction_id);
+            }
+            if transaction.outcome().is_some() {
+                timed_out.push(*transaction_id);
+            }
+        }
+
+        for transaction_id in timed_out {
+            self.complete(&transaction_id);
+        }
+    }
+
+    /// Route a received message to its transaction, completing the transaction.
+    ///
+    /// # Return
+    ///
+    /// An error if the message does not complete a transaction, which is either:
+    ///
+    /// * `UnexpectedMessageTypeError` - the message is not a response, or it is a response with
+    ///   a different method than the request with its transaction id
+    /// * `UnknownTransactionError` - no request with its transaction id is outstanding, e.g. it is
+    ///   a late response to a transaction which has already completed
+    pub fn handle_response(&mut self, message: &StunMessage) -> Result<(), StunTransactionError> {
+        let unexpected = StunTransactionError::UnexpectedMessageTypeError(
+            message.message_class,
+            message.message_method,
+        );
+        if !matches!(
+            message.message_class,
+            StunMessageClass::SuccessResponse | StunMessageClass::ErrorResponse
+        ) {
+            return Err(unexpected);
+        }
+
+        let transaction_id = message.transaction_id;
+        let completed = match self.transactions.get_mut(transaction_id) {
+            Some((transaction, _)) => transaction.handle_response(message),
+            None => {
+                return Err(StunTransactionError::UnknownTransactionError(
+                    *transaction_id,
+                ))
+            }
+        };
+        if !completed {
+            return Err(unexpected);
+        }
+
+        self.complete(transaction_id);
+        Ok(())
+    }
+
+    /// The context and outcome of the next completed transaction
+    pub fn poll_outcome(&mut self) -> Option<(T, TransactionOutcome)> {
+        self.outcomes.pop_front()
+    }
+
+    fn complete(&mut self, transaction_id: &[u8; STUN_TRANSACTION_ID_NUM_BYTES]) {
+        if let Some((transaction, context)) = self.transactions.remove(transaction_id) {
+            if let Some(outcome) = transaction.into_outcome() {
+                self.outcomes.push_back((context, outcome));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_stun_message;
+    use crate::stun_message_builder::*;
+
+    use core::time::Duration;
+
+    fn build(
+        message_class: StunMessageClass,
+        message_method: StunMessageMethod,
+        transaction_id: u8,
+    ) -> Vec<u8> {
+        StunMessageBuilder::new(message_class, message_method, &[transaction_id; 12])
+            .build()
+            .unwrap()
+    }
+
+    fn request(transaction_id: u8) -> Vec<u8> {
+        build(
+            StunMessageClass::Request,
+            StunMessageMethod::Binding,
+            transaction_id,
+        )
+    }
+
+    fn handle(
+        manager: &mut TransactionManager<u32>,
+        data: &[u8],
+    ) -> Result<(), StunTransactionError> {
+        let (_, message) = parse_stun_message(data).unwrap();
+        manager.handle_response(&message)
+    }
+
+    #[test]
+    fn test_route_responses() {
+        let start = Instant::now();
+        let mut manager = TransactionManager::new();
+
+        for id in 0..100u8 {
+            manager
+                .start(request(id), TransportProtocol::Udp, id as u32, start)
+                .unwrap();
+        }
+        assert_eq!(manager.len(), 100);
+        assert_eq!(
+            manager.start(request(7), TransportProtocol::Udp, 1000, start),
+            Err(StunTransactionError::DuplicateTransactionIdError)
+        );
+
+        // every request is sent once to start with
+        let mut sent = 0;
+        while let Some((context, request)) = manager.poll_transmit() {
+            assert_eq!(request[8..20], [*context as u8; 12]);
+            sent += 1;
+        }
+        assert_eq!(sent, 100);
+
+        let response = build(
+            StunMessageClass::SuccessResponse,
+            StunMessageMethod::Binding,
+            42,
+        );
+        assert_eq!(handle(&mut manager, &response), Ok(()));
+        assert_eq!(
+            manager.poll_outcome(),
+            Some((42, TransactionOutcome::Success(response.clone())))
+        );
+        assert_eq!(manager.poll_outcome(), None);
+        assert_eq!(manager.len(), 99);
+
+        // a retransmitted response no longer matches a transaction
+        assert_eq!(
+            handle(&mut manager, &response),
+            Err(StunTransactionError::UnknownTransactionError([42; 12]))
+        );
+
+        let error = build(
+            StunMessageClass::ErrorResponse,
+            StunMessageMethod::Binding,
+            43,
+        );
+        assert_eq!(handle(&mut manager, &error), Ok(()));
+        assert_eq!(
+            manager.poll_outcome(),
+            Some((43, TransactionOutcome::Error(error)))
+        );
+    }
+
+    #[test]
+    fn test_reject_mismatched_responses() {
+        let start = Instant::now();
+        let mut manager = TransactionManager::new();
+        manager
+            .start(request(1), TransportProtocol::Udp, 1, start)
+            .unwrap();
+
+        // a request or indication is not a response, even with the transaction id of a request
+        let indication = build(StunMessageClass::Indication, StunMessageMethod::Binding, 1);
+        assert_eq!(
+            handle(&mut manager, &indication),
+            Err(StunTransactionError::UnexpectedMessageTypeError(
+                StunMessageClass::Indication,
+                StunMessageMethod::Binding
+            ))
+        );
+
+        // a response for a different method
+        let response = build(
+            StunMessageClass::SuccessResponse,
+            StunMessageMethod::Allocate,
+            1,
+        );
+        assert_eq!(
+            handle(&mut manager, &response),
+            Err(StunTransactionError::UnexpectedMessageTypeError(
+                StunMessageClass::SuccessResponse,
+                StunMessageMethod::Allocate
+            ))
+        );
+
+        assert_eq!(manager.len(), 1);
+        assert_eq!(manager.poll_outcome(), None);
+    }
+
+    #[test]
+    fn test_timers() {
+        let start = Instant::now();
+        let mut manager = TransactionManager::new();
+        assert_eq!(manager.poll_timeout(), None);
+
+        manager
+            .start(request(1), TransportProtocol::Udp, 1, start)
+            .unwrap();
+        manager
+            .start(
+                request(2),
+                TransportProtocol::Tcp,
+                2,
+                start + Duration::from_millis(200),
+            )
+            .unwrap();
+        manager
+            .start(request(3), TransportProtocol::Udp, 3, start)
+            .unwrap();
+        while manager.poll_transmit().is_some() {}
+
+        assert_eq!(
+            manager.poll_timeout(),
+            Some(start + Duration::from_millis(500))
+        );
+        assert_eq!(manager.cancel(&[3; 12]), Some(3));
+
+        // only the UDP request is retransmitted
+        manager.handle_timeout(start + Duration::from_millis(500));
+        assert_eq!(
+            manager.poll_transmit().map(|(context, _)| *context),
+            Some(1)
+        );
+        assert!(manager.poll_transmit().is_none());
+        assert_eq!(
+            manager.poll_timeout(),
+            Some(start + Duration::from_millis(1500))
+        );
+
+        // both time out after 39.5 seconds
+        let mut now = start;
+        while let Some(deadline) = manager.poll_timeout() {
+            now = deadline;
+            manager.handle_timeout(now);
+        }
+        assert_eq!(now, start + Duration::from_millis(39_700));
+        assert_eq!(
+            manager.poll_outcome(),
+            Some((1, TransactionOutcome::Timeout))
+        );
+        assert_eq!(
+            manager.poll_outcome(),
+            Some((2, TransactionOutcome::Timeout))
+        );
+        assert!(manager.is_empty());
+    }
+}
@@ -0,0 +1,489 @@
+use crate::serializer::serialized_attributes_length;
+use crate::stun_attribute_types::*;
+use crate::stun_constants::*;
+use crate::stun_error_code::*;
+use crate::stun_message::*;
+use crate::stun_message_types::*;
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use nom::combinator::all_consuming;
+
+/// A way in which a structurally valid STUN message violates the rules of the RFCs
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum Violation {
+    /// The message length field does not match the length of the attributes
+    MessageLengthMismatch { expected: u16, actual: u16 },
+
+    /// The attribute of the given type appears after FINGERPRINT, https://tools.ietf.org/html/rfc5389#section-15.5
+    AttributeAfterFingerprint(u16),
+
+    /// The attribute of the given type, which is not FINGERPRINT, appears after MESSAGE-INTEGRITY,
+    /// https://tools.ietf.org/html/rfc5389#section-15.4, or after MESSAGE-INTEGRITY-SHA256,
+    /// which itself can only follow MESSAGE-INTEGRITY, https://tools.ietf.org/html/rfc8489#section-14.6
+    AttributeAfterMessageIntegrity(u16),
+
+    /// The attribute of the given type has a fixed length, and the value has a different length
+    InvalidAttributeLength(u16),
+
+    /// The value of the attribute of the given type is malformed
+    InvalidAttributeValue(u16),
+
+    /// The message contains an ERROR-CODE attribute but is not an error response
+    UnexpectedErrorCode,
+
+    /// The message does not contain the attribute of the given type, which is required for
+    /// its class and method
+    MissingAttribute(u16),
+}
+
+/// Attributes that must be present in a message with the given method and class
+const REQUIRED_ATTRIBUTES: &[(StunMessageMethod, StunMessageClass, &[StunAttributeType])] = &[
+    (
+        StunMessageMethod::Binding,
+        StunMessageClass::SuccessResponse,
+        &[StunAttributeType::XorMappedAddress],
+    ),
+    (
+        StunMessageMethod::Allocate,
+        StunMessageClass::SuccessResponse,
+        &[
+            StunAttributeType::XorRelayedAddress,
+            StunAttributeType::Lifetime,
+            StunAttributeType::XorMappedAddress,
+        ],
+    ),
+    (
+        StunMessageMethod::Refresh,
+        StunMessageClass::SuccessResponse,
+        &[StunAttributeType::Lifetime],
+    ),
+    (
+        StunMessageMethod::Allocate,
+        StunMessageClass::Request,
+        &[StunAttributeType::RequestedTransport],
+    ),
+    (
+        StunMessageMethod::CreatePermission,
+        StunMessageClass::Request,
+        &[StunAttributeType::XorPeerAddress],
+    ),
+    (
+        StunMessageMethod::ChannelBind,
+        StunMessageClass::Request,
+        &[
+            StunAttributeType::ChannelNumber,
+            StunAttributeType::XorPeerAddress,
+        ],
+    ),
+    (
+        StunMessageMethod::Send,
+        StunMessageClass::Indication,
+        &[StunAttributeType::XorPeerAddress, StunAttributeType::Data],
+    ),
+    (
+        StunMessageMethod::Data,
+        StunMessageClass::Indication,
+        &[StunAttributeType::XorPeerAddress, StunAttributeType::Data],
+    ),
+    (
+        StunMessageMethod::Connect,
+        StunMessageClass::Request,
+        &[StunAttributeType::XorPeerAddress],
+    ),
+    (
+        StunMessageMethod::Connect,
+        StunMessageClass::SuccessResponse,
+        &[StunAttributeType::ConnectionId],
+    ),
+    (
+        StunMessageMethod::ConnectionBind,
+        StunMessageClass::Request,
+        &[StunAttributeType::ConnectionId],
+    ),
+    (
+        StunMessageMethod::ConnectionAttempt,
+        StunMessageClass::Indication,
+        &[
+            StunAttributeType::XorPeerAddress,
+            StunAttributeType::ConnectionId,
+        ],
+    ),
+];
+
+/// Attributes that must be present in an error response with the given error code
+const REQUIRED_ERROR_ATTRIBUTES: &[(u16, &[StunAttributeType])] = &[
+    (
+        STUN_ERROR_TRY_ALTERNATE,
+        &[StunAttributeType::AlternateServer],
+    ),
+    (
+        STUN_ERROR_UNKNOWN_ATTRIBUTE,
+        &[StunAttributeType::UnknownAttributes],
+    ),
+    (
+        STUN_ERROR_STALE_NONCE,
+        &[StunAttributeType::Realm, StunAttributeType::Nonce],
+    ),
+];
+
+/// Attributes whose value has a fixed length
+const FIXED_LENGTH_ATTRIBUTES: &[(StunAttributeType, usize)] = &[
+    (
+        StunAttributeType::MessageIntegrity,
+        STUN_MESSAGE_INTEGRITY_NUM_BYTES,
+    ),
+    (StunAttributeType::Fingerprint, 4),
+    (StunAttributeType::Lifetime, 4),
+    (StunAttributeType::ConnectionId, 4),
+    (StunAttributeType::ChangeRequest, 4),
+    (StunAttributeType::ResponsePort, 4),
+    (StunAttributeType::ChannelNumber, 4),
+    (StunAttributeType::RequestedTransport, 4),
+];
+
+/// Check a parsed STUN message against the attribute rules of the RFCs.
+///
+/// Parsing only checks that a message is structurally valid, this checks the semantic rules:
+/// attribute ordering around MESSAGE-INTEGRITY and FINGERPRINT, attributes that are required or
+/// not allowed for the message class and method, and the lengths of fixed size attributes.
+///
+/// # Arguments
+///
+/// * `message` - The parsed STUN message to check
+///
+/// # Return
+///
+/// All of the violations found in the message, which is empty if the message is conformant.
+pub fn validate(message: &StunMessage) -> Vec<Violation> {
+    let mut violations = vec![];
+
+    let actual = serialized_attributes_length(&message.attributes) as u16;
+    if message.message_length != actual {
+        violations.push(Violation::MessageLengthMismatch {
+            expected: message.message_length,
+            actual,
+        });
+    }
+
+    validate_ordering(message, &mut violations);
+    validate_lengths(message, &mut violations);
+    validate_error_code(message, &mut violations);
+
+    for (method, class, attribute_types) in REQUIRED_ATTRIBUTES {
+        if message.message_method == *method && message.message_class == *class {
+            validate_required(message, attribute_types, &mut violations);
+        }
+    }
+
+    violations
+}
+
+fn validate_ordering(message: &StunMessage, violations: &mut Vec<Violation>) {
+    let mut seen_message_integrity = false;
+    let mut seen_message_integrity_sha256 = false;
+    let mut seen_fingerprint = false;
+
+    for attribute in &message.attributes {
+        let attribute_type = attribute.attribute_type;
+        let allowed_after_message_integrity = attribute_type
+            == StunAttributeType::Fingerprint as u16
+            || (attribute_type == StunAttributeType::MessageIntegritySha256 as u16
+                && !seen_message_integrity_sha256);
+
+        if seen_fingerprint {
+            violations.push(Violation::AttributeAfterFingerprint(attribute_type));
+        } else if (seen_message_integrity || seen_message_integrity_sha256)
+            && !allowed_after_message_integrity
+        {
+            violations.push(Violation::AttributeAfterMessageIntegrity(attribute_type));
+        }
+
+        if attribute_type == StunAttributeType::MessageIntegrity as u16 {
+            seen_message_integrity = true;
+        } else if attribute_type == StunAttributeType::MessageIntegritySha256 as u16 {
+            seen_message_integrity_sha256 = true;
+        } else if attribute_type == StunAttributeType::Fingerprint as u16 {
+            seen_fingerprint = true;
+        }
+    }
+}
+
+fn validate_lengths(message: &StunMessage, violations: &mut Vec<Violation>) {
+    for attribute in &message.attributes {
+        let expected = FIXED_LENGTH_ATTRIBUTES
+            .iter()
+            .find(|(t, _)| *t as u16 == attribute.attribute_type);
+
+        if let Some((_, length)) = expected {
+            if attribute.attribute_value.len() != *length {
+                violations.push(Violation::InvalidAttributeLength(attribute.attribute_type));
+            }
+        }
+
+        // MESSAGE-INTEGRITY-SHA256 can be truncated to a multiple of 4 bytes
+        if attribute.attribute_type == StunAttributeType::MessageIntegritySha256 as u16 {
+            let length = attribute.attribute_value.len();
+            if !(STUN_MESSAGE_INTEGRITY_SHA256_MIN_NUM_BYTES
+                ..=STUN_MESSAGE_INTEGRITY_SHA256_NUM_BYTES)
+                .contains(&length)
+                || length % 4 != 0
+            {
+                violations.push(Violation::InvalidAttributeLength(attribute.attribute_type));
+            }
+        }
+    }
+}
+
+fn validate_error_code(message: &StunMessage, violations: &mut Vec<Violation>) {
+    let attribute_type = StunAttributeType::ErrorCode as u16;
+    let attribute = message.get_attribute(attribute_type);
+
+    if message.message_class != StunMessageClass::ErrorResponse {
+        if attribute.is_some() {
+            violations.push(Violation::UnexpectedErrorCode);
+        }
+        return;
+    }
+
+    let attribute = match attribute {
+        Some(attribute) => attribute,
+        None => {
+            violations.push(Violation::MissingAttribute(attribute_type));
+            return;
+        }
+    };
+
+    let error_code = match all_consuming(parse_error_code)(attribute.attribute_value) {
+        Ok((_, error_code)) => error_code,
+        Err(_) => {
+            violations.push(Violation::InvalidAttributeValue(attribute_type));
+            return;
+        }
+    };
+
+    for (code, attribute_types) in REQUIRED_ERROR_ATTRIBUTES {
+        if error_code.code == *code {
+            validate_required(message, attribute_types, violations);
+        }
+    }
+}
+
+fn validate_required(
+    message: &StunMessage,
+    attribute_types: &[StunAttributeType],
+    violations: &mut Vec<Violation>,
+) {
+    for attribute_type in attribute_types {
+        if message.get_attribute(*attribute_type as u16).is_none() {
+            violations.push(Violation::MissingAttribute(*attribute_type as u16));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_stun_message;
+    use crate::stun_integrity::append_message_integrity;
+    use crate::stun_message_builder::*;
+
+    fn builder(class: StunMessageClass, method: StunMessageMethod) -> StunMessageBuilder {
+        StunMessageBuilder::new(class, method, &[0x31; STUN_TRANSACTION_ID_NUM_BYTES])
+    }
+
+    fn validate_bytes(data: &[u8]) -> Vec<Violation> {
+        let (_, message) = parse_stun_message(data).unwrap();
+        validate(&message)
+    }
+
+    fn append_fingerprint(data: &mut Vec<u8>) {
+        data.extend_from_slice(&[0x80, 0x28, 0x00, 0x04, 0x01, 0x02, 0x03, 0x04]);
+        data[3] += 8;
+    }
+
+    #[test]
+    fn test_validate_conformant() {
+        let mut data = builder(
+            StunMessageClass::SuccessResponse,
+            StunMessageMethod::Binding,
+        )
+        .add_xor_address_attribute(
+            StunAttributeType::XorMappedAddress as u16,
+            &"192.0.2.1:32853".parse().unwrap(),
+        )
+        .add_attribute(StunAttributeType::Software as u16, b"test")
+        .add_message_integrity(b"key")
+        .build()
+        .unwrap();
+        append_fingerprint(&mut data);
+
+        assert_eq!(validate_bytes(&data), vec![]);
+    }
+
+    #[test]
+    fn test_validate_attribute_after_fingerprint() {
+        let mut data = builder(StunMessageClass::Request, StunMessageMethod::Binding)
+            .build()
+            .unwrap();
+        append_fingerprint(&mut data);
+        data.extend_from_slice(&[0x80, 0x22, 0x00, 0x00]);
+        data[3] += 4;
+
+        assert_eq!(
+            validate_bytes(&data),
+            vec![Violation::AttributeAfterFingerprint(0x8022)]
+        );
+    }
+
+    #[test]
+    fn test_validate_attribute_after_message_integrity() {
+        let mut data = builder(StunMessageClass::Request, StunMessageMethod::Binding)
+            .add_message_integrity(b"key")
+            .build()
+            .unwrap();
+        data.extend_from_slice(&[0x80, 0x22, 0x00, 0x00]);
+        data[3] += 4;
+        append_fingerprint(&mut data);
+
+        assert_eq!(
+            validate_bytes(&data),
+            vec![Violation::AttributeAfterMessageIntegrity(0x8022)]
+        );
+    }
+
+    #[test]
+    fn test_validate_message_integrity_sha256() {
+        // MESSAGE-INTEGRITY-SHA256 can follow MESSAGE-INTEGRITY, but not the other way around
+        let data = builder(StunMessageClass::Request, StunMessageMethod::Binding)
+            .add_message_integrity(b"key")
+            .add_message_integrity_sha256(b"key")
+            .add_fingerprint()
+            .build()
+            .unwrap();
+        assert_eq!(validate_bytes(&data), vec![]);
+
+        let mut data = builder(StunMessageClass::Request, StunMessageMethod::Binding)
+            .add_message_integrity_sha256(b"key")
+            .build()
+            .unwrap();
+        append_message_integrity(&mut data, b"key").unwrap();
+        assert_eq!(
+            validate_bytes(&data),
+            vec![Violation::AttributeAfterMessageIntegrity(0x0008)]
+        );
+
+        // truncated to 16 bytes, but not to 12
+        let mut data = builder(StunMessageClass::Request, StunMessageMethod::Binding)
+            .add_attribute(StunAttributeType::MessageIntegritySha256 as u16, &[0; 16])
+            .build()
+            .unwrap();
+        assert_eq!(validate_bytes(&data), vec![]);
+        data[23] = 12;
+        data.truncate(data.len() - 4);
+        data[3] -= 4;
+        assert_eq!(
+            validate_bytes(&data),
+            vec![Violation::InvalidAttributeLength(0x001C)]
+        );
+    }
+
+    #[test]
+    fn test_validate_error_code_in_success_response() {
+        let data = builder(
+            StunMessageClass::SuccessResponse,
+            StunMessageMethod::Binding,
+        )
+        .add_xor_address_attribute(
+            StunAttributeType::XorMappedAddress as u16,
+            &"192.0.2.1:32853".parse().unwrap(),
+        )
+        .add_error_code_attribute(STUN_ERROR_BAD_REQUEST, "Bad Request")
+        .build()
+        .unwrap();
+
+        assert_eq!(validate_bytes(&data), vec![Violation::UnexpectedErrorCode]);
+    }
+
+    #[test]
+    fn test_validate_missing_xor_mapped_address() {
+        let data = builder(
+            StunMessageClass::SuccessResponse,
+            StunMessageMethod::Binding,
+        )
+        .build()
+        .unwrap();
+
+        assert_eq!(
+            validate_bytes(&data),
+            vec![Violation::MissingAttribute(0x0020)]
+        );
+    }
+
+    #[test]
+    fn test_validate_error_response() {
+        let data = builder(StunMessageClass::ErrorResponse, StunMessageMethod::Binding)
+            .build()
+            .unwrap();
+        assert_eq!(
+            validate_bytes(&data),
+            vec![Violation::MissingAttribute(0x0009)]
+        );
+
+        let data = builder(StunMessageClass::ErrorResponse, StunMessageMethod::Binding)
+            .add_error_code_attribute(STUN_ERROR_STALE_NONCE, "Stale Nonce")
+            .add_attribute(StunAttributeType::Realm as u16, b"example.org")
+            .build()
+            .unwrap();
+        assert_eq!(
+            validate_bytes(&data),
+            vec![Violation::MissingAttribute(0x0015)]
+        );
+
+        let data = builder(StunMessageClass::ErrorResponse, StunMessageMethod::Binding)
+            .add_attribute(
+                StunAttributeType::ErrorCode as u16,
+                &[0x00, 0x00, 0x09, 0x00],
+            )
+            .build()
+            .unwrap();
+        assert_eq!(
+            validate_bytes(&data),
+            vec![Violation::InvalidAttributeValue(0x0009)]
+        );
+    }
+
+    #[test]
+    fn test_validate_fixed_lengths() {
+        let data = builder(StunMessageClass::Request, StunMessageMethod::ConnectionBind)
+            .add_attribute(StunAttributeType::ConnectionId as u16, &[0x01, 0x02])
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            validate_bytes(&data),
+            vec![Violation::InvalidAttributeLength(0x002A)]
+        );
+    }
+
+    #[test]
+    fn test_validate_message_length_mismatch() {
+        let data = builder(StunMessageClass::Request, StunMessageMethod::Binding)
+            .add_attribute(StunAttributeType::Software as u16, b"test")
+            .build()
+            .unwrap();
+
+        // the parser rejects such a message, but one can be put together by hand
+        let (_, mut message) = parse_stun_message(&data).unwrap();
+        message.message_length = 4;
+
+        assert_eq!(
+            validate(&message),
+            vec![Violation::MessageLengthMismatch {
+                expected: 4,
+                actual: 8
+            }]
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use crate::stun_errors::StunParseError;
+
+use alloc::vec::Vec;
+
+use nom::number::complete::{be_u16, be_u24, be_u32, be_u8};
+use nom::IResult;
+
+/// Parse the value of a CHANNEL-NUMBER attribute, https://tools.ietf.org/html/rfc5766#section-14.1
+///
+/// 0                   1                   2                   3
+/// 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |        Channel Number         |         RFFU = 0              |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+pub fn parse_channel_number(input: &[u8]) -> IResult<&[u8], u16, StunParseError<&[u8]>> {
+    let (input, channel_number) = be_u16(input)?;
+    let (input, _) = be_u16(input)?;
+
+    Ok((input, channel_number))
+}
+
+/// Serialize the value of a CHANNEL-NUMBER attribute
+pub fn serialize_channel_number(channel_number: u16) -> Vec<u8> {
+    let mut output = Vec::with_capacity(4);
+
+    output.extend_from_slice(&channel_number.to_be_bytes());
+    output.extend_from_slice(&[0, 0]);
+
+    output
+}
+
+/// Parse the value of a LIFETIME attribute, the remaining lifetime of an allocation in seconds,
+/// https://tools.ietf.org/html/rfc5766#section-14.2
+pub fn parse_lifetime(input: &[u8]) -> IResult<&[u8], u32, StunParseError<&[u8]>> {
+    be_u32(input)
+}
+
+/// Parse the value of a REQUESTED-TRANSPORT attribute, the protocol number of the transport
+/// to allocate, https://tools.ietf.org/html/rfc5766#section-14.7
+///
+/// 0                   1                   2                   3
+/// 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |    Protocol   |                    RFFU                       |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+pub fn parse_requested_transport(input: &[u8]) -> IResult<&[u8], u8, StunParseError<&[u8]>> {
+    let (input, protocol) = be_u8(input)?;
+    let (input, _) = be_u24(input)?;
+
+    Ok((input, protocol))
+}
+
+/// Serialize the value of a REQUESTED-TRANSPORT attribute
+pub fn serialize_requested_transport(protocol: u8) -> Vec<u8> {
+    let mut output = Vec::with_capacity(4);
+
+    output.push(protocol);
+    output.extend_from_slice(&[0, 0, 0]);
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stun_constants::*;
+
+    #[test]
+    fn test_channel_number_roundtrip() {
+        let value = serialize_channel_number(0x4001);
+        assert_eq!(value, [0x40, 0x01, 0x00, 0x00]);
+        assert_eq!(parse_channel_number(&value).unwrap(), (&[][..], 0x4001));
+
+        assert!(parse_channel_number(&[0x40, 0x01]).is_err());
+    }
+
+    #[test]
+    fn test_requested_transport_roundtrip() {
+        let value = serialize_requested_transport(TURN_TRANSPORT_UDP);
+        assert_eq!(value, [17, 0, 0, 0]);
+        assert_eq!(
+            parse_requested_transport(&value).unwrap(),
+            (&[][..], TURN_TRANSPORT_UDP)
+        );
+
+        assert_eq!(
+            parse_lifetime(&[0x00, 0x00, 0x02, 0x58]).unwrap(),
+            (&[][..], 600)
+        );
+    }
+}
@@ -0,0 +1,164 @@
+use crate::stun_constants::*;
+use crate::stun_errors::StunParseError;
+
+use alloc::vec::Vec;
+
+use nom::bytes::complete::take;
+use nom::number::complete::be_u16;
+use nom::Err::Error;
+use nom::IResult;
+
+/// A ChannelData message, which carries application data between a client and a peer over a
+/// channel bound with ChannelBind, https://tools.ietf.org/html/rfc5766#section-11.4
+/// 0                   1                   2                   3
+/// 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |         Channel Number        |            Length             |
+/// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+/// |                                                               |
+/// /                       Application Data                        /
+/// /                                                               /
+/// |                                                               |
+/// |                               +-------------------------------+
+/// |                               |
+/// +-------------------------------+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct ChannelData<'a> {
+    /// the channel the data is sent on, in the range 0x4000-0x7FFF
+    pub channel_number: u16,
+
+    /// the application data, without padding
+    pub data: &'a [u8],
+}
+
+/// Parse a ChannelData message from the given input buffer.
+///
+/// # Return
+///
+/// A nom::IResult object.  On success a tuple containing the unparsed portion of the input
+/// buffer, which starts with the padding of the message if there is any, and a ChannelData
+/// object.  On error, an error object describing the error.
+pub fn parse_channel_data(input: &[u8]) -> IResult<&[u8], ChannelData<'_>, StunParseError<&[u8]>> {
+    let (input, channel_number) = be_u16(input)?;
+    if !(TURN_CHANNEL_NUMBER_MIN..=TURN_CHANNEL_NUMBER_MAX).contains(&channel_number) {
+        return Err(Error(StunParseError::InvalidChannelNumberError(
+            channel_number,
+        )));
+    }
+
+    let (input, length) = be_u16(input)?;
+    let (input, data) = take(length as usize)(input)?;
+
+    Ok((
+        input,
+        ChannelData {
+            channel_number,
+            data,
+        },
+    ))
+}
+
+/// Serialize a ChannelData message, padded to a multiple of 4 bytes as required over TCP and
+/// allowed over UDP
+pub fn serialize_channel_data(channel_number: u16, data: &[u8]) -> Vec<u8> {
+    let length = padded_length(TURN_CHANNEL_DATA_HEADER_NUM_BYTES + data.len());
+    let mut output = Vec::with_capacity(length);
+
+    output.extend_from_slice(&channel_number.to_be_bytes());
+    output.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    output.extend_from_slice(data);
+    output.resize(length, 0);
+
+    output
+}
+
+/// The length of the STUN or ChannelData message at the start of a TCP stream, where
+/// ChannelData messages are padded to a multiple of 4 bytes,
+/// https://tools.ietf.org/html/rfc5766#section-11.5
+///
+/// # Return
+///
+/// The length of the message including any padding, or `None` if the input is too short to hold
+/// the length.  An error if the first two bits are neither those of a STUN message (0b00) nor
+/// those of a ChannelData message (0b01), in which case the stream cannot be framed.
+pub fn stream_message_length(input: &[u8]) -> Result<Option<usize>, StunParseError<&[u8]>> {
+    if input.len() < TURN_CHANNEL_DATA_HEADER_NUM_BYTES {
+        return Ok(None);
+    }
+
+    let first = u16::from_be_bytes([input[0], input[1]]);
+    let length = u16::from_be_bytes([input[2], input[3]]) as usize;
+
+    match first & STUN_MESSAGE_TYPE_ZERO_MASK {
+        0 => Ok(Some(STUN_HEADER_NUM_BYTES + length)),
+        TURN_CHANNEL_NUMBER_MIN => Ok(Some(padded_length(
+            TURN_CHANNEL_DATA_HEADER_NUM_BYTES + length,
+        ))),
+        _ => Err(StunParseError::InvalidMessageFirstTwoBitsError(first)),
+    }
+}
+
+fn padded_length(length: usize) -> usize {
+    (length + 3) & !3
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_channel_data_roundtrip() {
+        let data = serialize_channel_data(0x4001, b"hello");
+        assert_eq!(
+            data,
+            [0x40, 0x01, 0x00, 0x05, b'h', b'e', b'l', b'l', b'o', 0x00, 0x00, 0x00]
+        );
+
+        let (remaining, message) = parse_channel_data(&data).unwrap();
+        assert_eq!(remaining, [0x00, 0x00, 0x00]);
+        assert_eq!(message.channel_number, 0x4001);
+        assert_eq!(message.data, b"hello");
+
+        // over UDP the padding may be left out
+        let (remaining, message) = parse_channel_data(&data[..9]).unwrap();
+        assert!(remaining.is_empty());
+        assert_eq!(message.data, b"hello");
+    }
+
+    #[test]
+    fn test_parse_channel_data_invalid() {
+        let data = serialize_channel_data(0x3FFF, b"hello");
+        assert_eq!(
+            parse_channel_data(&data),
+            Err(Error(StunParseError::InvalidChannelNumberError(0x3FFF)))
+        );
+
+        let data = serialize_channel_data(0x4000, b"hello");
+        assert!(parse_channel_data(&data[..8]).is_err());
+    }
+
+    #[test]
+    fn test_stream_message_length() {
+        // a Binding request with 8 bytes of attributes
+        assert_eq!(
+            stream_message_length(&[0x00, 0x01, 0x00, 0x08]),
+            Ok(Some(28))
+        );
+
+        // ChannelData is padded
+        assert_eq!(
+            stream_message_length(&[0x40, 0x00, 0x00, 0x05]),
+            Ok(Some(12))
+        );
+        assert_eq!(
+            stream_message_length(&[0x7F, 0xFF, 0x00, 0x00]),
+            Ok(Some(4))
+        );
+
+        assert_eq!(stream_message_length(&[0x00, 0x01, 0x00]), Ok(None));
+        assert_eq!(
+            stream_message_length(&[0x80, 0x01, 0x00, 0x00]),
+            Err(StunParseError::InvalidMessageFirstTwoBitsError(0x8001))
+        );
+    }
+}
@@ -0,0 +1,880 @@
+use crate::parser::{parse_attribute_value, parse_stun_message};
+use crate::stun_address::*;
+use crate::stun_attribute_types::*;
+use crate::stun_authenticator::*;
+use crate::stun_client_transaction::*;
+use crate::stun_constants::*;
+use crate::stun_error_code::*;
+use crate::stun_errors::StunTurnClientError;
+use crate::stun_five_tuple::*;
+use crate::stun_message::*;
+use crate::stun_message_builder::*;
+use crate::stun_message_types::*;
+use crate::stun_text_attributes::*;
+use crate::stun_transaction_manager::*;
+use crate::turn_attributes::*;
+use crate::turn_channel_data::*;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::time::Duration;
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, SocketAddr};
+use std::time::Instant;
+
+/// The longest data sent to a peer, which leaves room in a Send indication for its other
+/// attributes
+const MAX_PEER_DATA_NUM_BYTES: usize = u16::MAX as usize - 64;
+
+/// A request made by `TurnClient`
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum TurnRequest {
+    /// create the allocation, https://tools.ietf.org/html/rfc5766#section-6
+    Allocate,
+
+    /// refresh the allocation before it expires, https://tools.ietf.org/html/rfc5766#section-7
+    Refresh,
+
+    /// install or refresh the permission for the given peer IP address,
+    /// https://tools.ietf.org/html/rfc5766#section-9
+    CreatePermission(IpAddr),
+
+    /// bind or refresh the given channel to the given peer,
+    /// https://tools.ietf.org/html/rfc5766#section-11
+    ChannelBind {
+        channel_number: u16,
+        peer: SocketAddr,
+    },
+
+    /// delete the allocation with a Refresh request with a LIFETIME of 0
+    Deallocate,
+}
+
+/// Something that happened to the allocation of a `TurnClient`
+#[derive(Debug)]
+pub enum TurnClientEvent {
+    /// the allocation was created
+    Allocated {
+        /// the address of the relay, which peers send to
+        relayed_address: SocketAddr,
+
+        /// the address the server saw the client come from
+        mapped_address: SocketAddr,
+
+        /// the time until the allocation expires, unless it is refreshed
+        lifetime: Duration,
+    },
+
+    /// the allocation was refreshed
+    Refreshed { lifetime: Duration },
+
+    /// the permission for the given peer IP address was installed
+    PermissionCreated(IpAddr),
+
+    /// the channel was bound, and data to the peer is now sent as ChannelData
+    ChannelBound {
+        channel_number: u16,
+        peer: SocketAddr,
+    },
+
+    /// data from a peer, received in a Data indication or on a channel
+    Data { peer: SocketAddr, data: Vec<u8> },
+
+    /// the request failed, after any retry with credentials
+    RequestFailed {
+        request: TurnRequest,
+        error: StunTurnClientError,
+    },
+
+    /// the allocation was deleted, has expired or could not be created, and the client can no
+    /// longer be used
+    Closed,
+}
+
+/// Options of a `TurnClient`
+#[derive(Debug, Default, Clone)]
+pub struct TurnClientOptions {
+    /// the SOFTWARE attribute to add to the requests, if any
+    pub software: Option<String>,
+
+    /// add a FINGERPRINT attribute to the requests and indications
+    pub fingerprint: bool,
+
+    /// the lifetime to request for the allocation, or `None` for the default of the server
+    pub lifetime: Option<Duration>,
+
+    /// the retransmission parameters of the requests
+    pub config: TransactionConfig,
+}
+
+/// When something that lives for a while, i.e. the allocation, a permission or a channel,
+/// expires and must be refreshed
+#[derive(Debug, Clone, Copy)]
+struct Expiry {
+    expires_at: Instant,
+
+    /// `None` while a refresh is outstanding or once it has failed
+    refresh_at: Option<Instant>,
+}
+
+impl Expiry {
+    /// Refresh a minute before expiry, or half way through short lifetimes
+    fn new(now: Instant, lifetime: Duration) -> Self {
+        let refresh_after = match lifetime > Duration::from_secs(120) {
+            true => lifetime - Duration::from_secs(60),
+            false => lifetime / 2,
+        };
+
+        Expiry {
+            expires_at: now + lifetime,
+            refresh_at: Some(now + refresh_after),
+        }
+    }
+
+    fn earliest(&self) -> Instant {
+        match self.refresh_at {
+            Some(refresh_at) => refresh_at.min(self.expires_at),
+            None => self.expires_at,
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+enum State {
+    Allocating,
+    Allocated,
+    Deallocating,
+    Closed,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Channel {
+    peer: SocketAddr,
+
+    /// `None` until the first ChannelBind succeeds
+    expiry: Option<Expiry>,
+}
+
+/// A request in flight, and how far its authentication has gone
+#[derive(Debug, Clone)]
+struct Attempt {
+    request: TurnRequest,
+
+    /// the request as built by the authenticator, which tracks whether it was resent
+    authenticated: StunAuthenticatedRequest,
+}
+
+/// The address the authenticator caches the challenge of the server under, as the client only
+/// talks to one server and does not know its address
+fn server() -> SocketAddr {
+    SocketAddr::from(([0, 0, 0, 0], 0))
+}
+
+/// A sans-IO TURN client which creates and keeps an allocation on a server,
+/// https://tools.ietf.org/html/rfc5766
+///
+/// The requests are authenticated with `StunAuthenticator`: the first Allocate request is sent
+/// without credentials and resent with the long-term credentials once the server challenges it
+/// with 401 (Unauthorized), after which every request is authenticated, with SHA-256 if the
+/// server offers it.  A request answered with 438 (Stale Nonce) is resent with the new NONCE,
+/// and no request is resent more than once.  Once allocated, the client refreshes:
+///
+/// - the allocation a minute before its LIFETIME expires
+/// - each permission, which lasts 5 minutes, with CreatePermission
+/// - each channel, which lasts 10 minutes, with ChannelBind
+///
+/// Data to a peer is sent as ChannelData once a channel to it is bound, and in a Send indication
+/// otherwise.  Data from peers arrives in Data indications and ChannelData messages.
+///
+/// Like `ClientTransaction` the client does no I/O, everything is exchanged with the server:
+///
+/// - `poll_transmit` returns the messages to send to the server
+/// - `poll_timeout` returns when `handle_timeout` must next be called
+/// - `handle_input` must be called with each message received from the server, over UDP a
+///   datagram and over TCP a message framed with `stream_message_length`
+/// - `poll_event` returns what happened to the allocation, and the data received from peers
+#[derive(Debug)]
+pub struct TurnClient {
+    authenticator: StunAuthenticator,
+    protocol: TransportProtocol,
+    options: TurnClientOptions,
+
+    state: State,
+    relayed_address: Option<SocketAddr>,
+    mapped_address: Option<SocketAddr>,
+    allocation: Option<Expiry>,
+
+    /// permissions by peer IP address, `None` until the first CreatePermission succeeds
+    permissions: HashMap<IpAddr, Option<Expiry>>,
+    channels: HashMap<u16, Channel>,
+    next_channel_number: u16,
+
+    transactions: TransactionManager<Attempt>,
+
+    /// indications and ChannelData messages to send
+    transmits: VecDeque<Vec<u8>>,
+    events: VecDeque<TurnClientEvent>,
+}
+
+impl TurnClient {
+    /// Start creating an allocation with the given long-term credentials and the default
+    /// options.  The Allocate request must be sent right away, see `poll_transmit`.
+    pub fn new(
+        username: &str,
+        password: &str,
+        protocol: TransportProtocol,
+        now: Instant,
+    ) -> Result<Self, StunTurnClientError> {
+        Self::with_options(
+            username,
+            password,
+            protocol,
+            TurnClientOptions::default(),
+            now,
+        )
+    }
+
+    /// Start creating an allocation with the given options
+    pub fn with_options(
+        username: &str,
+        password: &str,
+        protocol: TransportProtocol,
+        options: TurnClientOptions,
+        now: Instant,
+    ) -> Result<Self, StunTurnClientError> {
+        let authenticator = StunAuthenticator::new(username, password)?;
+        if let Some(software) = &options.software {
+            StunSoftware::new(software).map_err(|_| StunTurnClientError::InvalidRequestError)?;
+        }
+
+        let mut client = TurnClient {
+            authenticator,
+            protocol,
+            options,
+            state: State::Allocating,
+            relayed_address: None,
+            mapped_address: None,
+            allocation: None,
+            permissions: HashMap::new(),
+            channels: HashMap::new(),
+            next_channel_number: TURN_CHANNEL_NUMBER_MIN,
+            transactions: TransactionManager::new(),
+            transmits: VecDeque::new(),
+            events: VecDeque::new(),
+        };
+        client.start(TurnRequest::Allocate, now)?;
+
+        Ok(client)
+    }
+
+    /// The relayed transport address, once allocated
+    pub fn relayed_address(&self) -> Option<SocketAddr> {
+        self.relayed_address
+    }
+
+    /// The address the server saw the client come from, once allocated
+    pub fn mapped_address(&self) -> Option<SocketAddr> {
+        self.mapped_address
+    }
+
+    /// Whether the allocation has been created and is neither deleted nor expired
+    pub fn is_allocated(&self) -> bool {
+        self.state == State::Allocated
+    }
+
+    /// Install a permission for the given peer IP address, which the client keeps refreshing
+    /// for as long as the allocation lives.  Nothing is sent if there already is one.
+    pub fn create_permission(
+        &mut self,
+        peer: IpAddr,
+        now: Instant,
+    ) -> Result<(), StunTurnClientError> {
+        self.check_allocated()?;
+        if self.permissions.contains_key(&peer) {
+            return Ok(());
+        }
+
+        self.start(TurnRequest::CreatePermission(peer), now)?;
+        self.permissions.insert(peer, None);
+
+        Ok(())
+    }
+
+    /// Bind a channel to the given peer, returning its channel number.  The binding also
+    /// installs a permission for the peer, and both are refreshed for as long as the allocation
+    /// lives.  Nothing is sent if the peer already has a channel.
+    pub fn bind_channel(
+        &mut self,
+        peer: SocketAddr,
+        now: Instant,
+    ) -> Result<u16, StunTurnClientError> {
+        self.check_allocated()?;
+        if let Some((&channel_number, _)) = self.channels.iter().find(|(_, c)| c.peer == peer) {
+            return Ok(channel_number);
+        }
+
+        let channel_numbers = TURN_CHANNEL_NUMBER_MIN..=TURN_CHANNEL_NUMBER_MAX;
+        let channel_number = channel_numbers
+            .clone()
+            .chain(channel_numbers)
+            .skip((self.next_channel_number - TURN_CHANNEL_NUMBER_MIN) as usize)
+            .take((TURN_CHANNEL_NUMBER_MAX - TURN_CHANNEL_NUMBER_MIN) as usize + 1)
+            .find(|channel_number| !self.channels.contains_key(channel_number))
+            .ok_or(StunTurnClientError::NoChannelAvailableError)?;
+
+        let request = TurnRequest::ChannelBind {
+            channel_number,
+            peer,
+        };
+        self.start(request, now)?;
+        self.channels
+            .insert(channel_number, Channel { peer, expiry: None });
+        self.next_channel_number = match channel_number {
+            TURN_CHANNEL_NUMBER_MAX => TURN_CHANNEL_NUMBER_MIN,
+            _ => channel_number + 1,
+        };
+
+        Ok(channel_number)
+    }
+
+    /// Send data to a peer through the relay, as ChannelData if a channel to the peer is bound
+    /// and in a Send indication otherwise.  The server drops the data unless there is a
+    /// permission for the peer.
+    pub fn send(&mut self, peer: SocketAddr, data: &[u8]) -> Result<(), StunTurnClientError> {
+        self.check_allocated()?;
+        if data.len() > MAX_PEER_DATA_NUM_BYTES {
+            return Err(StunTurnClientError::DataTooLongError);
+        }
+
+        let channel_number = self
+            .channels
+            .iter()
+            .find(|(_, channel)| channel.peer == peer && channel.expiry.is_some())
+            .map(|(&channel_number, _)| channel_number);
+        if let Some(channel_number) = channel_number {
+            self.transmits
+                .push_back(serialize_channel_data(channel_number, data));
+            return Ok(());
+        }
+
+        let transaction_id = random_transaction_id()?;
+        let mut builder = StunMessageBuilder::new(
+            StunMessageClass::Indication,
+            StunMessageMethod::Send,
+            &transaction_id,
+        );
+        builder
+            .add_xor_address_attribute(StunAttributeType::XorPeerAddress as u16, &peer)
+            .add_attribute(StunAttributeType::Data as u16, data);
+        if self.options.fingerprint {
+            builder.add_fingerprint();
+        }
+        let indication = builder
+            .build()
+            .map_err(|_| StunTurnClientError::InvalidRequestError)?;
+        self.transmits.push_back(indication);
+
+        Ok(())
+    }
+
+    /// Delete the allocation, which closes the client once the server confirms it
+    pub fn deallocate(&mut self, now: Instant) -> Result<(), StunTurnClientError> {
+        self.check_allocated()?;
+
+        self.start(TurnRequest::Deallocate, now)?;
+        self.state = State::Deallocating;
+        self.allocation = None;
+        self.permissions.clear();
+
+        Ok(())
+    }
+
+    /// The next message to send to the server, if any
+    pub fn poll_transmit(&mut self) -> Option<Vec<u8>> {
+        if let Some(transmit) = self.transmits.pop_front() {
+            return Some(transmit);
+        }
+
+        self.transactions
+            .poll_transmit()
+            .map(|(_, request)| request.to_vec())
+    }
+
+    /// The next thing that happened to the allocation, if any
+    pub fn poll_event(&mut self) -> Option<TurnClientEvent> {
+        self.events.pop_front()
+    }
+
+    /// The time at which `handle_timeout` must be called, or `None` once the client is closed
+    pub fn poll_timeout(&self) -> Option<Instant> {
+        if self.state == State::Closed {
+            return None;
+        }
+
+        let expiries = self
+            .allocation
+            .iter()
+            .chain(self.permissions.values().flatten())
+            .chain(self.channels.values().filter_map(|c| c.expiry.as_ref()))
+            .map(Expiry::earliest);
+
+        expiries.chain(self.transactions.poll_timeout()).min()
+    }
+
+    /// Advance the client to the given time, retransmitting requests, refreshing the
+    /// allocation, the permissions and the channels, and dropping anything that has expired
+    pub fn handle_timeout(&mut self, now: Instant) {
+        if self.state == State::Closed {
+            return;
+        }
+
+        self.transactions.handle_timeout(now);
+        self.handle_outcomes(now);
+
+        if let Some(allocation) = &mut self.allocation {
+            if now >= allocation.expires_at {
+                self.close();
+                return;
+            }
+            if allocation.refresh_at.is_some_and(|at| now >= at) {
+                allocation.refresh_at = None;
+                self.start_or_fail(TurnRequest::Refresh, now);
+            }
+        }
+
+        let mut refreshes = Vec::new();
+        self.permissions.retain(|&peer, expiry| match expiry {
+            Some(expiry) if now >= expiry.expires_at => false,
+            Some(expiry) => {
+                if expiry.refresh_at.is_some_and(|at| now >= at) {
+                    expiry.refresh_at = None;
+                    refreshes.push(TurnRequest::CreatePermission(peer));
+                }
+                true
+            }
+            None => true,
+        });
+        self.channels
+            .retain(|&channel_number, channel| match &mut channel.expiry {
+                Some(expiry) if now >= expiry.expires_at => false,
+                Some(expiry) => {
+                    if expiry.refresh_at.is_some_and(|at| now >= at) {
+                        expiry.refresh_at = None;
+                        refreshes.push(TurnRequest::ChannelBind {
+                            channel_number,
+                            peer: channel.peer,
+                        });
+                    }
+                    true
+                }
+                None => true,
+            });
+
+        for request in refreshes {
+            self.start_or_fail(request, now);
+        }
+    }
+
+    /// Handle a message received from the server.  Returns whether it is a response to one of
+    /// the requests, or data from a peer.  Anything else is ignored.
+    pub fn handle_input(&mut self, input: &[u8], now: Instant) -> bool {
+        if self.state == State::Closed || input.is_empty() {
+            return false;
+        }
+
+        if input[0] & 0xC0 == 0x40 {
+            let channel_data = match parse_channel_data(input) {
+                Ok((_, channel_data)) => channel_data,
+                Err(_) => return false,
+            };
+            return match self.channels.get(&channel_data.channel_number) {
+                Some(channel) if channel.expiry.is_some() => {
+                    self.events.push_back(TurnClientEvent::Data {
+                        peer: channel.peer,
+                        data: channel_data.data.to_vec(),
+                    });
+                    true
+                }
+                _ => false,
+            };
+        }
+
+        let message = match parse_stun_message(input) {
+            Ok((_, message)) => message,
+            Err(_) => return false,
+        };
+
+        match message.message_class {
+            StunMessageClass::Indication if message.message_method == StunMessageMethod::Data => {
+                match parse_data_indication(&message) {
+                    Some((peer, data)) if self.state == State::Allocated => {
+                        self.events.push_back(TurnClientEvent::Data {
+                            peer,
+                            data: data.to_vec(),
+                        });
+                        true
+                    }
+                    _ => false,
+                }
+            }
+            StunMessageClass::SuccessResponse | StunMessageClass::ErrorResponse => {
+                if self.transactions.handle_response(&message).is_err() {
+                    return false;
+                }
+                self.handle_outcomes(now);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn check_allocated(&self) -> Result<(), StunTurnClientError> {
+        match self.state {
+            State::Allocated => Ok(()),
+            _ => Err(StunTurnClientError::NoAllocationError),
+        }
+    }
+
+    fn start_or_fail(&mut self, request: TurnRequest, now: Instant) {
+        if let Err(error) = self.start(request, now) {
+            self.fail(request, error);
+        }
+    }
+
+    /// Send the request, with credentials once the server has challenged the client
+    fn start(&mut self, request: TurnRequest, now: Instant) -> Result<(), StunTurnClientError> {
+        let attempt = Attempt {
+            request,
+            authenticated: self.authenticated_request(request),
+        };
+
+        self.send_request(attempt, now)
+    }
+
+    /// Send the request with a new transaction id, which it needs each time it is sent
+    fn send_request(&mut self, attempt: Attempt, now: Instant) -> Result<(), StunTurnClientError> {
+        let transaction_id = random_transaction_id()?;
+        let request = self
+            .authenticator
+            .build_request(&attempt.authenticated, &transaction_id)?;
+
+        self.transactions
+            .start_with_config(request, self.protocol, self.options.config, attempt, now)
+            .map_err(|_| StunTurnClientError::InvalidRequestError)?;
+
+        Ok(())
+    }
+
+    /// The method and attributes of the request, to which the authenticator adds the
+    /// credentials
+    fn authenticated_request(&self, request: TurnRequest) -> StunAuthenticatedRequest {
+        let message_method = match request {
+            TurnRequest::Allocate => StunMessageMethod::Allocate,
+            TurnRequest::Refresh | TurnRequest::Deallocate => StunMessageMethod::Refresh,
+            TurnRequest::CreatePermission(_) => StunMessageMethod::CreatePermission,
+            TurnRequest::ChannelBind { .. } => StunMessageMethod::ChannelBind,
+        };
+        let mut authenticated = StunAuthenticatedRequest::new(server(), message_method);
+        let lifetime = self
+            .options
+            .lifetime
+            .map(|lifetime| (lifetime.as_secs() as u32).to_be_bytes());
+
+        match request {
+            TurnRequest::Allocate => {
+                authenticated.add_attribute(
+                    StunAttributeType::RequestedTransport as u16,
+                    &serialize_requested_transport(TURN_TRANSPORT_UDP),
+                );
+                if let Some(lifetime) = lifetime {
+                    authenticated.add_attribute(StunAttributeType::Lifetime as u16, &lifetime);
+                }
+            }
+            TurnRequest::Refresh => {
+                if let Some(lifetime) = lifetime {
+                    authenticated.add_attribute(StunAttributeType::Lifetime as u16, &lifetime);
+                }
+            }
+            TurnRequest::Deallocate => {
+                authenticated.add_attribute(StunAttributeType::Lifetime as u16, &[0; 4]);
+            }
+            TurnRequest::CreatePermission(peer) => {
+                authenticated.add_xor_address_attribute(
+                    StunAttributeType::XorPeerAddress as u16,
+                    &SocketAddr::new(peer, 0),
+                );
+            }
+            TurnRequest::ChannelBind {
+                channel_number,
+                peer,
+            } => {
+                authenticated
+                    .add_attribute(
+                        StunAttributeType::ChannelNumber as u16,
+                        &serialize_channel_number(channel_number),
+                    )
+                    .add_xor_address_attribute(StunAttributeType::XorPeerAddress as u16, &peer);
+            }
+        }
+
+        if let Some(software) = &self.options.software {
+            authenticated.add_attribute(StunAttributeType::Software as u16, software.as_bytes());
+        }
+        if self.options.fingerprint {
+            authenticated.add_fingerprint();
+        }
+
+        authenticated
+    }
+
+    fn handle_outcomes(&mut self, now: Instant) {
+        while let Some((attempt, outcome)) = self.transactions.poll_outcome() {
+            let request = attempt.request;
+            let result = match outcome {
+                TransactionOutcome::Success(response) | TransactionOutcome::Error(response) => {
+                    self.handle_response(attempt, &response, now)
+                }
+                TransactionOutcome::Timeout => Err(StunTurnClientError::TimeoutError),
+            };
+
+            if let Err(error) = result {
+                self.fail(request, error);
+            }
+        }
+    }
+
+    /// Resend a request which the server challenged for credentials or a new NONCE, otherwise
+    /// act on the final response
+    fn handle_response(
+        &mut self,
+        mut attempt: Attempt,
+        response: &[u8],
+        now: Instant,
+    ) -> Result<(), StunTurnClientError> {
+        let (_, message) =
+            parse_stun_message(response).map_err(|_| StunTurnClientError::InvalidResponseError)?;
+
+        match self
+            .authenticator
+            .handle_response(&mut attempt.authenticated, &message)?
+        {
+            StunAuthenticationOutcome::Resend => self.send_request(attempt, now),
+            StunAuthenticationOutcome::Complete
+                if message.message_class == StunMessageClass::SuccessResponse =>
+            {
+                self.handle_success(attempt.request, &message, now)
+            }
+            StunAuthenticationOutcome::Complete => {
+                let error_code = message
+                    .get_attribute(StunAttributeType::ErrorCode as u16)
+                    .and_then(|attribute| parse_attribute_value(attribute, parse_error_code).ok())
+                    .ok_or(StunTurnClientError::InvalidResponseError)?;
+
+                Err(StunTurnClientError::RequestFailedError(error_code.code))
+            }
+        }
+    }
+
+    fn handle_success(
+        &mut self,
+        request: TurnRequest,
+        message: &StunMessage,
+        now: Instant,
+    ) -> Result<(), StunTurnClientError> {
+        match request {
+            TurnRequest::Allocate => {
+                let transaction_id = message.transaction_id;
+                let xor_address = |attribute_type: StunAttributeType| {
+                    let attribute = message.get_attribute(attribute_type as u16)?;
+                    parse_attribute_value(attribute, |input| {
+                        parse_xor_address(input, transaction_id)
+                    })
+                    .ok()
+                };
+                let relayed_address = xor_address(StunAttributeType::XorRelayedAddress);
+                let mapped_address = xor_address(StunAttributeType::XorMappedAddress);
+                let (relayed_address, mapped_address) = match (relayed_address, mapped_address) {
+                    (Some(relayed_address), Some(mapped_address)) => {
+                        (relayed_address, mapped_address)
+                    }
+                    _ => return Err(StunTurnClientError::InvalidResponseError),
+                };
+                let lifetime = response_lifetime(message)?;
+
+                self.state = State::Allocated;
+                self.relayed_address = Some(relayed_address);
+                self.mapped_address = Some(mapped_address);
+                self.allocation = Some(Expiry::new(now, lifetime));
+                self.events.push_back(TurnClientEvent::Allocated {
+                    relayed_address,
+                    mapped_address,
+                    lifetime,
+                });
+            }
+            TurnRequest::Refresh => {
+                let lifetime = response_lifetime(message)?;
+                if self.state == State::Allocated {
+                    self.allocation = Some(Expiry::new(now, lifetime));
+                    self.events
+                        .push_back(TurnClientEvent::Refreshed { lifetime });
+                }
+            }
+            TurnRequest::Deallocate => self.close(),
+            TurnRequest::CreatePermission(peer) => {
+                if let Some(expiry) = self.permissions.get_mut(&peer) {
+                    let lifetime = Duration::from_secs(TURN_PERMISSION_LIFETIME_SECS.into());
+                    if expiry.replace(Expiry::new(now, lifetime)).is_none() {
+                        self.events
+                            .push_back(TurnClientEvent::PermissionCreated(peer));
+                    }
+                }
+            }
+            TurnRequest::ChannelBind {
+                channel_number,
+                peer,
+            } => {
+                let channel = match self.channels.get_mut(&channel_number) {
+                    Some(channel) if channel.peer == peer => channel,
+                    _ => return Ok(()),
+                };
+                let lifetime = Duration::from_secs(TURN_CHANNEL_LIFETIME_SECS.into());
+                let bound = channel.expiry.replace(Expiry::new(now, lifetime)).is_none();
+
+                // the binding installs or refreshes the permission for the peer, which is then
+                // refreshed along with the others
+                let lifetime = Duration::from_secs(TURN_PERMISSION_LIFETIME_SECS.into());
+                self.permissions
+                    .insert(peer.ip(), Some(Expiry::new(now, lifetime)));
+
+                if bound {
+                    self.events.push_back(TurnClientEvent::ChannelBound {
+                        channel_number,
+                        peer,
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Report a failed request, and drop what it was for
+    fn fail(&mut self, request: TurnRequest, error: StunTurnClientError) {
+        let allocation_mismatch = matches!(
+            error,
+            StunTurnClientError::RequestFailedError(STUN_ERROR_ALLOCATION_MISMATCH)
+        );
+        self.events
+            .push_back(TurnClientEvent::RequestFailed { request, error });
+
+        match request {
+            TurnRequest::Allocate | TurnRequest::Deallocate => self.close(),
+            // the allocation is gone, otherwise it lives until it expires
+            TurnRequest::Refresh if allocation_mismatch => self.close(),
+            TurnRequest::Refresh => {}
+            TurnRequest::CreatePermission(peer) => {
+                self.permissions.remove(&peer);
+            }
+            TurnRequest::ChannelBind { channel_number, .. } => {
+                self.channels.remove(&channel_number);
+            }
+        }
+    }
+
+    fn close(&mut self) {
+        if self.state == State::Closed {
+            return;
+        }
+
+        self.state = State::Closed;
+        self.allocation = None;
+        self.permissions.clear();
+        self.channels.clear();
+        self.transactions = TransactionManager::new();
+        self.transmits.clear();
+        self.events.push_back(TurnClientEvent::Closed);
+    }
+}
+
+/// The LIFETIME of a success response
+fn response_lifetime(message: &StunMessage) -> Result<Duration, StunTurnClientError> {
+    message
+        .get_attribute(StunAttributeType::Lifetime as u16)
+        .and_then(|attribute| parse_attribute_value(attribute, parse_lifetime).ok())
+        .map(|lifetime| Duration::from_secs(lifetime.into()))
+        .ok_or(StunTurnClientError::InvalidResponseError)
+}
+
+/// The peer address and the data of a Data indication
+fn parse_data_indication<'a>(message: &StunMessage<'a>) -> Option<(SocketAddr, &'a [u8])> {
+    let attribute = message.get_attribute(StunAttributeType::XorPeerAddress as u16)?;
+    let peer = parse_attribute_value(attribute, |input| {
+        parse_xor_address(input, message.transaction_id)
+    })
+    .ok()?;
+    let data = message.get_attribute(StunAttributeType::Data as u16)?;
+
+    Some((peer, data.attribute_value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expiry() {
+        let now = Instant::now();
+
+        let expiry = Expiry::new(now, Duration::from_secs(600));
+        assert_eq!(expiry.expires_at, now + Duration::from_secs(600));
+        assert_eq!(expiry.earliest(), now + Duration::from_secs(540));
+
+        // short lifetimes are refreshed half way through
+        let expiry = Expiry::new(now, Duration::from_secs(60));
+        assert_eq!(expiry.earliest(), now + Duration::from_secs(30));
+
+        let expiry = Expiry {
+            refresh_at: None,
+            ..expiry
+        };
+        assert_eq!(expiry.earliest(), now + Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_not_allocated() {
+        let now = Instant::now();
+        let peer: SocketAddr = "192.0.2.1:3478".parse().unwrap();
+
+        let username = "a".repeat(STUN_USERNAME_MAX_NUM_BYTES + 1);
+        assert!(matches!(
+            TurnClient::new(&username, "secret", TransportProtocol::Udp, now),
+            Err(StunTurnClientError::InvalidRequestError)
+        ));
+
+        let mut client = TurnClient::new("alice", "secret", TransportProtocol::Udp, now).unwrap();
+        assert!(!client.is_allocated());
+        assert!(matches!(
+            client.create_permission(peer.ip(), now),
+            Err(StunTurnClientError::NoAllocationError)
+        ));
+        assert!(matches!(
+            client.bind_channel(peer, now),
+            Err(StunTurnClientError::NoAllocationError)
+        ));
+        assert!(matches!(
+            client.send(peer, b"hello"),
+            Err(StunTurnClientError::NoAllocationError)
+        ));
+
+        // the Allocate request goes out first, without credentials
+        let request = client.poll_transmit().unwrap();
+        let (_, message) = parse_stun_message(&request).unwrap();
+        assert_eq!(message.message_class, StunMessageClass::Request);
+        assert_eq!(message.message_method, StunMessageMethod::Allocate);
+        assert!(message
+            .get_attribute(StunAttributeType::MessageIntegrity as u16)
+            .is_none());
+        assert!(client.poll_transmit().is_none());
+        assert!(client.poll_timeout().is_some());
+    }
+}
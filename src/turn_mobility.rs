@@ -0,0 +1,368 @@
+use crate::stun_address::*;
+use crate::stun_attribute_types::*;
+use crate::stun_errors::{StunMobilityError, StunParseError};
+use crate::stun_five_tuple::*;
+use crate::stun_integrity::*;
+use crate::stun_message::*;
+use crate::stun_message_types::*;
+
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::time::Duration;
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use nom::combinator::all_consuming;
+use nom::error::ErrorKind;
+use nom::multi::length_data;
+use nom::number::complete::{be_u16, be_u64, be_u8};
+use nom::sequence::tuple;
+use nom::Err::Error;
+use nom::IResult;
+
+/// Number of bytes in the key used to protect mobility tickets (AES-256-GCM)
+pub const TURN_MOBILITY_TICKET_KEY_NUM_BYTES: usize = 32;
+
+/// Number of bytes in the nonce that prefixes an encrypted mobility ticket
+pub const TURN_MOBILITY_TICKET_NONCE_NUM_BYTES: usize = 12;
+
+/// Associated data authenticated along with every mobility ticket, so that a ticket cannot be
+/// confused with other data encrypted under the same key
+const TURN_MOBILITY_TICKET_AAD: &[u8] = b"MOBILITY-TICKET";
+
+/// The contents of a MOBILITY-TICKET, https://tools.ietf.org/html/rfc8016#section-3.1
+///
+/// The ticket is opaque to the client, only the server that issued it can decrypt it.  It ties
+/// the ticket to an allocation, the 5-tuple the allocation was bound to when the ticket was
+/// issued and the username that authenticated the allocation.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct MobilityTicket {
+    /// server assigned identifier of the allocation
+    pub allocation_id: u64,
+
+    /// seconds since the UNIX epoch after which the ticket is no longer accepted
+    pub expires_at: u64,
+
+    /// 5-tuple the allocation was bound to when the ticket was issued
+    pub five_tuple: FiveTuple,
+
+    /// username that authenticated the allocation
+    pub username: Vec<u8>,
+}
+
+/// Encrypts and decrypts mobility tickets with a key known only to the server
+#[derive(Clone)]
+pub struct MobilityTicketCodec {
+    cipher: Aes256Gcm,
+}
+
+impl MobilityTicketCodec {
+    /// Create a codec using the given server secret
+    pub fn new(key: &[u8; TURN_MOBILITY_TICKET_KEY_NUM_BYTES]) -> Self {
+        MobilityTicketCodec {
+            cipher: Aes256Gcm::new(key.into()),
+        }
+    }
+
+    /// Encrypt the given ticket into the value of a MOBILITY-TICKET attribute
+    ///
+    /// # Arguments
+    ///
+    /// * `ticket` - The ticket to encrypt
+    /// * `nonce` - A nonce that must never be reused with the key of this codec
+    pub fn encrypt(
+        &self,
+        ticket: &MobilityTicket,
+        nonce: &[u8; TURN_MOBILITY_TICKET_NONCE_NUM_BYTES],
+    ) -> Vec<u8> {
+        let mut block = Vec::with_capacity(64 + ticket.username.len());
+        block.extend_from_slice(&ticket.allocation_id.to_be_bytes());
+        block.extend_from_slice(&ticket.expires_at.to_be_bytes());
+        block.push(ticket.five_tuple.protocol as u8);
+        block.extend_from_slice(&serialize_address(&ticket.five_tuple.client_address));
+        block.extend_from_slice(&serialize_address(&ticket.five_tuple.server_address));
+        block.extend_from_slice(&(ticket.username.len() as u16).to_be_bytes());
+        block.extend_from_slice(&ticket.username);
+
+        let payload = Payload {
+            msg: &block,
+            aad: TURN_MOBILITY_TICKET_AAD,
+        };
+
+        // encrypting into a Vec only fails if the plaintext is larger than GCM allows
+        let encrypted_block = self
+            .cipher
+            .encrypt(Nonce::from_slice(nonce), payload)
+            .expect("mobility ticket is small enough to encrypt");
+
+        let mut output = Vec::with_capacity(nonce.len() + encrypted_block.len());
+        output.extend_from_slice(nonce);
+        output.extend_from_slice(&encrypted_block);
+
+        output
+    }
+
+    /// Decrypt the value of a MOBILITY-TICKET attribute.  Note that the expiry time of the
+    /// ticket is not checked.
+    pub fn decrypt(&self, value: &[u8]) -> Result<MobilityTicket, StunMobilityError> {
+        if value.len() < TURN_MOBILITY_TICKET_NONCE_NUM_BYTES {
+            return Err(StunMobilityError::InvalidTicketError);
+        }
+
+        let (nonce, encrypted_block) = value.split_at(TURN_MOBILITY_TICKET_NONCE_NUM_BYTES);
+        let payload = Payload {
+            msg: encrypted_block,
+            aad: TURN_MOBILITY_TICKET_AAD,
+        };
+
+        let block = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), payload)
+            .map_err(|_| StunMobilityError::TicketDecryptionError)?;
+
+        let result = all_consuming(parse_mobility_ticket_block)(&block);
+        match result {
+            Ok((_, ticket)) => Ok(ticket),
+            Err(_) => Err(StunMobilityError::InvalidTicketError),
+        }
+    }
+}
+
+fn parse_mobility_ticket_block(
+    input: &[u8],
+) -> IResult<&[u8], MobilityTicket, StunParseError<&[u8]>> {
+    let (input, (allocation_id, expires_at, protocol, client_address, server_address, username)) =
+        tuple((
+            be_u64,
+            be_u64,
+            be_u8,
+            parse_address,
+            parse_address,
+            length_data(be_u16),
+        ))(input)?;
+
+    let protocol = TransportProtocol::try_from(protocol)
+        .map_err(|_| Error(StunParseError::Nom(input, ErrorKind::MapRes)))?;
+
+    Ok((
+        input,
+        MobilityTicket {
+            allocation_id,
+            expires_at,
+            five_tuple: FiveTuple {
+                client_address,
+                server_address,
+                protocol,
+            },
+            username: username.to_vec(),
+        },
+    ))
+}
+
+/// Check whether an Allocate request asks for a mobility ticket, i.e. contains an empty
+/// MOBILITY-TICKET attribute, https://tools.ietf.org/html/rfc8016#section-3.2
+pub fn is_mobility_requested(message: &StunMessage) -> bool {
+    message.message_class == StunMessageClass::Request
+        && message.message_method == StunMessageMethod::Allocate
+        && message
+            .get_attribute(StunAttributeType::MobilityTicket as u16)
+            .is_some_and(|a| a.attribute_value.is_empty())
+}
+
+/// Validate a Refresh request that carries a mobility ticket, https://tools.ietf.org/html/rfc8016#section-3.4
+///
+/// The ticket must decrypt with the codec's key and must not have expired, the request must be
+/// authenticated by the given MESSAGE-INTEGRITY key, and the USERNAME of the request must match
+/// the username the allocation was created with.  When successful, the server should move the
+/// allocation identified by the returned ticket to the 5-tuple the request arrived on and issue
+/// a new ticket in the Refresh response.  On failure the server should reject the request, with
+/// a 405 (Mobility Forbidden) error when the ticket itself is not acceptable.
+///
+/// # Arguments
+///
+/// * `message` - The parsed Refresh request
+/// * `codec` - The codec that issued the ticket
+/// * `key` - The MESSAGE-INTEGRITY key of the requesting user
+/// * `now` - The current time, since the UNIX epoch
+///
+/// # Return
+///
+/// A Result object, when successful contains the decrypted ticket.
+pub fn validate_mobility_refresh(
+    message: &StunMessage,
+    codec: &MobilityTicketCodec,
+    key: &[u8],
+    now: Duration,
+) -> Result<MobilityTicket, StunMobilityError> {
+    if message.message_class != StunMessageClass::Request
+        || message.message_method != StunMessageMethod::Refresh
+    {
+        return Err(StunMobilityError::UnexpectedMessageTypeError(
+            message.message_class,
+            message.message_method,
+        ));
+    }
+
+    let value = message
+        .get_attribute(StunAttributeType::MobilityTicket as u16)
+        .map(|a| a.attribute_value)
+        .filter(|v| !v.is_empty())
+        .ok_or(StunMobilityError::MissingTicketError)?;
+
+    let ticket = codec.decrypt(value)?;
+    if now.as_secs() >= ticket.expires_at {
+        return Err(StunMobilityError::TicketExpiredError);
+    }
+
+    verify_message_integrity(message, key)?;
+
+    let username = message
+        .get_attribute(StunAttributeType::Username as u16)
+        .map(|a| a.attribute_value);
+    if username != Some(&ticket.username[..]) {
+        return Err(StunMobilityError::UsernameMismatchError);
+    }
+
+    Ok(ticket)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_stun_message;
+    use crate::stun_constants::*;
+    use crate::stun_errors::StunAuthError;
+    use crate::stun_message_builder::*;
+
+    const NOW: Duration = Duration::from_secs(1_600_000_000);
+
+    fn test_ticket() -> MobilityTicket {
+        MobilityTicket {
+            allocation_id: 0x0102_0304_0506_0708,
+            expires_at: NOW.as_secs() + 600,
+            five_tuple: FiveTuple {
+                client_address: "198.51.100.1:50000".parse().unwrap(),
+                server_address: "[2001:db8::3478]:3478".parse().unwrap(),
+                protocol: TransportProtocol::Udp,
+            },
+            username: b"alice".to_vec(),
+        }
+    }
+
+    fn build_refresh(ticket: &[u8], username: &[u8], key: &[u8]) -> Vec<u8> {
+        StunMessageBuilder::new(
+            StunMessageClass::Request,
+            StunMessageMethod::Refresh,
+            &[0x10; STUN_TRANSACTION_ID_NUM_BYTES],
+        )
+        .add_attribute(StunAttributeType::Username as u16, username)
+        .add_attribute(StunAttributeType::MobilityTicket as u16, ticket)
+        .add_message_integrity(key)
+        .build()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_encrypt_decrypt_roundtrip() {
+        let codec = MobilityTicketCodec::new(&[0x01; 32]);
+        let value = codec.encrypt(&test_ticket(), &[0x02; 12]);
+
+        assert_eq!(value[0..12], [0x02; 12]);
+        assert_eq!(codec.decrypt(&value), Ok(test_ticket()));
+    }
+
+    #[test]
+    fn test_decrypt_wrong_key() {
+        let value = MobilityTicketCodec::new(&[0x01; 32]).encrypt(&test_ticket(), &[0x02; 12]);
+
+        assert_eq!(
+            MobilityTicketCodec::new(&[0x03; 32]).decrypt(&value),
+            Err(StunMobilityError::TicketDecryptionError)
+        );
+        assert_eq!(
+            MobilityTicketCodec::new(&[0x03; 32]).decrypt(&value[..8]),
+            Err(StunMobilityError::InvalidTicketError)
+        );
+    }
+
+    #[test]
+    fn test_is_mobility_requested() {
+        let data = StunMessageBuilder::new(
+            StunMessageClass::Request,
+            StunMessageMethod::Allocate,
+            &[0x11; STUN_TRANSACTION_ID_NUM_BYTES],
+        )
+        .add_attribute(StunAttributeType::MobilityTicket as u16, &[])
+        .build()
+        .unwrap();
+        let (_, message) = parse_stun_message(&data).unwrap();
+
+        assert!(is_mobility_requested(&message));
+    }
+
+    #[test]
+    fn test_validate_mobility_refresh_valid() {
+        let codec = MobilityTicketCodec::new(&[0x01; 32]);
+        let value = codec.encrypt(&test_ticket(), &[0x02; 12]);
+        let data = build_refresh(&value, b"alice", b"key");
+        let (_, message) = parse_stun_message(&data).unwrap();
+
+        assert_eq!(
+            validate_mobility_refresh(&message, &codec, b"key", NOW),
+            Ok(test_ticket())
+        );
+    }
+
+    #[test]
+    fn test_validate_mobility_refresh_expired() {
+        let codec = MobilityTicketCodec::new(&[0x01; 32]);
+        let value = codec.encrypt(&test_ticket(), &[0x02; 12]);
+        let data = build_refresh(&value, b"alice", b"key");
+        let (_, message) = parse_stun_message(&data).unwrap();
+
+        assert_eq!(
+            validate_mobility_refresh(&message, &codec, b"key", NOW + Duration::from_secs(600)),
+            Err(StunMobilityError::TicketExpiredError)
+        );
+    }
+
+    #[test]
+    fn test_validate_mobility_refresh_wrong_username() {
+        let codec = MobilityTicketCodec::new(&[0x01; 32]);
+        let value = codec.encrypt(&test_ticket(), &[0x02; 12]);
+        let data = build_refresh(&value, b"mallory", b"key");
+        let (_, message) = parse_stun_message(&data).unwrap();
+
+        assert_eq!(
+            validate_mobility_refresh(&message, &codec, b"key", NOW),
+            Err(StunMobilityError::UsernameMismatchError)
+        );
+    }
+
+    #[test]
+    fn test_validate_mobility_refresh_bad_integrity() {
+        let codec = MobilityTicketCodec::new(&[0x01; 32]);
+        let value = codec.encrypt(&test_ticket(), &[0x02; 12]);
+        let data = build_refresh(&value, b"alice", b"key");
+        let (_, message) = parse_stun_message(&data).unwrap();
+
+        assert_eq!(
+            validate_mobility_refresh(&message, &codec, b"other", NOW),
+            Err(StunMobilityError::AuthError(
+                StunAuthError::IntegrityCheckFailedError
+            ))
+        );
+    }
+
+    #[test]
+    fn test_validate_mobility_refresh_missing_ticket() {
+        let codec = MobilityTicketCodec::new(&[0x01; 32]);
+        let data = build_refresh(&[], b"alice", b"key");
+        let (_, message) = parse_stun_message(&data).unwrap();
+
+        assert_eq!(
+            validate_mobility_refresh(&message, &codec, b"key", NOW),
+            Err(StunMobilityError::MissingTicketError)
+        );
+    }
+}
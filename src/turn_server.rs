@@ -0,0 +1,1264 @@
+use crate::parser::{parse_attribute_value, parse_stun_message};
+use crate::stun_address::*;
+use crate::stun_attribute_types::*;
+use crate::stun_client_transaction::random_transaction_id;
+use crate::stun_constants::*;
+use crate::stun_credentials::*;
+use crate::stun_error_code::*;
+use crate::stun_errors::StunTurnServerError;
+use crate::stun_five_tuple::*;
+use crate::stun_integrity::*;
+use crate::stun_message::*;
+use crate::stun_message_builder::*;
+use crate::stun_message_types::*;
+use crate::stun_server::ServerTransmit;
+use crate::stun_text_attributes::*;
+use crate::stun_validator::*;
+use crate::turn_attributes::*;
+use crate::turn_channel_data::*;
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::time::Duration;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::panic;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Instant;
+
+/// Largest datagram the server receives from clients and peers
+const MAX_DATAGRAM_NUM_BYTES: usize = 65535;
+
+/// Options of a `TurnServer`
+#[derive(Debug, Clone)]
+pub struct TurnServerOptions {
+    /// the SOFTWARE attribute to add to the responses, if any
+    pub software: Option<String>,
+
+    /// the longest lifetime granted to an allocation, longer requested lifetimes are shortened
+    pub max_lifetime: Duration,
+
+    /// how long a NONCE is valid, after which requests with it are answered with 438 (Stale
+    /// Nonce) and a new NONCE
+    pub nonce_lifetime: Duration,
+}
+
+impl Default for TurnServerOptions {
+    fn default() -> Self {
+        TurnServerOptions {
+            software: None,
+            max_lifetime: Duration::from_secs(TURN_MAX_ALLOCATION_LIFETIME_SECS.into()),
+            nonce_lifetime: Duration::from_secs(3600),
+        }
+    }
+}
+
+/// An allocation, https://tools.ietf.org/html/rfc5766#section-5
+#[derive(Debug)]
+struct Allocation {
+    relayed_address: SocketAddr,
+    username: String,
+    key: [u8; STUN_LONG_TERM_KEY_NUM_BYTES],
+
+    /// the transaction id of the Allocate request, whose retransmissions are answered again
+    transaction_id: [u8; STUN_TRANSACTION_ID_NUM_BYTES],
+    expires_at: Instant,
+
+    /// permissions by peer IP address, with their expiry
+    permissions: HashMap<IpAddr, Instant>,
+
+    /// channels by channel number, with their peer and expiry
+    channels: HashMap<u16, (SocketAddr, Instant)>,
+}
+
+impl Allocation {
+    fn has_permission(&self, peer: IpAddr, now: Instant) -> bool {
+        self.permissions
+            .get(&peer)
+            .is_some_and(|&expires_at| now < expires_at)
+    }
+}
+
+/// An authenticated request
+struct Request<'a, 'b> {
+    message: &'b StunMessage<'a>,
+    five_tuple: FiveTuple,
+    username: &'a str,
+    key: [u8; STUN_LONG_TERM_KEY_NUM_BYTES],
+}
+
+/// A sans-IO TURN server relaying UDP for its clients, https://tools.ietf.org/html/rfc5766
+///
+/// Each client has at most one allocation, keyed by its 5-tuple, which gets a relayed transport
+/// address from the pool the server is created with.  Every request is authenticated with the
+/// long-term credentials of the users added with `add_user`: a request without them is
+/// challenged with 401 (Unauthorized), a REALM and a NONCE, and one with an expired NONCE is
+/// answered with 438 (Stale Nonce).  The server handles:
+///
+/// - Allocate, Refresh, CreatePermission and ChannelBind requests
+/// - Send indications and ChannelData messages from clients, relayed to peers with a permission
+/// - datagrams from peers, relayed to the client as ChannelData if the peer has a channel and in
+///   a Data indication otherwise
+///
+/// Allocations, permissions and channels are dropped when they expire.  Anything from a peer
+/// without a permission is dropped, and so is anything malformed, except requests which are
+/// answered with 400 (Bad Request).
+///
+/// Like `Server` the core does no I/O: `handle_datagram` takes the datagrams received on the
+/// addresses the server listens on and on the relayed addresses, `poll_transmit` returns the
+/// datagrams to send and `poll_timeout` and `handle_timeout` expire what has not been refreshed.
+/// `serve_udp` runs the server on sockets.
+#[derive(Debug)]
+pub struct TurnServer {
+    realm: String,
+    options: TurnServerOptions,
+
+    /// the long-term keys of the users, by username
+    users: HashMap<String, [u8; STUN_LONG_TERM_KEY_NUM_BYTES]>,
+    nonce: String,
+    nonce_expires_at: Instant,
+
+    /// the relayed transport addresses which are not allocated
+    relays: VecDeque<SocketAddr>,
+    allocations: HashMap<FiveTuple, Allocation>,
+
+    /// the 5-tuples of the allocations, by relayed transport address
+    relayed: HashMap<SocketAddr, FiveTuple>,
+
+    transmits: VecDeque<ServerTransmit>,
+}
+
+impl TurnServer {
+    /// Create a server for the given realm, which allocates the given relayed transport
+    /// addresses, with the default options
+    pub fn new(
+        realm: &str,
+        relayed_addresses: impl IntoIterator<Item = SocketAddr>,
+        now: Instant,
+    ) -> Result<Self, StunTurnServerError> {
+        Self::with_options(realm, relayed_addresses, TurnServerOptions::default(), now)
+    }
+
+    /// Create a server with the given options.  An error if the realm or the SOFTWARE value
+    /// exceed the length limits of their attributes.
+    pub fn with_options(
+        realm: &str,
+        relayed_addresses: impl IntoIterator<Item = SocketAddr>,
+        options: TurnServerOptions,
+        now: Instant,
+    ) -> Result<Self, StunTurnServerError> {
+        StunRealm::new(realm).map_err(|_| StunTurnServerError::InvalidValueError)?;
+        if let Some(software) = &options.software {
+            StunSoftware::new(software).map_err(|_| StunTurnServerError::InvalidValueError)?;
+        }
+
+        Ok(TurnServer {
+            realm: realm.to_string(),
+            users: HashMap::new(),
+            nonce: random_nonce()?,
+            nonce_expires_at: now + options.nonce_lifetime,
+            relays: relayed_addresses.into_iter().collect(),
+            allocations: HashMap::new(),
+            relayed: HashMap::new(),
+            transmits: VecDeque::new(),
+            options,
+        })
+    }
+
+    /// Accept the given long-term credentials.  An error if the username is too long or the
+    /// password cannot be prepared with SASLprep.
+    pub fn add_user(
+        &mut self,
+        username: &str,
+        password: &str,
+    ) -> Result<&mut Self, StunTurnServerError> {
+        let invalid = |_| StunTurnServerError::InvalidValueError;
+        let key = long_term_credential_key(
+            &StunUsername::new(username).map_err(invalid)?,
+            &StunRealm::new(&self.realm).map_err(invalid)?,
+            password,
+        )
+        .map_err(|_| StunTurnServerError::InvalidValueError)?;
+
+        self.users.insert(username.to_string(), key);
+        Ok(self)
+    }
+
+    /// The number of allocations
+    pub fn allocation_count(&self) -> usize {
+        self.allocations.len()
+    }
+
+    /// Handle a datagram received from the given address on the given local address, which is
+    /// either an address the server listens on or a relayed transport address
+    pub fn handle_datagram(
+        &mut self,
+        input: &[u8],
+        source: SocketAddr,
+        local: SocketAddr,
+        now: Instant,
+    ) {
+        if self.relayed.contains_key(&local) {
+            self.handle_peer_data(local, source, input, now);
+            return;
+        }
+        // a relayed transport address which is not allocated
+        if self.relays.contains(&local) {
+            return;
+        }
+
+        let five_tuple = FiveTuple {
+            client_address: source,
+            server_address: local,
+            protocol: TransportProtocol::Udp,
+        };
+        match input.first() {
+            Some(0x40..=0x7F) => {
+                if let Ok((_, channel_data)) = parse_channel_data(input) {
+                    self.handle_channel_data(&channel_data, five_tuple, now);
+                }
+            }
+            Some(_) => {
+                if let Ok((_, message)) = parse_stun_message(input) {
+                    self.handle_message(&message, five_tuple, now);
+                }
+            }
+            None => {}
+        }
+    }
+
+    /// Handle a STUN message received from a client on the given 5-tuple: a request, which is
+    /// answered, or a Send indication
+    pub fn handle_message(&mut self, message: &StunMessage, five_tuple: FiveTuple, now: Instant) {
+        if message
+            .get_attribute(StunAttributeType::Fingerprint as u16)
+            .is_some()
+            && verify_fingerprint(message).is_err()
+        {
+            return;
+        }
+
+        match message.message_class {
+            StunMessageClass::Request => {
+                if let Some(response) = self.respond(message, five_tuple, now) {
+                    self.transmits.push_back(ServerTransmit {
+                        source: five_tuple.server_address,
+                        destination: five_tuple.client_address,
+                        data: response,
+                    });
+                }
+            }
+            StunMessageClass::Indication if message.message_method == StunMessageMethod::Send => {
+                self.handle_send_indication(message, five_tuple, now)
+            }
+            _ => {}
+        }
+    }
+
+    /// Handle a ChannelData message received from a client on the given 5-tuple, relaying its
+    /// data to the peer the channel is bound to
+    pub fn handle_channel_data(
+        &mut self,
+        channel_data: &ChannelData,
+        five_tuple: FiveTuple,
+        now: Instant,
+    ) {
+        let allocation = match self.allocations.get(&five_tuple) {
+            Some(allocation) if now < allocation.expires_at => allocation,
+            _ => return,
+        };
+        let peer = match allocation.channels.get(&channel_data.channel_number) {
+            Some(&(peer, expires_at)) if now < expires_at => peer,
+            _ => return,
+        };
+
+        if allocation.has_permission(peer.ip(), now) {
+            self.transmits.push_back(ServerTransmit {
+                source: allocation.relayed_address,
+                destination: peer,
+                data: channel_data.data.to_vec(),
+            });
+        }
+    }
+
+    /// Handle a datagram received from a peer on the given relayed transport address, relaying
+    /// it to the client if the peer has a permission
+    pub fn handle_peer_data(
+        &mut self,
+        relayed_address: SocketAddr,
+        peer: SocketAddr,
+        data: &[u8],
+        now: Instant,
+    ) {
+        let five_tuple = match self.relayed.get(&relayed_address) {
+            Some(&five_tuple) => five_tuple,
+            None => return,
+        };
+        let allocation = &self.allocations[&five_tuple];
+        if now >= allocation.expires_at || !allocation.has_permission(peer.ip(), now) {
+            return;
+        }
+
+        let channel_number = allocation
+            .channels
+            .iter()
+            .find(|(_, &(p, expires_at))| p == peer && now < expires_at)
+            .map(|(&channel_number, _)| channel_number);
+        let data = match channel_number {
+            Some(channel_number) => serialize_channel_data(channel_number, data),
+            None => {
+                let transaction_id = match random_transaction_id() {
+                    Ok(transaction_id) => transaction_id,
+                    Err(_) => return,
+                };
+                let mut builder = StunMessageBuilder::new(
+                    StunMessageClass::Indication,
+                    StunMessageMethod::Data,
+                    &transaction_id,
+                );
+                builder
+                    .add_xor_address_attribute(StunAttributeType::XorPeerAddress as u16, &peer)
+                    .add_attribute(StunAttributeType::Data as u16, data);
+                match builder.build() {
+                    Ok(indication) => indication,
+                    Err(_) => return,
+                }
+            }
+        };
+
+        self.transmits.push_back(ServerTransmit {
+            source: five_tuple.server_address,
+            destination: five_tuple.client_address,
+            data,
+        });
+    }
+
+    /// The next datagram to send, if any
+    pub fn poll_transmit(&mut self) -> Option<ServerTransmit> {
+        self.transmits.pop_front()
+    }
+
+    /// The time at which `handle_timeout` must next be called, or `None` without allocations
+    pub fn poll_timeout(&self) -> Option<Instant> {
+        self.allocations
+            .values()
+            .flat_map(|allocation| {
+                let permissions = allocation.permissions.values().copied();
+                let channels = allocation.channels.values().map(|&(_, at)| at);
+                permissions
+                    .chain(channels)
+                    .chain(Some(allocation.expires_at))
+            })
+            .min()
+    }
+
+    /// Drop the allocations, permissions and channels which have expired at the given time,
+    /// returning the relayed transport addresses of the allocations to the pool
+    pub fn handle_timeout(&mut self, now: Instant) {
+        let relays = &mut self.relays;
+        let relayed = &mut self.relayed;
+
+        self.allocations.retain(|_, allocation| {
+            if now >= allocation.expires_at {
+                relayed.remove(&allocation.relayed_address);
+                relays.push_back(allocation.relayed_address);
+                return false;
+            }
+
+            allocation
+                .permissions
+                .retain(|_, &mut expires_at| now < expires_at);
+            allocation
+                .channels
+                .retain(|_, &mut (_, expires_at)| now < expires_at);
+            true
+        });
+    }
+
+    /// Run the server on the given socket, which clients send to, and the sockets bound to the
+    /// relayed transport addresses, each of which is read on its own thread, until receiving
+    /// fails on all of them, returning the first error
+    pub fn serve_udp(&mut self, socket: &UdpSocket, relays: &[UdpSocket]) -> io::Result<()> {
+        let sockets: Vec<&UdpSocket> = core::iter::once(socket).chain(relays).collect();
+        let locals = sockets
+            .iter()
+            .map(|socket| socket.local_addr())
+            .collect::<io::Result<Vec<SocketAddr>>>()?;
+        let (sender, receiver) = mpsc::channel();
+
+        thread::scope(|scope| {
+            let threads: Vec<_> = sockets
+                .iter()
+                .zip(&locals)
+                .map(|(&socket, &local)| {
+                    let sender = sender.clone();
+                    scope.spawn(move || receive_udp(socket, local, sender))
+                })
+                .collect();
+            drop(sender);
+
+            loop {
+                while let Some(transmit) = self.poll_transmit() {
+                    if let Some(index) = locals.iter().position(|&l| l == transmit.source) {
+                        // a datagram which cannot be sent is lost, like any other
+                        let _ = sockets[index].send_to(&transmit.data, transmit.destination);
+                    }
+                }
+
+                let received = match self.poll_timeout() {
+                    Some(timeout) => {
+                        let timeout = timeout.saturating_duration_since(Instant::now());
+                        match receiver.recv_timeout(timeout) {
+                            Ok(received) => Some(received),
+                            Err(mpsc::RecvTimeoutError::Timeout) => None,
+                            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                        }
+                    }
+                    None => match receiver.recv() {
+                        Ok(received) => Some(received),
+                        Err(_) => break,
+                    },
+                };
+
+                // under steady traffic the receive never times out, so expiry is checked each time
+                let now = Instant::now();
+                if let Some((data, source, local)) = received {
+                    self.handle_datagram(&data, source, local, now);
+                }
+                if self.poll_timeout().is_some_and(|timeout| now >= timeout) {
+                    self.handle_timeout(now);
+                }
+            }
+
+            let mut result = Ok(());
+            for thread in threads {
+                let thread_result = thread
+                    .join()
+                    .unwrap_or_else(|payload| panic::resume_unwind(payload));
+                result = result.and(thread_result);
+            }
+            result
+        })
+    }
+
+    /// Answer an authenticated request, challenge it or reject it
+    fn respond(
+        &mut self,
+        message: &StunMessage,
+        five_tuple: FiveTuple,
+        now: Instant,
+    ) -> Option<Vec<u8>> {
+        let fingerprint = message
+            .get_attribute(StunAttributeType::Fingerprint as u16)
+            .is_some();
+
+        let mut unknown_attributes: Vec<u16> = Vec::new();
+        for attribute in &message.attributes {
+            let attribute_type = attribute.attribute_type;
+            if attribute_type < STUN_ATTRIBUTE_COMPREHENSION_OPTIONAL_MIN
+                && StunAttributeType::try_from(attribute_type).is_err()
+                && !unknown_attributes.contains(&attribute_type)
+            {
+                unknown_attributes.push(attribute_type);
+            }
+        }
+        if !unknown_attributes.is_empty() {
+            let value: Vec<u8> = unknown_attributes
+                .iter()
+                .flat_map(|attribute_type| attribute_type.to_be_bytes())
+                .collect();
+            let mut builder = self.error_builder(message, STUN_ERROR_UNKNOWN_ATTRIBUTE);
+            builder.add_attribute(StunAttributeType::UnknownAttributes as u16, &value);
+            return self.finish(&mut builder, None, fingerprint);
+        }
+
+        let supported = matches!(
+            message.message_method,
+            StunMessageMethod::Allocate
+                | StunMessageMethod::Refresh
+                | StunMessageMethod::CreatePermission
+                | StunMessageMethod::ChannelBind
+        );
+        if !supported || !validate(message).is_empty() {
+            let mut builder = self.error_builder(message, STUN_ERROR_BAD_REQUEST);
+            return self.finish(&mut builder, None, fingerprint);
+        }
+
+        // long-term credentials, https://tools.ietf.org/html/rfc5389#section-10.2.2
+        if now >= self.nonce_expires_at {
+            self.nonce = random_nonce().ok()?;
+            self.nonce_expires_at = now + self.options.nonce_lifetime;
+        }
+        let text = |attribute_type: StunAttributeType| {
+            message
+                .get_attribute(attribute_type as u16)
+                .map(|attribute| core::str::from_utf8(attribute.attribute_value).ok())
+        };
+        let credentials = match (
+            message.get_attribute(StunAttributeType::MessageIntegrity as u16),
+            text(StunAttributeType::Username),
+            text(StunAttributeType::Realm),
+            text(StunAttributeType::Nonce),
+        ) {
+            (None, ..) => None,
+            (Some(_), Some(Some(username)), Some(Some(_)), Some(Some(nonce))) => {
+                Some((username, nonce))
+            }
+            _ => {
+                let mut builder = self.error_builder(message, STUN_ERROR_BAD_REQUEST);
+                return self.finish(&mut builder, None, fingerprint);
+            }
+        };
+        let key = credentials.and_then(|(username, _)| {
+            let key = self.users.get(username)?;
+            verify_message_integrity(message, key).ok()?;
+            Some(*key)
+        });
+        let (username, nonce, key) = match (credentials, key) {
+            (Some((username, nonce)), Some(key)) => (username, nonce, key),
+            _ => return self.challenge(message, STUN_ERROR_UNAUTHORIZED, fingerprint),
+        };
+        if nonce != self.nonce {
+            return self.challenge(message, STUN_ERROR_STALE_NONCE, fingerprint);
+        }
+
+        let request = Request {
+            message,
+            five_tuple,
+            username,
+            key,
+        };
+        let mut builder = StunMessageBuilder::new(
+            StunMessageClass::SuccessResponse,
+            message.message_method,
+            message.transaction_id,
+        );
+        let result = match message.message_method {
+            StunMessageMethod::Allocate => self.allocate(&request, &mut builder, now),
+            StunMessageMethod::Refresh => self.refresh(&request, &mut builder, now),
+            StunMessageMethod::CreatePermission => self.create_permission(&request, now),
+            _ => self.bind_channel(&request, now),
+        };
+
+        if let Err(code) = result {
+            builder = self.error_builder(message, code);
+        }
+        self.finish(&mut builder, Some(&key), fingerprint)
+    }
+
+    /// Create the allocation, https://tools.ietf.org/html/rfc5766#section-6.2
+    fn allocate(
+        &mut self,
+        request: &Request,
+        builder: &mut StunMessageBuilder,
+        now: Instant,
+    ) -> Result<(), u16> {
+        let message = request.message;
+
+        match self.allocations.get(&request.five_tuple) {
+            // an expired allocation which has not been dropped yet is replaced
+            Some(allocation) if now >= allocation.expires_at => {
+                self.remove_allocation(&request.five_tuple)
+            }
+            Some(allocation) => {
+                // a retransmission of the request which created the allocation is answered again
+                if allocation.transaction_id != *message.transaction_id {
+                    return Err(STUN_ERROR_ALLOCATION_MISMATCH);
+                }
+                let lifetime = allocation.expires_at.saturating_duration_since(now);
+                add_allocate_attributes(builder, allocation, request.five_tuple, lifetime);
+                return Ok(());
+            }
+            None => {}
+        }
+
+        let protocol = message
+            .get_attribute(StunAttributeType::RequestedTransport as u16)
+            .and_then(|attribute| parse_attribute_value(attribute, parse_requested_transport).ok())
+            .ok_or(STUN_ERROR_BAD_REQUEST)?;
+        if protocol != TURN_TRANSPORT_UDP {
+            return Err(STUN_ERROR_UNSUPPORTED_TRANSPORT_PROTOCOL);
+        }
+
+        let lifetime = match self.lifetime(message)? {
+            Some(lifetime) if lifetime > Duration::ZERO => lifetime,
+            _ => self.default_lifetime(),
+        };
+        let relayed_address = self
+            .relays
+            .pop_front()
+            .ok_or(STUN_ERROR_INSUFFICIENT_CAPACITY)?;
+        let allocation = Allocation {
+            relayed_address,
+            username: request.username.to_string(),
+            key: request.key,
+            transaction_id: *message.transaction_id,
+            expires_at: now + lifetime,
+            permissions: HashMap::new(),
+            channels: HashMap::new(),
+        };
+        add_allocate_attributes(builder, &allocation, request.five_tuple, lifetime);
+
+        self.relayed.insert(relayed_address, request.five_tuple);
+        self.allocations.insert(request.five_tuple, allocation);
+        Ok(())
+    }
+
+    /// Refresh or delete the allocation, https://tools.ietf.org/html/rfc5766#section-7.2
+    fn refresh(
+        &mut self,
+        request: &Request,
+        builder: &mut StunMessageBuilder,
+        now: Instant,
+    ) -> Result<(), u16> {
+        let lifetime = self
+            .lifetime(request.message)?
+            .unwrap_or_else(|| self.default_lifetime());
+        let allocation = self.allocation(request, now)?;
+
+        if lifetime > Duration::ZERO {
+            allocation.expires_at = now + lifetime;
+        } else {
+            self.remove_allocation(&request.five_tuple);
+        }
+
+        builder.add_u32_attribute(
+            StunAttributeType::Lifetime as u16,
+            lifetime.as_secs() as u32,
+        );
+        Ok(())
+    }
+
+    /// Delete the allocation of the given 5-tuple, returning its relayed transport address to the
+    /// pool
+    fn remove_allocation(&mut self, five_tuple: &FiveTuple) {
+        if let Some(allocation) = self.allocations.remove(five_tuple) {
+            self.relayed.remove(&allocation.relayed_address);
+            self.relays.push_back(allocation.relayed_address);
+        }
+    }
+
+    /// Install or refresh permissions for the peers, https://tools.ietf.org/html/rfc5766#section-9.2
+    fn create_permission(&mut self, request: &Request, now: Instant) -> Result<(), u16> {
+        let message = request.message;
+        let peers = message
+            .attributes
+            .iter()
+            .filter(|attribute| {
+                attribute.attribute_type == StunAttributeType::XorPeerAddress as u16
+            })
+            .map(|attribute| {
+                parse_attribute_value(attribute, |input| {
+                    parse_xor_address(input, message.transaction_id)
+                })
+            })
+            .collect::<Result<Vec<SocketAddr>, _>>()
+            .map_err(|_| STUN_ERROR_BAD_REQUEST)?;
+
+        let allocation = self.allocation(request, now)?;
+        let expires_at = now + Duration::from_secs(TURN_PERMISSION_LIFETIME_SECS.into());
+        for peer in peers {
+            allocation.permissions.insert(peer.ip(), expires_at);
+        }
+
+        Ok(())
+    }
+
+    /// Bind or refresh a channel, and the permission of its peer,
+    /// https://tools.ietf.org/html/rfc5766#section-11.2
+    fn bind_channel(&mut self, request: &Request, now: Instant) -> Result<(), u16> {
+        let message = request.message;
+        let attribute = |attribute_type: StunAttributeType| {
+            message
+                .get_attribute(attribute_type as u16)
+                .ok_or(STUN_ERROR_BAD_REQUEST)
+        };
+        let channel_number = parse_attribute_value(
+            attribute(StunAttributeType::ChannelNumber)?,
+            parse_channel_number,
+        )
+        .map_err(|_| STUN_ERROR_BAD_REQUEST)?;
+        let peer = parse_attribute_value(attribute(StunAttributeType::XorPeerAddress)?, |input| {
+            parse_xor_address(input, message.transaction_id)
+        })
+        .map_err(|_| STUN_ERROR_BAD_REQUEST)?;
+
+        let allocation = self.allocation(request, now)?;
+        if !(TURN_CHANNEL_NUMBER_MIN..=TURN_CHANNEL_NUMBER_MAX).contains(&channel_number) {
+            return Err(STUN_ERROR_BAD_REQUEST);
+        }
+        // a channel is bound to a single peer, and a peer to a single channel
+        let conflict = allocation
+            .channels
+            .iter()
+            .any(|(&c, &(p, _))| (c == channel_number) != (p == peer));
+        if conflict {
+            return Err(STUN_ERROR_BAD_REQUEST);
+        }
+
+        let expires_at = now + Duration::from_secs(TURN_CHANNEL_LIFETIME_SECS.into());
+        allocation
+            .channels
+            .insert(channel_number, (peer, expires_at));
+        let expires_at = now + Duration::from_secs(TURN_PERMISSION_LIFETIME_SECS.into());
+        allocation.permissions.insert(peer.ip(), expires_at);
+
+        Ok(())
+    }
+
+    /// Relay the data of a Send indication to its peer, https://tools.ietf.org/html/rfc5766#section-10.2
+    fn handle_send_indication(
+        &mut self,
+        message: &StunMessage,
+        five_tuple: FiveTuple,
+        now: Instant,
+    ) {
+        let allocation = match self.allocations.get(&five_tuple) {
+            Some(allocation) if now < allocation.expires_at => allocation,
+            _ => return,
+        };
+        let peer = message
+            .get_attribute(StunAttributeType::XorPeerAddress as u16)
+            .and_then(|attribute| {
+                parse_attribute_value(attribute, |input| {
+                    parse_xor_address(input, message.transaction_id)
+                })
+                .ok()
+            });
+        let data = message.get_attribute(StunAttributeType::Data as u16);
+
+        if let (Some(peer), Some(data)) = (peer, data) {
+            if allocation.has_permission(peer.ip(), now) {
+                self.transmits.push_back(ServerTransmit {
+                    source: allocation.relayed_address,
+                    destination: peer,
+                    data: data.attribute_value.to_vec(),
+                });
+            }
+        }
+    }
+
+    /// The live allocation of the 5-tuple of the request, which must have been created with the
+    /// same credentials
+    fn allocation(&mut self, request: &Request, now: Instant) -> Result<&mut Allocation, u16> {
+        let allocation = match self.allocations.get_mut(&request.five_tuple) {
+            Some(allocation) if now < allocation.expires_at => allocation,
+            _ => return Err(STUN_ERROR_ALLOCATION_MISMATCH),
+        };
+        if allocation.username != request.username || allocation.key != request.key {
+            return Err(STUN_ERROR_WRONG_CREDENTIALS);
+        }
+
+        Ok(allocation)
+    }
+
+    /// The LIFETIME requested, shortened to the maximum lifetime
+    fn lifetime(&self, message: &StunMessage) -> Result<Option<Duration>, u16> {
+        let attribute = match message.get_attribute(StunAttributeType::Lifetime as u16) {
+            Some(attribute) => attribute,
+            None => return Ok(None),
+        };
+        let lifetime =
+            parse_attribute_value(attribute, parse_lifetime).map_err(|_| STUN_ERROR_BAD_REQUEST)?;
+
+        Ok(Some(
+            Duration::from_secs(lifetime.into()).min(self.options.max_lifetime),
+        ))
+    }
+
+    fn default_lifetime(&self) -> Duration {
+        Duration::from_secs(TURN_DEFAULT_ALLOCATION_LIFETIME_SECS.into())
+            .min(self.options.max_lifetime)
+    }
+
+    /// Challenge the client with 401 (Unauthorized) or 438 (Stale Nonce), the REALM and the
+    /// current NONCE
+    fn challenge(&self, message: &StunMessage, code: u16, fingerprint: bool) -> Option<Vec<u8>> {
+        let mut builder = self.error_builder(message, code);
+        builder
+            .add_attribute(StunAttributeType::Realm as u16, self.realm.as_bytes())
+            .add_attribute(StunAttributeType::Nonce as u16, self.nonce.as_bytes());
+
+        self.finish(&mut builder, None, fingerprint)
+    }
+
+    fn error_builder(&self, message: &StunMessage, code: u16) -> StunMessageBuilder {
+        let mut builder = StunMessageBuilder::new(
+            StunMessageClass::ErrorResponse,
+            message.message_method,
+            message.transaction_id,
+        );
+        builder.add_error_code_attribute(code, error_reason(code));
+        builder
+    }
+
+    /// Add SOFTWARE, MESSAGE-INTEGRITY if the request was authenticated and FINGERPRINT if the
+    /// request has one, and serialize the response
+    fn finish(
+        &self,
+        builder: &mut StunMessageBuilder,
+        key: Option<&[u8; STUN_LONG_TERM_KEY_NUM_BYTES]>,
+        fingerprint: bool,
+    ) -> Option<Vec<u8>> {
+        if let Some(software) = &self.options.software {
+            builder.add_attribute(StunAttributeType::Software as u16, software.as_bytes());
+        }
+        if let Some(key) = key {
+            builder.add_message_integrity(key);
+        }
+        if fingerprint {
+            builder.add_fingerprint();
+        }
+
+        builder.build().ok()
+    }
+}
+
+/// Add the attributes of an Allocate success response
+fn add_allocate_attributes(
+    builder: &mut StunMessageBuilder,
+    allocation: &Allocation,
+    five_tuple: FiveTuple,
+    lifetime: Duration,
+) {
+    builder
+        .add_xor_address_attribute(
+            StunAttributeType::XorRelayedAddress as u16,
+            &allocation.relayed_address,
+        )
+        .add_u32_attribute(
+            StunAttributeType::Lifetime as u16,
+            lifetime.as_secs() as u32,
+        )
+        .add_xor_address_attribute(
+            StunAttributeType::XorMappedAddress as u16,
+            &five_tuple.client_address,
+        );
+}
+
+/// The reason phrase of the error codes the server answers with
+fn error_reason(code: u16) -> &'static str {
+    match code {
+        STUN_ERROR_BAD_REQUEST => "Bad Request",
+        STUN_ERROR_UNAUTHORIZED => "Unauthorized",
+        STUN_ERROR_UNKNOWN_ATTRIBUTE => "Unknown Attribute",
+        STUN_ERROR_ALLOCATION_MISMATCH => "Allocation Mismatch",
+        STUN_ERROR_STALE_NONCE => "Stale Nonce",
+        STUN_ERROR_WRONG_CREDENTIALS => "Wrong Credentials",
+        STUN_ERROR_UNSUPPORTED_TRANSPORT_PROTOCOL => "Unsupported Transport Protocol",
+        STUN_ERROR_INSUFFICIENT_CAPACITY => "Insufficient Capacity",
+        _ => "Server Error",
+    }
+}
+
+/// A NONCE from the operating system's random number generator
+fn random_nonce() -> io::Result<String> {
+    let bytes = random_transaction_id()?;
+
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// Receive datagrams on the given socket until receiving fails or the server stops
+fn receive_udp(
+    socket: &UdpSocket,
+    local: SocketAddr,
+    sender: mpsc::Sender<(Vec<u8>, SocketAddr, SocketAddr)>,
+) -> io::Result<()> {
+    let mut buffer = vec![0; MAX_DATAGRAM_NUM_BYTES];
+
+    loop {
+        let (length, source) = match socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            // an ICMP error for an earlier datagram, on some platforms
+            Err(e) if e.kind() == io::ErrorKind::ConnectionReset => continue,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+
+        if sender
+            .send((buffer[..length].to_vec(), source, local))
+            .is_err()
+        {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::turn_client::*;
+
+    const REALM: &str = "example.org";
+
+    fn client_address() -> SocketAddr {
+        "198.51.100.2:40000".parse().unwrap()
+    }
+
+    fn server_address() -> SocketAddr {
+        "192.0.2.1:3478".parse().unwrap()
+    }
+
+    fn relayed_addresses() -> [SocketAddr; 2] {
+        [
+            "192.0.2.1:50000".parse().unwrap(),
+            "192.0.2.1:50001".parse().unwrap(),
+        ]
+    }
+
+    fn server(now: Instant) -> TurnServer {
+        let mut server = TurnServer::new(REALM, relayed_addresses(), now).unwrap();
+        server
+            .add_user("alice", "secret")
+            .unwrap()
+            .add_user("bob", "hunter2")
+            .unwrap();
+        server
+    }
+
+    fn key(username: &str, password: &str) -> [u8; STUN_LONG_TERM_KEY_NUM_BYTES] {
+        let username = StunUsername::new(username).unwrap();
+        let realm = StunRealm::new(REALM).unwrap();
+        long_term_credential_key(&username, &realm, password).unwrap()
+    }
+
+    /// A request with the given transaction id, authenticated if credentials are given
+    fn request(
+        method: StunMessageMethod,
+        transaction_id: u8,
+        attributes: &[(StunAttributeType, &[u8])],
+        credentials: Option<(&str, &[u8], &str)>,
+    ) -> Vec<u8> {
+        let transaction_id = [transaction_id; STUN_TRANSACTION_ID_NUM_BYTES];
+        let mut builder =
+            StunMessageBuilder::new(StunMessageClass::Request, method, &transaction_id);
+        for &(attribute_type, value) in attributes {
+            builder.add_attribute(attribute_type as u16, value);
+        }
+        if let Some((username, key, nonce)) = credentials {
+            builder
+                .add_attribute(StunAttributeType::Username as u16, username.as_bytes())
+                .add_attribute(StunAttributeType::Realm as u16, REALM.as_bytes())
+                .add_attribute(StunAttributeType::Nonce as u16, nonce.as_bytes())
+                .add_message_integrity(key);
+        }
+        builder.build().unwrap()
+    }
+
+    /// Send a request from the given client address, returning the response
+    fn respond(
+        server: &mut TurnServer,
+        request: &[u8],
+        client_address: SocketAddr,
+        now: Instant,
+    ) -> Vec<u8> {
+        server.handle_datagram(request, client_address, server_address(), now);
+        let transmit = server.poll_transmit().unwrap();
+        assert_eq!(transmit.source, server_address());
+        assert_eq!(transmit.destination, client_address);
+        assert!(server.poll_transmit().is_none());
+        transmit.data
+    }
+
+    /// The error code and NONCE of an error response
+    fn error(response: &[u8]) -> (u16, Option<String>) {
+        let (_, message) = parse_stun_message(response).unwrap();
+        assert_eq!(message.message_class, StunMessageClass::ErrorResponse);
+        let attribute = message
+            .get_attribute(StunAttributeType::ErrorCode as u16)
+            .unwrap();
+        let code = parse_attribute_value(attribute, parse_error_code)
+            .unwrap()
+            .code;
+        let nonce = message
+            .get_attribute(StunAttributeType::Nonce as u16)
+            .map(|attribute| parse_attribute_value(attribute, parse_nonce).unwrap())
+            .map(|nonce| nonce.as_str().to_string());
+        (code, nonce)
+    }
+
+    /// The XOR-RELAYED-ADDRESS of an Allocate success response
+    fn relayed_address(response: &[u8], key: &[u8]) -> SocketAddr {
+        let (_, message) = parse_stun_message(response).unwrap();
+        assert_eq!(message.message_class, StunMessageClass::SuccessResponse);
+        assert_eq!(verify_message_integrity(&message, key), Ok(()));
+        let attribute = message
+            .get_attribute(StunAttributeType::XorRelayedAddress as u16)
+            .unwrap();
+        parse_attribute_value(attribute, |i| parse_xor_address(i, message.transaction_id)).unwrap()
+    }
+
+    /// Exchange messages between the client and the server, returning what is sent to peers
+    fn exchange(
+        client: &mut TurnClient,
+        server: &mut TurnServer,
+        now: Instant,
+    ) -> Vec<ServerTransmit> {
+        let mut to_peers = Vec::new();
+
+        loop {
+            let mut idle = true;
+            while let Some(transmit) = client.poll_transmit() {
+                server.handle_datagram(&transmit, client_address(), server_address(), now);
+                idle = false;
+            }
+            while let Some(transmit) = server.poll_transmit() {
+                match transmit.destination == client_address() {
+                    true => {
+                        client.handle_input(&transmit.data, now);
+                    }
+                    false => to_peers.push(transmit),
+                }
+                idle = false;
+            }
+            if idle {
+                return to_peers;
+            }
+        }
+    }
+
+    #[test]
+    fn test_relay() {
+        let now = Instant::now();
+        let mut server = server(now);
+        let mut client = TurnClient::new("alice", "secret", TransportProtocol::Udp, now).unwrap();
+        let [relayed_address, _] = relayed_addresses();
+        let peer: SocketAddr = "203.0.113.1:5000".parse().unwrap();
+        let channel_peer: SocketAddr = "203.0.113.2:5000".parse().unwrap();
+
+        exchange(&mut client, &mut server, now);
+        assert_eq!(client.relayed_address(), Some(relayed_address));
+        assert_eq!(client.mapped_address(), Some(client_address()));
+        assert_eq!(server.allocation_count(), 1);
+
+        // nothing is relayed without a permission
+        client.send(peer, b"dropped").unwrap();
+        assert!(exchange(&mut client, &mut server, now).is_empty());
+        server.handle_datagram(b"dropped", peer, relayed_address, now);
+        assert!(server.poll_transmit().is_none());
+
+        // Send and Data indications
+        client.create_permission(peer.ip(), now).unwrap();
+        exchange(&mut client, &mut server, now);
+        client.send(peer, b"hello").unwrap();
+        let to_peers = exchange(&mut client, &mut server, now);
+        assert_eq!(
+            to_peers,
+            [ServerTransmit {
+                source: relayed_address,
+                destination: peer,
+                data: b"hello".to_vec(),
+            }]
+        );
+
+        server.handle_datagram(b"hi", peer, relayed_address, now);
+        let transmit = server.poll_transmit().unwrap();
+        assert_eq!(transmit.source, server_address());
+        assert!(client.handle_input(&transmit.data, now));
+
+        // ChannelData
+        let channel_number = client.bind_channel(channel_peer, now).unwrap();
+        exchange(&mut client, &mut server, now);
+        client.send(channel_peer, b"hello channel").unwrap();
+        let to_peers = exchange(&mut client, &mut server, now);
+        assert_eq!(to_peers[0].source, relayed_address);
+        assert_eq!(to_peers[0].destination, channel_peer);
+        assert_eq!(to_peers[0].data, b"hello channel");
+
+        server.handle_datagram(b"hi channel", channel_peer, relayed_address, now);
+        let transmit = server.poll_transmit().unwrap();
+        assert_eq!(
+            transmit.data,
+            serialize_channel_data(channel_number, b"hi channel")
+        );
+        assert!(client.handle_input(&transmit.data, now));
+
+        let data: Vec<(SocketAddr, Vec<u8>)> = core::iter::from_fn(|| client.poll_event())
+            .filter_map(|event| match event {
+                TurnClientEvent::Data { peer, data } => Some((peer, data)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            data,
+            [
+                (peer, b"hi".to_vec()),
+                (channel_peer, b"hi channel".to_vec())
+            ]
+        );
+
+        // deleting the allocation returns the relayed transport address to the pool
+        client.deallocate(now).unwrap();
+        exchange(&mut client, &mut server, now);
+        assert!(!client.is_allocated());
+        assert_eq!(server.allocation_count(), 0);
+        server.handle_datagram(b"late", peer, relayed_address, now);
+        assert!(server.poll_transmit().is_none());
+    }
+
+    #[test]
+    fn test_error_responses() {
+        let now = Instant::now();
+        let options = TurnServerOptions {
+            nonce_lifetime: Duration::from_secs(60),
+            ..TurnServerOptions::default()
+        };
+        let mut server =
+            TurnServer::with_options(REALM, relayed_addresses(), options, now).unwrap();
+        server.add_user("alice", "secret").unwrap();
+        server.add_user("bob", "hunter2").unwrap();
+        let alice = key("alice", "secret");
+        let bob = key("bob", "hunter2");
+        let udp = serialize_requested_transport(TURN_TRANSPORT_UDP);
+        let transport = [(StunAttributeType::RequestedTransport, &udp[..])];
+        let client = client_address();
+
+        // only the TURN methods are supported
+        let binding = request(StunMessageMethod::Binding, 1, &[], None);
+        assert_eq!(error(&respond(&mut server, &binding, client, now)).0, 400);
+
+        // requests without valid credentials are challenged
+        let allocate = request(StunMessageMethod::Allocate, 1, &transport, None);
+        let (code, nonce) = error(&respond(&mut server, &allocate, client, now));
+        assert_eq!(code, STUN_ERROR_UNAUTHORIZED);
+        let nonce = nonce.unwrap();
+        let wrong = key("alice", "wrong");
+        let allocate = request(
+            StunMessageMethod::Allocate,
+            1,
+            &transport,
+            Some(("alice", &wrong, &nonce)),
+        );
+        let response = respond(&mut server, &allocate, client, now);
+        assert_eq!(
+            error(&response),
+            (STUN_ERROR_UNAUTHORIZED, Some(nonce.clone()))
+        );
+
+        let alice_credentials = Some(("alice", &alice[..], &nonce[..]));
+        let tcp = serialize_requested_transport(6);
+        let allocate = request(
+            StunMessageMethod::Allocate,
+            2,
+            &[(StunAttributeType::RequestedTransport, &tcp)],
+            alice_credentials,
+        );
+        let response = respond(&mut server, &allocate, client, now);
+        assert_eq!(
+            error(&response).0,
+            STUN_ERROR_UNSUPPORTED_TRANSPORT_PROTOCOL
+        );
+
+        let refresh = request(StunMessageMethod::Refresh, 3, &[], alice_credentials);
+        let response = respond(&mut server, &refresh, client, now);
+        assert_eq!(error(&response).0, STUN_ERROR_ALLOCATION_MISMATCH);
+
+        // a retransmitted Allocate request is answered again, a new one is a mismatch
+        let allocate = request(
+            StunMessageMethod::Allocate,
+            4,
+            &transport,
+            alice_credentials,
+        );
+        let response = respond(&mut server, &allocate, client, now);
+        assert_eq!(relayed_address(&response, &alice), relayed_addresses()[0]);
+        let response = respond(&mut server, &allocate, client, now);
+        assert_eq!(relayed_address(&response, &alice), relayed_addresses()[0]);
+        let allocate = request(
+            StunMessageMethod::Allocate,
+            5,
+            &transport,
+            alice_credentials,
+        );
+        let response = respond(&mut server, &allocate, client, now);
+        assert_eq!(error(&response).0, STUN_ERROR_ALLOCATION_MISMATCH);
+
+        // the allocation belongs to the user who created it
+        let bob_credentials = Some(("bob", &bob[..], &nonce[..]));
+        let refresh = request(StunMessageMethod::Refresh, 6, &[], bob_credentials);
+        let response = respond(&mut server, &refresh, client, now);
+        assert_eq!(error(&response).0, STUN_ERROR_WRONG_CREDENTIALS);
+
+        // until the pool is exhausted
+        let other: SocketAddr = "198.51.100.3:40000".parse().unwrap();
+        let allocate = request(StunMessageMethod::Allocate, 7, &transport, bob_credentials);
+        let response = respond(&mut server, &allocate, other, now);
+        assert_eq!(relayed_address(&response, &bob), relayed_addresses()[1]);
+        let third: SocketAddr = "198.51.100.4:40000".parse().unwrap();
+        let allocate = request(StunMessageMethod::Allocate, 8, &transport, bob_credentials);
+        let response = respond(&mut server, &allocate, third, now);
+        assert_eq!(error(&response).0, STUN_ERROR_INSUFFICIENT_CAPACITY);
+
+        // the NONCE expires
+        let later = now + Duration::from_secs(60);
+        let refresh = request(StunMessageMethod::Refresh, 9, &[], alice_credentials);
+        let (code, new_nonce) = error(&respond(&mut server, &refresh, client, later));
+        assert_eq!(code, STUN_ERROR_STALE_NONCE);
+        assert_ne!(new_nonce.unwrap(), nonce);
+    }
+
+    #[test]
+    fn test_expiry() {
+        let now = Instant::now();
+        let mut server = server(now);
+        let options = TurnClientOptions {
+            lifetime: Some(Duration::from_secs(1200)),
+            ..TurnClientOptions::default()
+        };
+        let mut client =
+            TurnClient::with_options("alice", "secret", TransportProtocol::Udp, options, now)
+                .unwrap();
+        let [relayed_address, _] = relayed_addresses();
+        let peer: SocketAddr = "203.0.113.1:5000".parse().unwrap();
+
+        exchange(&mut client, &mut server, now);
+        client.create_permission(peer.ip(), now).unwrap();
+        exchange(&mut client, &mut server, now);
+
+        // the permission expires first, as the client is not driven to refresh it
+        let permission_expiry = now + Duration::from_secs(300);
+        assert_eq!(server.poll_timeout(), Some(permission_expiry));
+        server.handle_timeout(permission_expiry);
+        server.handle_datagram(b"hi", peer, relayed_address, permission_expiry);
+        assert!(server.poll_transmit().is_none());
+
+        // then the allocation, with its requested lifetime
+        let allocation_expiry = now + Duration::from_secs(1200);
+        assert_eq!(server.poll_timeout(), Some(allocation_expiry));
+        server.handle_timeout(allocation_expiry);
+        assert_eq!(server.allocation_count(), 0);
+        assert_eq!(server.poll_timeout(), None);
+
+        // and its relayed transport address can be allocated again
+        let mut client = TurnClient::new("bob", "hunter2", TransportProtocol::Udp, now).unwrap();
+        exchange(&mut client, &mut server, allocation_expiry);
+        assert!(client.is_allocated());
+        assert_eq!(server.allocation_count(), 1);
+    }
+
+    #[test]
+    fn test_allocate_after_expiry() {
+        let now = Instant::now();
+        let mut server = server(now);
+        let mut client = TurnClient::new("alice", "secret", TransportProtocol::Udp, now).unwrap();
+        exchange(&mut client, &mut server, now);
+        assert!(client.is_allocated());
+
+        // the expired allocation has not been dropped with handle_timeout, and is replaced by a
+        // new one rather than answered with 437 (Allocation Mismatch)
+        let expiry = now + Duration::from_secs(TURN_DEFAULT_ALLOCATION_LIFETIME_SECS.into());
+        let mut client =
+            TurnClient::new("alice", "secret", TransportProtocol::Udp, expiry).unwrap();
+        exchange(&mut client, &mut server, expiry);
+        assert!(client.is_allocated());
+        assert_eq!(server.allocation_count(), 1);
+
+        // the relayed transport address of the expired allocation went back to the pool
+        let [first, second] = relayed_addresses();
+        assert_eq!(client.relayed_address(), Some(second));
+        assert_eq!(server.relays, [first]);
+    }
+}
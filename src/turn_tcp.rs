@@ -0,0 +1,203 @@
+use crate::parser::parse_attribute_value;
+use crate::stun_address::*;
+use crate::stun_attribute_types::*;
+use crate::stun_constants::*;
+use crate::stun_errors::StunParseError;
+use crate::stun_message::*;
+use crate::stun_message_builder::*;
+use crate::stun_message_types::*;
+
+use alloc::vec::Vec;
+use core::net::SocketAddr;
+
+use cookie_factory::GenError;
+use nom::number::complete::be_u32;
+use nom::IResult;
+
+/// The contents of a ConnectionAttempt indication, https://tools.ietf.org/html/rfc6062#section-4.4
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct ConnectionAttempt {
+    /// address of the peer that connected to the relayed transport address, from XOR-PEER-ADDRESS
+    pub peer_address: SocketAddr,
+
+    /// identifier for the new peer data connection, from CONNECTION-ID
+    pub connection_id: u32,
+}
+
+/// Parse the value of a CONNECTION-ID attribute, https://tools.ietf.org/html/rfc6062#section-6.2.1
+pub fn parse_connection_id(input: &[u8]) -> IResult<&[u8], u32, StunParseError<&[u8]>> {
+    be_u32(input)
+}
+
+/// Build a Connect request asking the server to open a TCP connection to the given peer,
+/// https://tools.ietf.org/html/rfc6062#section-4.3
+pub fn build_connect_request(
+    transaction_id: &[u8; STUN_TRANSACTION_ID_NUM_BYTES],
+    peer_address: &SocketAddr,
+) -> Result<Vec<u8>, GenError> {
+    StunMessageBuilder::new(
+        StunMessageClass::Request,
+        StunMessageMethod::Connect,
+        transaction_id,
+    )
+    .add_xor_address_attribute(StunAttributeType::XorPeerAddress as u16, peer_address)
+    .build()
+}
+
+/// Build a ConnectionBind request associating a new client data connection with the given
+/// connection id, https://tools.ietf.org/html/rfc6062#section-4.3
+pub fn build_connection_bind_request(
+    transaction_id: &[u8; STUN_TRANSACTION_ID_NUM_BYTES],
+    connection_id: u32,
+) -> Result<Vec<u8>, GenError> {
+    StunMessageBuilder::new(
+        StunMessageClass::Request,
+        StunMessageMethod::ConnectionBind,
+        transaction_id,
+    )
+    .add_u32_attribute(StunAttributeType::ConnectionId as u16, connection_id)
+    .build()
+}
+
+/// Build a ConnectionAttempt indication informing the client of a new peer connection,
+/// https://tools.ietf.org/html/rfc6062#section-5.3
+pub fn build_connection_attempt_indication(
+    transaction_id: &[u8; STUN_TRANSACTION_ID_NUM_BYTES],
+    connection_attempt: &ConnectionAttempt,
+) -> Result<Vec<u8>, GenError> {
+    StunMessageBuilder::new(
+        StunMessageClass::Indication,
+        StunMessageMethod::ConnectionAttempt,
+        transaction_id,
+    )
+    .add_u32_attribute(
+        StunAttributeType::ConnectionId as u16,
+        connection_attempt.connection_id,
+    )
+    .add_xor_address_attribute(
+        StunAttributeType::XorPeerAddress as u16,
+        &connection_attempt.peer_address,
+    )
+    .build()
+}
+
+/// Extract the peer address and connection id from a parsed ConnectionAttempt indication.
+///
+/// # Arguments
+///
+/// * `message` - A parsed STUN message, which must be a ConnectionAttempt indication
+///
+/// # Return
+///
+/// A Result object, when successful contains the peer address and connection id.  On error, an
+/// error object describing why the message is not a valid ConnectionAttempt indication.
+pub fn parse_connection_attempt<'a>(
+    message: &StunMessage<'a>,
+) -> Result<ConnectionAttempt, StunParseError<&'a [u8]>> {
+    if message.message_class != StunMessageClass::Indication
+        || message.message_method != StunMessageMethod::ConnectionAttempt
+    {
+        return Err(StunParseError::UnexpectedMessageTypeError(
+            message.message_class,
+            message.message_method,
+        ));
+    }
+
+    let connection_id = message.get_required_attribute(StunAttributeType::ConnectionId as u16)?;
+    let connection_id = parse_attribute_value(connection_id, parse_connection_id)?;
+
+    let peer_address = message.get_required_attribute(StunAttributeType::XorPeerAddress as u16)?;
+    let transaction_id = message.transaction_id;
+    let peer_address =
+        parse_attribute_value(peer_address, |i| parse_xor_address(i, transaction_id))?;
+
+    Ok(ConnectionAttempt {
+        peer_address,
+        connection_id,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_stun_message;
+
+    #[test]
+    fn test_build_connect_request() {
+        let transaction_id = [0x01; STUN_TRANSACTION_ID_NUM_BYTES];
+        let peer_address: SocketAddr = "198.51.100.20:8080".parse().unwrap();
+        let data = build_connect_request(&transaction_id, &peer_address).unwrap();
+
+        let (_, message) = parse_stun_message(&data).unwrap();
+        assert_eq!(message.message_class, StunMessageClass::Request);
+        assert_eq!(message.message_method, StunMessageMethod::Connect);
+
+        let attribute = message.get_attribute(0x0012).unwrap();
+        let result = parse_attribute_value(attribute, |i| parse_xor_address(i, &transaction_id));
+        assert_eq!(result, Ok(peer_address));
+    }
+
+    #[test]
+    fn test_build_connection_bind_request() {
+        let transaction_id = [0x02; STUN_TRANSACTION_ID_NUM_BYTES];
+        let data = build_connection_bind_request(&transaction_id, 0xDEAD_BEEF).unwrap();
+
+        assert_eq!(data[0..2], [0x00, 0x0B]);
+
+        let (_, message) = parse_stun_message(&data).unwrap();
+        assert_eq!(message.message_method, StunMessageMethod::ConnectionBind);
+
+        let attribute = message.get_attribute(0x002A).unwrap();
+        assert_eq!(attribute.attribute_value, [0xDE, 0xAD, 0xBE, 0xEF]);
+    }
+
+    #[test]
+    fn test_parse_connection_attempt_roundtrip() {
+        let transaction_id = [0x03; STUN_TRANSACTION_ID_NUM_BYTES];
+        let connection_attempt = ConnectionAttempt {
+            peer_address: "[2001:db8::7]:5000".parse().unwrap(),
+            connection_id: 42,
+        };
+        let data =
+            build_connection_attempt_indication(&transaction_id, &connection_attempt).unwrap();
+
+        assert_eq!(data[0..2], [0x00, 0x1C]);
+
+        let (_, message) = parse_stun_message(&data).unwrap();
+        assert_eq!(parse_connection_attempt(&message), Ok(connection_attempt));
+    }
+
+    #[test]
+    fn test_parse_connection_attempt_wrong_method() {
+        let transaction_id = [0x04; STUN_TRANSACTION_ID_NUM_BYTES];
+        let data = build_connection_bind_request(&transaction_id, 1).unwrap();
+
+        let (_, message) = parse_stun_message(&data).unwrap();
+        assert_eq!(
+            parse_connection_attempt(&message),
+            Err(StunParseError::UnexpectedMessageTypeError(
+                StunMessageClass::Request,
+                StunMessageMethod::ConnectionBind
+            ))
+        );
+    }
+
+    #[test]
+    fn test_parse_connection_attempt_missing_peer_address() {
+        let transaction_id = [0x05; STUN_TRANSACTION_ID_NUM_BYTES];
+        let data = StunMessageBuilder::new(
+            StunMessageClass::Indication,
+            StunMessageMethod::ConnectionAttempt,
+            &transaction_id,
+        )
+        .add_u32_attribute(StunAttributeType::ConnectionId as u16, 7)
+        .build()
+        .unwrap();
+
+        let (_, message) = parse_stun_message(&data).unwrap();
+        assert_eq!(
+            parse_connection_attempt(&message),
+            Err(StunParseError::MissingAttributeError(0x0012))
+        );
+    }
+}
@@ -0,0 +1,32 @@
+use std::fs;
+use std::path::Path;
+
+/// Load a hex dump from the fixtures directory, in the layout used by RFC 5769.
+///
+/// Each line starts with zero or more bytes written as pairs of hex digits separated by
+/// whitespace, anything after the first token which is not a byte is a comment.  Lines starting
+/// with `#` are comments.
+pub fn load_hex_fixture(name: &str) -> Vec<u8> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join(name);
+    let dump = fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("Unable to read {}:  {:?}", path.display(), e));
+
+    parse_hex_dump(&dump)
+}
+
+/// Parse a hex dump, see `load_hex_fixture`
+pub fn parse_hex_dump(dump: &str) -> Vec<u8> {
+    dump.lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .flat_map(|line| {
+            line.split_whitespace()
+                .map_while(|token| match token.len() {
+                    2 => u8::from_str_radix(token, 16).ok(),
+                    _ => None,
+                })
+        })
+        .collect()
+}
@@ -0,0 +1,218 @@
+//! NAT behavior discovery against a server on 127.0.0.1 and 127.0.0.2,
+//! https://tools.ietf.org/html/rfc5780
+#![cfg(feature = "std")]
+
+use stun_message::*;
+
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const TRANSACTION_ID: [u8; STUN_TRANSACTION_ID_NUM_BYTES] = [0x55; 12];
+
+/// Start a server listening on two loopback addresses and two ports
+fn start_server() -> NatBehaviorAddresses {
+    // the two ports must be free on both addresses
+    for _ in 0..20 {
+        let primary = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let alternate = UdpSocket::bind("127.0.0.2:0").unwrap();
+        let addresses = NatBehaviorAddresses {
+            primary: primary.local_addr().unwrap(),
+            alternate: alternate.local_addr().unwrap(),
+        };
+        let [_, primary_ip, alternate_ip, _] = addresses.all();
+        let sockets = match (UdpSocket::bind(primary_ip), UdpSocket::bind(alternate_ip)) {
+            (Ok(primary_ip), Ok(alternate_ip)) => [primary, primary_ip, alternate_ip, alternate],
+            _ => continue,
+        };
+
+        let mut server = Server::with_software("nat behavior test").unwrap();
+        server.set_nat_behavior_addresses(addresses);
+        thread::spawn(move || server.serve_udp_sockets(&sockets));
+
+        return addresses;
+    }
+
+    panic!("no pair of ports is free on both 127.0.0.1 and 127.0.0.2");
+}
+
+fn client() -> UdpSocket {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    socket
+}
+
+fn request(change_request: StunChangeRequest, response_port: Option<u16>) -> Vec<u8> {
+    let change_request = serialize_change_request(&change_request);
+    let mut builder = StunMessageBuilder::new(
+        StunMessageClass::Request,
+        StunMessageMethod::Binding,
+        &TRANSACTION_ID,
+    );
+    builder.add_attribute(StunAttributeType::ChangeRequest as u16, &change_request);
+    if let Some(port) = response_port {
+        builder.add_attribute(
+            StunAttributeType::ResponsePort as u16,
+            &serialize_response_port(port),
+        );
+    }
+    builder.add_fingerprint().build().unwrap()
+}
+
+/// Receive a success response, returning the address it came from and its XOR-MAPPED-ADDRESS,
+/// RESPONSE-ORIGIN and OTHER-ADDRESS
+fn receive(socket: &UdpSocket) -> (SocketAddr, SocketAddr, SocketAddr, SocketAddr) {
+    let mut buffer = [0; 1024];
+    let (length, from) = socket.recv_from(&mut buffer).unwrap();
+
+    let (_, response) = parse_stun_message(&buffer[..length]).unwrap();
+    assert_eq!(response.message_class, StunMessageClass::SuccessResponse);
+    assert_eq!(*response.transaction_id, TRANSACTION_ID);
+    assert!(validate(&response).is_empty());
+    assert_eq!(verify_fingerprint(&response), Ok(()));
+
+    let attribute = |attribute_type: StunAttributeType| {
+        response
+            .get_required_attribute(attribute_type as u16)
+            .unwrap()
+    };
+    let mapped_address =
+        parse_attribute_value(attribute(StunAttributeType::XorMappedAddress), |i| {
+            parse_xor_address(i, &TRANSACTION_ID)
+        })
+        .unwrap();
+    let response_origin =
+        parse_attribute_value(attribute(StunAttributeType::ResponseOrigin), parse_address).unwrap();
+    let other_address =
+        parse_attribute_value(attribute(StunAttributeType::OtherAddress), parse_address).unwrap();
+
+    (from, mapped_address, response_origin, other_address)
+}
+
+#[test]
+fn test_change_request() {
+    let addresses = start_server();
+    let [primary, primary_ip, alternate_ip, alternate] = addresses.all();
+    let socket = client();
+
+    let cases = [
+        (false, false, primary),
+        (false, true, primary_ip),
+        (true, false, alternate_ip),
+        (true, true, alternate),
+    ];
+    for &(change_ip, change_port, expected) in &cases {
+        let change_request = StunChangeRequest {
+            change_ip,
+            change_port,
+        };
+        socket
+            .send_to(&request(change_request, None), primary)
+            .unwrap();
+
+        let (from, mapped_address, response_origin, other_address) = receive(&socket);
+        assert_eq!(from, expected);
+        assert_eq!(mapped_address, socket.local_addr().unwrap());
+        assert_eq!(response_origin, expected);
+        assert_eq!(other_address, alternate);
+    }
+
+    // OTHER-ADDRESS is relative to the address the request was received on
+    socket
+        .send_to(&request(StunChangeRequest::default(), None), alternate_ip)
+        .unwrap();
+    let (from, _, response_origin, other_address) = receive(&socket);
+    assert_eq!(from, alternate_ip);
+    assert_eq!(response_origin, alternate_ip);
+    assert_eq!(other_address, primary_ip);
+}
+
+#[test]
+fn test_response_port() {
+    let addresses = start_server();
+    let sender = client();
+    let receiver = client();
+
+    let change_request = StunChangeRequest {
+        change_ip: false,
+        change_port: true,
+    };
+    let port = receiver.local_addr().unwrap().port();
+    sender
+        .send_to(&request(change_request, Some(port)), addresses.primary)
+        .unwrap();
+
+    // the response goes to the RESPONSE-PORT, with the mapped address of the sender
+    let (from, mapped_address, response_origin, _) = receive(&receiver);
+    assert_eq!(from, addresses.all()[1]);
+    assert_eq!(response_origin, from);
+    assert_eq!(mapped_address, sender.local_addr().unwrap());
+}
+
+#[test]
+fn test_discovery() {
+    let addresses = start_server();
+    let primary = client();
+    let secondary = client();
+
+    // the datagrams received on both sockets
+    let (sender, receiver) = mpsc::channel();
+    for socket in [&primary, &secondary] {
+        let socket = socket.try_clone().unwrap();
+        let sender = sender.clone();
+        thread::spawn(move || {
+            let mut buffer = [0; 1024];
+            while let Ok(length) = socket.recv(&mut buffer) {
+                if sender.send(buffer[..length].to_vec()).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    let options = NatBehaviorOptions {
+        fingerprint: true,
+        lifetime_probes: vec![Duration::from_millis(50), Duration::from_millis(100)],
+        ..NatBehaviorOptions::default()
+    };
+    let local = primary.local_addr().unwrap();
+    let mut discovery =
+        NatBehaviorDiscovery::with_options(addresses.primary, local, options, Instant::now())
+            .unwrap();
+
+    while let Some(deadline) = discovery.poll_timeout() {
+        while let Some(transmit) = discovery.poll_transmit() {
+            let socket = match transmit.socket {
+                NatBehaviorSocket::Primary => &primary,
+                NatBehaviorSocket::Secondary => &secondary,
+            };
+            socket.send_to(transmit.data, transmit.destination).unwrap();
+        }
+
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        match receiver.recv_timeout(timeout) {
+            Ok(data) => {
+                discovery.handle_input(&data, Instant::now());
+            }
+            Err(_) => discovery.handle_timeout(Instant::now()),
+        }
+    }
+
+    // there is no NAT on loopback, and the server answers from all of its addresses
+    assert_eq!(
+        discovery.into_outcome().unwrap().unwrap(),
+        NatBehavior {
+            mapped_address: local,
+            mapping: NatMapping::NoNat,
+            filtering: NatFiltering::EndpointIndependent,
+            hairpinning: Some(true),
+            binding_lifetime: Some(BindingLifetime {
+                at_least: Duration::from_millis(100),
+                less_than: None,
+            }),
+        }
+    );
+}
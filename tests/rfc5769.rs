@@ -0,0 +1,221 @@
+//! Test vectors from RFC 5769, https://tools.ietf.org/html/rfc5769
+#![cfg(feature = "std")]
+
+mod common;
+
+use common::*;
+
+use stun_message::*;
+
+use std::convert::TryFrom;
+use std::net::SocketAddr;
+
+const SHORT_TERM_PASSWORD: &str = "VOkJxbRl1RmTxUk/WvJxBt";
+
+const TRANSACTION_ID: [u8; STUN_TRANSACTION_ID_NUM_BYTES] = [
+    0xB7, 0xE7, 0xA7, 0x01, 0xBC, 0x34, 0xD6, 0x86, 0xFA, 0x87, 0xDF, 0xAE,
+];
+
+fn attribute_types(message: &StunMessage) -> Vec<u16> {
+    message
+        .attributes
+        .iter()
+        .map(|a| a.attribute_type)
+        .collect()
+}
+
+fn parse_fixture(data: &[u8]) -> StunMessage<'_> {
+    let (remaining, message) = parse_stun_message(data).unwrap();
+
+    assert_eq!(remaining.len(), 0);
+    assert_eq!(message.message_length as usize, data.len() - 20);
+    assert!(validate(&message).is_empty());
+
+    message
+}
+
+fn xor_mapped_address(message: &StunMessage) -> SocketAddr {
+    let attribute = message
+        .get_required_attribute(StunAttributeType::XorMappedAddress as u16)
+        .unwrap();
+
+    parse_attribute_value(attribute, |input| {
+        parse_xor_address(input, message.transaction_id)
+    })
+    .unwrap()
+}
+
+fn software(message: &StunMessage) -> String {
+    let attribute = message
+        .get_required_attribute(StunAttributeType::Software as u16)
+        .unwrap();
+
+    parse_attribute_value(attribute, parse_software)
+        .unwrap()
+        .as_str()
+        .to_string()
+}
+
+#[test]
+fn test_hex_dump_loader() {
+    let dump = "# comment\n00 01 00 58     Request type and message length\nb7 e7  }  ID\n";
+
+    assert_eq!(parse_hex_dump(dump), [0x00, 0x01, 0x00, 0x58, 0xB7, 0xE7]);
+}
+
+#[test]
+fn test_sample_request() {
+    let data = load_hex_fixture("rfc5769/sample_request.txt");
+    assert_eq!(data.len(), 108);
+
+    let message = parse_fixture(&data);
+    assert_eq!(message.message_class, StunMessageClass::Request);
+    assert_eq!(message.message_method, StunMessageMethod::Binding);
+    assert_eq!(message.transaction_id, &TRANSACTION_ID);
+    assert_eq!(
+        attribute_types(&message),
+        [0x8022, 0x0024, 0x8029, 0x0006, 0x0008, 0x8028]
+    );
+
+    // the padding of SOFTWARE is empty, the padding of USERNAME is made of spaces
+    assert_eq!(software(&message), "STUN test client");
+    let username = message.get_required_attribute(0x0006).unwrap();
+    assert_eq!(
+        parse_attribute_value(username, parse_username)
+            .unwrap()
+            .as_str(),
+        "evtj:h6vY"
+    );
+    assert_eq!(username.padding, b"   ");
+
+    let key = short_term_credential_key(SHORT_TERM_PASSWORD).unwrap();
+    assert_eq!(verify_message_integrity(&message, &key), Ok(()));
+    assert_eq!(verify_fingerprint(&message), Ok(()));
+}
+
+#[test]
+fn test_sample_ipv4_response() {
+    let data = load_hex_fixture("rfc5769/sample_ipv4_response.txt");
+    assert_eq!(data.len(), 80);
+
+    let message = parse_fixture(&data);
+    assert_eq!(message.message_class, StunMessageClass::SuccessResponse);
+    assert_eq!(message.message_method, StunMessageMethod::Binding);
+    assert_eq!(message.transaction_id, &TRANSACTION_ID);
+    assert_eq!(attribute_types(&message), [0x8022, 0x0020, 0x0008, 0x8028]);
+
+    assert_eq!(software(&message), "test vector");
+    assert_eq!(
+        xor_mapped_address(&message),
+        "192.0.2.1:32853".parse().unwrap()
+    );
+
+    let key = short_term_credential_key(SHORT_TERM_PASSWORD).unwrap();
+    assert_eq!(verify_message_integrity(&message, &key), Ok(()));
+    assert_eq!(verify_fingerprint(&message), Ok(()));
+}
+
+#[test]
+fn test_sample_ipv6_response() {
+    let data = load_hex_fixture("rfc5769/sample_ipv6_response.txt");
+    assert_eq!(data.len(), 92);
+
+    let message = parse_fixture(&data);
+    assert_eq!(message.message_class, StunMessageClass::SuccessResponse);
+    assert_eq!(message.message_method, StunMessageMethod::Binding);
+    assert_eq!(message.transaction_id, &TRANSACTION_ID);
+    assert_eq!(attribute_types(&message), [0x8022, 0x0020, 0x0008, 0x8028]);
+
+    assert_eq!(software(&message), "test vector");
+    assert_eq!(
+        xor_mapped_address(&message),
+        "[2001:db8:1234:5678:11:2233:4455:6677]:32853"
+            .parse()
+            .unwrap()
+    );
+
+    let key = short_term_credential_key(SHORT_TERM_PASSWORD).unwrap();
+    assert_eq!(verify_message_integrity(&message, &key), Ok(()));
+    assert_eq!(verify_fingerprint(&message), Ok(()));
+}
+
+#[test]
+fn test_sample_long_term_request() {
+    let data = load_hex_fixture("rfc5769/sample_long_term_request.txt");
+    assert_eq!(data.len(), 116);
+
+    let message = parse_fixture(&data);
+    assert_eq!(message.message_class, StunMessageClass::Request);
+    assert_eq!(message.message_method, StunMessageMethod::Binding);
+    assert_eq!(
+        message.transaction_id,
+        &[0x78, 0xAD, 0x34, 0x33, 0xC6, 0xAD, 0x72, 0xC0, 0x29, 0xDA, 0x41, 0x2E]
+    );
+    assert_eq!(attribute_types(&message), [0x0006, 0x0015, 0x0014, 0x0008]);
+
+    let username =
+        parse_attribute_value(message.get_attribute(0x0006).unwrap(), parse_username).unwrap();
+    let nonce = parse_attribute_value(message.get_attribute(0x0015).unwrap(), parse_nonce).unwrap();
+    let realm = parse_attribute_value(message.get_attribute(0x0014).unwrap(), parse_realm).unwrap();
+    assert_eq!(
+        username.as_str(),
+        "\u{30DE}\u{30C8}\u{30EA}\u{30C3}\u{30AF}\u{30B9}"
+    );
+    assert_eq!(nonce.as_str(), "f//499k954d6OL34oL9FSTvy64sA");
+    assert_eq!(realm.as_str(), "example.org");
+
+    let key =
+        long_term_credential_key(&username, &realm, "The\u{00AD}M\u{00AA}tr\u{2168}").unwrap();
+    assert_eq!(verify_message_integrity(&message, &key), Ok(()));
+    assert_eq!(
+        verify_fingerprint(&message),
+        Err(StunAuthError::MissingAttributeError(0x8028))
+    );
+}
+
+#[test]
+fn test_samples_reproduce_bytes() {
+    for name in &[
+        "rfc5769/sample_request.txt",
+        "rfc5769/sample_ipv4_response.txt",
+        "rfc5769/sample_ipv6_response.txt",
+        "rfc5769/sample_long_term_request.txt",
+    ] {
+        let data = load_hex_fixture(name);
+        let message = parse_fixture(&data);
+
+        assert_eq!(serialize(&message).unwrap(), data, "{}", name);
+        assert_eq!(
+            OwnedStunMessage::from(&message).to_bytes().unwrap(),
+            data,
+            "{}",
+            name
+        );
+    }
+}
+
+#[test]
+fn test_sample_response_rebuilt() {
+    let data = load_hex_fixture("rfc5769/sample_ipv4_response.txt");
+    let mut rebuilt = StunMessageBuilder::new(
+        StunMessageClass::SuccessResponse,
+        StunMessageMethod::Binding,
+        <&[u8; 12]>::try_from(&data[8..20]).unwrap(),
+    )
+    .add_attribute(0x8022, b"test vector")
+    .add_xor_address_attribute(0x0020, &"192.0.2.1:32853".parse().unwrap())
+    .add_message_integrity(&short_term_credential_key(SHORT_TERM_PASSWORD).unwrap())
+    .add_fingerprint()
+    .build()
+    .unwrap();
+
+    // the builder pads with zeros rather than the spaces used by the sample
+    rebuilt[35] = 0x20;
+    let mut message = StunMessageMut::new(&mut rebuilt).unwrap();
+    message
+        .update_message_integrity(&short_term_credential_key(SHORT_TERM_PASSWORD).unwrap())
+        .unwrap();
+    message.update_fingerprint().unwrap();
+
+    assert_eq!(rebuilt, data);
+}
@@ -0,0 +1,230 @@
+//! Property based tests: serialize -> parse roundtrips, and parsing arbitrary input never panics
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+use stun_message::*;
+
+use std::net::{IpAddr, SocketAddr};
+
+fn message_class() -> impl Strategy<Value = StunMessageClass> {
+    prop_oneof![
+        Just(StunMessageClass::Request),
+        Just(StunMessageClass::Indication),
+        Just(StunMessageClass::SuccessResponse),
+        Just(StunMessageClass::ErrorResponse),
+    ]
+}
+
+fn message_method() -> impl Strategy<Value = StunMessageMethod> {
+    prop_oneof![
+        Just(StunMessageMethod::Binding),
+        Just(StunMessageMethod::Allocate),
+        Just(StunMessageMethod::Refresh),
+        Just(StunMessageMethod::Send),
+        Just(StunMessageMethod::Data),
+        Just(StunMessageMethod::CreatePermission),
+        Just(StunMessageMethod::ChannelBind),
+        Just(StunMessageMethod::Connect),
+        Just(StunMessageMethod::ConnectionBind),
+        Just(StunMessageMethod::ConnectionAttempt),
+    ]
+}
+
+/// An attribute type and value, the type is biased towards known attributes
+fn attribute() -> impl Strategy<Value = (u16, Vec<u8>)> {
+    let attribute_type = prop_oneof![
+        any::<u16>(),
+        Just(StunAttributeType::MappedAddress as u16),
+        Just(StunAttributeType::XorMappedAddress as u16),
+        Just(StunAttributeType::ErrorCode as u16),
+        Just(StunAttributeType::Software as u16),
+        Just(StunAttributeType::MessageIntegrity as u16),
+        Just(StunAttributeType::Fingerprint as u16),
+    ];
+
+    (attribute_type, vec(any::<u8>(), 0..64))
+}
+
+/// The parts of a valid message, which borrows them when it is created with `message`
+type MessageParts = (
+    StunMessageClass,
+    StunMessageMethod,
+    [u8; STUN_TRANSACTION_ID_NUM_BYTES],
+    Vec<(u16, Vec<u8>)>,
+);
+
+fn message_parts() -> impl Strategy<Value = MessageParts> {
+    (
+        message_class(),
+        message_method(),
+        any::<[u8; STUN_TRANSACTION_ID_NUM_BYTES]>(),
+        vec(attribute(), 0..8),
+    )
+}
+
+fn message(parts: &MessageParts) -> StunMessage<'_> {
+    let (message_class, message_method, transaction_id, attributes) = parts;
+
+    let mut offset = 20;
+    let attributes: Vec<StunAttribute> = attributes
+        .iter()
+        .map(|(attribute_type, attribute_value)| {
+            let attribute = StunAttribute {
+                attribute_type: *attribute_type,
+                attribute_length: attribute_value.len() as u16,
+                attribute_value,
+                padding: &[],
+                offset,
+            };
+            offset += attribute.serialized_length();
+            attribute
+        })
+        .collect();
+
+    StunMessage {
+        message_class: *message_class,
+        message_method: *message_method,
+        message_length: serialized_attributes_length(&attributes) as u16,
+        magic_cookie: STUN_MAGIC_COOKIE,
+        transaction_id,
+        attributes,
+        raw: &[],
+    }
+}
+
+/// Exercise everything that consumes parsed input, none of which may panic
+fn exercise(data: &[u8]) {
+    if let Ok((_, message)) = parse_stun_message(data) {
+        let _ = validate(&message);
+        let _ = message.to_string();
+        let _ = verify_message_integrity(&message, b"key");
+        let _ = verify_fingerprint(&message);
+
+        assert_eq!(serialize(&message).unwrap(), message.raw);
+        assert_eq!(
+            OwnedStunMessage::from(&message).to_bytes().unwrap(),
+            message.raw
+        );
+    }
+
+    if let Ok((_, view)) = parse_stun_message_view(data) {
+        let _ = view.attributes().count();
+    }
+
+    let mut buffer = data.to_vec();
+    if let Ok(mut message) = StunMessageMut::new(&mut buffer) {
+        let _ = message.update_message_integrity(b"key");
+        let _ = message.update_fingerprint();
+    }
+}
+
+proptest! {
+    #[test]
+    fn serialize_parse_roundtrip(parts in message_parts()) {
+        let message = message(&parts);
+        let data = serialize(&message).unwrap();
+
+        let (remaining, parsed) = parse_stun_message(&data).unwrap();
+        prop_assert!(remaining.is_empty());
+        prop_assert_eq!(parsed.message_class, message.message_class);
+        prop_assert_eq!(parsed.message_method, message.message_method);
+        prop_assert_eq!(parsed.message_length, message.message_length);
+        prop_assert_eq!(parsed.transaction_id, message.transaction_id);
+        prop_assert_eq!(parsed.raw, &data[..]);
+        prop_assert_eq!(parsed.attributes.len(), message.attributes.len());
+        for (a, b) in parsed.attributes.iter().zip(&message.attributes) {
+            prop_assert_eq!(a.attribute_type, b.attribute_type);
+            prop_assert_eq!(a.attribute_value, b.attribute_value);
+            prop_assert_eq!(a.offset, b.offset);
+        }
+
+        let (_, view) = parse_stun_message_view(&data).unwrap();
+        prop_assert_eq!(view.attributes().count(), message.attributes.len());
+
+        prop_assert_eq!(OwnedStunMessage::from(&parsed).to_bytes().unwrap(), data);
+    }
+
+    #[test]
+    fn builder_signs_any_message(parts in message_parts(), key in vec(any::<u8>(), 0..32)) {
+        let (message_class, message_method, transaction_id, attributes) = &parts;
+        let mut builder = StunMessageBuilder::new(*message_class, *message_method, transaction_id);
+
+        // an earlier MESSAGE-INTEGRITY or FINGERPRINT would be the one that is verified
+        for (attribute_type, attribute_value) in attributes
+            .iter()
+            .filter(|(attribute_type, _)| *attribute_type != 0x0008 && *attribute_type != 0x8028)
+        {
+            builder.add_attribute(*attribute_type, attribute_value);
+        }
+        let data = builder.add_message_integrity(&key).add_fingerprint().build().unwrap();
+
+        let (_, parsed) = parse_stun_message(&data).unwrap();
+        prop_assert_eq!(verify_message_integrity(&parsed, &key), Ok(()));
+        prop_assert_eq!(verify_fingerprint(&parsed), Ok(()));
+
+        // signing in place gives the same result
+        let mut buffer = data.clone();
+        let mut message = StunMessageMut::new(&mut buffer).unwrap();
+        message.remove_trailing_attributes(0x0008).unwrap();
+        message.append_message_integrity(&key).unwrap();
+        message.append_fingerprint().unwrap();
+        prop_assert_eq!(buffer, data);
+    }
+
+    #[test]
+    fn parse_arbitrary_input(data in vec(any::<u8>(), 0..256)) {
+        exercise(&data);
+    }
+
+    #[test]
+    fn parse_corrupted_message(
+        parts in message_parts(),
+        corruptions in vec((any::<prop::sample::Index>(), any::<u8>()), 1..4),
+        truncate in any::<prop::sample::Index>(),
+    ) {
+        let mut data = serialize(&message(&parts)).unwrap();
+        for (index, value) in corruptions {
+            let index = index.index(data.len());
+            data[index] = value;
+        }
+
+        exercise(&data);
+        exercise(&data[..truncate.index(data.len())]);
+    }
+
+    #[test]
+    fn address_roundtrip(
+        ip in any::<IpAddr>(),
+        port in any::<u16>(),
+        transaction_id in any::<[u8; 12]>(),
+    ) {
+        // the flow info and scope id of IPv6 addresses are not carried by STUN
+        let address = SocketAddr::new(ip, port);
+        let value = serialize_address(&address);
+        prop_assert_eq!(parse_address(&value).unwrap(), (&[][..], address));
+
+        let value = serialize_xor_address(&address, &transaction_id);
+        prop_assert_eq!(parse_xor_address(&value, &transaction_id).unwrap(), (&[][..], address));
+    }
+
+    #[test]
+    fn error_code_roundtrip(code in 300u16..700, reason in "\\PC{0,32}") {
+        let value = serialize_error_code(code, &reason);
+        let (_, error_code) = parse_error_code(&value).unwrap();
+
+        prop_assert_eq!(error_code.code, code);
+        prop_assert_eq!(error_code.reason, reason.as_str());
+    }
+
+    #[test]
+    fn decode_arbitrary_values(value in vec(any::<u8>(), 0..64), transaction_id in any::<[u8; 12]>()) {
+        let _ = parse_address(&value);
+        let _ = parse_xor_address(&value, &transaction_id);
+        let _ = parse_error_code(&value);
+        let _ = parse_username(&value);
+        let _ = parse_realm(&value);
+        let _ = parse_nonce(&value);
+        let _ = parse_software(&value);
+        let _ = parse_connection_id(&value);
+    }
+}
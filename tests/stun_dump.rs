@@ -0,0 +1,104 @@
+#![cfg(feature = "cli")]
+mod common;
+
+use common::load_hex_fixture;
+use stun_message::*;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::process::{self, Command, Output, Stdio};
+
+fn stun_dump(args: &[&str], input: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_stun-dump"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+const IPV4_RESPONSE: &str = "\
+Binding Success Response, length 60, transaction id 0xb7e7a701bc34d686fa87dfae
+  SOFTWARE: \"test vector\"
+  XOR-MAPPED-ADDRESS: 192.0.2.1:32853
+  MESSAGE-INTEGRITY: 2b91f599fd9e90c38c7489f92af9ba53f06be7d7
+  FINGERPRINT: c07d4c96
+";
+
+#[test]
+fn dump_hex_base64_and_raw() {
+    let data = load_hex_fixture("rfc5769/sample_ipv4_response.txt");
+
+    // hex as it might be copied from a log, with separators and prefixes
+    let logged: Vec<String> = data.iter().map(|b| format!("0x{:02X}", b)).collect();
+    let inputs = [
+        hex(&data).into_bytes(),
+        logged.join(", ").into_bytes(),
+        STANDARD.encode(&data).into_bytes(),
+        data.clone(),
+    ];
+
+    for input in &inputs {
+        let output = stun_dump(&[], input);
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), IPV4_RESPONSE);
+    }
+}
+
+#[test]
+fn dump_files() {
+    let data = load_hex_fixture("rfc5769/sample_request.txt");
+    let path = env::temp_dir().join(format!("stun-dump-{}.hex", process::id()));
+    fs::write(&path, hex(&data)).unwrap();
+
+    let path = path.to_str().unwrap();
+    let output = stun_dump(&[path, "missing.bin"], &[]);
+    fs::remove_file(path).unwrap();
+
+    // each message is labelled with its file, and a file that cannot be read is reported
+    assert!(!output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with(&format!("{}:\nBinding Request, length 88", path)));
+    assert!(stdout.ends_with("\n\nmissing.bin:\n"));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("stun-dump: missing.bin: "));
+}
+
+#[test]
+fn dump_json() {
+    let data = load_hex_fixture("rfc5769/sample_ipv6_response.txt");
+
+    let output = stun_dump(&["--json"], hex(&data).as_bytes());
+    assert!(output.status.success());
+
+    let message: OwnedStunMessage = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(message.to_bytes().unwrap(), data);
+}
+
+#[test]
+fn dump_parse_error() {
+    let mut data = load_hex_fixture("rfc5769/sample_request.txt");
+
+    // the SOFTWARE attribute claims to be longer than the message
+    data[22..24].copy_from_slice(&[0x00, 0x60]);
+
+    let output = stun_dump(&["--raw"], &data);
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "stun-dump: <stdin>: parse error at byte 24: Nom(Eof)\n"
+    );
+}
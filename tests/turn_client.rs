@@ -0,0 +1,515 @@
+//! The TURN client against an in-process TURN server on virtual time,
+//! https://tools.ietf.org/html/rfc5766
+#![cfg(feature = "std")]
+
+use stun_message::*;
+
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+const USERNAME: &str = "alice";
+const PASSWORD: &str = "secret";
+const REALM: &str = "example.org";
+
+const CLIENT: &str = "198.51.100.2:40000";
+const RELAYED: &str = "192.0.2.15:50000";
+
+/// A request the server accepted, when it was received and the peer it was for
+type Accepted = (Duration, StunMessageMethod, Option<SocketAddr>);
+
+/// A TURN server with a single allocation, which challenges every request without credentials
+struct TestServer {
+    start: Instant,
+    key: [u8; STUN_LONG_TERM_KEY_NUM_BYTES],
+    nonce: String,
+
+    /// offer SHA-256 in PASSWORD-ALGORITHMS and expect MESSAGE-INTEGRITY-SHA256 with this key
+    sha256_key: Option<[u8; STUN_LONG_TERM_KEY_SHA256_NUM_BYTES]>,
+
+    /// answer the next authenticated request with 438 (Stale Nonce) and a new NONCE
+    stale_nonce: bool,
+
+    allocated: bool,
+    permissions: HashMap<IpAddr, Instant>,
+    channels: HashMap<u16, SocketAddr>,
+
+    accepted: Vec<Accepted>,
+    stale_nonces: usize,
+
+    /// the data relayed to peers
+    relayed: Vec<(SocketAddr, Vec<u8>)>,
+}
+
+impl TestServer {
+    fn new(start: Instant) -> Self {
+        let username = StunUsername::new(USERNAME).unwrap();
+        let realm = StunRealm::new(REALM).unwrap();
+
+        TestServer {
+            start,
+            key: long_term_credential_key(&username, &realm, PASSWORD).unwrap(),
+            nonce: "nonce-0".to_string(),
+            sha256_key: None,
+            stale_nonce: false,
+            allocated: false,
+            permissions: HashMap::new(),
+            channels: HashMap::new(),
+            accepted: Vec::new(),
+            stale_nonces: 0,
+            relayed: Vec::new(),
+        }
+    }
+
+    /// The times of the accepted requests with the given method and peer
+    fn accepted(&self, method: StunMessageMethod, peer: Option<SocketAddr>) -> Vec<u64> {
+        self.accepted
+            .iter()
+            .filter(|&&(_, m, p)| m == method && p == peer)
+            .map(|(time, _, _)| time.as_secs())
+            .collect()
+    }
+
+    fn has_permission(&self, peer: SocketAddr, now: Instant) -> bool {
+        self.permissions
+            .get(&peer.ip())
+            .is_some_and(|&expires_at| now < expires_at)
+    }
+
+    /// Handle a message from the client, returning the response if any
+    fn handle(&mut self, input: &[u8], now: Instant) -> Option<Vec<u8>> {
+        if let Ok((_, channel_data)) = parse_channel_data(input) {
+            let peer = self.channels[&channel_data.channel_number];
+            if self.has_permission(peer, now) {
+                self.relayed.push((peer, channel_data.data.to_vec()));
+            }
+            return None;
+        }
+
+        let (_, message) = parse_stun_message(input).unwrap();
+        assert!(validate(&message).is_empty());
+        if message
+            .get_attribute(StunAttributeType::Fingerprint as u16)
+            .is_some()
+        {
+            assert_eq!(verify_fingerprint(&message), Ok(()));
+        }
+
+        let peer = message
+            .get_attribute(StunAttributeType::XorPeerAddress as u16)
+            .map(|attribute| {
+                parse_attribute_value(attribute, |i| parse_xor_address(i, message.transaction_id))
+                    .unwrap()
+            });
+
+        if message.message_class == StunMessageClass::Indication {
+            assert_eq!(message.message_method, StunMessageMethod::Send);
+            let peer = peer.unwrap();
+            let data = message
+                .get_required_attribute(StunAttributeType::Data as u16)
+                .unwrap();
+            if self.has_permission(peer, now) {
+                self.relayed.push((peer, data.attribute_value.to_vec()));
+            }
+            return None;
+        }
+
+        let authenticated = match &self.sha256_key {
+            Some(key) => {
+                message
+                    .get_attribute(StunAttributeType::MessageIntegritySha256 as u16)
+                    .is_some()
+                    && verify_message_integrity_sha256(&message, key).is_ok()
+            }
+            None => {
+                message
+                    .get_attribute(StunAttributeType::MessageIntegrity as u16)
+                    .is_some()
+                    && verify_message_integrity(&message, &self.key).is_ok()
+            }
+        };
+        if !authenticated {
+            return Some(self.error_response(&message, STUN_ERROR_UNAUTHORIZED, "Unauthorized"));
+        }
+        if self.stale_nonce {
+            self.stale_nonce = false;
+            self.stale_nonces += 1;
+            self.nonce = format!("nonce-{}", self.stale_nonces);
+            return Some(self.error_response(&message, STUN_ERROR_STALE_NONCE, "Stale Nonce"));
+        }
+
+        let lifetime = message
+            .get_attribute(StunAttributeType::Lifetime as u16)
+            .map(|attribute| parse_attribute_value(attribute, parse_lifetime).unwrap())
+            .unwrap_or(TURN_DEFAULT_ALLOCATION_LIFETIME_SECS);
+        let permission_expires_at = now + Duration::from_secs(TURN_PERMISSION_LIFETIME_SECS.into());
+
+        let mut builder = StunMessageBuilder::new(
+            StunMessageClass::SuccessResponse,
+            message.message_method,
+            message.transaction_id,
+        );
+        match message.message_method {
+            StunMessageMethod::Allocate => {
+                if self.allocated {
+                    return Some(self.error_response(
+                        &message,
+                        STUN_ERROR_ALLOCATION_MISMATCH,
+                        "Allocation Mismatch",
+                    ));
+                }
+                self.allocated = true;
+                builder
+                    .add_xor_address_attribute(
+                        StunAttributeType::XorRelayedAddress as u16,
+                        &RELAYED.parse().unwrap(),
+                    )
+                    .add_xor_address_attribute(
+                        StunAttributeType::XorMappedAddress as u16,
+                        &CLIENT.parse().unwrap(),
+                    )
+                    .add_u32_attribute(StunAttributeType::Lifetime as u16, lifetime);
+            }
+            StunMessageMethod::Refresh => {
+                self.allocated = lifetime > 0;
+                builder.add_u32_attribute(StunAttributeType::Lifetime as u16, lifetime);
+            }
+            StunMessageMethod::CreatePermission => {
+                self.permissions
+                    .insert(peer.unwrap().ip(), permission_expires_at);
+            }
+            StunMessageMethod::ChannelBind => {
+                let attribute = message
+                    .get_required_attribute(StunAttributeType::ChannelNumber as u16)
+                    .unwrap();
+                let channel_number =
+                    parse_attribute_value(attribute, parse_channel_number).unwrap();
+                self.channels.insert(channel_number, peer.unwrap());
+                self.permissions
+                    .insert(peer.unwrap().ip(), permission_expires_at);
+            }
+            method => panic!("unexpected request {}", method),
+        }
+
+        self.accepted
+            .push((now - self.start, message.message_method, peer));
+        match &self.sha256_key {
+            Some(key) => builder.add_message_integrity_sha256(key),
+            None => builder.add_message_integrity(&self.key),
+        };
+        Some(builder.build().unwrap())
+    }
+
+    fn error_response(&self, request: &StunMessage, code: u16, reason: &str) -> Vec<u8> {
+        let mut builder = StunMessageBuilder::new(
+            StunMessageClass::ErrorResponse,
+            request.message_method,
+            request.transaction_id,
+        );
+        builder
+            .add_error_code_attribute(code, reason)
+            .add_attribute(StunAttributeType::Realm as u16, REALM.as_bytes())
+            .add_attribute(StunAttributeType::Nonce as u16, self.nonce.as_bytes());
+        if self.sha256_key.is_some() {
+            let algorithms = [StunPasswordAlgorithm::Md5, StunPasswordAlgorithm::Sha256]
+                .iter()
+                .map(|&algorithm| StunPasswordAlgorithmValue {
+                    algorithm: algorithm as u16,
+                    parameters: &[],
+                })
+                .collect::<Vec<_>>();
+            builder.add_attribute(
+                StunAttributeType::PasswordAlgorithms as u16,
+                &serialize_password_algorithms(&algorithms),
+            );
+        }
+
+        builder.build().unwrap()
+    }
+
+    /// Data received on the relayed address from a peer, as ChannelData if the peer has a
+    /// channel and in a Data indication otherwise
+    fn receive_from_peer(&self, peer: SocketAddr, data: &[u8], now: Instant) -> Option<Vec<u8>> {
+        if !self.has_permission(peer, now) {
+            return None;
+        }
+        if let Some((&channel_number, _)) = self.channels.iter().find(|(_, &p)| p == peer) {
+            return Some(serialize_channel_data(channel_number, data));
+        }
+
+        StunMessageBuilder::new(
+            StunMessageClass::Indication,
+            StunMessageMethod::Data,
+            &[0x33; STUN_TRANSACTION_ID_NUM_BYTES],
+        )
+        .add_xor_address_attribute(StunAttributeType::XorPeerAddress as u16, &peer)
+        .add_attribute(StunAttributeType::Data as u16, data)
+        .build()
+        .ok()
+    }
+}
+
+/// Exchange messages and advance the virtual time up to `until`, returning the events
+fn run(
+    client: &mut TurnClient,
+    server: &mut TestServer,
+    now: &mut Instant,
+    until: Duration,
+) -> Vec<TurnClientEvent> {
+    let until = server.start + until;
+
+    loop {
+        while let Some(transmit) = client.poll_transmit() {
+            if let Some(response) = server.handle(&transmit, *now) {
+                assert!(client.handle_input(&response, *now));
+            }
+        }
+
+        match client.poll_timeout() {
+            Some(timeout) if timeout <= until => {
+                *now = timeout.max(*now);
+                client.handle_timeout(*now);
+            }
+            _ => break,
+        }
+    }
+    *now = until;
+
+    std::iter::from_fn(|| client.poll_event()).collect()
+}
+
+fn allocate(options: TurnClientOptions) -> (TurnClient, TestServer, Instant) {
+    let mut now = Instant::now();
+    let mut server = TestServer::new(now);
+    let mut client =
+        TurnClient::with_options(USERNAME, PASSWORD, TransportProtocol::Udp, options, now).unwrap();
+
+    let events = run(&mut client, &mut server, &mut now, Duration::ZERO);
+    assert!(matches!(events[..], [TurnClientEvent::Allocated { .. }]));
+
+    (client, server, now)
+}
+
+#[test]
+fn test_allocate() {
+    let mut now = Instant::now();
+    let mut server = TestServer::new(now);
+    let options = TurnClientOptions {
+        software: Some("turn client test".to_string()),
+        fingerprint: true,
+        ..TurnClientOptions::default()
+    };
+    let mut client =
+        TurnClient::with_options(USERNAME, PASSWORD, TransportProtocol::Udp, options, now).unwrap();
+    assert!(!client.is_allocated());
+
+    let events = run(&mut client, &mut server, &mut now, Duration::ZERO);
+    match &events[..] {
+        [TurnClientEvent::Allocated {
+            relayed_address,
+            mapped_address,
+            lifetime,
+        }] => {
+            assert_eq!(*relayed_address, RELAYED.parse().unwrap());
+            assert_eq!(*mapped_address, CLIENT.parse().unwrap());
+            assert_eq!(*lifetime, Duration::from_secs(600));
+        }
+        events => panic!("unexpected events {:?}", events),
+    }
+
+    // the first request was challenged, and only the second one accepted
+    assert_eq!(server.accepted(StunMessageMethod::Allocate, None), [0]);
+    assert!(client.is_allocated());
+    assert_eq!(client.relayed_address(), Some(RELAYED.parse().unwrap()));
+    assert_eq!(client.mapped_address(), Some(CLIENT.parse().unwrap()));
+    assert_eq!(client.poll_timeout(), Some(now + Duration::from_secs(540)));
+}
+
+#[test]
+fn test_allocate_sha256() {
+    let mut now = Instant::now();
+    let mut server = TestServer::new(now);
+    let username = StunUsername::new(USERNAME).unwrap();
+    let realm = StunRealm::new(REALM).unwrap();
+    server.sha256_key = Some(long_term_credential_key_sha256(&username, &realm, PASSWORD).unwrap());
+    let mut client = TurnClient::new(USERNAME, PASSWORD, TransportProtocol::Udp, now).unwrap();
+
+    // the challenge offers SHA-256, which the client picks over MD5
+    let events = run(&mut client, &mut server, &mut now, Duration::ZERO);
+    assert!(matches!(events[..], [TurnClientEvent::Allocated { .. }]));
+    assert_eq!(server.accepted(StunMessageMethod::Allocate, None), [0]);
+
+    let peer: SocketAddr = "[2001:db8::1]:3478".parse().unwrap();
+    client.bind_channel(peer, now).unwrap();
+    let events = run(&mut client, &mut server, &mut now, Duration::ZERO);
+    assert!(matches!(events[..], [TurnClientEvent::ChannelBound { peer: p, .. }] if p == peer));
+    assert_eq!(
+        server.accepted(StunMessageMethod::ChannelBind, Some(peer)),
+        [0]
+    );
+}
+
+#[test]
+fn test_refresh() {
+    let (mut client, mut server, mut now) = allocate(TurnClientOptions::default());
+    let permission_peer: SocketAddr = "203.0.113.1:3478".parse().unwrap();
+    let channel_peer: SocketAddr = "203.0.113.2:3478".parse().unwrap();
+
+    client.create_permission(permission_peer.ip(), now).unwrap();
+    let channel_number = client.bind_channel(channel_peer, now).unwrap();
+    assert_eq!(channel_number, TURN_CHANNEL_NUMBER_MIN);
+
+    let minutes = Duration::from_secs(30 * 60);
+    run(&mut client, &mut server, &mut now, minutes);
+
+    // the allocation a minute before its lifetime, permissions every 4 minutes and channels
+    // every 9 minutes
+    assert_eq!(
+        server.accepted(StunMessageMethod::Refresh, None),
+        [540, 1080, 1620]
+    );
+    assert_eq!(
+        server.accepted(
+            StunMessageMethod::CreatePermission,
+            Some(SocketAddr::new(permission_peer.ip(), 0))
+        ),
+        [0, 240, 480, 720, 960, 1200, 1440, 1680]
+    );
+    assert_eq!(
+        server.accepted(StunMessageMethod::ChannelBind, Some(channel_peer)),
+        [0, 540, 1080, 1620]
+    );
+
+    // both peers are still reachable
+    assert!(client.is_allocated());
+    assert!(server.has_permission(permission_peer, now));
+    assert!(server.has_permission(channel_peer, now));
+}
+
+#[test]
+fn test_send_and_receive() {
+    let (mut client, mut server, mut now) = allocate(TurnClientOptions::default());
+    let peer: SocketAddr = "203.0.113.1:3478".parse().unwrap();
+    let channel_peer: SocketAddr = "203.0.113.2:3478".parse().unwrap();
+
+    // dropped without a permission
+    client.send(peer, b"dropped").unwrap();
+    run(&mut client, &mut server, &mut now, Duration::ZERO);
+    assert!(server.relayed.is_empty());
+    assert!(server.receive_from_peer(peer, b"dropped", now).is_none());
+
+    // Send and Data indications
+    client.create_permission(peer.ip(), now).unwrap();
+    let events = run(&mut client, &mut server, &mut now, Duration::ZERO);
+    assert!(matches!(events[..], [TurnClientEvent::PermissionCreated(ip)] if ip == peer.ip()));
+
+    client.send(peer, b"hello").unwrap();
+    let indication = client.poll_transmit().unwrap();
+    assert!(server.handle(&indication, now).is_none());
+    assert_eq!(server.relayed, [(peer, b"hello".to_vec())]);
+
+    let indication = server.receive_from_peer(peer, b"hi", now).unwrap();
+    assert!(client.handle_input(&indication, now));
+    assert!(matches!(
+        client.poll_event(),
+        Some(TurnClientEvent::Data { peer: p, data }) if p == peer && data == b"hi"
+    ));
+
+    // ChannelData, once the channel is bound
+    let channel_number = client.bind_channel(channel_peer, now).unwrap();
+    let events = run(&mut client, &mut server, &mut now, Duration::ZERO);
+    assert!(matches!(
+        events[..],
+        [TurnClientEvent::ChannelBound { channel_number: c, peer: p }]
+            if c == channel_number && p == channel_peer
+    ));
+
+    client.send(channel_peer, b"hello channel").unwrap();
+    let channel_data = client.poll_transmit().unwrap();
+    assert_eq!(
+        channel_data,
+        serialize_channel_data(channel_number, b"hello channel")
+    );
+    assert!(server.handle(&channel_data, now).is_none());
+    assert_eq!(server.relayed[1], (channel_peer, b"hello channel".to_vec()));
+
+    let channel_data = server
+        .receive_from_peer(channel_peer, b"hi channel", now)
+        .unwrap();
+    assert!(client.handle_input(&channel_data, now));
+    assert!(matches!(
+        client.poll_event(),
+        Some(TurnClientEvent::Data { peer: p, data }) if p == channel_peer && data == b"hi channel"
+    ));
+
+    // unknown channels are ignored
+    let channel_data = serialize_channel_data(channel_number + 1, b"unknown");
+    assert!(!client.handle_input(&channel_data, now));
+    assert!(client.poll_event().is_none());
+}
+
+#[test]
+fn test_stale_nonce() {
+    let (mut client, mut server, mut now) = allocate(TurnClientOptions::default());
+    let peer: IpAddr = "203.0.113.1".parse().unwrap();
+
+    server.stale_nonce = true;
+    client.create_permission(peer, now).unwrap();
+    let events = run(&mut client, &mut server, &mut now, Duration::ZERO);
+
+    // resent once with the new NONCE
+    assert!(matches!(events[..], [TurnClientEvent::PermissionCreated(ip)] if ip == peer));
+    assert_eq!(server.stale_nonces, 1);
+
+    // and the new NONCE is used from then on
+    let events = run(&mut client, &mut server, &mut now, Duration::from_secs(540));
+    assert!(matches!(events[..], [TurnClientEvent::Refreshed { .. }]));
+    assert_eq!(server.accepted(StunMessageMethod::Refresh, None), [540]);
+}
+
+#[test]
+fn test_wrong_password() {
+    let mut now = Instant::now();
+    let mut server = TestServer::new(now);
+    let mut client = TurnClient::new(USERNAME, "wrong", TransportProtocol::Udp, now).unwrap();
+
+    let events = run(&mut client, &mut server, &mut now, Duration::ZERO);
+    assert!(matches!(
+        events[..],
+        [
+            TurnClientEvent::RequestFailed {
+                request: TurnRequest::Allocate,
+                error: StunTurnClientError::RequestFailedError(STUN_ERROR_UNAUTHORIZED),
+            },
+            TurnClientEvent::Closed,
+        ]
+    ));
+    assert!(server.accepted.is_empty());
+    assert!(!client.is_allocated());
+    assert_eq!(client.poll_timeout(), None);
+}
+
+#[test]
+fn test_deallocate() {
+    let (mut client, mut server, mut now) = allocate(TurnClientOptions::default());
+    let peer: SocketAddr = "203.0.113.1:3478".parse().unwrap();
+
+    client.create_permission(peer.ip(), now).unwrap();
+    run(&mut client, &mut server, &mut now, Duration::ZERO);
+
+    client.deallocate(now).unwrap();
+    let events = run(&mut client, &mut server, &mut now, Duration::from_secs(60));
+    assert!(matches!(events[..], [TurnClientEvent::Closed]));
+    assert!(!server.allocated);
+
+    assert!(!client.is_allocated());
+    assert_eq!(client.poll_timeout(), None);
+    assert!(matches!(
+        client.send(peer, b"hello"),
+        Err(StunTurnClientError::NoAllocationError)
+    ));
+    assert!(matches!(
+        client.bind_channel(peer, now),
+        Err(StunTurnClientError::NoAllocationError)
+    ));
+}
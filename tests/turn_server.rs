@@ -0,0 +1,170 @@
+//! A `TurnClient` relaying through a `TurnServer` on 127.0.0.1,
+//! https://tools.ietf.org/html/rfc5766
+#![cfg(feature = "std")]
+
+use stun_message::*;
+
+use std::net::{SocketAddr, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const USERNAME: &str = "alice";
+const PASSWORD: &str = "secret";
+
+/// Start a server with two relayed transport addresses, returning the address clients send to
+fn start_server() -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let relays = [
+        UdpSocket::bind("127.0.0.1:0").unwrap(),
+        UdpSocket::bind("127.0.0.1:0").unwrap(),
+    ];
+    let relayed_addresses = relays.iter().map(|relay| relay.local_addr().unwrap());
+
+    let mut server = TurnServer::new("example.org", relayed_addresses, Instant::now()).unwrap();
+    server.add_user(USERNAME, PASSWORD).unwrap();
+    let address = socket.local_addr().unwrap();
+    thread::spawn(move || server.serve_udp(&socket, &relays));
+
+    address
+}
+
+fn bind() -> UdpSocket {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    socket
+}
+
+/// Drive the client on the socket until it reports an event, which is returned
+fn next_event(client: &mut TurnClient, socket: &UdpSocket, server: SocketAddr) -> TurnClientEvent {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut buffer = [0; 2048];
+
+    loop {
+        while let Some(transmit) = client.poll_transmit() {
+            socket.send_to(&transmit, server).unwrap();
+        }
+        if let Some(event) = client.poll_event() {
+            return event;
+        }
+
+        let now = Instant::now();
+        assert!(now < deadline, "no event from the client");
+        let timeout = client
+            .poll_timeout()
+            .map_or(deadline, |timeout| timeout.min(deadline))
+            .saturating_duration_since(now)
+            .max(Duration::from_millis(1));
+        socket.set_read_timeout(Some(timeout)).unwrap();
+
+        match socket.recv(&mut buffer) {
+            Ok(length) => {
+                client.handle_input(&buffer[..length], Instant::now());
+            }
+            Err(_) => client.handle_timeout(Instant::now()),
+        }
+    }
+}
+
+#[test]
+fn test_relay() {
+    let server = start_server();
+    let socket = bind();
+    let peer = bind();
+    let peer_address = peer.local_addr().unwrap();
+    let mut buffer = [0; 2048];
+
+    let mut client =
+        TurnClient::new(USERNAME, PASSWORD, TransportProtocol::Udp, Instant::now()).unwrap();
+    let relayed_address = match next_event(&mut client, &socket, server) {
+        TurnClientEvent::Allocated {
+            relayed_address,
+            mapped_address,
+            ..
+        } => {
+            assert_eq!(mapped_address, socket.local_addr().unwrap());
+            relayed_address
+        }
+        event => panic!("unexpected event {:?}", event),
+    };
+
+    // Send and Data indications
+    client
+        .create_permission(peer_address.ip(), Instant::now())
+        .unwrap();
+    assert!(matches!(
+        next_event(&mut client, &socket, server),
+        TurnClientEvent::PermissionCreated(_)
+    ));
+
+    client.send(peer_address, b"hello").unwrap();
+    while let Some(transmit) = client.poll_transmit() {
+        socket.send_to(&transmit, server).unwrap();
+    }
+    let (length, from) = peer.recv_from(&mut buffer).unwrap();
+    assert_eq!((&buffer[..length], from), (&b"hello"[..], relayed_address));
+
+    peer.send_to(b"hi", relayed_address).unwrap();
+    match next_event(&mut client, &socket, server) {
+        TurnClientEvent::Data { peer, data } => {
+            assert_eq!((peer, &data[..]), (peer_address, &b"hi"[..]));
+        }
+        event => panic!("unexpected event {:?}", event),
+    }
+
+    // ChannelData
+    client.bind_channel(peer_address, Instant::now()).unwrap();
+    assert!(matches!(
+        next_event(&mut client, &socket, server),
+        TurnClientEvent::ChannelBound { .. }
+    ));
+
+    client.send(peer_address, b"hello channel").unwrap();
+    while let Some(transmit) = client.poll_transmit() {
+        socket.send_to(&transmit, server).unwrap();
+    }
+    let (length, from) = peer.recv_from(&mut buffer).unwrap();
+    assert_eq!(
+        (&buffer[..length], from),
+        (&b"hello channel"[..], relayed_address)
+    );
+
+    peer.send_to(b"hi channel", relayed_address).unwrap();
+    match next_event(&mut client, &socket, server) {
+        TurnClientEvent::Data { peer, data } => {
+            assert_eq!((peer, &data[..]), (peer_address, &b"hi channel"[..]));
+        }
+        event => panic!("unexpected event {:?}", event),
+    }
+
+    client.deallocate(Instant::now()).unwrap();
+    assert!(matches!(
+        next_event(&mut client, &socket, server),
+        TurnClientEvent::Closed
+    ));
+}
+
+#[test]
+fn test_allocation_mismatch() {
+    let server = start_server();
+    let socket = bind();
+
+    let mut client =
+        TurnClient::new(USERNAME, PASSWORD, TransportProtocol::Udp, Instant::now()).unwrap();
+    assert!(matches!(
+        next_event(&mut client, &socket, server),
+        TurnClientEvent::Allocated { .. }
+    ));
+
+    // a second allocation on the same 5-tuple is refused
+    let mut second =
+        TurnClient::new(USERNAME, PASSWORD, TransportProtocol::Udp, Instant::now()).unwrap();
+    assert!(matches!(
+        next_event(&mut second, &socket, server),
+        TurnClientEvent::RequestFailed {
+            request: TurnRequest::Allocate,
+            error: StunTurnClientError::RequestFailedError(STUN_ERROR_ALLOCATION_MISMATCH),
+        }
+    ));
+}